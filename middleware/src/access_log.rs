@@ -0,0 +1,215 @@
+// External crates
+use actix_web::body::{BodySize, BoxBody, MessageBody};
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::{Error, HttpMessage};
+use bytes::Bytes;
+use futures::future::{ok, LocalBoxFuture, Ready};
+use log::warn;
+use serde_json::json;
+
+// Standard library
+use std::fs::OpenOptions;
+use std::io::{self, LineWriter, Write};
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant, SystemTime};
+
+// Internal modules
+use crate::auth::AuthInfo;
+
+// -----------------------------------------------------------------------------
+// Access Log
+// -----------------------------------------------------------------------------
+
+// One JSON line per request, written once its response body is done (the last chunk of a
+// stream, or the client going away). The lines go to a file or stdout of their own, apart
+// from the application logs on stderr, so they can be shipped as they are.
+
+// The endpoint that answered a proxied request, left on its response for the access log
+#[derive(Debug, Clone)]
+pub struct UpstreamInfo {
+    pub endpoint: String,
+    // None if the endpoint could not be reached
+    pub status: Option<u16>,
+}
+
+type Sink = Arc<Mutex<Box<dyn Write + Send>>>;
+
+#[derive(Clone)]
+pub struct AccessLog {
+    sink: Option<Sink>,
+}
+
+impl AccessLog {
+    pub fn disabled() -> Self {
+        AccessLog { sink: None }
+    }
+
+    // Append to the file at `target`, or write to stdout for `-`
+    pub fn open(target: &str) -> io::Result<Self> {
+        let writer: Box<dyn Write + Send> = if target == "-" {
+            Box::new(io::stdout())
+        } else {
+            Box::new(LineWriter::new(OpenOptions::new().create(true).append(true).open(target)?))
+        };
+        Ok(AccessLog { sink: Some(Arc::new(Mutex::new(writer))) })
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for AccessLog
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<AccessLogBody>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = AccessLogService<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(AccessLogService {
+            service: Rc::new(service),
+            sink: self.sink.clone(),
+        })
+    }
+}
+
+pub struct AccessLogService<S> {
+    service: Rc<S>,
+    sink: Option<Sink>,
+}
+
+impl<S, B> Service<ServiceRequest> for AccessLogService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<AccessLogBody>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    actix_web::dev::forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let svc = self.service.clone();
+        let Some(sink) = self.sink.clone() else {
+            return Box::pin(async move {
+                let res = svc.call(req).await?;
+                Ok(res.map_body(|_, body| AccessLogBody { body: body.boxed(), entry: None }))
+            });
+        };
+        let ts = SystemTime::now();
+        let started = Instant::now();
+        let method = req.method().to_string();
+        // Without the query, which may carry anything
+        let path = req.path().to_string();
+
+        Box::pin(async move {
+            let res = svc.call(req).await?;
+            // Set by the auth middleware, which runs inside this one
+            let group = res.request().extensions().get::<AuthInfo>().map(|info| info.groups.join(","));
+            let upstream = res.response().extensions().get::<UpstreamInfo>().cloned();
+            let entry = Entry {
+                sink,
+                ts,
+                started,
+                method,
+                path,
+                status: res.status().as_u16(),
+                group,
+                upstream,
+                first_byte: None,
+                bytes: 0,
+                complete: false,
+            };
+            Ok(res.map_body(|_, body| AccessLogBody { body: body.boxed(), entry: Some(entry) }))
+        })
+    }
+}
+
+// What is known of a request until its line is written
+struct Entry {
+    sink: Sink,
+    ts: SystemTime,
+    started: Instant,
+    method: String,
+    path: String,
+    status: u16,
+    group: Option<String>,
+    upstream: Option<UpstreamInfo>,
+    first_byte: Option<Duration>,
+    bytes: u64,
+    // The body was sent to its end rather than dropped with the connection
+    complete: bool,
+}
+
+impl Entry {
+    fn write(self) {
+        let line = json!({
+            "ts": humantime::format_rfc3339_millis(self.ts).to_string(),
+            "method": self.method,
+            "path": self.path,
+            "status": self.status,
+            "group": self.group,
+            "endpoint": self.upstream.as_ref().map(|upstream| upstream.endpoint.as_str()),
+            "upstream_status": self.upstream.as_ref().and_then(|upstream| upstream.status),
+            "ttfb_ms": self.first_byte.map(|ttfb| ttfb.as_millis() as u64),
+            "duration_ms": self.started.elapsed().as_millis() as u64,
+            "bytes": self.bytes,
+            "complete": self.complete,
+        });
+        let mut sink = self.sink.lock().unwrap();
+        if let Err(e) = writeln!(sink, "{}", line) {
+            warn!("Failed to write the access log: {}", e);
+        }
+    }
+}
+
+// The response body, counting what is sent of it
+pub struct AccessLogBody {
+    body: BoxBody,
+    entry: Option<Entry>,
+}
+
+impl MessageBody for AccessLogBody {
+    type Error = Box<dyn std::error::Error>;
+
+    fn size(&self) -> BodySize {
+        self.body.size()
+    }
+
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Bytes, Self::Error>>> {
+        let this = self.get_mut();
+        let poll = Pin::new(&mut this.body).poll_next(cx);
+        if let Some(entry) = &mut this.entry {
+            match &poll {
+                Poll::Ready(Some(Ok(chunk))) => {
+                    entry.first_byte.get_or_insert_with(|| entry.started.elapsed());
+                    entry.bytes += chunk.len() as u64;
+                }
+                Poll::Ready(None) => entry.complete = true,
+                _ => {}
+            }
+        }
+        poll
+    }
+}
+
+// Written once actix is done with the body, however the response ended
+impl Drop for AccessLogBody {
+    fn drop(&mut self) {
+        if let Some(mut entry) = self.entry.take() {
+            // Empty bodies are never polled
+            if matches!(self.body.size(), BodySize::None | BodySize::Sized(0)) {
+                entry.complete = true;
+            }
+            entry.write();
+        }
+    }
+}
@@ -0,0 +1,172 @@
+// External crates
+use log::info;
+use tokio::sync::Notify;
+use tokio::time::timeout;
+
+// Standard library
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+// -----------------------------------------------------------------------------
+// Admission Control
+// -----------------------------------------------------------------------------
+
+// Waiting requests look again this often even without a released slot, endpoints may come
+// back or be added meanwhile
+const RECHECK_INTERVAL: Duration = Duration::from_millis(250);
+
+// A request waiting in a queue
+#[derive(Debug, Clone, Copy)]
+struct Waiter {
+    ticket: u64,
+    // Of the caller's groups
+    priority: u32,
+}
+
+#[derive(Default)]
+struct Queues {
+    // Queue name -> waiting requests in the order of their turns: by priority, then first in,
+    // first out
+    waiting: HashMap<String, Vec<Waiter>>,
+    // Tickets pushed out of a full queue by requests of a higher priority
+    shed: HashSet<u64>,
+}
+
+// Requests waiting for a slot while the pools of every endpoint of their model are full, in
+// a bounded queue per model and route class. Higher priorities get their turn first and push
+// the lowest out of a full queue.
+pub struct AdmissionQueue {
+    // Requests waiting per queue, 0 turns busy requests away at once
+    pub max_depth: usize,
+    // Longest wait before a request is turned away
+    pub max_wait: Duration,
+    next_ticket: AtomicU64,
+    queues: Arc<Mutex<Queues>>,
+    // Woken when a request leaves a queue, so the next one gets its turn
+    turns: Arc<Notify>,
+}
+
+impl AdmissionQueue {
+    // VLLM_COMPOSER_QUEUE_DEPTH (default 100, 0 disables queueing) and
+    // VLLM_COMPOSER_QUEUE_WAIT_SECS (default 30)
+    pub fn from_env() -> Self {
+        let env_u64 = |name: &str, default: u64| {
+            std::env::var(name)
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(default)
+        };
+        let queue = AdmissionQueue::new(
+            env_u64("VLLM_COMPOSER_QUEUE_DEPTH", 100) as usize,
+            Duration::from_secs(env_u64("VLLM_COMPOSER_QUEUE_WAIT_SECS", 30)),
+        );
+        if queue.is_enabled() {
+            info!(
+                "Queueing up to {} requests per model for up to {:?} while its endpoints are busy",
+                queue.max_depth, queue.max_wait
+            );
+        }
+        queue
+    }
+
+    pub fn new(max_depth: usize, max_wait: Duration) -> Self {
+        AdmissionQueue {
+            max_depth,
+            max_wait,
+            next_ticket: AtomicU64::new(0),
+            queues: Arc::new(Mutex::new(Queues::default())),
+            turns: Arc::new(Notify::new()),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.max_depth > 0 && !self.max_wait.is_zero()
+    }
+
+    // Whether requests wait in the queue; newcomers line up with them
+    pub fn has_waiting(&self, queue: &str) -> bool {
+        self.queues.lock().unwrap().waiting.get(queue).is_some_and(|waiters| !waiters.is_empty())
+    }
+
+    // Line up behind the waiting requests of the same or a higher priority. A full queue
+    // sheds its newest request of the lowest priority if that is lower than the newcomer's,
+    // else the newcomer is turned away with None.
+    pub fn join(&self, queue: &str, priority: u32) -> Option<Ticket> {
+        let mut queues = self.queues.lock().unwrap();
+        let Queues { waiting, shed } = &mut *queues;
+        let waiters = waiting.entry(queue.to_string()).or_default();
+        if waiters.len() >= self.max_depth {
+            let last = waiters.last().copied()?;
+            if last.priority >= priority {
+                return None;
+            }
+            waiters.pop();
+            shed.insert(last.ticket);
+            self.turns.notify_waiters();
+        }
+        let ticket = self.next_ticket.fetch_add(1, Ordering::Relaxed);
+        let position = waiters.partition_point(|waiter| waiter.priority >= priority);
+        waiters.insert(position, Waiter { ticket, priority });
+        Some(Ticket {
+            ticket,
+            queue: queue.to_string(),
+            queues: Arc::clone(&self.queues),
+            turns: Arc::clone(&self.turns),
+        })
+    }
+
+    // Wait until a slot may have been released or the turn passed on, at most `remaining`
+    pub async fn wait(&self, released: &Notify, remaining: Duration) {
+        let woken = async {
+            tokio::select! {
+                _ = released.notified() => {}
+                _ = self.turns.notified() => {}
+            }
+        };
+        let _ = timeout(remaining.min(RECHECK_INTERVAL), woken).await;
+    }
+
+    pub fn entries(&self) -> usize {
+        self.queues.lock().unwrap().waiting.len()
+    }
+}
+
+// A request's place in a queue, left when dropped, e.g. once the request got its slot or the
+// client went away
+pub struct Ticket {
+    ticket: u64,
+    queue: String,
+    queues: Arc<Mutex<Queues>>,
+    turns: Arc<Notify>,
+}
+
+impl Ticket {
+    // Whether no request waits ahead of this one
+    pub fn is_first(&self) -> bool {
+        let queues = self.queues.lock().unwrap();
+        let first = queues.waiting.get(&self.queue).and_then(|waiters| waiters.first());
+        first.is_some_and(|waiter| waiter.ticket == self.ticket)
+    }
+
+    // Whether a request of a higher priority took this one's place
+    pub fn was_shed(&self) -> bool {
+        self.queues.lock().unwrap().shed.contains(&self.ticket)
+    }
+}
+
+impl Drop for Ticket {
+    fn drop(&mut self) {
+        let mut queues = self.queues.lock().unwrap();
+        if !queues.shed.remove(&self.ticket)
+            && let Some(waiters) = queues.waiting.get_mut(&self.queue)
+        {
+            waiters.retain(|waiter| waiter.ticket != self.ticket);
+            if waiters.is_empty() {
+                queues.waiting.remove(&self.queue);
+            }
+        }
+        self.turns.notify_waiters();
+    }
+}
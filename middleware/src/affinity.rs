@@ -0,0 +1,68 @@
+// External crates
+use actix_web::HttpRequest;
+use log::info;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+// Internal modules
+use crate::state::Endpoint;
+
+// -----------------------------------------------------------------------------
+// Session Affinity
+// -----------------------------------------------------------------------------
+
+// Opt-in sticky routing: requests of one session go to the same endpoint while it stays
+// healthy, so multi-turn chats hit vLLM's prefix cache
+pub struct SessionAffinity {
+    enabled: bool,
+    // Request header carrying the session id
+    header: String,
+}
+
+impl SessionAffinity {
+    // VLLM_COMPOSER_SESSION_AFFINITY (true/1) enables it, VLLM_COMPOSER_SESSION_HEADER names
+    // the header (default X-Session-Id)
+    pub fn from_env() -> Self {
+        let enabled = std::env::var("VLLM_COMPOSER_SESSION_AFFINITY")
+            .map(|v| v == "true" || v == "1")
+            .unwrap_or(false);
+        let header = std::env::var("VLLM_COMPOSER_SESSION_HEADER")
+            .ok()
+            .filter(|h| !h.is_empty())
+            .unwrap_or_else(|| "X-Session-Id".to_string());
+        if enabled {
+            info!("Session affinity on {} header or `user` field", header);
+        }
+        SessionAffinity { enabled, header }
+    }
+
+    // The session header, else the body's `user` field
+    pub fn session_key(&self, req: &HttpRequest, body: &Value) -> Option<String> {
+        if !self.enabled {
+            return None;
+        }
+        req.headers()
+            .get(&self.header)
+            .and_then(|v| v.to_str().ok())
+            .or_else(|| body.get("user").and_then(Value::as_str))
+            .filter(|key| !key.is_empty())
+            .map(String::from)
+    }
+}
+
+// Weighted rendezvous hashing: every endpoint scores the session and the best score wins.
+// Losing an endpoint only moves the sessions it held; the others stay where they are.
+pub fn pick_by_session(key: &str, candidates: Vec<Endpoint>) -> Option<Endpoint> {
+    let score = |ep: &Endpoint| {
+        let digest = Sha256::new().chain_update(key).chain_update([0]).chain_update(&ep.url).finalize();
+        let hash = u64::from_be_bytes(digest[..8].try_into().unwrap());
+        // Uniform in (0, 1)
+        let unit = (hash as f64 + 1.0) / (u64::MAX as f64 + 2.0);
+        ep.weight.unwrap_or(1).max(1) as f64 / -unit.ln()
+    };
+    candidates
+        .into_iter()
+        .map(|ep| (score(&ep), ep))
+        .max_by(|(a, _), (b, _)| a.total_cmp(b))
+        .map(|(_, ep)| ep)
+}
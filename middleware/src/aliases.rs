@@ -0,0 +1,58 @@
+// External crates
+use serde_json::Value;
+
+// Internal modules
+use crate::body::replace_model;
+use crate::state::Endpoint;
+
+// -----------------------------------------------------------------------------
+// Model Aliases
+// -----------------------------------------------------------------------------
+
+// The endpoint's models followed by an entry per alias of a served model, so aliases are
+// listed and routed like the models they stand for
+pub fn with_aliases(endpoint: &Endpoint, mut models: Vec<Value>) -> Vec<Value> {
+    let Some(aliases) = &endpoint.aliases else {
+        return models;
+    };
+    let mut alias_entries = Vec::new();
+    for (alias, target) in aliases {
+        if models.iter().any(|m| m.get("id").and_then(Value::as_str) == Some(alias)) {
+            continue;
+        }
+        if let Some(model) = models.iter().find(|m| m.get("id").and_then(Value::as_str) == Some(target)) {
+            let mut entry = model.clone();
+            entry["id"] = Value::String(alias.clone());
+            alias_entries.push(entry);
+        }
+    }
+    models.extend(alias_entries);
+    models
+}
+
+// The name the endpoint serves an alias under, None for other models
+pub fn resolve_alias<'a>(endpoint: &'a Endpoint, model: &str) -> Option<&'a str> {
+    endpoint.aliases.as_ref()?.get(model).map(String::as_str)
+}
+
+// Restore the requested name in the `model` field of a JSON response
+pub fn rename_model_in_json(text: &str, from: &str, to: &str) -> Option<String> {
+    let renamed = replace_model(text.as_bytes(), from, to)?;
+    String::from_utf8(renamed.to_vec()).ok()
+}
+
+// Restore the requested name in the `model` field of each `data:` event of complete SSE lines.
+// Only the name changes, the events stay as the endpoint wrote them.
+pub fn rename_model_in_sse(lines: &[u8], from: &str, to: &str) -> Vec<u8> {
+    let mut out = Vec::with_capacity(lines.len());
+    for line in lines.split_inclusive(|b| *b == b'\n') {
+        match line.strip_prefix(b"data:").and_then(|data| replace_model(data, from, to)) {
+            Some(event) => {
+                out.extend_from_slice(b"data:");
+                out.extend_from_slice(&event);
+            }
+            None => out.extend_from_slice(line),
+        }
+    }
+    out
+}
@@ -0,0 +1,411 @@
+// External crates
+use actix_web::http::StatusCode;
+use serde_json::{json, Map, Value};
+
+// -----------------------------------------------------------------------------
+// Anthropic Messages
+// -----------------------------------------------------------------------------
+
+// /v1/messages takes requests of Anthropic's Messages API, which are translated to chat
+// completions for the vLLM endpoints and their answers back, so tools built on the
+// Anthropic SDKs use the same models
+
+// A Messages request as a chat completion request
+pub fn to_chat_request(body: &Value) -> Result<Value, String> {
+    let Some(messages) = body.get("messages").and_then(Value::as_array) else {
+        return Err("`messages` must be a list of messages.".to_string());
+    };
+    let mut chat = Map::new();
+    let mut chat_messages = Vec::new();
+    match body.get("system") {
+        Some(Value::String(system)) => chat_messages.push(json!({ "role": "system", "content": system })),
+        Some(blocks @ Value::Array(_)) => {
+            chat_messages.push(json!({ "role": "system", "content": joined_text(blocks) }))
+        }
+        _ => {}
+    }
+    for message in messages {
+        match message.get("role").and_then(Value::as_str) {
+            Some("user") => user_messages(message.get("content"), &mut chat_messages),
+            Some("assistant") => chat_messages.push(assistant_message(message.get("content"))),
+            _ => return Err("Each message needs a `role` of `user` or `assistant`.".to_string()),
+        }
+    }
+    chat.insert("messages".to_string(), Value::Array(chat_messages));
+
+    for field in ["model", "max_tokens", "temperature", "top_p", "top_k", "stream"] {
+        if let Some(value) = body.get(field) {
+            chat.insert(field.to_string(), value.clone());
+        }
+    }
+    if let Some(stop) = body.get("stop_sequences") {
+        chat.insert("stop".to_string(), stop.clone());
+    }
+    if let Some(user) = body.pointer("/metadata/user_id") {
+        chat.insert("user".to_string(), user.clone());
+    }
+    if let Some(tools) = body.get("tools").and_then(Value::as_array) {
+        let tools = tools
+            .iter()
+            .map(|tool| {
+                json!({
+                    "type": "function",
+                    "function": {
+                        "name": tool.get("name"),
+                        "description": tool.get("description"),
+                        "parameters": tool.get("input_schema"),
+                    },
+                })
+            })
+            .collect();
+        chat.insert("tools".to_string(), Value::Array(tools));
+    }
+    if let Some(choice) = body.get("tool_choice") {
+        let choice = match choice.get("type").and_then(Value::as_str) {
+            Some("any") => json!("required"),
+            Some("none") => json!("none"),
+            Some("tool") => json!({ "type": "function", "function": { "name": choice.get("name") } }),
+            _ => json!("auto"),
+        };
+        chat.insert("tool_choice".to_string(), choice);
+    }
+    Ok(Value::Object(chat))
+}
+
+// A user turn: its tool results as tool messages, which must follow the assistant's tool
+// calls, then the rest of its content
+fn user_messages(content: Option<&Value>, chat_messages: &mut Vec<Value>) {
+    let blocks = match content {
+        Some(Value::Array(blocks)) => blocks,
+        Some(text) => {
+            chat_messages.push(json!({ "role": "user", "content": text }));
+            return;
+        }
+        None => return,
+    };
+    let mut parts = Vec::new();
+    for block in blocks {
+        match block.get("type").and_then(Value::as_str) {
+            Some("tool_result") => {
+                let content = match block.get("content") {
+                    Some(Value::String(text)) => text.clone(),
+                    Some(blocks) => joined_text(blocks),
+                    None => String::new(),
+                };
+                chat_messages.push(json!({
+                    "role": "tool",
+                    "tool_call_id": block.get("tool_use_id"),
+                    "content": content,
+                }));
+            }
+            Some("text") => parts.push(json!({ "type": "text", "text": block.get("text") })),
+            Some("image") => {
+                let source = block.get("source").unwrap_or(&Value::Null);
+                let url = match source.get("type").and_then(Value::as_str) {
+                    Some("url") => source.get("url").and_then(Value::as_str).unwrap_or_default().to_string(),
+                    _ => format!(
+                        "data:{};base64,{}",
+                        source.get("media_type").and_then(Value::as_str).unwrap_or("image/png"),
+                        source.get("data").and_then(Value::as_str).unwrap_or_default()
+                    ),
+                };
+                parts.push(json!({ "type": "image_url", "image_url": { "url": url } }));
+            }
+            _ => {}
+        }
+    }
+    if parts.is_empty() {
+        return;
+    }
+    // Plain text reads better to text-only chat templates
+    let content = if parts.iter().all(|part| part["type"] == "text") {
+        json!(joined_text(&Value::Array(parts)))
+    } else {
+        Value::Array(parts)
+    };
+    chat_messages.push(json!({ "role": "user", "content": content }));
+}
+
+// An assistant turn with its text and tool calls; thinking blocks are left out
+fn assistant_message(content: Option<&Value>) -> Value {
+    let Some(Value::Array(blocks)) = content else {
+        return json!({ "role": "assistant", "content": content });
+    };
+    let tool_calls: Vec<Value> = blocks
+        .iter()
+        .filter(|block| block.get("type").and_then(Value::as_str) == Some("tool_use"))
+        .map(|block| {
+            json!({
+                "id": block.get("id"),
+                "type": "function",
+                "function": {
+                    "name": block.get("name"),
+                    "arguments": block.get("input").unwrap_or(&json!({})).to_string(),
+                },
+            })
+        })
+        .collect();
+    let mut message = json!({ "role": "assistant", "content": joined_text(&Value::Array(blocks.clone())) });
+    if !tool_calls.is_empty() {
+        message["tool_calls"] = Value::Array(tool_calls);
+    }
+    message
+}
+
+// The text of a list of content blocks
+fn joined_text(blocks: &Value) -> String {
+    blocks
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter(|block| block.get("type").and_then(Value::as_str) == Some("text"))
+        .filter_map(|block| block.get("text").and_then(Value::as_str))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+// Anthropic's stop reason for a choice's finish reason, and the stop sequence that ended it.
+// vLLM names the matched stop string in `stop_reason`.
+fn stop_reason(choice: &Value) -> (&'static str, Value) {
+    let stop_sequence = choice.get("stop_reason").filter(|reason| reason.is_string()).cloned();
+    match choice.get("finish_reason").and_then(Value::as_str) {
+        Some("length") => ("max_tokens", Value::Null),
+        Some("tool_calls") => ("tool_use", Value::Null),
+        _ => match stop_sequence {
+            Some(sequence) => ("stop_sequence", sequence),
+            None => ("end_turn", Value::Null),
+        },
+    }
+}
+
+fn message_id(completion_id: Option<&str>) -> String {
+    let id = completion_id.unwrap_or_default();
+    format!("msg_{}", id.strip_prefix("chatcmpl-").unwrap_or(id))
+}
+
+// A chat completion as a Messages response, named after the requested model
+pub fn from_chat_response(completion: &Value, model: &str) -> Value {
+    let choice = completion.pointer("/choices/0").unwrap_or(&Value::Null);
+    let message = choice.get("message").unwrap_or(&Value::Null);
+    let mut content = Vec::new();
+    if let Some(text) = message.get("content").and_then(Value::as_str).filter(|text| !text.is_empty()) {
+        content.push(json!({ "type": "text", "text": text }));
+    }
+    for call in message.get("tool_calls").and_then(Value::as_array).into_iter().flatten() {
+        content.push(tool_use_block(call, parsed_arguments(call)));
+    }
+    let (stop_reason, stop_sequence) = stop_reason(choice);
+    json!({
+        "id": message_id(completion.get("id").and_then(Value::as_str)),
+        "type": "message",
+        "role": "assistant",
+        "model": model,
+        "content": content,
+        "stop_reason": stop_reason,
+        "stop_sequence": stop_sequence,
+        "usage": usage_of(completion),
+    })
+}
+
+fn tool_use_block(call: &Value, input: Value) -> Value {
+    json!({
+        "type": "tool_use",
+        "id": call.get("id"),
+        "name": call.pointer("/function/name"),
+        "input": input,
+    })
+}
+
+fn parsed_arguments(call: &Value) -> Value {
+    call.pointer("/function/arguments")
+        .and_then(Value::as_str)
+        .and_then(|arguments| serde_json::from_str(arguments).ok())
+        .unwrap_or_else(|| json!({}))
+}
+
+fn usage_of(completion: &Value) -> Value {
+    json!({
+        "input_tokens": completion.pointer("/usage/prompt_tokens").and_then(Value::as_u64).unwrap_or(0),
+        "output_tokens": completion.pointer("/usage/completion_tokens").and_then(Value::as_u64).unwrap_or(0),
+    })
+}
+
+// An error response body in Anthropic's schema, from one in OpenAI's or any other body
+pub fn error_body(status: StatusCode, body: &[u8]) -> Value {
+    let message = serde_json::from_slice::<Value>(body)
+        .ok()
+        .and_then(|error| {
+            let message = error.pointer("/error/message").or_else(|| error.get("message"))?;
+            message.as_str().map(String::from)
+        })
+        .unwrap_or_else(|| String::from_utf8_lossy(body).into_owned());
+    let kind = match status.as_u16() {
+        401 => "authentication_error",
+        403 => "permission_error",
+        404 => "not_found_error",
+        413 => "request_too_large",
+        429 => "rate_limit_error",
+        503 | 529 => "overloaded_error",
+        500.. => "api_error",
+        _ => "invalid_request_error",
+    };
+    json!({ "type": "error", "error": { "type": kind, "message": message } })
+}
+
+// The open content block of a translated stream
+enum OpenBlock {
+    Text,
+    // Index of the tool call in the chat completion's deltas
+    ToolUse(u64),
+}
+
+// Turns the server-sent events of a streamed chat completion into those of a streamed
+// Messages response. Chunks may end anywhere, incomplete lines wait for the next one.
+pub struct StreamTranslator {
+    model: String,
+    pending: Vec<u8>,
+    started: bool,
+    // Content blocks opened so far, the open one is the last
+    blocks: usize,
+    open: Option<OpenBlock>,
+    stop: Option<(&'static str, Value)>,
+    usage: Value,
+    finished: bool,
+}
+
+impl StreamTranslator {
+    pub fn new(model: &str) -> Self {
+        StreamTranslator {
+            model: model.to_string(),
+            pending: Vec::new(),
+            started: false,
+            blocks: 0,
+            open: None,
+            stop: None,
+            usage: json!({ "input_tokens": 0, "output_tokens": 0 }),
+            finished: false,
+        }
+    }
+
+    // Events for a chunk of the chat completion stream
+    pub fn feed(&mut self, chunk: &[u8]) -> String {
+        self.pending.extend_from_slice(chunk);
+        let mut events = String::new();
+        while let Some(end) = self.pending.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.pending.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            let Some(data) = line.trim().strip_prefix("data:").map(str::trim) else {
+                continue;
+            };
+            if data == "[DONE]" {
+                self.close(&mut events);
+            } else if let Ok(chunk) = serde_json::from_str::<Value>(data) {
+                self.on_chunk(&chunk, &mut events);
+            }
+        }
+        events
+    }
+
+    // Closing events of a stream that ended without `[DONE]`
+    pub fn finish(&mut self) -> String {
+        let mut events = String::new();
+        self.close(&mut events);
+        events
+    }
+
+    fn on_chunk(&mut self, chunk: &Value, events: &mut String) {
+        if let Some(error) = chunk.get("error") {
+            let message = error.get("message").and_then(Value::as_str).unwrap_or_default();
+            let error = json!({ "type": "error", "error": { "type": "api_error", "message": message } });
+            push_event(events, "error", &error);
+            return;
+        }
+        if !self.started {
+            self.started = true;
+            let message = json!({
+                "id": message_id(chunk.get("id").and_then(Value::as_str)),
+                "type": "message",
+                "role": "assistant",
+                "model": self.model,
+                "content": [],
+                "stop_reason": null,
+                "stop_sequence": null,
+                "usage": self.usage,
+            });
+            push_event(events, "message_start", &json!({ "type": "message_start", "message": message }));
+        }
+        if chunk.get("usage").is_some_and(Value::is_object) {
+            self.usage = usage_of(chunk);
+        }
+        let Some(choice) = chunk.pointer("/choices/0") else {
+            return;
+        };
+        let delta = choice.get("delta").unwrap_or(&Value::Null);
+        if let Some(text) = delta.get("content").and_then(Value::as_str).filter(|text| !text.is_empty()) {
+            if !matches!(self.open, Some(OpenBlock::Text)) {
+                self.open_block(OpenBlock::Text, json!({ "type": "text", "text": "" }), events);
+            }
+            let delta = json!({ "type": "text_delta", "text": text });
+            self.push_delta(delta, events);
+        }
+        for call in delta.get("tool_calls").and_then(Value::as_array).into_iter().flatten() {
+            let index = call.get("index").and_then(Value::as_u64).unwrap_or(0);
+            if !matches!(self.open, Some(OpenBlock::ToolUse(open)) if open == index) {
+                self.open_block(OpenBlock::ToolUse(index), tool_use_block(call, json!({})), events);
+            }
+            if let Some(arguments) = call.pointer("/function/arguments").and_then(Value::as_str)
+                && !arguments.is_empty()
+            {
+                let delta = json!({ "type": "input_json_delta", "partial_json": arguments });
+                self.push_delta(delta, events);
+            }
+        }
+        if choice.get("finish_reason").is_some_and(Value::is_string) {
+            self.stop = Some(stop_reason(choice));
+        }
+    }
+
+    fn open_block(&mut self, block: OpenBlock, content_block: Value, events: &mut String) {
+        self.close_block(events);
+        let start = json!({
+            "type": "content_block_start",
+            "index": self.blocks,
+            "content_block": content_block,
+        });
+        push_event(events, "content_block_start", &start);
+        self.blocks += 1;
+        self.open = Some(block);
+    }
+
+    fn push_delta(&self, delta: Value, events: &mut String) {
+        let delta = json!({ "type": "content_block_delta", "index": self.blocks - 1, "delta": delta });
+        push_event(events, "content_block_delta", &delta);
+    }
+
+    fn close_block(&mut self, events: &mut String) {
+        if self.open.take().is_some() {
+            let stop = json!({ "type": "content_block_stop", "index": self.blocks - 1 });
+            push_event(events, "content_block_stop", &stop);
+        }
+    }
+
+    fn close(&mut self, events: &mut String) {
+        if self.finished || !self.started {
+            return;
+        }
+        self.finished = true;
+        self.close_block(events);
+        let (stop_reason, stop_sequence) = self.stop.take().unwrap_or(("end_turn", Value::Null));
+        let delta = json!({
+            "type": "message_delta",
+            "delta": { "stop_reason": stop_reason, "stop_sequence": stop_sequence },
+            "usage": self.usage,
+        });
+        push_event(events, "message_delta", &delta);
+        push_event(events, "message_stop", &json!({ "type": "message_stop" }));
+    }
+}
+
+fn push_event(events: &mut String, name: &str, data: &Value) {
+    events.push_str(&format!("event: {}\ndata: {}\n\n", name, data));
+}
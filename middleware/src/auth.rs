@@ -2,23 +2,145 @@
 use actix_web::{
     dev::{Service, ServiceRequest, ServiceResponse, Transform},
     HttpMessage,
-    HttpResponse,
+    HttpRequest,
     web,
     Error,
 };
 use actix_web::body::{BoxBody, MessageBody};
+use actix_web::http::StatusCode;
 use futures::future::{ok, LocalBoxFuture, Ready};
+use sha2::{Digest, Sha256};
+use tracing::{info_span, Instrument};
 
 // Standard library
 use std::rc::Rc;
 use std::sync::Arc;
 
 // Internal modules
+use crate::errors::ApiError;
+use crate::otel;
 use crate::state::AppState;
 
 #[derive(Debug, Clone)]
 pub struct AuthInfo {
     pub groups: Vec<String>,
+    // The bearer token the groups were granted to
+    pub token: String,
+    // A canary of this composer, booked to the synthetic group
+    pub synthetic: bool,
+    // Owner of the token's entry in secrets.yaml
+    pub owner: Option<String>,
+}
+
+impl AuthInfo {
+    // Stable id of the token for usage reports and logs, which must not hold the token.
+    // A prefix of its SHA-256, so persisted ids survive rebuilds.
+    pub fn token_id(&self) -> String {
+        let digest = Sha256::digest(self.token.as_bytes());
+        digest[..8].iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    // Whether the caller operates the composer, see OPERATOR_GROUPS
+    pub fn is_operator(&self) -> bool {
+        self.groups.iter().any(|group| OPERATOR_GROUPS.contains(&group.as_str()))
+    }
+
+    // Whether the caller may scrape /metrics and /telemetry: operators and scrapers
+    pub fn reads_metrics(&self) -> bool {
+        self.is_operator() || self.groups.iter().any(|group| group == METRICS_GROUP)
+    }
+}
+
+// Groups whose tokens operate the composer: they reload it, manage endpoints, tokens and
+// logging, and see the usage and models of every group
+pub const OPERATOR_GROUPS: [&str; 2] = ["admin", "staff"];
+
+// Group of metrics scrapers
+pub const METRICS_GROUP: &str = "metrics";
+
+// Whether the request's caller operates the composer, None without a caller
+pub fn caller_is_operator(req: &HttpRequest) -> Option<bool> {
+    req.extensions().get::<AuthInfo>().map(AuthInfo::is_operator)
+}
+
+// Hashed entries in secrets.yaml read `sha256:<salt>:<hex SHA-256 of salt followed by token>`;
+// plaintext entries keep working next to them
+pub const HASHED_TOKEN_PREFIX: &str = "sha256:";
+
+// Whether a presented bearer token matches a token entry of secrets.yaml
+pub fn token_matches(entry: &str, token: &str) -> bool {
+    let Some(hashed) = entry.strip_prefix(HASHED_TOKEN_PREFIX) else {
+        return constant_time_eq(entry, token);
+    };
+    let Some((salt, digest)) = hashed.split_once(':') else {
+        return false;
+    };
+    constant_time_eq(&salted_digest(salt, token), &digest.to_ascii_lowercase())
+}
+
+// Compare secrets in constant time so response times don't leak them
+pub fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len() && a.bytes().zip(b.bytes()).fold(0u8, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+// Reject hashed entries that could never match
+pub fn validate_token_entry(entry: &str) -> Result<(), String> {
+    let Some(hashed) = entry.strip_prefix(HASHED_TOKEN_PREFIX) else {
+        return Ok(());
+    };
+    match hashed.split_once(':') {
+        Some((salt, digest))
+            if !salt.is_empty() && digest.len() == 64 && digest.chars().all(|c| c.is_ascii_hexdigit()) =>
+        {
+            Ok(())
+        }
+        _ => Err(format!(
+            "Malformed hashed token, expected {}<salt>:<64 hex digits>",
+            HASHED_TOKEN_PREFIX
+        )),
+    }
+}
+
+// A secrets.yaml entry for a token with a fresh random salt
+pub fn hash_token(token: &str) -> std::io::Result<String> {
+    let salt = random_hex(16)?;
+    Ok(format!("{}{}:{}", HASHED_TOKEN_PREFIX, salt, salted_digest(&salt, token)))
+}
+
+// `bytes` random bytes from the OS, hex encoded
+pub fn random_hex(bytes: usize) -> std::io::Result<String> {
+    let mut buf = vec![0u8; bytes];
+    std::io::Read::read_exact(&mut std::fs::File::open("/dev/urandom")?, &mut buf)?;
+    Ok(buf.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+// Stable id of a secrets.yaml token entry. For plaintext entries it equals the token's
+// AuthInfo::token_id, hashed entries get the id of the entry itself.
+pub fn entry_id(entry: &str) -> String {
+    let digest = Sha256::digest(entry.as_bytes());
+    digest[..8].iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn salted_digest(salt: &str, token: &str) -> String {
+    let digest = Sha256::new().chain_update(salt).chain_update(token).finalize();
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// Browsers can't set headers on WebSocket connections, they offer the token as a subprotocol
+pub const TOKEN_SUBPROTOCOL: &str = "openai-insecure-api-key.";
+
+// The bearer token of a request, or its `x-api-key` header as sent by the Anthropic SDKs, or
+// the token subprotocol of a WebSocket connection
+fn presented_token(req: &ServiceRequest) -> Option<&str> {
+    let header = |name: &str| req.headers().get(name).and_then(|h| h.to_str().ok());
+    match header("Authorization") {
+        Some(auth_header) => auth_header.strip_prefix("Bearer ").map(str::trim),
+        None => header("x-api-key").map(str::trim).or_else(|| {
+            header("Sec-WebSocket-Protocol")?
+                .split(',')
+                .find_map(|protocol| protocol.trim().strip_prefix(TOKEN_SUBPROTOCOL))
+        }),
+    }
 }
 
 pub struct AuthMiddleware;
@@ -60,46 +182,51 @@ where
 
     fn call(&self, req: ServiceRequest) -> Self::Future {
         let svc = self.service.clone();
+        // Root span of the request, the spans of the handler nest under it
+        let span = otel::request_span(&req);
+        let request_span = span.clone();
 
         Box::pin(async move {
-            // Skip auth check if path is /health
-            if req.path() == "/health" {
-                return Ok(svc.call(req).await?.map_into_boxed_body());
+            // Skip auth check for the probes of orchestrators and the playground page, which asks
+            // for a token itself
+            if ["/health", "/ready", "/playground"].contains(&req.path()) {
+                let res = svc.call(req).await?;
+                otel::record_status(&request_span, res.status().as_u16());
+                return Ok(res.map_into_boxed_body());
             }
-            
+
             // Otherwise determine the user groups based on the token
-            if let Some(auth_header) = req.headers().get("Authorization").and_then(|h| h.to_str().ok()) {
-                if auth_header.starts_with("Bearer ") {
-                    let token = auth_header.trim_start_matches("Bearer ").trim();
-                    if let Some(state) = req.app_data::<web::Data<Arc<AppState>>>() {
-                        let groups: Vec<String> = {
-                            let auth_tokens = state.auth_tokens.lock().unwrap();
-                            auth_tokens
-                                .iter()
-                                .filter_map(|(group, tokens)| {
-                                    if tokens.contains(&token.to_string()) {
-                                        Some(group.clone())
-                                    } else {
-                                        None
-                                    }
-                                })
-                                .collect()
-                        };
-                        if !groups.is_empty() {
-                            req.extensions_mut().insert(AuthInfo { groups });
-                            // Now that all borrows are dropped, we can move `req`.
-                            let res = svc.call(req).await?;
-                            return Ok(res.map_into_boxed_body());
-                        }
+            let auth_info = info_span!("auth").in_scope(|| {
+                let token = presented_token(&req)?;
+                let state = req.app_data::<web::Data<Arc<AppState>>>()?;
+                let (groups, synthetic, owner) = match state.canaries.groups_of(token) {
+                    Some(groups) => (groups, true, None),
+                    None => {
+                        let tokens = state.auth_tokens.load();
+                        (tokens.groups_of(token), false, tokens.owner_of(token))
                     }
-                }
+                };
+                (!groups.is_empty()).then(|| AuthInfo { groups, token: token.to_string(), synthetic, owner })
+            });
+            if let Some(auth_info) = auth_info {
+                req.extensions_mut().insert(auth_info);
+                let res = svc.call(req).await?;
+                otel::record_status(&request_span, res.status().as_u16());
+                return Ok(res.map_into_boxed_body());
             }
 
             // If no valid token is found, return an unauthorized response
-            let response = req.into_response(
-                HttpResponse::Unauthorized().finish().map_into_boxed_body()
-            );
-            Ok(response)
-        })
+            let expired = presented_token(&req)
+                .zip(req.app_data::<web::Data<Arc<AppState>>>())
+                .is_some_and(|(token, state)| state.auth_tokens.load().is_expired(token));
+            let error = if expired {
+                ApiError::new(StatusCode::UNAUTHORIZED, "This API key has expired.").code("token_expired")
+            } else {
+                ApiError::unauthorized()
+            };
+            otel::record_status(&request_span, 401);
+            Ok(req.into_response(error.into_response()))
+        }
+        .instrument(span))
     }
 }
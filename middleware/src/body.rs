@@ -0,0 +1,296 @@
+// External crates
+use actix_web::dev::Payload;
+use actix_web::http::StatusCode;
+use actix_web::{web, Error, FromRequest, HttpMessage, HttpRequest};
+use bytes::{Bytes, BytesMut};
+use futures::future::LocalBoxFuture;
+use log::info;
+use serde_json::Value;
+
+// Standard library
+use std::ops::{Deref, Range};
+use std::sync::Arc;
+
+// Internal modules
+use crate::auth::AuthInfo;
+use crate::errors::ApiError;
+use crate::state::AppState;
+use crate::usage::{request_stream_usage, stream_usage_requested};
+
+// -----------------------------------------------------------------------------
+// Request Bodies
+// -----------------------------------------------------------------------------
+
+// Largest accepted request body, same as actix' JSON default
+const DEFAULT_MAX_BODY_BYTES: usize = 2 * 1024 * 1024;
+
+pub fn max_body_bytes() -> usize {
+    std::env::var("VLLM_COMPOSER_MAX_BODY_BYTES")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(DEFAULT_MAX_BODY_BYTES)
+}
+
+// Where JSON bodies are accepted regardless of their content type (e.g. `text/plain` or
+// none at all, as sent by some scripts). Elsewhere a JSON content type is required.
+#[derive(Debug, Default)]
+pub struct BodyPolicy {
+    // Request paths, "*" for all
+    lenient_routes: Vec<String>,
+    // Access groups, "*" for all
+    lenient_groups: Vec<String>,
+}
+
+impl BodyPolicy {
+    // Read the comma-separated VLLM_COMPOSER_LENIENT_CONTENT_TYPE_{ROUTES,GROUPS}
+    pub fn from_env() -> Self {
+        let list = |name: &str| -> Vec<String> {
+            std::env::var(name)
+                .unwrap_or_default()
+                .split(',')
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+                .collect()
+        };
+        let policy = BodyPolicy {
+            lenient_routes: list("VLLM_COMPOSER_LENIENT_CONTENT_TYPE_ROUTES"),
+            lenient_groups: list("VLLM_COMPOSER_LENIENT_CONTENT_TYPE_GROUPS"),
+        };
+        if !policy.lenient_routes.is_empty() || !policy.lenient_groups.is_empty() {
+            info!(
+                "Accept JSON bodies of any content type on routes {:?} and for groups {:?}",
+                policy.lenient_routes, policy.lenient_groups
+            );
+        }
+        policy
+    }
+
+    // Either a lenient route or a lenient group of the caller suffices
+    pub fn is_lenient(&self, path: &str, groups: &[String]) -> bool {
+        self.lenient_routes.iter().any(|r| r == "*" || r == path)
+            || self
+                .lenient_groups
+                .iter()
+                .any(|g| g == "*" || groups.contains(g))
+    }
+}
+
+fn is_json_content_type(req: &HttpRequest) -> bool {
+    match req.mime_type() {
+        // application/json, also vendor types like application/problem+json
+        Ok(Some(mime)) => mime.subtype() == "json" || mime.suffix().is_some_and(|s| s == "json"),
+        _ => false,
+    }
+}
+
+// A JSON request body. Unlike `web::Json` it honors the BodyPolicy and answers malformed
+// bodies with an explanation instead of actix' generic error.
+pub struct JsonBody(pub Value);
+
+impl Deref for JsonBody {
+    type Target = Value;
+
+    fn deref(&self) -> &Value {
+        &self.0
+    }
+}
+
+impl FromRequest for JsonBody {
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let read = read_json(req, payload);
+        Box::pin(async move { read.await.map(|(json, _)| JsonBody(json)) })
+    }
+}
+
+// A JSON request body to forward. The composer reads the parsed body, but the bytes it
+// arrived as go upstream untouched, so large bodies like base64 images aren't serialized
+// again. Renaming the model and asking for stream usage are spliced into the bytes, other
+// changes to the request mean it is serialized after all.
+pub struct ProxyBody {
+    pub json: Value,
+    raw: Option<Bytes>,
+}
+
+impl ProxyBody {
+    // The composer changed `json` in a way the bytes don't carry
+    pub fn rewritten(&mut self) {
+        self.raw = None;
+    }
+
+    // Send the request for another model, like the served name of an alias
+    pub fn set_model(&mut self, model: &str) {
+        self.json["model"] = Value::String(model.to_string());
+        self.raw = self.raw.take().and_then(|raw| {
+            let range = top_level_string(&raw, "model")?;
+            let quoted = Value::String(model.to_string()).to_string();
+            Some(splice(&raw, range, quoted.as_bytes()))
+        });
+    }
+
+    // Ask the backend for the usage of a stream in its last event
+    pub fn request_stream_usage(&mut self) {
+        if stream_usage_requested(&self.json) {
+            return;
+        }
+        let has_options = self.json.get("stream_options").is_some();
+        request_stream_usage(&mut self.json);
+        self.raw = self.raw.take().filter(|_| !has_options).and_then(|raw| {
+            // First in the object, which has a `model` after it
+            let open = raw.iter().position(|b| !b.is_ascii_whitespace()).filter(|i| raw[*i] == b'{')?;
+            let options = br#""stream_options":{"include_usage":true},"#;
+            Some(splice(&raw, open + 1..open + 1, options))
+        });
+    }
+
+    // The body sent upstream
+    pub fn to_bytes(&self) -> Bytes {
+        match &self.raw {
+            Some(raw) => raw.clone(),
+            None => Bytes::from(self.json.to_string()),
+        }
+    }
+}
+
+impl From<Value> for ProxyBody {
+    fn from(json: Value) -> Self {
+        ProxyBody { json, raw: None }
+    }
+}
+
+impl Deref for ProxyBody {
+    type Target = Value;
+
+    fn deref(&self) -> &Value {
+        &self.json
+    }
+}
+
+impl FromRequest for ProxyBody {
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let read = read_json(req, payload);
+        Box::pin(async move { read.await.map(|(json, raw)| ProxyBody { json, raw: Some(raw) }) })
+    }
+}
+
+// Read and parse a JSON body, also returning the bytes it was read from
+fn read_json(req: &HttpRequest, payload: &mut Payload) -> LocalBoxFuture<'static, Result<(Value, Bytes), Error>> {
+    let req = req.clone();
+    let bytes = web::Bytes::from_request(&req, payload);
+    Box::pin(async move {
+        // Bodies over the limit or cut off, in the same schema as other errors
+        let bytes = bytes.await.map_err(|e| match e.as_response_error().status_code() {
+            StatusCode::PAYLOAD_TOO_LARGE => ApiError::new(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                format!("Request body is larger than the limit of {} bytes.", max_body_bytes()),
+            ),
+            status => ApiError::new(status, e.to_string()),
+        })?;
+
+        if !is_json_content_type(&req) {
+            let groups = req
+                .extensions()
+                .get::<AuthInfo>()
+                .map(|info| info.groups.clone())
+                .unwrap_or_default();
+            let lenient = req
+                .app_data::<web::Data<Arc<AppState>>>()
+                .is_some_and(|state| state.body_policy.is_lenient(req.path(), &groups));
+            if !lenient {
+                let content_type = req
+                    .headers()
+                    .get("Content-Type")
+                    .and_then(|h| h.to_str().ok())
+                    .unwrap_or("none");
+                return Err(ApiError::new(
+                    StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                    format!(
+                        "Expected a JSON body with `Content-Type: application/json`, got content type: {}.",
+                        content_type
+                    ),
+                )
+                .into());
+            }
+        }
+
+        match serde_json::from_slice(&bytes) {
+            Ok(json) => Ok((json, bytes)),
+            Err(e) => Err(ApiError::bad_request(format!("Request body is not valid JSON: {}.", e)).into()),
+        }
+    })
+}
+
+// `raw` with the top-level `model` replaced by `to` if it is `from`, everything else left as
+// it was; None if the model is another one
+pub fn replace_model(raw: &[u8], from: &str, to: &str) -> Option<Bytes> {
+    let range = top_level_string(raw, "model")?;
+    let model: String = serde_json::from_slice(&raw[range.clone()]).ok()?;
+    let quoted = Value::String(to.to_string()).to_string();
+    (model == from).then(|| splice(raw, range, quoted.as_bytes()))
+}
+
+// Byte range of the string value of a key of the outermost object, quotes included. Only the
+// nesting and the bounds of strings are tracked on the way, nothing is parsed. Like serde_json
+// the last of duplicate keys counts; None if the value isn't a string.
+fn top_level_string(raw: &[u8], key: &str) -> Option<Range<usize>> {
+    let mut found = None;
+    let mut depth = 0usize;
+    let mut i = 0;
+    while i < raw.len() {
+        match raw[i] {
+            b'{' | b'[' => depth += 1,
+            b'}' | b']' => depth = depth.checked_sub(1)?,
+            b'"' => {
+                let end = string_end(raw, i)?;
+                if depth == 1 && &raw[i + 1..end - 1] == key.as_bytes() {
+                    let mut value = end;
+                    while raw.get(value).is_some_and(u8::is_ascii_whitespace) {
+                        value += 1;
+                    }
+                    // A key, not a value that happens to be spelled like it
+                    if raw.get(value) == Some(&b':') {
+                        value += 1;
+                        while raw.get(value).is_some_and(u8::is_ascii_whitespace) {
+                            value += 1;
+                        }
+                        found = match raw.get(value) {
+                            Some(b'"') => Some(value..string_end(raw, value)?),
+                            _ => None,
+                        };
+                    }
+                }
+                i = end;
+                continue;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    found
+}
+
+// Index after the closing quote of the string starting at `start`
+fn string_end(raw: &[u8], start: usize) -> Option<usize> {
+    let mut i = start + 1;
+    while i < raw.len() {
+        match raw[i] {
+            b'\\' => i += 2,
+            b'"' => return Some(i + 1),
+            _ => i += 1,
+        }
+    }
+    None
+}
+
+fn splice(raw: &[u8], range: Range<usize>, with: &[u8]) -> Bytes {
+    let mut spliced = BytesMut::with_capacity(raw.len() + with.len());
+    spliced.extend_from_slice(&raw[..range.start]);
+    spliced.extend_from_slice(with);
+    spliced.extend_from_slice(&raw[range.end..]);
+    spliced.freeze()
+}
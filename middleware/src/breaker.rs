@@ -0,0 +1,131 @@
+// External crates
+use log::{info, warn};
+
+// Standard library
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// -----------------------------------------------------------------------------
+// Circuit Breakers
+// -----------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Copy)]
+enum Circuit {
+    // Routable; counts failures in a row
+    Closed { failures: u32 },
+    // Not routable until the instant passes
+    Open { until: Instant },
+    // One probe request is let through; its outcome closes or reopens the circuit
+    HalfOpen { probe_sent: Option<Instant> },
+}
+
+// Takes endpoints out of routing after repeated request failures (errors, timeouts, 5xx),
+// independently of the /health polling, and lets single probes decide when they return.
+pub struct CircuitBreakers {
+    // Failures in a row that open the circuit
+    threshold: u32,
+    // How long an open circuit blocks the endpoint
+    open_for: Duration,
+    circuits: Mutex<HashMap<String, Circuit>>,
+}
+
+impl CircuitBreakers {
+    // VLLM_COMPOSER_BREAKER_FAILURES (0 disables) and VLLM_COMPOSER_BREAKER_OPEN_SECS
+    pub fn from_env() -> Self {
+        let env_u64 = |name: &str, default: u64| {
+            std::env::var(name)
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(default)
+        };
+        CircuitBreakers {
+            threshold: env_u64("VLLM_COMPOSER_BREAKER_FAILURES", 5) as u32,
+            open_for: Duration::from_secs(env_u64("VLLM_COMPOSER_BREAKER_OPEN_SECS", 30)),
+            circuits: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Whether requests may be routed to the endpoint
+    pub fn is_routable(&self, url: &str) -> bool {
+        match self.circuits.lock().unwrap().get(url) {
+            None | Some(Circuit::Closed { .. }) => true,
+            Some(Circuit::Open { until }) => Instant::now() >= *until,
+            // A probe that never reported back (e.g. dropped client) is replaced
+            Some(Circuit::HalfOpen { probe_sent }) => {
+                probe_sent.is_none_or(|sent| sent.elapsed() >= self.open_for)
+            }
+        }
+    }
+
+    // Note that a request is sent to the endpoint; past an open circuit it is the probe
+    pub fn on_dispatch(&self, url: &str) {
+        let mut circuits = self.circuits.lock().unwrap();
+        if let Some(circuit) = circuits.get_mut(url) {
+            match circuit {
+                Circuit::Open { .. } | Circuit::HalfOpen { .. } => {
+                    *circuit = Circuit::HalfOpen { probe_sent: Some(Instant::now()) };
+                }
+                Circuit::Closed { .. } => {}
+            }
+        }
+    }
+
+    pub fn record_success(&self, url: &str) {
+        let mut circuits = self.circuits.lock().unwrap();
+        if let Some(Circuit::HalfOpen { .. } | Circuit::Open { .. }) = circuits.get(url) {
+            info!("Circuit of {} closed, probe succeeded", url);
+        }
+        circuits.remove(url);
+    }
+
+    pub fn record_failure(&self, url: &str) {
+        if self.threshold == 0 {
+            return;
+        }
+        let mut circuits = self.circuits.lock().unwrap();
+        let circuit = circuits
+            .entry(url.to_string())
+            .or_insert(Circuit::Closed { failures: 0 });
+        let open = Circuit::Open { until: Instant::now() + self.open_for };
+        match circuit {
+            Circuit::Closed { failures } => {
+                *failures += 1;
+                if *failures >= self.threshold {
+                    warn!("Circuit of {} opened after {} failures in a row", url, failures);
+                    *circuit = open;
+                }
+            }
+            Circuit::HalfOpen { .. } => {
+                warn!("Circuit of {} reopened, probe failed", url);
+                *circuit = open;
+            }
+            Circuit::Open { .. } => {}
+        }
+    }
+
+    // "closed", "open" or "half_open", for /health-status
+    pub fn state_label(&self, url: &str) -> &'static str {
+        match self.circuits.lock().unwrap().get(url) {
+            None | Some(Circuit::Closed { .. }) => "closed",
+            Some(Circuit::Open { until }) if Instant::now() < *until => "open",
+            Some(_) => "half_open",
+        }
+    }
+
+    pub fn forget(&self, url: &str) {
+        self.circuits.lock().unwrap().remove(url);
+    }
+
+    // Drop the circuits of endpoints that are gone, returns how many
+    pub fn retain(&self, active: &HashSet<String>) -> usize {
+        let mut circuits = self.circuits.lock().unwrap();
+        let before = circuits.len();
+        circuits.retain(|url, _| active.contains(url));
+        before - circuits.len()
+    }
+
+    pub fn entries(&self) -> usize {
+        self.circuits.lock().unwrap().len()
+    }
+}
@@ -0,0 +1,351 @@
+// External crates
+use log::{info, warn};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+// Standard library
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+// Internal modules
+use crate::reports::civil_date;
+use crate::shared::{now_ms, SharedStore};
+use crate::usage::DAY_SECS;
+use crate::usage_db::UsageDb;
+
+// -----------------------------------------------------------------------------
+// Token Budgets
+// -----------------------------------------------------------------------------
+
+// Tokens allowed per UTC day and calendar month, unlimited if unset
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq)]
+pub struct Budget {
+    #[serde(default)]
+    pub daily: Option<u64>,
+    #[serde(default)]
+    pub monthly: Option<u64>,
+}
+
+// A group's budget in secrets.yaml: for all of its tokens together, and for each one
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq)]
+pub struct GroupBudget {
+    #[serde(default)]
+    pub daily: Option<u64>,
+    #[serde(default)]
+    pub monthly: Option<u64>,
+    #[serde(default)]
+    pub per_token: Budget,
+}
+
+impl GroupBudget {
+    fn group(&self) -> Budget {
+        Budget { daily: self.daily, monthly: self.monthly }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Period {
+    Day,
+    Month,
+}
+
+impl Period {
+    pub fn label(self) -> &'static str {
+        match self {
+            Period::Day => "daily",
+            Period::Month => "monthly",
+        }
+    }
+
+    fn limit(self, budget: &Budget) -> Option<u64> {
+        match self {
+            Period::Day => budget.daily,
+            Period::Month => budget.monthly,
+        }
+    }
+}
+
+// Start of the current UTC day and month and of the next ones, in unix seconds
+#[derive(Debug, Clone, Copy)]
+struct Calendar {
+    day: u64,
+    next_day: u64,
+    month: u64,
+    next_month: u64,
+}
+
+impl Calendar {
+    fn at(secs: u64) -> Self {
+        let days = secs / DAY_SECS;
+        let (year, month, day) = civil_date(days);
+        let month_start = days - (day as u64 - 1);
+        let leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+        let month_days = match month {
+            2 if leap => 29,
+            2 => 28,
+            4 | 6 | 9 | 11 => 30,
+            _ => 31,
+        };
+        Calendar {
+            day: days * DAY_SECS,
+            next_day: (days + 1) * DAY_SECS,
+            month: month_start * DAY_SECS,
+            next_month: (month_start + month_days) * DAY_SECS,
+        }
+    }
+
+    fn now() -> Self {
+        Calendar::at(now_ms() / 1000)
+    }
+
+    fn resets_in(self, period: Period, now: u64) -> u64 {
+        match period {
+            Period::Day => self.next_day - now,
+            Period::Month => self.next_month - now,
+        }
+    }
+}
+
+// Names of the shared counters of a group's or token's usage this day and month, with their
+// TTLs, kept a little beyond the period
+fn shared_counters(kind: &str, id: &str, calendar: Calendar) -> [(String, u64); 2] {
+    [
+        (format!("budget:{}:{}:day:{}", kind, id, calendar.day), 2 * DAY_SECS),
+        (format!("budget:{}:{}:month:{}", kind, id, calendar.month), 32 * DAY_SECS),
+    ]
+}
+
+// Tokens used within a day and a month, each starting over with the next one
+#[derive(Debug, Clone, Copy, Default)]
+struct Consumption {
+    day: u64,
+    daily: u64,
+    month: u64,
+    monthly: u64,
+}
+
+impl Consumption {
+    fn add(&mut self, calendar: Calendar, tokens: u64) {
+        *self = self.current(calendar);
+        self.daily += tokens;
+        self.monthly += tokens;
+    }
+
+    // Without the usage of past days and months
+    fn current(self, calendar: Calendar) -> Self {
+        Consumption {
+            day: calendar.day,
+            daily: if self.day == calendar.day { self.daily } else { 0 },
+            month: calendar.month,
+            monthly: if self.month == calendar.month { self.monthly } else { 0 },
+        }
+    }
+
+    fn used(self, period: Period) -> u64 {
+        match period {
+            Period::Day => self.daily,
+            Period::Month => self.monthly,
+        }
+    }
+}
+
+// A budget the caller used up
+#[derive(Debug, Clone)]
+pub struct Exhausted {
+    pub group: String,
+    // Whether the group's budget for each token ran out, not the group's own
+    pub per_token: bool,
+    pub period: Period,
+    pub limit: u64,
+    pub used: u64,
+    pub resets_in_secs: u64,
+}
+
+#[derive(Default)]
+struct Usage {
+    groups: HashMap<String, Consumption>,
+    // Keyed by token id (see AuthInfo::token_id), never the token itself
+    tokens: HashMap<String, Consumption>,
+}
+
+// Tokens used per group and per token in the current day and month, checked against the
+// budgets of secrets.yaml before requests are proxied
+#[derive(Default)]
+pub struct BudgetTracker {
+    usage: Mutex<Usage>,
+}
+
+impl BudgetTracker {
+    // Pick up the usage of this day and month from the usage database, so budgets survive
+    // restarts
+    pub fn load(&self, db: &UsageDb) {
+        let calendar = Calendar::now();
+        let (groups, tokens) = match db.consumption_since(calendar.month, calendar.day) {
+            Ok(consumed) => consumed,
+            Err(e) => {
+                warn!("Failed to load budget usage from the usage database: {}", e);
+                return;
+            }
+        };
+        let consumption = |(key, (monthly, daily))| {
+            (key, Consumption { day: calendar.day, daily, month: calendar.month, monthly })
+        };
+        let mut usage = self.usage.lock().unwrap();
+        usage.groups = groups.into_iter().map(consumption).collect();
+        usage.tokens = tokens.into_iter().map(consumption).collect();
+        info!(
+            "Loaded this month's usage of {} groups and {} tokens for budgets",
+            usage.groups.len(),
+            usage.tokens.len()
+        );
+    }
+
+    // Book the tokens of a response to the caller's token and each of its groups
+    pub fn record(&self, token_id: &str, groups: &[String], tokens: u64) {
+        let calendar = Calendar::now();
+        let mut usage = self.usage.lock().unwrap();
+        usage.tokens.entry(token_id.to_string()).or_default().add(calendar, tokens);
+        for group in groups {
+            usage.groups.entry(group.clone()).or_default().add(calendar, tokens);
+        }
+    }
+
+    // Book the tokens in the shared store too, so the budgets hold across replicas
+    pub async fn record_shared(&self, shared: &SharedStore, token_id: &str, groups: &[String], tokens: u64) {
+        if !shared.is_enabled() || tokens == 0 {
+            return;
+        }
+        let calendar = Calendar::now();
+        let counters: Vec<(String, u64)> = std::iter::once(("token", token_id))
+            .chain(groups.iter().map(|group| ("group", group.as_str())))
+            .flat_map(|(kind, id)| shared_counters(kind, id, calendar))
+            .collect();
+        shared.add_to_counters(&counters, tokens).await;
+    }
+
+    // Take over what all replicas booked for the token and its groups from the shared store,
+    // where it exceeds this replica's count. Without a reachable store the local count stays.
+    pub async fn sync_shared(&self, shared: &SharedStore, token_id: &str, groups: &[String]) {
+        if !shared.is_enabled() {
+            return;
+        }
+        let calendar = Calendar::now();
+        let owners: Vec<(&str, &str)> = std::iter::once(("token", token_id))
+            .chain(groups.iter().map(|group| ("group", group.as_str())))
+            .collect();
+        let names: Vec<String> = owners
+            .iter()
+            .flat_map(|(kind, id)| shared_counters(kind, id, calendar))
+            .map(|(name, _)| name)
+            .collect();
+        let Some(counts) = shared.counters(&names).await else {
+            return;
+        };
+        let mut usage = self.usage.lock().unwrap();
+        for ((kind, id), counts) in owners.into_iter().zip(counts.chunks(2)) {
+            let consumptions = if kind == "token" { &mut usage.tokens } else { &mut usage.groups };
+            let consumption = consumptions.entry(id.to_string()).or_default();
+            let current = consumption.current(calendar);
+            *consumption = Consumption {
+                daily: current.daily.max(counts[0]),
+                monthly: current.monthly.max(counts[1]),
+                ..current
+            };
+        }
+    }
+
+    // Whether the caller may still use tokens: any of its groups with a budget has some left
+    // for the group and for the caller's token. Groups without a budget don't count, as with
+    // rate limits. Err names the budget of the group that resets first.
+    pub fn check(
+        &self,
+        budgets: &HashMap<String, GroupBudget>,
+        token_id: &str,
+        groups: &[String],
+    ) -> Result<(), Exhausted> {
+        let now = now_ms() / 1000;
+        let calendar = Calendar::at(now);
+        let usage = self.usage.lock().unwrap();
+        let token = usage.tokens.get(token_id).map(|c| c.current(calendar)).unwrap_or_default();
+        let mut exhausted: Option<Exhausted> = None;
+        for group in groups {
+            let Some(budget) = budgets.get(group) else {
+                continue;
+            };
+            let used = usage.groups.get(group).map(|c| c.current(calendar)).unwrap_or_default();
+            let limits = [(false, budget.group(), used), (true, budget.per_token, token)];
+            let over = [Period::Day, Period::Month]
+                .into_iter()
+                .flat_map(|period| limits.map(|limit| (period, limit)))
+                .filter_map(|(period, (per_token, budget, used))| {
+                    let limit = period.limit(&budget)?;
+                    (used.used(period) >= limit).then(|| Exhausted {
+                        group: group.clone(),
+                        per_token,
+                        period,
+                        limit,
+                        used: used.used(period),
+                        resets_in_secs: calendar.resets_in(period, now),
+                    })
+                })
+                // A group is open again once all of its exhausted budgets reset
+                .max_by_key(|exhausted| exhausted.resets_in_secs);
+            let Some(over) = over else {
+                return Ok(());
+            };
+            if exhausted.as_ref().is_none_or(|first| over.resets_in_secs < first.resets_in_secs) {
+                exhausted = Some(over);
+            }
+        }
+        exhausted.map_or(Ok(()), Err)
+    }
+
+    // Limits and usage of a group's budget, and of a token's share if given, for /budgets
+    pub fn status(&self, budget: &GroupBudget, group: &str, token_id: Option<&str>) -> Value {
+        let now = now_ms() / 1000;
+        let calendar = Calendar::at(now);
+        let usage = self.usage.lock().unwrap();
+        let current = |consumption: Option<&Consumption>| {
+            consumption.map(|c| c.current(calendar)).unwrap_or_default()
+        };
+        let periods = |budget: &Budget, consumption: Consumption| {
+            let mut status = json!({});
+            for period in [Period::Day, Period::Month] {
+                let limit = period.limit(budget);
+                let used = consumption.used(period);
+                status[period.label()] = json!({
+                    "limit": limit,
+                    "used": used,
+                    "remaining": limit.map(|limit| limit.saturating_sub(used)),
+                    "resets_in_secs": calendar.resets_in(period, now),
+                });
+            }
+            status
+        };
+        let mut status = periods(&budget.group(), current(usage.groups.get(group)));
+        if let Some(token_id) = token_id {
+            status["per_token"] = periods(&budget.per_token, current(usage.tokens.get(token_id)));
+        }
+        status
+    }
+
+    // This day's and month's tokens per token id
+    pub fn token_usage(&self) -> Value {
+        let calendar = Calendar::now();
+        let usage = self.usage.lock().unwrap();
+        let tokens: serde_json::Map<String, Value> = usage
+            .tokens
+            .iter()
+            .map(|(id, consumption)| {
+                let current = consumption.current(calendar);
+                (id.clone(), json!({ "daily": current.daily, "monthly": current.monthly }))
+            })
+            .collect();
+        Value::Object(tokens)
+    }
+
+    pub fn entries(&self) -> usize {
+        let usage = self.usage.lock().unwrap();
+        usage.groups.len() + usage.tokens.len()
+    }
+}
@@ -0,0 +1,137 @@
+// External crates
+use actix_web::HttpRequest;
+use log::info;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+// Standard library
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// -----------------------------------------------------------------------------
+// Response Cache
+// -----------------------------------------------------------------------------
+
+// Non-streamed chat completions of deterministic requests are answered from memory when the
+// same body comes again, as in evaluation reruns. Bodies are compared after presets and
+// policies were applied, so an answer is shared by all callers sending the same request.
+pub struct ResponseCache {
+    // None leaves the cache off
+    ttl: Option<Duration>,
+    // Largest total size of the cached bodies, the oldest are evicted beyond it
+    max_bytes: usize,
+    entries: Mutex<CacheEntries>,
+}
+
+#[derive(Default)]
+struct CacheEntries {
+    // Key -> time cached and response body
+    bodies: HashMap<String, (Instant, String)>,
+    // Keys from the oldest to the newest entry
+    order: VecDeque<String>,
+    bytes: usize,
+}
+
+impl CacheEntries {
+    fn remove(&mut self, key: &str) {
+        if let Some((_, body)) = self.bodies.remove(key) {
+            self.bytes -= body.len();
+            self.order.retain(|k| k != key);
+        }
+    }
+}
+
+impl ResponseCache {
+    // From VLLM_COMPOSER_RESPONSE_CACHE_TTL_SECS (default 0, off) and
+    // VLLM_COMPOSER_RESPONSE_CACHE_MAX_MB (default 64)
+    pub fn from_env() -> Self {
+        let env_u64 = |name: &str, default: u64| {
+            std::env::var(name)
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(default)
+        };
+        let ttl_secs = env_u64("VLLM_COMPOSER_RESPONSE_CACHE_TTL_SECS", 0);
+        let max_mb = env_u64("VLLM_COMPOSER_RESPONSE_CACHE_MAX_MB", 64);
+        let ttl = Some(Duration::from_secs(ttl_secs)).filter(|ttl| !ttl.is_zero());
+        if ttl.is_some() {
+            info!("Caching deterministic chat completions for {}s, up to {} MB", ttl_secs, max_mb);
+        }
+        ResponseCache::new(ttl, max_mb)
+    }
+
+    pub fn new(ttl: Option<Duration>, max_mb: u64) -> Self {
+        ResponseCache {
+            ttl,
+            max_bytes: (max_mb * 1024 * 1024) as usize,
+            entries: Mutex::new(CacheEntries::default()),
+        }
+    }
+
+    // Key of a cacheable request, None for others: `X-Cache: true` makes any request
+    // cacheable, `X-Cache: false` none, otherwise requests with `temperature: 0` are. Callers
+    // share answers only with callers of the same groups, who may use the same endpoints.
+    pub fn key(&self, req: &HttpRequest, body: &Value, groups: &[String]) -> Option<String> {
+        self.ttl?;
+        let requested = req
+            .headers()
+            .get("x-cache")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.trim().parse::<bool>().ok());
+        let deterministic = body.get("temperature").and_then(Value::as_f64) == Some(0.0);
+        if !requested.unwrap_or(deterministic) {
+            return None;
+        }
+        let mut groups = groups.to_vec();
+        groups.sort();
+        // serde_json orders object keys, the serialized body is canonical
+        let digest = Sha256::new()
+            .chain_update(groups.join("\n"))
+            .chain_update("\0")
+            .chain_update(body.to_string())
+            .finalize();
+        Some(digest.iter().map(|b| format!("{:02x}", b)).collect())
+    }
+
+    // The cached response body, if it has not expired
+    pub fn get(&self, key: &str) -> Option<String> {
+        let ttl = self.ttl?;
+        let mut entries = self.entries.lock().unwrap();
+        let (cached, body) = entries.bodies.get(key)?;
+        if cached.elapsed() < ttl {
+            return Some(body.clone());
+        }
+        entries.remove(key);
+        None
+    }
+
+    pub fn insert(&self, key: &str, body: String) {
+        let Some(ttl) = self.ttl else {
+            return;
+        };
+        if body.len() > self.max_bytes {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        entries.remove(key);
+        entries.bytes += body.len();
+        entries.bodies.insert(key.to_string(), (Instant::now(), body));
+        entries.order.push_back(key.to_string());
+        // Make room, expired entries go first as they are the oldest
+        while let Some(oldest) = entries.order.front().cloned() {
+            let expired = entries.bodies.get(&oldest).is_none_or(|(cached, _)| cached.elapsed() >= ttl);
+            if !expired && entries.bytes <= self.max_bytes {
+                break;
+            }
+            entries.order.pop_front();
+            if let Some((_, body)) = entries.bodies.remove(&oldest) {
+                entries.bytes -= body.len();
+            }
+        }
+    }
+
+    pub fn entries(&self) -> usize {
+        self.entries.lock().unwrap().bodies.len()
+    }
+}
@@ -0,0 +1,237 @@
+// External crates
+use log::{info, warn};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::time::sleep;
+
+// Standard library
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+// Internal modules
+use crate::auth::{constant_time_eq, random_hex};
+use crate::clients::Transport;
+use crate::logging::redact_secret;
+use crate::shared::now_ms;
+use crate::state::AppState;
+
+// -----------------------------------------------------------------------------
+// Synthetic Canaries
+// -----------------------------------------------------------------------------
+
+// Delay of the first run, so the monitors have discovered the models
+const FIRST_RUN_DELAY: Duration = Duration::from_secs(30);
+
+// Group synthetic requests are booked to in usage accounting
+pub const SYNTHETIC_GROUP: &str = "synthetic";
+
+fn default_interval_secs() -> u64 {
+    300
+}
+
+fn default_group() -> String {
+    "admin".to_string()
+}
+
+fn default_max_tokens() -> u32 {
+    32
+}
+
+// A prompt sent to a model on a schedule, whose answer must contain all `expect` substrings
+#[derive(Debug, Deserialize, Clone)]
+pub struct Canary {
+    // Name in metrics, logs and alerts, the model by default
+    #[serde(default)]
+    pub name: Option<String>,
+    pub model: String,
+    pub prompt: String,
+    // Compared case-insensitively
+    #[serde(default)]
+    pub expect: Vec<String>,
+    #[serde(default = "default_interval_secs")]
+    pub interval_secs: u64,
+    // Access group the requests are made as, which decides the endpoints they can reach
+    #[serde(default = "default_group")]
+    pub group: String,
+    #[serde(default = "default_max_tokens")]
+    pub max_tokens: u32,
+}
+
+impl Canary {
+    pub fn name(&self) -> &str {
+        self.name.as_deref().unwrap_or(&self.model)
+    }
+}
+
+pub struct Canaries {
+    pub canaries: Vec<Canary>,
+    // URL failures and recoveries are posted to as JSON
+    webhook_url: Option<String>,
+    // Bearer token of the canaries' own requests, random per process
+    token: String,
+    // Canary name -> whether its last run passed
+    passing: Mutex<HashMap<String, bool>>,
+}
+
+impl Canaries {
+    // VLLM_COMPOSER_CANARIES_FILE names a YAML list of canaries, VLLM_COMPOSER_CANARY_WEBHOOK_URL
+    // receives alerts
+    pub fn from_env() -> Self {
+        let non_empty = |name: &str| std::env::var(name).ok().filter(|s| !s.is_empty());
+        let canaries = match non_empty("VLLM_COMPOSER_CANARIES_FILE") {
+            Some(path) => match load_canaries(&path) {
+                Ok(canaries) => {
+                    info!("Loaded {} canaries from {}", canaries.len(), path);
+                    canaries
+                }
+                Err(e) => {
+                    warn!("Failed to load canaries from {}: {}", path, e);
+                    Vec::new()
+                }
+            },
+            None => Vec::new(),
+        };
+        let token = if canaries.is_empty() {
+            String::new()
+        } else {
+            random_hex(32).unwrap_or_else(|e| {
+                warn!("Failed to create the canary token, canaries are disabled: {}", e);
+                String::new()
+            })
+        };
+        redact_secret(&token);
+        Canaries {
+            canaries: if token.is_empty() { Vec::new() } else { canaries },
+            webhook_url: non_empty("VLLM_COMPOSER_CANARY_WEBHOOK_URL"),
+            token,
+            passing: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Groups of the canaries if the token is theirs
+    pub fn groups_of(&self, token: &str) -> Option<Vec<String>> {
+        if self.token.is_empty() || !constant_time_eq(&self.token, token) {
+            return None;
+        }
+        let groups: BTreeSet<&String> = self.canaries.iter().map(|canary| &canary.group).collect();
+        Some(groups.into_iter().cloned().collect())
+    }
+
+    // Remember the outcome of a run, returns whether it changed (a first failure counts)
+    fn set_passing(&self, name: &str, passed: bool) -> bool {
+        let previous = self.passing.lock().unwrap().insert(name.to_string(), passed);
+        previous.map_or(!passed, |previous| previous != passed)
+    }
+}
+
+fn load_canaries(path: &str) -> Result<Vec<Canary>, String> {
+    let contents = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let canaries: Vec<Canary> = serde_yaml::from_str(&contents).map_err(|e| e.to_string())?;
+    for canary in &canaries {
+        if canary.interval_secs == 0 {
+            return Err(format!("Canary {} has an interval of 0 seconds", canary.name()));
+        }
+    }
+    Ok(canaries)
+}
+
+// Run every canary on its own schedule through this composer, reached at `base_url`
+pub async fn run(state: Arc<AppState>, base_url: String) {
+    for index in 0..state.canaries.canaries.len() {
+        let state = Arc::clone(&state);
+        let base_url = base_url.clone();
+        tokio::spawn(async move {
+            let canary = state.canaries.canaries[index].clone();
+            info!("Canary {} runs every {}s", canary.name(), canary.interval_secs);
+            sleep(FIRST_RUN_DELAY).await;
+            loop {
+                check(&state, &canary, &base_url).await;
+                sleep(Duration::from_secs(canary.interval_secs)).await;
+            }
+        });
+    }
+}
+
+// Run a canary once and alert if its outcome changed
+async fn check(state: &Arc<AppState>, canary: &Canary, base_url: &str) {
+    let result = run_once(state, canary, base_url).await;
+    let passed = result.is_ok();
+    state
+        .metrics
+        .canary_checks
+        .with_label_values(&[canary.name(), &canary.model, if passed { "pass" } else { "fail" }])
+        .inc();
+    state
+        .metrics
+        .canary_passing
+        .with_label_values(&[canary.name(), &canary.model])
+        .set(passed as i64);
+    if let Err(reason) = &result {
+        warn!("Canary {} failed: {}", canary.name(), reason);
+    }
+    if !state.canaries.set_passing(canary.name(), passed) {
+        return;
+    }
+    if passed {
+        info!("Canary {} passes again", canary.name());
+    }
+    if let Some(url) = &state.canaries.webhook_url {
+        let payload = json!({
+            "event": if passed { "canary_recovered" } else { "canary_failed" },
+            "canary": canary.name(),
+            "model": canary.model,
+            "reason": result.err(),
+            "ts": now_ms() / 1000,
+        });
+        let sent = state.http.monitor.post(url).json(&payload).send().await;
+        if let Err(e) = sent.and_then(|response| response.error_for_status()) {
+            warn!("Failed to post canary alert: {}", e);
+        }
+    }
+}
+
+// Send the prompt through the normal proxy path and check the answer
+async fn run_once(state: &AppState, canary: &Canary, base_url: &str) -> Result<(), String> {
+    let timeouts = state.http.timeouts;
+    let client = if base_url.starts_with("https://") {
+        // The certificate names the public host, not the loopback address
+        reqwest::Client::builder()
+            .danger_accept_invalid_certs(true)
+            .connect_timeout(timeouts.connect)
+            .build()
+            .map_err(|e| format!("client failed: {}", e))?
+    } else {
+        state.http.upstream(timeouts.connect, &Transport::default())
+    };
+    let response = client
+        .post(format!("{}/v1/chat/completions", base_url))
+        .bearer_auth(&state.canaries.token)
+        .timeout(timeouts.request)
+        .json(&json!({
+            "model": canary.model,
+            "messages": [{"role": "user", "content": canary.prompt}],
+            "max_tokens": canary.max_tokens,
+            "temperature": 0,
+        }))
+        .send()
+        .await
+        .map_err(|e| format!("request failed: {}", e))?;
+    let status = response.status();
+    let text = response.text().await.map_err(|e| format!("response failed: {}", e))?;
+    if !status.is_success() {
+        return Err(format!("status {}: {}", status.as_u16(), text.chars().take(200).collect::<String>()));
+    }
+    let body: Value = serde_json::from_str(&text).map_err(|e| format!("invalid response: {}", e))?;
+    let answer = body["choices"][0]["message"]["content"].as_str().unwrap_or_default().to_lowercase();
+    let missing: Vec<&String> = canary
+        .expect
+        .iter()
+        .filter(|expected| !answer.contains(&expected.to_lowercase()))
+        .collect();
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("answer lacks {:?}", missing))
+    }
+}
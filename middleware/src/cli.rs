@@ -0,0 +1,164 @@
+// External crates
+use clap::{Parser, Subcommand};
+
+// Standard library
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+// Internal modules
+use crate::clients::{unless_zero, Timeouts};
+use crate::logging::LogFormat;
+use crate::state::{DEFAULT_ENDPOINTS_PATH, DEFAULT_SECRETS_PATH};
+
+// -----------------------------------------------------------------------------
+// Command Line
+// -----------------------------------------------------------------------------
+
+// Options fall back to their VLLM_COMPOSER_* variable, then to the default, so containers
+// can be configured through the environment alone
+
+/// OpenAI-compatible middleware in front of vLLM servers
+#[derive(Debug, Parser)]
+#[command(name = "vllm_middleware", version)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Port to listen on
+    #[arg(long, env = "VLLM_COMPOSER_PORT", default_value_t = 8080)]
+    pub port: u16,
+
+    // `vllm_middleware 9000` of earlier releases, wins over --port
+    #[arg(value_name = "PORT", hide = true)]
+    pub legacy_port: Option<u16>,
+
+    /// Address to bind, or unix:PATH for a Unix domain socket
+    #[arg(long, env = "VLLM_COMPOSER_BIND", default_value = "0.0.0.0")]
+    pub bind: String,
+
+    /// Permissions of the Unix domain socket in octal, e.g. 660 for its owner and group
+    #[arg(long, value_name = "MODE", env = "VLLM_COMPOSER_SOCKET_MODE", value_parser = parse_mode)]
+    pub socket_mode: Option<u32>,
+
+    /// Endpoints config file
+    #[arg(
+        long,
+        global = true,
+        value_name = "PATH",
+        env = "VLLM_COMPOSER_ENDPOINTS",
+        default_value = DEFAULT_ENDPOINTS_PATH
+    )]
+    pub endpoints_file: PathBuf,
+
+    /// Auth tokens config file
+    #[arg(
+        long,
+        global = true,
+        value_name = "PATH",
+        env = "VLLM_COMPOSER_SECRETS",
+        default_value = DEFAULT_SECRETS_PATH
+    )]
+    pub secrets_file: PathBuf,
+
+    /// PEM certificate chain to serve HTTPS with, reloaded when it changes
+    #[arg(long, value_name = "PATH", env = "VLLM_COMPOSER_TLS_CERT", requires = "tls_key")]
+    pub tls_cert: Option<PathBuf>,
+
+    /// PEM private key of the certificate
+    #[arg(long, value_name = "PATH", env = "VLLM_COMPOSER_TLS_KEY", requires = "tls_cert")]
+    pub tls_key: Option<PathBuf>,
+
+    /// Log filters like RUST_LOG (e.g. "info" or "vllm_middleware=debug"), overrides RUST_LOG
+    #[arg(long, value_name = "FILTERS")]
+    pub log_level: Option<String>,
+
+    /// Format of log lines
+    #[arg(long, value_enum, env = "VLLM_COMPOSER_LOG_FORMAT", default_value_t = LogFormat::Text)]
+    pub log_format: LogFormat,
+
+    /// Write a JSON access log line per request to PATH, or to stdout with `-`
+    #[arg(long, value_name = "PATH", env = "VLLM_COMPOSER_ACCESS_LOG")]
+    pub access_log: Option<String>,
+
+    /// Upstream connect timeout [default: 5]
+    #[arg(long, value_name = "SECS", env = "VLLM_COMPOSER_CONNECT_TIMEOUT_SECS")]
+    pub connect_timeout_secs: Option<u64>,
+
+    /// Upstream timeout of non-streamed requests [default: 90]
+    #[arg(long, value_name = "SECS", env = "VLLM_COMPOSER_REQUEST_TIMEOUT_SECS")]
+    pub request_timeout_secs: Option<u64>,
+
+    /// Upstream timeout between two stream chunks [default: 30]
+    #[arg(long, value_name = "SECS", env = "VLLM_COMPOSER_STREAM_CHUNK_TIMEOUT_SECS")]
+    pub stream_chunk_timeout_secs: Option<u64>,
+
+    /// Upstream timeout of a whole streamed response, 0 for none [default: 0]
+    #[arg(long, value_name = "SECS", env = "VLLM_COMPOSER_STREAM_TOTAL_TIMEOUT_SECS")]
+    pub stream_total_timeout_secs: Option<u64>,
+
+    /// Interval of SSE heartbeats while a stream waits for its first token, 0 for none [default: 0]
+    #[arg(long, value_name = "SECS", env = "VLLM_COMPOSER_SSE_HEARTBEAT_SECS")]
+    pub sse_heartbeat_secs: Option<u64>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Print the hashed entry of a token for secrets.yaml
+    HashToken {
+        token: String,
+    },
+    /// Check the endpoints and secrets files and exit, non-zero if they have errors
+    ValidateConfig,
+}
+
+impl Cli {
+    pub fn port(&self) -> u16 {
+        self.legacy_port.unwrap_or(self.port)
+    }
+
+    pub fn bind_address(&self) -> String {
+        // IPv6 addresses need brackets in front of a port
+        if self.bind.contains(':') && !self.bind.starts_with('[') {
+            format!("[{}]:{}", self.bind, self.port())
+        } else {
+            format!("{}:{}", self.bind, self.port())
+        }
+    }
+
+    // Path of the Unix domain socket to listen on instead of a TCP port
+    pub fn unix_socket(&self) -> Option<&Path> {
+        self.bind.strip_prefix("unix:").map(Path::new)
+    }
+
+    // Where this server is reached from the same host, None on a Unix domain socket
+    pub fn local_url(&self) -> Option<String> {
+        if self.unix_socket().is_some() {
+            return None;
+        }
+        let scheme = if self.tls_cert.is_some() { "https" } else { "http" };
+        Some(match self.bind.as_str() {
+            "0.0.0.0" | "::" | "[::]" => format!("{}://127.0.0.1:{}", scheme, self.port()),
+            _ => format!("{}://{}", scheme, self.bind_address()),
+        })
+    }
+
+    // `timeouts` with the overrides given on the command line
+    pub fn timeouts(&self, timeouts: Timeouts) -> Timeouts {
+        Timeouts {
+            connect: self.connect_timeout_secs.map_or(timeouts.connect, Duration::from_secs),
+            request: self.request_timeout_secs.map_or(timeouts.request, Duration::from_secs),
+            stream_chunk: self
+                .stream_chunk_timeout_secs
+                .map_or(timeouts.stream_chunk, Duration::from_secs),
+            stream_total: self.stream_total_timeout_secs.map_or(timeouts.stream_total, unless_zero),
+            stream_heartbeat: self.sse_heartbeat_secs.map_or(timeouts.stream_heartbeat, unless_zero),
+        }
+    }
+}
+
+fn parse_mode(mode: &str) -> Result<u32, String> {
+    u32::from_str_radix(mode, 8)
+        .ok()
+        .filter(|mode| *mode <= 0o777)
+        .ok_or_else(|| format!("expected octal permissions like 660, got {}", mode))
+}
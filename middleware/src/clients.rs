@@ -0,0 +1,225 @@
+// External crates
+use log::info;
+
+// Standard library
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+// Internal modules
+use crate::resolver::{CachingResolver, DnsCache};
+use crate::state::{Endpoint, EndpointProxy, EndpointTls};
+
+// -----------------------------------------------------------------------------
+// HTTP Clients
+// -----------------------------------------------------------------------------
+
+// Timeouts of upstream inference requests
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timeouts {
+    pub connect: Duration,
+    // Whole non-streaming request
+    pub request: Duration,
+    // Gap between two chunks of a streamed response
+    pub stream_chunk: Duration,
+    // Whole streamed response, None for no limit
+    pub stream_total: Option<Duration>,
+    // Interval of SSE comments sent to the client while a stream waits for its first chunk,
+    // None for no comments
+    pub stream_heartbeat: Option<Duration>,
+}
+
+impl Timeouts {
+    // Global defaults from VLLM_COMPOSER_*_TIMEOUT_SECS variables
+    pub fn from_env() -> Self {
+        Timeouts {
+            connect: Duration::from_secs(env_u64("VLLM_COMPOSER_CONNECT_TIMEOUT_SECS", 5)),
+            request: Duration::from_secs(env_u64("VLLM_COMPOSER_REQUEST_TIMEOUT_SECS", 90)),
+            stream_chunk: Duration::from_secs(env_u64("VLLM_COMPOSER_STREAM_CHUNK_TIMEOUT_SECS", 30)),
+            stream_total: unless_zero(env_u64("VLLM_COMPOSER_STREAM_TOTAL_TIMEOUT_SECS", 0)),
+            stream_heartbeat: unless_zero(env_u64("VLLM_COMPOSER_SSE_HEARTBEAT_SECS", 0)),
+        }
+    }
+
+    // Apply the endpoint's overrides from endpoints.yaml
+    pub fn for_endpoint(&self, endpoint: &Endpoint) -> Self {
+        let Some(overrides) = &endpoint.timeouts else {
+            return *self;
+        };
+        Timeouts {
+            connect: overrides.connect_secs.map_or(self.connect, Duration::from_secs),
+            request: overrides.request_secs.map_or(self.request, Duration::from_secs),
+            stream_chunk: overrides.stream_chunk_secs.map_or(self.stream_chunk, Duration::from_secs),
+            stream_total: overrides.stream_total_secs.map_or(self.stream_total, unless_zero),
+            stream_heartbeat: self.stream_heartbeat,
+        }
+    }
+}
+
+// 0 turns a limit or the heartbeats off
+pub fn unless_zero(secs: u64) -> Option<Duration> {
+    (secs > 0).then(|| Duration::from_secs(secs))
+}
+
+// How an endpoint is reached: through a proxy, with its own TLS settings. Endpoints reached
+// the same way share clients.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Transport {
+    pub proxy: Option<EndpointProxy>,
+    pub tls: Option<EndpointTls>,
+}
+
+impl Transport {
+    pub fn of(endpoint: &Endpoint) -> Self {
+        Transport {
+            proxy: endpoint.proxy.clone(),
+            tls: endpoint.tls.clone(),
+        }
+    }
+
+    pub fn is_direct(&self) -> bool {
+        self.proxy.is_none() && self.tls.is_none()
+    }
+}
+
+// Long-lived clients shared by all requests, so connections (and TLS sessions) to the
+// backends are reused. Pool settings come from VLLM_COMPOSER_POOL_* variables.
+pub struct HttpClients {
+    // Health checks, model listings and capability probes
+    pub monitor: reqwest::Client,
+    // Global upstream timeouts, see Timeouts::for_endpoint
+    pub timeouts: Timeouts,
+    // Endpoint hostnames resolved by all clients
+    pub dns: Arc<DnsCache>,
+    // Inference clients by connect timeout and transport, which reqwest only sets per client.
+    // Streams may run for long, so the request timeout is set per request.
+    upstream: Mutex<HashMap<(Duration, Transport), reqwest::Client>>,
+    // Monitor clients of endpoints behind a proxy or with TLS settings
+    transport_monitors: Mutex<HashMap<Transport, reqwest::Client>>,
+    max_idle_per_host: usize,
+    idle_timeout: Duration,
+}
+
+impl HttpClients {
+    pub fn from_env() -> Self {
+        let max_idle_per_host = env_u64("VLLM_COMPOSER_POOL_MAX_IDLE_PER_HOST", 32) as usize;
+        let idle_timeout = Duration::from_secs(env_u64("VLLM_COMPOSER_POOL_IDLE_TIMEOUT_SECS", 90));
+        info!(
+            "HTTP connection pool: {} idle connections per host, idle timeout {:?}",
+            max_idle_per_host, idle_timeout
+        );
+
+        let timeouts = Timeouts::from_env();
+        info!("Upstream timeouts: {:?}", timeouts);
+        let dns = Arc::new(DnsCache::from_env());
+
+        let clients = HttpClients {
+            monitor: monitor_builder(&dns, max_idle_per_host, idle_timeout).build().unwrap(),
+            timeouts,
+            dns,
+            upstream: Mutex::new(HashMap::new()),
+            transport_monitors: Mutex::new(HashMap::new()),
+            max_idle_per_host,
+            idle_timeout,
+        };
+        clients.upstream(timeouts.connect, &Transport::default());
+        clients
+    }
+
+    // The inference client with the given connect timeout and transport, created on first use
+    pub fn upstream(&self, connect_timeout: Duration, transport: &Transport) -> reqwest::Client {
+        let mut upstream = self.upstream.lock().unwrap();
+        // Pooled connections may still lead to the old address of a moved host
+        if self.dns.take_changed() {
+            info!("Endpoint addresses changed, opening new upstream connections");
+            upstream.clear();
+        }
+        upstream
+            .entry((connect_timeout, transport.clone()))
+            .or_insert_with(|| {
+                let builder = pooled_builder(&self.dns, self.max_idle_per_host, self.idle_timeout)
+                    .connect_timeout(connect_timeout);
+                with_transport(builder, transport).build().unwrap()
+            })
+            .clone()
+    }
+
+    // The client probing an endpoint, through its proxy and with its TLS settings
+    pub fn monitor_for(&self, endpoint: &Endpoint) -> reqwest::Client {
+        let transport = Transport::of(endpoint);
+        if transport.is_direct() {
+            return self.monitor.clone();
+        }
+        self.transport_monitors
+            .lock()
+            .unwrap()
+            .entry(transport)
+            .or_insert_with_key(|transport| {
+                let builder = monitor_builder(&self.dns, self.max_idle_per_host, self.idle_timeout);
+                with_transport(builder, transport).build().unwrap()
+            })
+            .clone()
+    }
+}
+
+impl HttpClients {
+    // Drop the clients of transports no endpoint uses any more, returns how many
+    pub fn retain_transports(&self, transports: &HashSet<Transport>) -> usize {
+        let mut upstream = self.upstream.lock().unwrap();
+        let mut monitors = self.transport_monitors.lock().unwrap();
+        let before = upstream.len() + monitors.len();
+        upstream.retain(|(_, transport), _| transport.is_direct() || transports.contains(transport));
+        monitors.retain(|transport, _| transports.contains(transport));
+        before - upstream.len() - monitors.len()
+    }
+
+    pub fn entries(&self) -> usize {
+        self.upstream.lock().unwrap().len() + self.transport_monitors.lock().unwrap().len()
+    }
+}
+
+// A hanging backend must not stall its monitor
+fn monitor_builder(dns: &Arc<DnsCache>, max_idle_per_host: usize, idle_timeout: Duration) -> reqwest::ClientBuilder {
+    pooled_builder(dns, max_idle_per_host, idle_timeout)
+        .connect_timeout(Duration::from_secs(5))
+        .timeout(Duration::from_secs(10))
+}
+
+// Send all requests through the proxy and apply the TLS settings. Both were validated when
+// the endpoint was read.
+fn with_transport(mut builder: reqwest::ClientBuilder, transport: &Transport) -> reqwest::ClientBuilder {
+    if let Some(proxy) = &transport.proxy {
+        let mut reqwest_proxy = reqwest::Proxy::all(&proxy.url).unwrap();
+        if let Some(username) = &proxy.username {
+            reqwest_proxy = reqwest_proxy.basic_auth(username, proxy.password.as_deref().unwrap_or_default());
+        }
+        builder = builder.proxy(reqwest_proxy);
+    }
+    if let Some(tls) = &transport.tls {
+        if let Some(ca_pem) = &tls.ca_pem {
+            for cert in reqwest::Certificate::from_pem_bundle(ca_pem).unwrap() {
+                builder = builder.add_root_certificate(cert);
+            }
+        }
+        if let Some((cert, key)) = &tls.identity_pem {
+            builder = builder.identity(reqwest::Identity::from_pkcs8_pem(cert, key).unwrap());
+        }
+        builder = builder.danger_accept_invalid_certs(tls.insecure_skip_verify);
+    }
+    builder
+}
+
+fn pooled_builder(dns: &Arc<DnsCache>, max_idle_per_host: usize, idle_timeout: Duration) -> reqwest::ClientBuilder {
+    reqwest::Client::builder()
+        .dns_resolver(Arc::new(CachingResolver(Arc::clone(dns))))
+        .pool_max_idle_per_host(max_idle_per_host)
+        .pool_idle_timeout(idle_timeout)
+        .tcp_keepalive(Duration::from_secs(60))
+}
+
+fn env_u64(name: &str, default: u64) -> u64 {
+    std::env::var(name)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(default)
+}
@@ -0,0 +1,143 @@
+// External crates
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::Notify;
+
+// Standard library
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+// -----------------------------------------------------------------------------
+// Concurrency Limits
+// -----------------------------------------------------------------------------
+
+// Requests in flight per group, limits come with the auth tokens
+#[derive(Default)]
+pub struct ConcurrencyLimiter {
+    in_flight: Arc<Mutex<HashMap<String, u32>>>,
+    // Woken whenever a slot is given back
+    released: Arc<Notify>,
+}
+
+impl ConcurrencyLimiter {
+    // Take a slot of the group, None once `limit` requests of the group are in flight
+    pub fn try_acquire(&self, group: &str, limit: u32) -> Option<GroupSlot> {
+        let mut in_flight = self.in_flight.lock().unwrap();
+        let count = in_flight.entry(group.to_string()).or_insert(0);
+        if *count >= limit {
+            return None;
+        }
+        *count += 1;
+        Some(GroupSlot {
+            in_flight: Arc::clone(&self.in_flight),
+            group: group.to_string(),
+            released: Arc::clone(&self.released),
+        })
+    }
+
+    pub fn in_flight(&self, group: &str) -> u32 {
+        self.in_flight.lock().unwrap().get(group).copied().unwrap_or_default()
+    }
+}
+
+// -----------------------------------------------------------------------------
+// Endpoint Pools
+// -----------------------------------------------------------------------------
+
+// Kind of request for an endpoint's pools
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RouteClass {
+    // Streamed generations, someone is waiting for the tokens
+    Interactive,
+    // Everything else: non-streamed generations, embeddings, pooling, scoring
+    Batch,
+}
+
+impl RouteClass {
+    pub fn of(task: &str, body: &Value) -> Self {
+        if task == "generate" && body.get("stream").and_then(Value::as_bool).unwrap_or(false) {
+            RouteClass::Interactive
+        } else {
+            RouteClass::Batch
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            RouteClass::Interactive => "interactive",
+            RouteClass::Batch => "batch",
+        }
+    }
+}
+
+// Requests of each class an endpoint takes at once; unset classes are unlimited, 0 turns
+// a class away
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq)]
+pub struct EndpointPools {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interactive: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub batch: Option<u32>,
+}
+
+impl EndpointPools {
+    pub fn limit(&self, class: RouteClass) -> Option<u32> {
+        match class {
+            RouteClass::Interactive => self.interactive,
+            RouteClass::Batch => self.batch,
+        }
+    }
+}
+
+// Requests in flight per endpoint and route class, limits come with the endpoints
+#[derive(Default)]
+pub struct PoolLimiter {
+    slots: ConcurrencyLimiter,
+}
+
+impl PoolLimiter {
+    // Whether the endpoint's pool of the class has a free slot
+    pub fn has_room(&self, pools: Option<&EndpointPools>, url: &str, class: RouteClass) -> bool {
+        pools
+            .and_then(|pools| pools.limit(class))
+            .is_none_or(|limit| self.in_flight(url, class) < limit)
+    }
+
+    // Take a slot of the endpoint's pool, None once `limit` requests of the class are in flight
+    pub fn try_acquire(&self, url: &str, class: RouteClass, limit: u32) -> Option<GroupSlot> {
+        self.slots.try_acquire(&pool_key(url, class), limit)
+    }
+
+    pub fn in_flight(&self, url: &str, class: RouteClass) -> u32 {
+        self.slots.in_flight(&pool_key(url, class))
+    }
+
+    // Woken whenever a slot of any pool is given back, for the requests queueing for one
+    pub fn released(&self) -> &Notify {
+        &self.slots.released
+    }
+}
+
+fn pool_key(url: &str, class: RouteClass) -> String {
+    format!("{} {}", class.label(), url)
+}
+
+// Holds one of a group's slots until dropped; streams carry it until they end
+pub struct GroupSlot {
+    in_flight: Arc<Mutex<HashMap<String, u32>>>,
+    group: String,
+    released: Arc<Notify>,
+}
+
+impl Drop for GroupSlot {
+    fn drop(&mut self) {
+        let mut in_flight = self.in_flight.lock().unwrap();
+        if let Some(count) = in_flight.get_mut(&self.group) {
+            *count = count.saturating_sub(1);
+            if *count == 0 {
+                in_flight.remove(&self.group);
+            }
+        }
+        self.released.notify_waiters();
+    }
+}
@@ -0,0 +1,118 @@
+// External crates
+use actix_web::HttpRequest;
+use log::info;
+use serde_json::Value;
+
+// Standard library
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// -----------------------------------------------------------------------------
+// Conversation Budgets
+// -----------------------------------------------------------------------------
+
+pub const CONVERSATION_HEADER: &str = "X-Conversation-Id";
+// Body field alternative to the header, removed before forwarding
+pub const CONVERSATION_FIELD: &str = "conversation_id";
+
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+// Conversations are scoped to the auth token that started them
+pub type ConversationKey = (String, String);
+
+struct ConversationUsage {
+    tokens: u64,
+    last_seen: Instant,
+}
+
+// Cumulative token usage per conversation, capped by a budget per access group
+pub struct ConversationBudgets {
+    // Group -> tokens a single conversation may use
+    limits: HashMap<String, u64>,
+    // Conversations idle for longer are forgotten
+    idle_ttl: Duration,
+    usage: Mutex<HashMap<ConversationKey, ConversationUsage>>,
+    last_pruned: Mutex<Instant>,
+}
+
+impl ConversationBudgets {
+    // VLLM_COMPOSER_CONVERSATION_BUDGETS is a list like "student=200000,guest=50000"
+    pub fn from_env() -> Self {
+        let limits: HashMap<String, u64> = std::env::var("VLLM_COMPOSER_CONVERSATION_BUDGETS")
+            .unwrap_or_default()
+            .split(',')
+            .filter_map(|entry| {
+                let (group, limit) = entry.split_once('=')?;
+                Some((group.trim().to_string(), limit.trim().parse().ok()?))
+            })
+            .collect();
+        let idle_ttl = Duration::from_secs(
+            std::env::var("VLLM_COMPOSER_CONVERSATION_TTL_SECS")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(3600),
+        );
+        if !limits.is_empty() {
+            info!("Conversation token budgets: {:?}", limits);
+        }
+        ConversationBudgets {
+            limits,
+            idle_ttl,
+            usage: Mutex::new(HashMap::new()),
+            last_pruned: Mutex::new(Instant::now()),
+        }
+    }
+
+    // The most generous budget among the groups, None if none of them is limited
+    pub fn limit_for(&self, groups: &[String]) -> Option<u64> {
+        groups.iter().filter_map(|g| self.limits.get(g)).max().copied()
+    }
+
+    // Tokens the conversation used so far
+    pub fn used(&self, key: &ConversationKey) -> u64 {
+        self.usage
+            .lock()
+            .unwrap()
+            .get(key)
+            .filter(|usage| usage.last_seen.elapsed() < self.idle_ttl)
+            .map_or(0, |usage| usage.tokens)
+    }
+
+    pub fn add(&self, key: &ConversationKey, tokens: u64) {
+        let mut usage = self.usage.lock().unwrap();
+        let now = Instant::now();
+        {
+            let mut last_pruned = self.last_pruned.lock().unwrap();
+            if last_pruned.elapsed() >= PRUNE_INTERVAL {
+                usage.retain(|_, u| u.last_seen.elapsed() < self.idle_ttl);
+                *last_pruned = now;
+            }
+        }
+        let entry = usage.entry(key.clone()).or_insert(ConversationUsage {
+            tokens: 0,
+            last_seen: now,
+        });
+        // Start over after the conversation went idle
+        if entry.last_seen.elapsed() >= self.idle_ttl {
+            entry.tokens = 0;
+        }
+        entry.tokens += tokens;
+        entry.last_seen = now;
+    }
+}
+
+// The conversation id of a request from the header or the body field, which is
+// stripped as backends don't know it
+pub fn conversation_id(req: &HttpRequest, body: &mut Value) -> Option<String> {
+    let from_body = body
+        .as_object_mut()
+        .and_then(|obj| obj.remove(CONVERSATION_FIELD))
+        .and_then(|v| v.as_str().map(String::from));
+    req.headers()
+        .get(CONVERSATION_HEADER)
+        .and_then(|h| h.to_str().ok())
+        .map(String::from)
+        .or(from_body)
+        .filter(|id| !id.is_empty())
+}
@@ -0,0 +1,159 @@
+// External crates
+use log::{debug, info, warn};
+use serde_json::Value;
+use tokio::time::sleep;
+
+// Standard library
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+// Internal modules
+use crate::discovery::{endpoint_from_metadata, registry_poll_interval, registry_ttl, DiscoveredSet};
+use crate::logging::redact_secret;
+use crate::state::{AppState, Endpoint};
+
+// -----------------------------------------------------------------------------
+// Consul Discovery
+// -----------------------------------------------------------------------------
+
+// Pause after a blocking query returned, so bursts of changes are picked up in one go
+const WATCH_SETTLE: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone)]
+pub struct ConsulConfig {
+    // Agent or server base URL, e.g. http://consul:8500
+    pub url: String,
+    pub service: String,
+    // ACL token for the catalog, if required
+    pub token: Option<String>,
+    // Hold the catalog query until the service changes (a blocking query), else poll it
+    pub watch: bool,
+    // Used for instances without an access-token meta entry
+    pub default_access_token: String,
+}
+
+impl ConsulConfig {
+    // Read the VLLM_COMPOSER_CONSUL_* variables; discovery is off unless a URL is set
+    pub fn from_env() -> Option<Self> {
+        let url = std::env::var("VLLM_COMPOSER_CONSUL_URL").ok().filter(|s| !s.is_empty())?;
+        let token = std::env::var("VLLM_COMPOSER_CONSUL_TOKEN").ok().filter(|s| !s.is_empty());
+        if let Some(token) = &token {
+            redact_secret(token);
+        }
+        Some(ConsulConfig {
+            url: url.trim_end_matches('/').to_string(),
+            service: std::env::var("VLLM_COMPOSER_CONSUL_SERVICE").unwrap_or_else(|_| "vllm".to_string()),
+            token,
+            watch: std::env::var("VLLM_COMPOSER_CONSUL_WATCH").map_or(true, |v| v != "false" && v != "0"),
+            default_access_token: std::env::var("VLLM_COMPOSER_DISCOVERY_ACCESS_TOKEN").unwrap_or_default(),
+        })
+    }
+}
+
+// Instances of the service that pass their Consul health checks, and the catalog's index.
+// Given the index of the last list, Consul holds the answer until the service changes or
+// `wait` runs out.
+async fn list_instances(
+    client: &reqwest::Client,
+    config: &ConsulConfig,
+    index: Option<u64>,
+    wait: Duration,
+) -> Result<(Vec<Value>, Option<u64>), Box<dyn std::error::Error>> {
+    let mut request = client
+        .get(format!("{}/v1/health/service/{}", config.url, config.service))
+        .query(&[("passing", "true")]);
+    if let Some(index) = index {
+        request = request
+            .query(&[("index", index.to_string()), ("wait", format!("{}s", wait.as_secs().max(1)))])
+            // Outlasts the wait, Consul adds some jitter of its own
+            .timeout(wait + Duration::from_secs(10));
+    }
+    if let Some(token) = &config.token {
+        request = request.header("X-Consul-Token", token);
+    }
+    let response = request.send().await?.error_for_status()?;
+    let index = response
+        .headers()
+        .get("X-Consul-Index")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse().ok())
+        // An index of 0 must not be waited on
+        .filter(|index| *index > 0);
+    let entries: Vec<Value> = response.json().await?;
+    Ok((entries, index))
+}
+
+// The service's tags of the form key=value (e.g. groups=admin,student or task=embed),
+// overridden by its Meta map
+fn metadata(service: &Value) -> HashMap<String, String> {
+    let mut metadata: HashMap<String, String> = service
+        .get("Tags")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(|tag| tag.as_str()?.split_once('='))
+        .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+        .collect();
+    let meta = service.get("Meta").and_then(Value::as_object).into_iter().flatten();
+    metadata.extend(meta.filter_map(|(key, value)| Some((key.clone(), value.as_str()?.to_string()))));
+    metadata
+}
+
+// Translate a health entry into an endpoint using the service's tags and Meta map
+fn to_endpoint(entry: &Value, config: &ConsulConfig) -> Option<Endpoint> {
+    let service = entry.get("Service")?;
+    let name = service.get("ID").and_then(Value::as_str).unwrap_or("?");
+    // The service address falls back to the node address when unset
+    let host = service
+        .get("Address")
+        .and_then(Value::as_str)
+        .filter(|a| !a.is_empty())
+        .or_else(|| entry.pointer("/Node/Address").and_then(Value::as_str))?;
+    let port = service.get("Port").and_then(Value::as_u64).filter(|p| *p > 0);
+    let metadata = metadata(service);
+    endpoint_from_metadata(
+        name,
+        host,
+        port,
+        |key| metadata.get(key).map(String::as_str),
+        &config.default_access_token,
+    )
+}
+
+// Keep the endpoints of the registered service instances in sync with the catalog
+pub async fn run(config: ConsulConfig, state: Arc<AppState>) {
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()
+        .unwrap();
+    info!(
+        "Consul discovery of service {} at {}, {}",
+        config.service,
+        config.url,
+        if config.watch { "watching for changes" } else { "polling" }
+    );
+
+    let interval = registry_poll_interval();
+    let mut discovered = DiscoveredSet::new("Consul").with_ttl(registry_ttl());
+    let mut index = None;
+    loop {
+        match list_instances(&client, &config, index, interval).await {
+            Ok((entries, new_index)) => {
+                let desired = entries.iter().filter_map(|e| to_endpoint(e, &config)).collect();
+                discovered.sync(&state, desired);
+                // Consul may reset its index, e.g. after a snapshot restore; start over then
+                if index.is_some() && new_index != index {
+                    debug!("Consul service {} changed", config.service);
+                }
+                index = new_index.filter(|new| config.watch && index.is_none_or(|old| *new >= old));
+            }
+            Err(e) => {
+                warn!("Consul discovery failed to list instances: {}", e);
+                discovered.expire(&state);
+                index = None;
+            }
+        }
+        sleep(if index.is_some() { WATCH_SETTLE } else { interval }).await;
+    }
+}
@@ -0,0 +1,110 @@
+// External crates
+use hickory_resolver::TokioAsyncResolver;
+use log::{info, warn};
+use reqwest::Url;
+use tokio::time::sleep;
+
+// Standard library
+use std::collections::HashMap;
+use std::sync::Arc;
+
+// Internal modules
+use crate::discovery::{registry_poll_interval, DiscoveredSet};
+use crate::state::{AppState, Endpoint};
+
+// -----------------------------------------------------------------------------
+// DNS Discovery
+// -----------------------------------------------------------------------------
+
+// One endpoint per address the template's host resolves to
+async fn resolve_template(
+    resolver: &TokioAsyncResolver,
+    template: &Endpoint,
+) -> Result<Vec<Endpoint>, Box<dyn std::error::Error>> {
+    let url = Url::parse(&template.url)?;
+    let host = url.host_str().ok_or("URL has no host")?.to_string();
+
+    let mut resolved = Vec::new();
+    match template.resolve.as_deref() {
+        // SRV records carry target hosts and ports
+        Some("srv") => {
+            for record in resolver.srv_lookup(host.as_str()).await?.iter() {
+                let mut target_url = url.clone();
+                let target = record.target().to_utf8();
+                target_url.set_host(Some(target.trim_end_matches('.')))?;
+                target_url
+                    .set_port(Some(record.port()))
+                    .map_err(|_| "URL can't carry a port")?;
+                resolved.push(resolved_endpoint(template, &target_url));
+            }
+        }
+        // A/AAAA records, the port stays the template's
+        _ => {
+            for ip in resolver.lookup_ip(host.as_str()).await?.iter() {
+                let mut target_url = url.clone();
+                target_url
+                    .set_ip_host(ip)
+                    .map_err(|_| "URL can't carry an IP host")?;
+                resolved.push(resolved_endpoint(template, &target_url));
+            }
+        }
+    }
+    Ok(resolved)
+}
+
+fn resolved_endpoint(template: &Endpoint, url: &Url) -> Endpoint {
+    let mut endpoint = template.clone();
+    endpoint.url = url.as_str().trim_end_matches('/').to_string();
+    endpoint.resolve = None;
+    endpoint
+}
+
+// Re-resolve the DNS templates from endpoints.yaml (those with `resolve` set) periodically
+pub async fn run(state: Arc<AppState>) {
+    let resolver = match TokioAsyncResolver::tokio_from_system_conf() {
+        Ok(resolver) => resolver,
+        Err(e) => {
+            warn!("DNS discovery disabled, failed to read resolver config: {}", e);
+            return;
+        }
+    };
+
+    let mut discovered = DiscoveredSet::new("DNS");
+    // Last good resolution per template URL, kept while lookups fail
+    let mut last_resolved: HashMap<(String, String), Vec<Endpoint>> = HashMap::new();
+    loop {
+        let templates = state.dns_templates.lock().unwrap().clone();
+        let mut current = HashMap::new();
+        for template in templates {
+            let key = (template.task.clone(), template.url.clone());
+            match resolve_template(&resolver, &template).await {
+                Ok(endpoints) => {
+                    current.insert(key, endpoints);
+                }
+                Err(e) => {
+                    warn!("Failed to resolve {}: {}", template.url, e);
+                    if let Some(previous) = last_resolved.remove(&key) {
+                        current.insert(key, previous);
+                    }
+                }
+            }
+        }
+        last_resolved = current;
+
+        let desired: Vec<Endpoint> = last_resolved.values().flatten().cloned().collect();
+        discovered.sync(&state, desired);
+        sleep(registry_poll_interval()).await;
+    }
+}
+
+// Log what will be resolved, once at startup
+pub fn log_templates(templates: &[Endpoint]) {
+    for template in templates {
+        info!(
+            "Resolving {} endpoint {} via DNS ({})",
+            template.task,
+            template.url,
+            template.resolve.as_deref().unwrap_or("all")
+        );
+    }
+}
@@ -0,0 +1,177 @@
+// External crates
+use log::{debug, info, warn};
+use serde_json::{json, Value};
+use tokio::net::UnixStream;
+use tokio::time::{sleep, timeout};
+
+// Standard library
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+// Internal modules
+use crate::discovery::{endpoint_from_metadata, registry_poll_interval, DiscoveredSet};
+use crate::state::{validate_endpoint, AppState, Endpoint};
+
+// -----------------------------------------------------------------------------
+// Docker Discovery
+// -----------------------------------------------------------------------------
+
+const DEFAULT_SOCKET: &str = "/var/run/docker.sock";
+const LABEL_PREFIX: &str = "vllm-composer.";
+const API_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Where the Docker API listens
+#[derive(Debug, Clone, PartialEq)]
+pub enum DockerHost {
+    Socket(PathBuf),
+    // Base URL of a daemon listening on TCP, e.g. http://localhost:2375
+    Http(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct DockerConfig {
+    pub host: DockerHost,
+    // Network whose container address is routed to, the first with an address if unset
+    pub network: Option<String>,
+    pub poll_interval: Duration,
+    // Used for containers without an access-token label
+    pub default_access_token: String,
+}
+
+impl DockerConfig {
+    // With VLLM_COMPOSER_DOCKER_DISCOVERY=true, read VLLM_COMPOSER_DOCKER_HOST (unix://PATH or
+    // http://HOST:PORT, default the local socket) and VLLM_COMPOSER_DOCKER_NETWORK
+    pub fn from_env() -> Option<Self> {
+        if !DockerConfig::is_enabled() {
+            return None;
+        }
+        let host = match std::env::var("VLLM_COMPOSER_DOCKER_HOST").ok().filter(|s| !s.is_empty()) {
+            None => DockerHost::Socket(PathBuf::from(DEFAULT_SOCKET)),
+            Some(host) => match host.strip_prefix("unix://") {
+                Some(path) => DockerHost::Socket(PathBuf::from(path)),
+                None => {
+                    let url = host.replacen("tcp://", "http://", 1);
+                    DockerHost::Http(url.trim_end_matches('/').to_string())
+                }
+            },
+        };
+        Some(DockerConfig {
+            host,
+            network: std::env::var("VLLM_COMPOSER_DOCKER_NETWORK").ok().filter(|s| !s.is_empty()),
+            poll_interval: registry_poll_interval(),
+            default_access_token: std::env::var("VLLM_COMPOSER_DISCOVERY_ACCESS_TOKEN").unwrap_or_default(),
+        })
+    }
+
+    pub fn is_enabled() -> bool {
+        std::env::var("VLLM_COMPOSER_DOCKER_DISCOVERY").is_ok_and(|v| v == "true" || v == "1")
+    }
+}
+
+// GET a path of the Docker API as JSON
+async fn get_json(
+    client: &reqwest::Client,
+    host: &DockerHost,
+    path: &str,
+) -> Result<Value, Box<dyn std::error::Error>> {
+    let socket = match host {
+        DockerHost::Http(url) => {
+            let response = client.get(format!("{}{}", url, path)).send().await?.error_for_status()?;
+            return Ok(response.json().await?);
+        }
+        DockerHost::Socket(socket) => socket,
+    };
+    let stream = UnixStream::connect(socket).await?;
+    let (mut sender, connection) = hyper::client::conn::handshake(stream).await?;
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            debug!("Docker API connection closed: {}", e);
+        }
+    });
+    let request = hyper::Request::get(path)
+        .header(hyper::header::HOST, "docker")
+        .body(hyper::Body::empty())?;
+    let response = sender.send_request(request).await?;
+    if !response.status().is_success() {
+        return Err(format!("Docker API answered {}", response.status()).into());
+    }
+    let body = hyper::body::to_bytes(response.into_body()).await?;
+    Ok(serde_json::from_slice(&body)?)
+}
+
+// Running containers that name the groups allowed to use them
+async fn list_containers(
+    client: &reqwest::Client,
+    config: &DockerConfig,
+) -> Result<Vec<Value>, Box<dyn std::error::Error>> {
+    let filters = json!({ "label": [format!("{}groups", LABEL_PREFIX)], "status": ["running"] });
+    let mut url = reqwest::Url::parse("http://docker/containers/json")?;
+    url.query_pairs_mut().append_pair("filters", &filters.to_string());
+    let path = format!("{}?{}", url.path(), url.query().unwrap_or_default());
+    let containers = timeout(API_TIMEOUT, get_json(client, &config.host, &path))
+        .await
+        .map_err(|_| "Docker API timed out")??;
+    Ok(containers.as_array().cloned().unwrap_or_default())
+}
+
+// Translate a container into an endpoint from its labels (the keys of the other discovery
+// sources plus url and network), None if it can't be routed to
+fn to_endpoint(container: &Value, config: &DockerConfig) -> Option<Endpoint> {
+    let name = container
+        .pointer("/Names/0")
+        .and_then(Value::as_str)
+        .map_or("?", |name| name.trim_start_matches('/'));
+    let labels = container.get("Labels");
+    let label = |key: &str| {
+        labels
+            .and_then(|labels| labels.get(format!("{}{}", LABEL_PREFIX, key)))
+            .and_then(Value::as_str)
+    };
+    let network = label("network").or(config.network.as_deref());
+    let address = container
+        .pointer("/NetworkSettings/Networks")
+        .and_then(Value::as_object)
+        .into_iter()
+        .flatten()
+        .filter(|(name, _)| network.is_none_or(|network| network == name.as_str()))
+        .find_map(|(_, settings)| {
+            settings.get("IPAddress").and_then(Value::as_str).filter(|ip| !ip.is_empty())
+        });
+    // An explicit url, e.g. a published port, needs no address
+    let url = label("url");
+    let Some(host) = address.or(url.map(|_| "localhost")) else {
+        debug!("Skip {}: no address on network {}", name, network.unwrap_or("any"));
+        return None;
+    };
+    let port = container.pointer("/Ports/0/PrivatePort").and_then(Value::as_u64);
+    let mut endpoint = endpoint_from_metadata(name, host, port, label, &config.default_access_token)?;
+    if let Some(url) = url {
+        endpoint.url = url.to_string();
+    }
+    // Checked like endpoints added via the admin API
+    if let Err(e) = validate_endpoint(&mut endpoint) {
+        warn!("Skip {}: {}", name, e);
+        return None;
+    }
+    Some(endpoint)
+}
+
+// Keep the endpoints of labeled containers in sync with the containers running
+pub async fn run(config: DockerConfig, state: Arc<AppState>) {
+    let client = reqwest::Client::builder().timeout(API_TIMEOUT).build().unwrap();
+    info!("Docker discovery of labeled containers via {:?}", config.host);
+
+    let mut discovered = DiscoveredSet::new("Docker");
+    loop {
+        match list_containers(&client, &config).await {
+            Ok(containers) => {
+                let desired = containers.iter().filter_map(|c| to_endpoint(c, &config)).collect();
+                discovered.sync(&state, desired);
+            }
+            // Keep the last known endpoints while the daemon is unreachable
+            Err(e) => warn!("Docker discovery failed to list containers: {}", e),
+        }
+        sleep(config.poll_interval).await;
+    }
+}
@@ -0,0 +1,198 @@
+// External crates
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use log::{debug, info, warn};
+use serde_json::{json, Value};
+use tokio::time::{sleep, timeout};
+
+// Standard library
+use std::sync::Arc;
+use std::time::Duration;
+
+// Internal modules
+use crate::discovery::{registry_poll_interval, registry_ttl, DiscoveredSet};
+use crate::state::{validate_endpoint, AppState, Endpoint};
+
+// -----------------------------------------------------------------------------
+// etcd Discovery
+// -----------------------------------------------------------------------------
+
+// Pause after a watch reported a change, so bursts of changes are picked up in one go
+const WATCH_SETTLE: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone)]
+pub struct EtcdConfig {
+    // Base URL of etcd's v3 JSON gateway, e.g. http://etcd:2379
+    pub url: String,
+    // Every key under this prefix holds one endpoint as JSON
+    pub prefix: String,
+    // Watch the prefix to pick up changes at once, else list it every poll interval
+    pub watch: bool,
+}
+
+impl EtcdConfig {
+    // Read the VLLM_COMPOSER_ETCD_* variables; discovery is off unless a URL is set
+    pub fn from_env() -> Option<Self> {
+        let url = std::env::var("VLLM_COMPOSER_ETCD_URL").ok().filter(|s| !s.is_empty())?;
+        Some(EtcdConfig {
+            url: url.trim_end_matches('/').to_string(),
+            prefix: std::env::var("VLLM_COMPOSER_ETCD_PREFIX")
+                .unwrap_or_else(|_| "/vllm-composer/endpoints/".to_string()),
+            watch: std::env::var("VLLM_COMPOSER_ETCD_WATCH").map_or(true, |v| v != "false" && v != "0"),
+        })
+    }
+}
+
+// Smallest key greater than every key with the given prefix
+fn prefix_range_end(prefix: &str) -> Vec<u8> {
+    let mut end = prefix.as_bytes().to_vec();
+    while let Some(last) = end.pop() {
+        if last < 0xff {
+            end.push(last + 1);
+            return end;
+        }
+    }
+    // All 0xff: range to the end of the keyspace
+    vec![0]
+}
+
+// Values of all keys under the prefix, and the revision of the store they were read at
+async fn list_values(
+    client: &reqwest::Client,
+    config: &EtcdConfig,
+) -> Result<(Vec<(String, Vec<u8>)>, Option<u64>), Box<dyn std::error::Error>> {
+    let request = json!({
+        "key": BASE64.encode(config.prefix.as_bytes()),
+        "range_end": BASE64.encode(prefix_range_end(&config.prefix)),
+    });
+    let resp: Value = client
+        .post(format!("{}/v3/kv/range", config.url))
+        .json(&request)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    let mut values = Vec::new();
+    for kv in resp.get("kvs").and_then(Value::as_array).into_iter().flatten() {
+        let key = kv.get("key").and_then(Value::as_str).unwrap_or_default();
+        let key = String::from_utf8_lossy(&BASE64.decode(key)?).to_string();
+        let value = BASE64.decode(kv.get("value").and_then(Value::as_str).unwrap_or_default())?;
+        values.push((key, value));
+    }
+    // int64 fields are strings in the JSON gateway
+    let revision = resp.pointer("/header/revision").and_then(|r| match r {
+        Value::String(r) => r.parse().ok(),
+        r => r.as_u64(),
+    });
+    Ok((values, revision))
+}
+
+// Whether a message of a watch stream reports changed keys, not just the watch's creation
+fn has_events(line: &[u8]) -> bool {
+    serde_json::from_slice::<Value>(line)
+        .ok()
+        .and_then(|message| message.pointer("/result/events")?.as_array().map(|e| !e.is_empty()))
+        .unwrap_or(false)
+}
+
+// Wait until a key under the prefix changed after `revision`, at most a poll interval
+async fn wait_for_change(client: &reqwest::Client, config: &EtcdConfig, revision: Option<u64>) {
+    let interval = registry_poll_interval();
+    let Some(revision) = revision.filter(|_| config.watch) else {
+        sleep(interval).await;
+        return;
+    };
+    let request = json!({
+        "create_request": {
+            "key": BASE64.encode(config.prefix.as_bytes()),
+            "range_end": BASE64.encode(prefix_range_end(&config.prefix)),
+            "start_revision": (revision + 1).to_string(),
+        }
+    });
+    let event = async {
+        let mut response = client
+            .post(format!("{}/v3/watch", config.url))
+            .json(&request)
+            // Outlasts the client's timeout, which is meant for lists
+            .timeout(interval + Duration::from_secs(10))
+            .send()
+            .await?
+            .error_for_status()?;
+        // Messages are separated by newlines, the first one confirms the watch
+        let mut buffer = Vec::new();
+        while let Some(chunk) = response.chunk().await? {
+            buffer.extend_from_slice(&chunk);
+            while let Some(end) = buffer.iter().position(|b| *b == b'\n') {
+                let line: Vec<u8> = buffer.drain(..=end).collect();
+                if has_events(&line) {
+                    return Ok(true);
+                }
+            }
+        }
+        Ok::<_, reqwest::Error>(has_events(&buffer))
+    };
+    match timeout(interval, event).await {
+        // Nothing changed, list anyway
+        Err(_) => return,
+        Ok(Ok(true)) => debug!("etcd prefix {} changed", config.prefix),
+        // etcd closed the watch
+        Ok(Ok(false)) => {}
+        Ok(Err(e)) => {
+            warn!("etcd discovery failed to watch endpoints: {}", e);
+            sleep(interval).await;
+            return;
+        }
+    }
+    sleep(WATCH_SETTLE).await;
+}
+
+// Values use the endpoints.yaml schema, serialized as JSON
+fn to_endpoint(key: &str, value: &[u8]) -> Option<Endpoint> {
+    match serde_json::from_slice::<Endpoint>(value) {
+        // Checked like endpoints.yaml entries
+        Ok(mut endpoint) => match validate_endpoint(&mut endpoint) {
+            Ok(()) => Some(endpoint),
+            Err(e) => {
+                warn!("Skip etcd key {}: {}", key, e);
+                None
+            }
+        },
+        Err(e) => {
+            warn!("Skip etcd key {}: {}", key, e);
+            None
+        }
+    }
+}
+
+// Keep the endpoints stored under the prefix in sync with etcd. Registrants should attach
+// their keys to a lease so crashed backends disappear on their own.
+pub async fn run(config: EtcdConfig, state: Arc<AppState>) {
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()
+        .unwrap();
+    info!(
+        "etcd discovery of prefix {} at {}, {}",
+        config.prefix,
+        config.url,
+        if config.watch { "watching for changes" } else { "polling" }
+    );
+
+    let mut discovered = DiscoveredSet::new("etcd").with_ttl(registry_ttl());
+    loop {
+        let revision = match list_values(&client, &config).await {
+            Ok((values, revision)) => {
+                let desired = values.iter().filter_map(|(k, v)| to_endpoint(k, v)).collect();
+                discovered.sync(&state, desired);
+                revision
+            }
+            Err(e) => {
+                warn!("etcd discovery failed to list endpoints: {}", e);
+                discovered.expire(&state);
+                None
+            }
+        };
+        wait_for_change(&client, &config, revision).await;
+    }
+}
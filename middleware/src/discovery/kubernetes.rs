@@ -0,0 +1,258 @@
+// External crates
+use log::{debug, info, warn};
+use serde_json::Value;
+use tokio::time::{sleep, timeout};
+
+// Standard library
+use std::collections::HashMap;
+use std::fs;
+use std::sync::Arc;
+use std::time::Duration;
+
+// Internal modules
+use crate::discovery::{endpoint_from_metadata, DiscoveredSet};
+use crate::logging::redact_secret;
+use crate::state::{AppState, Endpoint};
+
+// -----------------------------------------------------------------------------
+// Kubernetes Discovery
+// -----------------------------------------------------------------------------
+
+const SERVICE_ACCOUNT_DIR: &str = "/var/run/secrets/kubernetes.io/serviceaccount";
+const ANNOTATION_PREFIX: &str = "vllm-composer/";
+// Changes come in bursts, e.g. during a rollout; the list after a change waits this long
+const WATCH_SETTLE: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ResourceKind {
+    Pods,
+    Services,
+}
+
+#[derive(Debug, Clone)]
+pub struct KubernetesConfig {
+    // API server base URL, in-cluster by default
+    pub api_url: String,
+    pub namespace: String,
+    pub label_selector: String,
+    pub resource: ResourceKind,
+    pub resync_interval: Duration,
+    // Watch the resources to pick up changes at once, else list them every resync interval
+    pub watch: bool,
+    // Used for endpoints without an access-token annotation
+    pub default_access_token: String,
+}
+
+impl KubernetesConfig {
+    // Read the VLLM_COMPOSER_K8S_* variables; discovery is off unless a selector is set
+    pub fn from_env() -> Option<Self> {
+        let label_selector = std::env::var("VLLM_COMPOSER_K8S_SELECTOR").ok().filter(|s| !s.is_empty())?;
+        let api_url = std::env::var("VLLM_COMPOSER_K8S_API").unwrap_or_else(|_| {
+            let host = std::env::var("KUBERNETES_SERVICE_HOST").unwrap_or_else(|_| "kubernetes.default.svc".to_string());
+            let port = std::env::var("KUBERNETES_SERVICE_PORT").unwrap_or_else(|_| "443".to_string());
+            format!("https://{}:{}", host, port)
+        });
+        let namespace = std::env::var("VLLM_COMPOSER_K8S_NAMESPACE")
+            .ok()
+            .or_else(|| fs::read_to_string(format!("{}/namespace", SERVICE_ACCOUNT_DIR)).ok())
+            .map(|ns| ns.trim().to_string())
+            .unwrap_or_else(|| "default".to_string());
+        let resource = match std::env::var("VLLM_COMPOSER_K8S_RESOURCE").as_deref() {
+            Ok("services") => ResourceKind::Services,
+            _ => ResourceKind::Pods,
+        };
+        let resync_interval = std::env::var("VLLM_COMPOSER_K8S_RESYNC_SECS")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(Duration::from_secs(10));
+        let watch = std::env::var("VLLM_COMPOSER_K8S_WATCH").map_or(true, |v| v != "false" && v != "0");
+        let default_access_token = std::env::var("VLLM_COMPOSER_K8S_ACCESS_TOKEN").unwrap_or_default();
+        Some(KubernetesConfig {
+            api_url,
+            namespace,
+            label_selector,
+            resource,
+            resync_interval,
+            watch,
+            default_access_token,
+        })
+    }
+
+    // Whether discovery is configured, without reading the rest of the settings
+    pub fn is_enabled() -> bool {
+        std::env::var("VLLM_COMPOSER_K8S_SELECTOR").is_ok_and(|s| !s.is_empty())
+    }
+
+    fn resources_url(&self) -> String {
+        let kind = match self.resource {
+            ResourceKind::Pods => "pods",
+            ResourceKind::Services => "services",
+        };
+        format!("{}/api/v1/namespaces/{}/{}", self.api_url, self.namespace, kind)
+    }
+}
+
+fn build_client() -> Result<reqwest::Client, Box<dyn std::error::Error>> {
+    let mut builder = reqwest::Client::builder().timeout(Duration::from_secs(10));
+    if let Ok(ca) = fs::read(format!("{}/ca.crt", SERVICE_ACCOUNT_DIR)) {
+        builder = builder.add_root_certificate(reqwest::Certificate::from_pem(&ca)?);
+    }
+    Ok(builder.build()?)
+}
+
+// The service account's token, rotated by the kubelet, so read for every request
+fn authorized(mut request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+    if let Ok(token) = fs::read_to_string(format!("{}/token", SERVICE_ACCOUNT_DIR)) {
+        redact_secret(token.trim());
+        request = request.bearer_auth(token.trim());
+    }
+    request
+}
+
+// The labeled resources and the version of the list, which a watch starts from
+async fn list_resources(
+    client: &reqwest::Client,
+    config: &KubernetesConfig,
+) -> Result<(Vec<Value>, Option<String>), Box<dyn std::error::Error>> {
+    let request = client
+        .get(config.resources_url())
+        .query(&[("labelSelector", &config.label_selector)]);
+    let list: Value = authorized(request).send().await?.error_for_status()?.json().await?;
+    let version = list.pointer("/metadata/resourceVersion").and_then(Value::as_str).map(String::from);
+    let items = list.get("items").and_then(Value::as_array).cloned().unwrap_or_default();
+    Ok((items, version))
+}
+
+// Wait until one of the labeled resources changes after the listed version, at most a resync
+// interval. Any event ends the wait, the next list picks the change up.
+async fn wait_for_change(client: &reqwest::Client, config: &KubernetesConfig, version: Option<&str>) {
+    let Some(version) = version.filter(|_| config.watch) else {
+        sleep(config.resync_interval).await;
+        return;
+    };
+    let secs = config.resync_interval.as_secs().max(1).to_string();
+    let request = client
+        .get(config.resources_url())
+        .query(&[
+            ("labelSelector", config.label_selector.as_str()),
+            ("watch", "true"),
+            ("resourceVersion", version),
+            ("timeoutSeconds", &secs),
+        ])
+        // Outlasts the client's timeout, which is meant for lists
+        .timeout(config.resync_interval + Duration::from_secs(10));
+    let event = async {
+        let mut response = authorized(request).send().await?.error_for_status()?;
+        response.chunk().await
+    };
+    match timeout(config.resync_interval, event).await {
+        // Nothing changed, resync anyway
+        Err(_) => return,
+        Ok(Ok(Some(_))) => debug!("Kubernetes {:?} changed", config.resource),
+        // The API server closed the watch
+        Ok(Ok(None)) => {}
+        Ok(Err(e)) => {
+            warn!("Kubernetes discovery failed to watch resources: {}", e);
+            sleep(config.resync_interval).await;
+            return;
+        }
+    }
+    sleep(WATCH_SETTLE).await;
+}
+
+// The vllm-composer/ keys of the resource's annotations, else of its labels. Label values
+// can't hold commas, groups in a label are separated by dots.
+fn metadata(item: &Value) -> HashMap<String, String> {
+    let keys = |field: &str| {
+        item.pointer(&format!("/metadata/{}", field))
+            .and_then(Value::as_object)
+            .into_iter()
+            .flatten()
+            .filter_map(|(key, value)| Some((key.strip_prefix(ANNOTATION_PREFIX)?.to_string(), value.as_str()?)))
+    };
+    let mut metadata: HashMap<String, String> = keys("labels")
+        .map(|(key, value)| {
+            let value = if key == "groups" { value.replace('.', ",") } else { value.to_string() };
+            (key, value)
+        })
+        .collect();
+    metadata.extend(keys("annotations").map(|(key, value)| (key, value.to_string())));
+    metadata
+}
+
+// Translate a pod or service into an endpoint, None if it is not (yet) routable
+fn to_endpoint(item: &Value, config: &KubernetesConfig) -> Option<Endpoint> {
+    let name = item.pointer("/metadata/name").and_then(Value::as_str).unwrap_or("?");
+    let (host, port) = match config.resource {
+        ResourceKind::Pods => {
+            let ready = item.pointer("/status/phase").and_then(Value::as_str) == Some("Running")
+                && item
+                    .pointer("/status/conditions")
+                    .and_then(Value::as_array)
+                    .is_some_and(|conds| {
+                        conds.iter().any(|c| c["type"] == "Ready" && c["status"] == "True")
+                    });
+            if !ready {
+                return None;
+            }
+            let host = item.pointer("/status/podIP").and_then(Value::as_str)?.to_string();
+            let port = item
+                .pointer("/spec/containers/0/ports/0/containerPort")
+                .and_then(Value::as_u64);
+            (host, port)
+        }
+        ResourceKind::Services => {
+            let namespace = item
+                .pointer("/metadata/namespace")
+                .and_then(Value::as_str)
+                .unwrap_or(&config.namespace);
+            let host = format!("{}.{}.svc", name, namespace);
+            let port = item.pointer("/spec/ports/0/port").and_then(Value::as_u64);
+            (host, port)
+        }
+    };
+    let metadata = metadata(item);
+    endpoint_from_metadata(
+        name,
+        &host,
+        port,
+        |key| metadata.get(key).map(String::as_str),
+        &config.default_access_token,
+    )
+}
+
+// Keep the endpoints of labeled pods or services in sync with the cluster
+pub async fn run(config: KubernetesConfig, state: Arc<AppState>) {
+    let client = match build_client() {
+        Ok(client) => client,
+        Err(e) => {
+            warn!("Kubernetes discovery disabled, failed to set up client: {}", e);
+            return;
+        }
+    };
+    info!(
+        "Kubernetes discovery of {:?} in namespace {} with selector {}, {}",
+        config.resource,
+        config.namespace,
+        config.label_selector,
+        if config.watch { "watching for changes" } else { "polling" }
+    );
+
+    let mut discovered = DiscoveredSet::new("Kubernetes");
+    loop {
+        let version = match list_resources(&client, &config).await {
+            Ok((items, version)) => {
+                let desired = items.iter().filter_map(|item| to_endpoint(item, &config)).collect();
+                discovered.sync(&state, desired);
+                version
+            }
+            // Keep the last known endpoints while the API server is unreachable
+            Err(e) => {
+                warn!("Kubernetes discovery failed to list resources: {}", e);
+                None
+            }
+        };
+        wait_for_change(&client, &config, version.as_deref()).await;
+    }
+}
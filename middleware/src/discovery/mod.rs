@@ -0,0 +1,183 @@
+pub mod consul;
+pub mod dns;
+pub mod docker;
+pub mod etcd;
+pub mod kubernetes;
+pub mod registration;
+
+// External crates
+use log::{debug, info, warn};
+
+// Standard library
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// Internal modules
+use crate::monitoring::spawn_monitor;
+use crate::state::{normalize_url, AppState, Endpoint, TASKS};
+
+// -----------------------------------------------------------------------------
+// Discovery
+// -----------------------------------------------------------------------------
+
+// Settings shared by the polling backends (Consul, etcd, DNS, Docker)
+pub fn registry_poll_interval() -> Duration {
+    env_secs("VLLM_COMPOSER_DISCOVERY_INTERVAL_SECS", 10)
+}
+
+pub fn registry_ttl() -> Duration {
+    env_secs("VLLM_COMPOSER_DISCOVERY_TTL_SECS", 30)
+}
+
+fn env_secs(name: &str, default: u64) -> Duration {
+    Duration::from_secs(
+        std::env::var(name)
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(default),
+    )
+}
+
+// Build the endpoint of a discovered backend from its metadata (annotations, service meta, ...).
+// Recognized keys: groups (required), task, port, scheme, access-token, guided-decoding,
+// vision, max-batch-size, weight, zone. Returns None if the backend can't be routed to.
+pub fn endpoint_from_metadata<'a>(
+    name: &str,
+    host: &str,
+    port: Option<u64>,
+    meta: impl Fn(&str) -> Option<&'a str>,
+    default_access_token: &str,
+) -> Option<Endpoint> {
+    let groups: Vec<String> = meta("groups")
+        .map(|g| g.split(',').map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect())
+        .unwrap_or_default();
+    if groups.is_empty() {
+        debug!("Skip {}: no groups in metadata", name);
+        return None;
+    }
+    let task = meta("task").unwrap_or("generate").to_string();
+    if !TASKS.contains(&task.as_str()) {
+        warn!("Skip {}: invalid task value: {}", name, task);
+        return None;
+    }
+
+    let port = meta("port").and_then(|p| p.parse().ok()).or(port).unwrap_or(8000);
+    let scheme = meta("scheme").unwrap_or("http");
+    // IPv6 addresses need brackets
+    let host = if host.contains(':') && !host.starts_with('[') {
+        format!("[{}]", host)
+    } else {
+        host.to_string()
+    };
+
+    let url = match normalize_url(&format!("{}://{}:{}", scheme, host, port)) {
+        Ok(url) => url,
+        Err(e) => {
+            warn!("Skip {}: {}", name, e);
+            return None;
+        }
+    };
+
+    let mut endpoint = Endpoint {
+        url,
+        access_token: meta("access-token")
+            .map(String::from)
+            .unwrap_or_else(|| default_access_token.to_string()),
+        groups,
+        task,
+        ..Default::default()
+    };
+    endpoint.guided_decoding = meta("guided-decoding").and_then(|v| v.parse().ok());
+    endpoint.vision = meta("vision").and_then(|v| v.parse().ok());
+    endpoint.max_batch_size = meta("max-batch-size").and_then(|v| v.parse().ok());
+    endpoint.weight = meta("weight").and_then(|v| v.parse().ok());
+    endpoint.zone = meta("zone").map(String::from);
+    Some(endpoint)
+}
+
+// Endpoints a discovery source added, so it only ever removes its own
+pub struct DiscoveredSet {
+    source: &'static str,
+    // Endpoints missing from the source for longer than this are removed
+    ttl: Duration,
+    // (task, url) -> last time the source listed it
+    known: HashMap<(String, String), Instant>,
+}
+
+impl DiscoveredSet {
+    pub fn new(source: &'static str) -> Self {
+        DiscoveredSet {
+            source,
+            ttl: Duration::ZERO,
+            known: HashMap::new(),
+        }
+    }
+
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    // Make the source's endpoints in `state` match `desired`. Endpoints that went missing
+    // from `state` (e.g. after a /reload) are added again.
+    pub fn sync(&mut self, state: &Arc<AppState>, desired: Vec<Endpoint>) {
+        let now = Instant::now();
+        for endpoint in desired {
+            self.sync_one(state, endpoint, now);
+        }
+        self.expire_before(state, now);
+    }
+
+    fn sync_one(&mut self, state: &Arc<AppState>, endpoint: Endpoint, now: Instant) {
+        let key = (endpoint.task.clone(), endpoint.url.clone());
+        // Changed settings replace our endpoint, its monitor exits on its own
+        if self.known.contains_key(&key)
+            && state.endpoint(&endpoint.task, &endpoint.url).is_some_and(|current| current != endpoint)
+        {
+            state.remove_endpoint(&endpoint.task, &endpoint.url);
+            info!(
+                "{} discovery updated {} endpoint {}",
+                self.source, endpoint.task, endpoint.url
+            );
+        }
+        let added = state.add_endpoint(endpoint.clone());
+        if added {
+            info!(
+                "{} discovery added {} endpoint {}",
+                self.source, endpoint.task, endpoint.url
+            );
+            spawn_monitor(endpoint, Arc::clone(state));
+        }
+        // Endpoints configured elsewhere under the same URL are not ours to remove
+        if added || self.known.contains_key(&key) {
+            self.known.insert(key, now);
+        }
+    }
+
+    // Add or refresh a single endpoint without expiring the others, for sources that
+    // announce endpoints one at a time
+    pub fn refresh(&mut self, state: &Arc<AppState>, endpoint: Endpoint) {
+        self.sync_one(state, endpoint, Instant::now());
+    }
+
+    // Remove endpoints the source has not listed within the TTL. Also called when the
+    // source is unreachable, so its endpoints age out instead of lingering forever.
+    pub fn expire(&mut self, state: &Arc<AppState>) {
+        self.expire_before(state, Instant::now());
+    }
+
+    fn expire_before(&mut self, state: &Arc<AppState>, now: Instant) {
+        let ttl = self.ttl;
+        let source = self.source;
+        self.known.retain(|(task, url), last_seen| {
+            if *last_seen + ttl >= now {
+                return true;
+            }
+            if state.remove_endpoint(task, url) {
+                info!("{} discovery removed {} endpoint {}", source, task, url);
+            }
+            false
+        });
+    }
+}
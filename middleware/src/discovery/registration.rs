@@ -0,0 +1,133 @@
+// External crates
+use log::info;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::time::sleep;
+
+// Standard library
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+// Internal modules
+use crate::discovery::{registry_poll_interval, registry_ttl, DiscoveredSet};
+use crate::health::EndpointHealth;
+use crate::state::{normalize_url, AppState, Endpoint, TASKS};
+
+// -----------------------------------------------------------------------------
+// Push Registration
+// -----------------------------------------------------------------------------
+
+// What a backend sidecar announces via POST /register
+#[derive(Debug, Deserialize)]
+pub struct Registration {
+    pub url: String,
+    #[serde(default = "default_task")]
+    pub task: String,
+    pub groups: Vec<String>,
+    // Token the composer presents to the backend
+    #[serde(default)]
+    pub access_token: String,
+    #[serde(default)]
+    pub guided_decoding: Option<bool>,
+    #[serde(default)]
+    pub vision: Option<bool>,
+    #[serde(default)]
+    pub max_batch_size: Option<usize>,
+    #[serde(default)]
+    pub weight: Option<u32>,
+}
+
+fn default_task() -> String {
+    "generate".to_string()
+}
+
+impl Registration {
+    // The endpoint to add, or why the registration is invalid. Backends may only serve the
+    // allowed groups.
+    pub fn to_endpoint(&self, allowed_groups: &[String]) -> Result<Endpoint, String> {
+        let url = normalize_url(&self.url)?;
+        if !TASKS.contains(&self.task.as_str()) {
+            return Err(format!("Invalid task: {}", self.task));
+        }
+        if self.groups.is_empty() {
+            return Err("At least one group is required".to_string());
+        }
+        if let Some(group) = self.groups.iter().find(|group| !allowed_groups.contains(group)) {
+            return Err(format!("Registering for group {} is not allowed", group));
+        }
+        Ok(Endpoint {
+            url,
+            access_token: self.access_token.clone(),
+            groups: self.groups.clone(),
+            task: self.task.clone(),
+            guided_decoding: self.guided_decoding,
+            vision: self.vision,
+            max_batch_size: self.max_batch_size,
+            weight: self.weight,
+            ..Default::default()
+        })
+    }
+}
+
+// Endpoints backends registered themselves; they expire unless registered again within the TTL
+pub struct Registrations {
+    // Group of the tokens backends register with
+    pub group: String,
+    // Groups registered endpoints may serve, none if empty
+    pub allowed_groups: Vec<String>,
+    pub ttl: Duration,
+    discovered: Mutex<DiscoveredSet>,
+}
+
+impl Registrations {
+    // The register group comes from VLLM_COMPOSER_REGISTER_GROUP (default "endpoint"), the
+    // groups backends may serve from VLLM_COMPOSER_REGISTER_ALLOWED_GROUPS (comma-separated),
+    // the TTL is the discovery TTL
+    pub fn from_env() -> Self {
+        let ttl = registry_ttl();
+        Registrations {
+            group: std::env::var("VLLM_COMPOSER_REGISTER_GROUP")
+                .ok()
+                .filter(|s| !s.is_empty())
+                .unwrap_or_else(|| "endpoint".to_string()),
+            allowed_groups: std::env::var("VLLM_COMPOSER_REGISTER_ALLOWED_GROUPS")
+                .unwrap_or_default()
+                .split(',')
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+                .map(String::from)
+                .collect(),
+            ttl,
+            discovered: Mutex::new(DiscoveredSet::new("Registration").with_ttl(ttl)),
+        }
+    }
+
+    // Add or refresh a registered endpoint. It is routed to once its health probes pass and
+    // the monitor listed its models, not on the registrant's word.
+    pub fn register(&self, state: &Arc<AppState>, registration: &Registration) -> Result<Value, String> {
+        let endpoint = registration.to_endpoint(&self.allowed_groups)?;
+        let (task, url) = (endpoint.task.clone(), endpoint.url.clone());
+        self.discovered.lock().unwrap().refresh(state, endpoint);
+        if state.endpoint(&task, &url).is_some() {
+            let mut health_status = state.task(&task).health_status.lock().unwrap();
+            health_status.entry(url.clone()).or_insert_with(EndpointHealth::unproven);
+        }
+        Ok(json!({ "url": url, "task": task, "ttl_secs": self.ttl.as_secs() }))
+    }
+
+    fn expire(&self, state: &Arc<AppState>) {
+        self.discovered.lock().unwrap().expire(state);
+    }
+}
+
+// Remove registered endpoints whose backends stopped registering
+pub async fn run(state: Arc<AppState>) {
+    info!(
+        "Accepting registrations from group {} for groups {:?}, TTL {:?}",
+        state.registrations.group, state.registrations.allowed_groups, state.registrations.ttl
+    );
+    loop {
+        sleep(registry_poll_interval()).await;
+        state.registrations.expire(&state);
+    }
+}
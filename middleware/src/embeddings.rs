@@ -0,0 +1,39 @@
+// External crates
+use log::info;
+use serde_json::Value;
+
+// -----------------------------------------------------------------------------
+// Embedding Splits
+// -----------------------------------------------------------------------------
+
+// Long input lists of /v1/embeddings requests are split into chunks, which are routed on
+// their own and embedded concurrently across the model's endpoints
+pub struct EmbeddingSplit {
+    // Inputs per chunk, None leaves requests whole
+    pub chunk_size: Option<usize>,
+}
+
+impl EmbeddingSplit {
+    // From VLLM_COMPOSER_EMBEDDING_CHUNK_SIZE (default 0, off)
+    pub fn from_env() -> Self {
+        let chunk_size = std::env::var("VLLM_COMPOSER_EMBEDDING_CHUNK_SIZE")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .filter(|size| *size > 0);
+        if let Some(size) = chunk_size {
+            info!("Splitting embedding requests into chunks of {} inputs", size);
+        }
+        EmbeddingSplit { chunk_size }
+    }
+
+    // The inputs of the body in chunks, None if it is not split. Only lists of texts or of
+    // token lists are, a single token list is one input.
+    pub fn chunks(&self, body: &Value) -> Option<Vec<Vec<Value>>> {
+        let size = self.chunk_size?;
+        let inputs = body.get("input").and_then(Value::as_array)?;
+        if inputs.len() <= size || inputs.iter().any(Value::is_number) {
+            return None;
+        }
+        Some(inputs.chunks(size).map(<[Value]>::to_vec).collect())
+    }
+}
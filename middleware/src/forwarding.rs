@@ -0,0 +1,121 @@
+// External crates
+use actix_web::{HttpMessage, HttpRequest};
+use log::{info, warn};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+
+// Internal modules
+use crate::auth::AuthInfo;
+
+// -----------------------------------------------------------------------------
+// Forwarded Headers
+// -----------------------------------------------------------------------------
+
+// Never passed on even when allowed: the credentials presented to the composer, and what the
+// connection to the endpoint sets itself
+const NEVER_FORWARDED: [&str; 12] = [
+    "authorization",
+    "proxy-authorization",
+    "api-key",
+    "x-api-key",
+    "cookie",
+    "host",
+    "connection",
+    "upgrade",
+    "content-length",
+    "content-type",
+    "transfer-encoding",
+    "sec-websocket-protocol",
+];
+
+// Names the caller to the endpoint
+pub const USER_HEADER: &str = "x-forwarded-user";
+
+// Where the caller is named upstream
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UserForwarding {
+    Off,
+    // The X-Forwarded-User header
+    Header,
+    // The `user` field of JSON bodies, which vLLM logs with the request
+    Body,
+    Both,
+}
+
+// Client headers and the caller's identity passed on to endpoints, so their logs and metrics
+// can be attributed to callers
+pub struct Forwarding {
+    // Allowed client headers
+    pub headers: Vec<HeaderName>,
+    pub user: UserForwarding,
+}
+
+impl Forwarding {
+    // From VLLM_COMPOSER_FORWARD_HEADERS, a comma-separated list of client headers (default
+    // none), and VLLM_COMPOSER_FORWARD_USER: off (default), header, body or both
+    pub fn from_env() -> Self {
+        let headers: Vec<HeaderName> = std::env::var("VLLM_COMPOSER_FORWARD_HEADERS")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .filter_map(|name| match HeaderName::from_bytes(name.as_bytes()) {
+                Ok(name) if NEVER_FORWARDED.contains(&name.as_str()) => {
+                    warn!("Header {} is never forwarded upstream, ignoring it", name);
+                    None
+                }
+                Ok(name) => Some(name),
+                Err(_) => {
+                    warn!("Invalid header name in VLLM_COMPOSER_FORWARD_HEADERS: {}", name);
+                    None
+                }
+            })
+            .collect();
+        let user = match std::env::var("VLLM_COMPOSER_FORWARD_USER").as_deref() {
+            Ok("header") => UserForwarding::Header,
+            Ok("body") => UserForwarding::Body,
+            Ok("both") => UserForwarding::Both,
+            Ok("off") | Ok("") | Err(_) => UserForwarding::Off,
+            Ok(other) => {
+                warn!("Invalid VLLM_COMPOSER_FORWARD_USER value {}, not forwarding callers", other);
+                UserForwarding::Off
+            }
+        };
+        if !headers.is_empty() || user != UserForwarding::Off {
+            info!("Forwarding client headers {:?} and callers {:?}", headers, user);
+        }
+        Forwarding { headers, user }
+    }
+
+    // The allowed headers of the client's request and the caller's header, sent to the
+    // endpoint ahead of its own headers
+    pub fn headers_of(&self, req: &HttpRequest) -> HeaderMap {
+        let mut forwarded = HeaderMap::new();
+        for name in &self.headers {
+            for value in req.headers().get_all(name.as_str()) {
+                if let Ok(value) = HeaderValue::from_bytes(value.as_bytes()) {
+                    forwarded.append(name.clone(), value);
+                }
+            }
+        }
+        if matches!(self.user, UserForwarding::Header | UserForwarding::Both)
+            && let Some(user) = req.extensions().get::<AuthInfo>().map(caller_name)
+            && let Ok(value) = HeaderValue::from_str(&user)
+        {
+            forwarded.insert(HeaderName::from_static(USER_HEADER), value);
+        }
+        forwarded
+    }
+
+    // The caller as the `user` field of the body, None unless callers go into bodies
+    pub fn body_user(&self, req: &HttpRequest) -> Option<String> {
+        if !matches!(self.user, UserForwarding::Body | UserForwarding::Both) {
+            return None;
+        }
+        req.extensions().get::<AuthInfo>().map(caller_name)
+    }
+}
+
+// The owner of the caller's token in secrets.yaml, else the token's id, never the token
+pub fn caller_name(info: &AuthInfo) -> String {
+    info.owner.clone().unwrap_or_else(|| info.token_id())
+}
@@ -0,0 +1,107 @@
+// External crates
+use log::{debug, info};
+use tokio::time::sleep;
+
+// Standard library
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+
+// Internal modules
+use crate::clients::Transport;
+use crate::state::AppState;
+
+// -----------------------------------------------------------------------------
+// State Garbage Collection
+// -----------------------------------------------------------------------------
+
+// Endpoints come and go with discovery, registrations and reloads. Most maps are cleaned
+// up when an endpoint is removed, but late writers (a probe finishing after the removal,
+// a request completing on a removed endpoint) can leave entries behind. This sweep drops
+// whatever belongs to no active endpoint.
+
+// Every VLLM_COMPOSER_GC_INTERVAL_SECS (default 300, 0 disables)
+pub async fn run(state: Arc<AppState>) {
+    let interval_secs = std::env::var("VLLM_COMPOSER_GC_INTERVAL_SECS")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(300u64);
+    if interval_secs == 0 {
+        info!("State garbage collection is disabled");
+        return;
+    }
+    info!("Collecting state of removed endpoints every {}s", interval_secs);
+    loop {
+        sleep(Duration::from_secs(interval_secs)).await;
+        collect(&state);
+    }
+}
+
+// One sweep, returns how many entries were dropped
+pub fn collect(state: &AppState) -> usize {
+    let mut removed: Vec<(&'static str, usize)> = Vec::new();
+    let mut active: HashSet<String> = HashSet::new();
+    let mut served: HashSet<(String, String)> = HashSet::new();
+    let mut transports = HashSet::new();
+    let mut task_entries = 0;
+    for (task, task_state) in state.tasks() {
+        task_entries += task_state.retain_endpoints();
+        for endpoint in task_state.endpoints.lock().unwrap().iter() {
+            active.insert(endpoint.url.clone());
+            transports.insert(Transport::of(endpoint));
+        }
+        for model in task_state.model_to_endpoints.lock().unwrap().keys() {
+            served.insert((task.to_string(), model.clone()));
+        }
+    }
+    removed.push(("task_state", task_entries));
+    let served_models: HashSet<String> = served.iter().map(|(_, model)| model.clone()).collect();
+    // Hosts still resolved: those of endpoints, DNS templates and proxies
+    let mut hosts: HashSet<String> = active.iter().filter_map(|url| host_of(url)).collect();
+    hosts.extend(state.dns_templates.lock().unwrap().iter().filter_map(|ep| host_of(&ep.url)));
+    hosts.extend(transports.iter().filter_map(|transport| host_of(&transport.proxy.as_ref()?.url)));
+
+    removed.push(("endpoint_capabilities", {
+        let mut capabilities = state.endpoint_capabilities.lock().unwrap();
+        let before = capabilities.len();
+        capabilities.retain(|url, _| active.contains(url));
+        before - capabilities.len()
+    }));
+    removed.push(("backend_metrics", {
+        let mut backend_metrics = state.backend_metrics.lock().unwrap();
+        let before = backend_metrics.len();
+        backend_metrics.retain(|url, _| active.contains(url));
+        before - backend_metrics.len()
+    }));
+    removed.push(("deep_checks", {
+        let mut deep_checks = state.deep_checks.lock().unwrap();
+        let before = deep_checks.len();
+        deep_checks.retain(|url, _| active.contains(url));
+        before - deep_checks.len()
+    }));
+    removed.push(("breakers", state.breakers.retain(&active)));
+    removed.push(("outliers", state.outliers.retain(&active)));
+    removed.push(("slow_start", state.slow_start.retain(&active)));
+    removed.push(("round_robin", state.round_robin.retain(&served, &active)));
+    removed.push(("model_stats", state.model_stats.retain(&served_models, &active)));
+    removed.push(("truncation", state.truncation.retain(&state.metrics, &active)));
+    removed.push(("http_clients", state.http.retain_transports(&transports)));
+    removed.push(("dns_cache", state.http.dns.retain(&hosts)));
+
+    let total = removed.iter().map(|(_, count)| count).sum();
+    if total > 0 {
+        let details: Vec<String> = removed
+            .iter()
+            .filter(|(_, count)| *count > 0)
+            .map(|(map, count)| format!("{} {}", count, map))
+            .collect();
+        info!("Dropped state of removed endpoints: {}", details.join(", "));
+    } else {
+        debug!("No state of removed endpoints to drop");
+    }
+    total
+}
+
+fn host_of(url: &str) -> Option<String> {
+    reqwest::Url::parse(url).ok()?.host_str().map(str::to_string)
+}
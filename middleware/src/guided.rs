@@ -0,0 +1,79 @@
+// External crates
+use serde_json::Value;
+
+// Standard library
+use std::collections::HashMap;
+
+// Internal modules
+use crate::state::{Endpoint, EndpointCapabilities};
+
+// -----------------------------------------------------------------------------
+// Guided decoding
+// -----------------------------------------------------------------------------
+
+// vLLM's guided decoding extensions to the OpenAI request schema
+pub const GUIDED_PARAMS: [&str; 4] = ["guided_json", "guided_regex", "guided_grammar", "guided_choice"];
+
+// Names of the guided decoding fields present (and non-null) in a request body
+pub fn requested_guided_params(body: &Value) -> Vec<&'static str> {
+    GUIDED_PARAMS
+        .iter()
+        .copied()
+        .filter(|param| body.get(*param).is_some_and(|v| !v.is_null()))
+        .collect()
+}
+
+// Reject malformed guided decoding fields before they reach a backend
+pub fn validate_guided_params(body: &Value) -> Result<(), String> {
+    let requested = requested_guided_params(body);
+    if requested.len() > 1 {
+        return Err(format!(
+            "Only one guided decoding parameter may be set per request, got: {}.",
+            requested.join(", ")
+        ));
+    }
+    for param in requested {
+        let value = &body[param];
+        let valid = match param {
+            "guided_json" => value.is_object() || value.is_string(),
+            "guided_choice" => value
+                .as_array()
+                .is_some_and(|choices| !choices.is_empty() && choices.iter().all(Value::is_string)),
+            _ => value.is_string(),
+        };
+        if !valid {
+            let expected = match param {
+                "guided_json" => "a JSON schema object or string",
+                "guided_choice" => "a non-empty array of strings",
+                _ => "a string",
+            };
+            return Err(format!("`{}` must be {}.", param, expected));
+        }
+    }
+    Ok(())
+}
+
+// Whether an endpoint supports guided decoding: the config override wins over the probe
+pub fn supports_guided_decoding(
+    endpoint: &Endpoint,
+    capabilities: &HashMap<String, EndpointCapabilities>,
+) -> Option<bool> {
+    endpoint.guided_decoding.or_else(|| {
+        capabilities
+            .get(&endpoint.url)
+            .and_then(|caps| caps.guided_decoding)
+    })
+}
+
+// Keep endpoints known to support guided decoding. Endpoints that could not be probed
+// are only used when no endpoint is known to support it.
+pub fn filter_guided_capable(
+    endpoints: Vec<Endpoint>,
+    capabilities: &HashMap<String, EndpointCapabilities>,
+) -> Vec<Endpoint> {
+    let (supported, unknown): (Vec<Endpoint>, Vec<Endpoint>) = endpoints
+        .into_iter()
+        .filter(|ep| supports_guided_decoding(ep, capabilities) != Some(false))
+        .partition(|ep| supports_guided_decoding(ep, capabilities) == Some(true));
+    if supported.is_empty() { unknown } else { supported }
+}
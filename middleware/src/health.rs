@@ -0,0 +1,227 @@
+// External crates
+use log::info;
+use serde::{Deserialize, Serialize};
+
+// Standard library
+use std::time::Duration;
+
+// Internal modules
+use crate::shared::now_ms;
+
+// -----------------------------------------------------------------------------
+// Endpoint Health
+// -----------------------------------------------------------------------------
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum HealthState {
+    Healthy,
+    // Failing probes, but not for long enough to be taken out of routing
+    Degraded,
+    // Failed enough probes in a row; stays out until enough probes pass in a row
+    Unhealthy,
+    // The endpoint rejects the configured access token
+    AuthError,
+    // Set by an operator: no new requests, probes and in-flight requests go on
+    Draining,
+    // Set by an operator: no requests and no probes
+    Maintenance,
+}
+
+impl HealthState {
+    // Whether requests may be sent to an endpoint in this state
+    pub fn is_routable(self) -> bool {
+        matches!(self, HealthState::Healthy | HealthState::Degraded)
+    }
+
+    // Whether the endpoint answers its probes well enough to keep its models listed
+    pub fn keeps_models(self) -> bool {
+        !matches!(self, HealthState::Unhealthy | HealthState::AuthError)
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            HealthState::Healthy => "healthy",
+            HealthState::Degraded => "degraded",
+            HealthState::Unhealthy => "unhealthy",
+            HealthState::AuthError => "auth_error",
+            HealthState::Draining => "draining",
+            HealthState::Maintenance => "maintenance",
+        }
+    }
+}
+
+// Outcome of one health probe
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Probe {
+    Passed,
+    Failed,
+    // 401 or 403
+    AuthRejected,
+}
+
+// Hysteresis of the health state machine
+#[derive(Debug, Clone, Copy)]
+pub struct HealthThresholds {
+    // Failed probes in a row that take a healthy endpoint out of routing
+    pub failures: u32,
+    // Passed probes in a row that bring an unhealthy endpoint back
+    pub recoveries: u32,
+}
+
+impl HealthThresholds {
+    // VLLM_COMPOSER_HEALTH_FAILURES (default 3) and VLLM_COMPOSER_HEALTH_RECOVERIES (default 2)
+    pub fn from_env() -> Self {
+        let env_u32 = |name: &str, default: u32| {
+            std::env::var(name)
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(default)
+                .max(1)
+        };
+        let thresholds = HealthThresholds {
+            failures: env_u32("VLLM_COMPOSER_HEALTH_FAILURES", 3),
+            recoveries: env_u32("VLLM_COMPOSER_HEALTH_RECOVERIES", 2),
+        };
+        info!("Health thresholds: {:?}", thresholds);
+        thresholds
+    }
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct EndpointHealth {
+    // As derived from the probes
+    pub state: HealthState,
+    // Probes in a row with the same outcome
+    pub consecutive_checks: u32,
+    // Probes in a row that didn't pass
+    pub consecutive_failures: u32,
+    pub check_interval: u64,
+    // Unix time in ms of the last probe, None if only synced from another replica
+    pub last_check_ms: Option<u64>,
+    pub check_latency_ms: Option<u64>,
+    // Last error of a probe or model refresh, kept after the endpoint recovers
+    pub last_error: Option<String>,
+    pub last_error_ms: Option<u64>,
+    // Draining or maintenance as set by an operator, overrides `state` for routing
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub admin_state: Option<HealthState>,
+    #[serde(skip)]
+    last_probe: Option<Probe>,
+}
+
+impl EndpointHealth {
+    // The first probe decides right away, there is nothing to damp yet
+    pub fn new(probe: Probe) -> Self {
+        let state = match probe {
+            Probe::Passed => HealthState::Healthy,
+            Probe::Failed => HealthState::Unhealthy,
+            Probe::AuthRejected => HealthState::AuthError,
+        };
+        EndpointHealth {
+            state,
+            consecutive_checks: 1,
+            consecutive_failures: (probe != Probe::Passed) as u32,
+            check_interval: 500,
+            last_check_ms: None,
+            check_latency_ms: None,
+            last_error: None,
+            last_error_ms: None,
+            admin_state: None,
+            last_probe: Some(probe),
+        }
+    }
+
+    // The state of an endpoint last seen by another replica
+    pub fn synced(state: HealthState) -> Self {
+        EndpointHealth {
+            state,
+            consecutive_checks: 0,
+            consecutive_failures: 0,
+            check_interval: 500,
+            last_check_ms: None,
+            check_latency_ms: None,
+            last_error: None,
+            last_error_ms: None,
+            admin_state: None,
+            last_probe: None,
+        }
+    }
+
+    // An endpoint kept out of routing until its probes pass, e.g. one that registered itself
+    pub fn unproven() -> Self {
+        EndpointHealth::synced(HealthState::Unhealthy)
+    }
+
+    // The state routing goes by
+    pub fn effective(&self) -> HealthState {
+        self.admin_state.unwrap_or(self.state)
+    }
+
+    pub fn is_routable(&self) -> bool {
+        self.effective().is_routable()
+    }
+
+    // Advance the state machine by a probe, returns whether the state changed
+    pub fn observe(&mut self, probe: Probe, thresholds: &HealthThresholds) -> bool {
+        if self.last_probe == Some(probe) {
+            self.consecutive_checks += 1;
+        } else {
+            self.consecutive_checks = 1;
+        }
+        self.last_probe = Some(probe);
+        if probe == Probe::Passed {
+            self.consecutive_failures = 0;
+        } else {
+            self.consecutive_failures += 1;
+        }
+
+        let next = match (self.state, probe) {
+            // Token problems don't heal by waiting, no damping
+            (_, Probe::AuthRejected) => HealthState::AuthError,
+            // Left once an authenticated request succeeds, see auth_accepted
+            (HealthState::AuthError, Probe::Passed) => HealthState::AuthError,
+            (HealthState::Healthy | HealthState::Degraded, Probe::Passed) => HealthState::Healthy,
+            (HealthState::Healthy | HealthState::Degraded, Probe::Failed) => {
+                if self.consecutive_checks >= thresholds.failures {
+                    HealthState::Unhealthy
+                } else {
+                    HealthState::Degraded
+                }
+            }
+            (_, Probe::Passed) if self.consecutive_checks >= thresholds.recoveries => {
+                HealthState::Healthy
+            }
+            (HealthState::AuthError, Probe::Failed) => HealthState::Unhealthy,
+            (state, _) => state,
+        };
+        let changed = next != self.state;
+        self.state = next;
+        changed
+    }
+
+    // Note when a probe ran, how long it took and why it didn't pass
+    pub fn checked(&mut self, latency: Duration, error: Option<String>) {
+        self.last_check_ms = Some(now_ms());
+        self.check_latency_ms = Some(latency.as_millis() as u64);
+        if let Some(error) = error {
+            self.failed(error);
+        }
+    }
+
+    // Keep an error seen by a probe or the model refresh
+    pub fn failed(&mut self, error: String) {
+        self.last_error = Some(error);
+        self.last_error_ms = Some(now_ms());
+    }
+
+    // An authenticated request went through, returns whether this ends an auth error
+    pub fn auth_accepted(&mut self) -> bool {
+        if self.state != HealthState::AuthError {
+            return false;
+        }
+        self.state = HealthState::Healthy;
+        self.consecutive_checks = 1;
+        true
+    }
+}
@@ -0,0 +1,173 @@
+// External crates
+use actix_web::{web, HttpRequest};
+
+// Standard library
+use std::sync::Arc;
+
+// Internal modules
+pub mod access_log;
+pub mod admission;
+pub mod affinity;
+pub mod aliases;
+pub mod anthropic;
+pub mod auth;
+pub mod body;
+pub mod breaker;
+pub mod budgets;
+pub mod cache;
+pub mod canaries;
+pub mod cli;
+pub mod clients;
+pub mod concurrency;
+pub mod conversations;
+pub mod discovery;
+pub mod embeddings;
+pub mod errors;
+pub mod forwarding;
+pub mod gc;
+pub mod guided;
+pub mod health;
+pub mod logging;
+pub mod loops;
+pub mod metrics;
+pub mod moderation;
+pub mod monitoring;
+pub mod multipart;
+pub mod otel;
+pub mod outliers;
+pub mod policies;
+pub mod prefix;
+pub mod presets;
+pub mod pricing;
+pub mod ratelimit;
+pub mod reload;
+pub mod reports;
+pub mod resolver;
+pub mod responses;
+pub mod routes;
+pub mod routing;
+pub mod sanitize;
+pub mod shared;
+pub mod shutdown;
+pub mod slow_start;
+pub mod state;
+pub mod stats;
+pub mod telemetry;
+pub mod tls;
+pub mod token_admin;
+pub mod token_expiry;
+pub mod truncation;
+pub mod usage;
+pub mod usage_db;
+pub mod validate;
+pub mod vision;
+
+use routes::{
+    endpoints_handler,
+    health_status_handler,
+    reload_handler,
+    health_handler,
+    ready_handler,
+    metrics_handler,
+    auth_version_handler,
+    endpoint_state_handler,
+    add_endpoint_handler,
+    remove_endpoint_handler,
+    drain_handler,
+    models_handler,
+    admin_models_handler,
+    model_to_endpoints_handler,
+    chat_completions_handler,
+    messages_handler,
+    responses_handler,
+    embeddings_handler,
+    chat_completions_handler_legacy,
+    pooling_handler,
+    classify_handler,
+    tokenize_handler,
+    detokenize_handler,
+    score_handler,
+    rerank_handler,
+    rerank_v2_handler,
+    transcriptions_handler,
+    translations_handler,
+    image_generations_handler,
+    moderations_handler,
+    websocket_handler,
+    usage_handler,
+    budgets_handler,
+    playground_handler,
+    register_handler,
+    telemetry_handler,
+    list_tokens_handler,
+    create_token_handler,
+    revoke_token_handler,
+    get_logging_handler,
+    put_logging_handler,
+};
+use errors::ApiError;
+use state::AppState;
+
+// -----------------------------------------------------------------------------
+// App
+// -----------------------------------------------------------------------------
+
+// State, body limit and routes of the server; the caller wraps the app in AuthMiddleware
+pub fn configure(cfg: &mut web::ServiceConfig, state: Arc<AppState>, playground: bool) {
+    let telemetry = state.telemetry.enabled;
+    cfg.app_data(web::Data::new(state))
+        .app_data(web::PayloadConfig::new(body::max_body_bytes()))
+        .route("/endpoints", web::get().to(endpoints_handler))
+        .route("/reload", web::get().to(reload_handler))
+        .route("/health-status", web::get().to(health_status_handler))
+        .route("/v1/models", web::get().to(models_handler))
+        .route("/model-to-endpoints", web::get().to(model_to_endpoints_handler))
+        .route("/admin/models", web::get().to(admin_models_handler))
+        .route("/health", web::get().to(health_handler))
+        .route("/ready", web::get().to(ready_handler))
+        .route("/metrics", web::get().to(metrics_handler))
+        .route("/admin/auth-version", web::get().to(auth_version_handler))
+        .route("/admin/endpoint-state", web::post().to(endpoint_state_handler))
+        .route("/admin/endpoints", web::post().to(add_endpoint_handler))
+        .route("/admin/endpoints", web::delete().to(remove_endpoint_handler))
+        .route("/drain", web::post().to(drain_handler))
+        .route("/admin/tokens", web::get().to(list_tokens_handler))
+        .route("/admin/tokens", web::post().to(create_token_handler))
+        .route("/admin/tokens/{id}", web::delete().to(revoke_token_handler))
+        .route("/admin/logging", web::get().to(get_logging_handler))
+        .route("/admin/logging", web::put().to(put_logging_handler))
+        .route("/usage", web::get().to(usage_handler))
+        .route("/budgets", web::get().to(budgets_handler))
+        .route("/v1/chat/completions", web::post().to(chat_completions_handler))
+        .route("/v1/messages", web::post().to(messages_handler))
+        .route("/v1/responses", web::post().to(responses_handler))
+        .route("/v1/embeddings", web::post().to(embeddings_handler))
+        .route("/v1/completions", web::get().to(chat_completions_handler_legacy))
+        .route("/pooling", web::post().to(pooling_handler))
+        .route("/classify", web::post().to(classify_handler))
+        .route("/tokenize", web::post().to(tokenize_handler))
+        .route("/detokenize", web::post().to(detokenize_handler))
+        .route("/v1/score", web::post().to(score_handler))
+        .route("/score", web::post().to(score_handler))
+        .route("/v1/rerank", web::post().to(rerank_handler))
+        .route("/rerank", web::post().to(rerank_handler))
+        .route("/v2/rerank", web::post().to(rerank_v2_handler))
+        .route("/v1/audio/transcriptions", web::post().to(transcriptions_handler))
+        .route("/v1/audio/translations", web::post().to(translations_handler))
+        .route("/v1/images/generations", web::post().to(image_generations_handler))
+        .route("/v1/moderations", web::post().to(moderations_handler))
+        .route("/v1/realtime", web::get().to(websocket_handler))
+        .route("/ws/{path:.*}", web::get().to(websocket_handler))
+        .route("/register", web::post().to(register_handler));
+    // Unregistered unless enabled, so disabled features are a plain 404
+    if telemetry {
+        cfg.route("/telemetry", web::get().to(telemetry_handler));
+    }
+    if playground {
+        cfg.route("/playground", web::get().to(playground_handler));
+    }
+    // Unknown routes answer in the error schema of the others
+    cfg.default_service(web::to(|req: HttpRequest| async move {
+        ApiError::not_found(format!("No route {} {}.", req.method(), req.path())).into_response()
+    }));
+}
@@ -0,0 +1,319 @@
+// External crates
+use log::kv::{self, Key, VisitSource};
+use log::{LevelFilter, Log, Metadata, Record};
+use regex::Regex;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+
+// Standard library
+use std::borrow::Cow;
+use std::io::Write;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, OnceLock, RwLock};
+
+// Internal modules
+use crate::shared::now_ms;
+
+// -----------------------------------------------------------------------------
+// Runtime Logging
+// -----------------------------------------------------------------------------
+
+// Target of the request traces of a debug scope, logged whatever the filters say
+pub const DEBUG_TARGET: &str = "vllm_middleware::debug";
+
+// Bytes of request and response bodies included in traces
+const TRACE_BODY_BYTES: usize = 4096;
+
+static LOGGER: OnceLock<DynamicLogger> = OnceLock::new();
+
+// How records are written to stderr
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum LogFormat {
+    // env_logger's default lines
+    #[default]
+    Text,
+    // One JSON object per line, for log collectors, with the fields of a record (model,
+    // endpoint, group, status, latency_ms, ...) as keys of their own
+    Json,
+}
+
+static FORMAT: OnceLock<LogFormat> = OnceLock::new();
+
+// env_logger with filters that can be replaced while running
+struct DynamicLogger {
+    inner: RwLock<env_logger::Logger>,
+    // The filters `inner` was built from, env_logger syntax
+    filters: Mutex<String>,
+    // Writes the traces of debug scopes, which only traced requests log
+    traces: env_logger::Logger,
+}
+
+impl Log for DynamicLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.target() == DEBUG_TARGET || self.inner.read().unwrap().enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        let inner = self.inner.read().unwrap();
+        let logger = if record.target() == DEBUG_TARGET { &self.traces } else { &*inner };
+        // Records the filters drop are neither formatted nor redacted
+        if !logger.enabled(record.metadata()) {
+            return;
+        }
+        // Secrets never reach the output, whatever logged them and at whichever level
+        let msg = record.args().to_string();
+        let msg = redact(&msg);
+        logger.log(&record.to_builder().args(format_args!("{}", msg)).build());
+    }
+
+    fn flush(&self) {
+        self.inner.read().unwrap().flush();
+    }
+}
+
+// Install the logger with `filters`, those of RUST_LOG if None (error only if unset)
+pub fn init(filters: Option<String>, format: LogFormat) {
+    let _ = FORMAT.set(format);
+    let filters = filters.unwrap_or_else(|| std::env::var("RUST_LOG").unwrap_or_default());
+    let logger = LOGGER.get_or_init(|| DynamicLogger {
+        inner: RwLock::new(build(&filters)),
+        filters: Mutex::new(filters),
+        traces: build("info"),
+    });
+    if log::set_logger(logger).is_ok() {
+        update_max_level(logger);
+    }
+}
+
+// Replace the filters, e.g. `info,vllm_middleware::routing=debug`
+pub fn set_filters(filters: &str) -> Result<(), String> {
+    validate_filters(filters)?;
+    let Some(logger) = LOGGER.get() else {
+        return Err("Logging is not initialized.".to_string());
+    };
+    *logger.inner.write().unwrap() = build(filters);
+    *logger.filters.lock().unwrap() = filters.to_string();
+    update_max_level(logger);
+    Ok(())
+}
+
+// The active filters
+pub fn filters() -> String {
+    LOGGER.get().map(|logger| logger.filters.lock().unwrap().clone()).unwrap_or_default()
+}
+
+fn build(filters: &str) -> env_logger::Logger {
+    let mut builder = env_logger::Builder::new();
+    builder.parse_filters(filters);
+    if FORMAT.get() == Some(&LogFormat::Json) {
+        builder.format(|buf, record| {
+            let mut line = Map::new();
+            line.insert("ts".to_string(), json!(buf.timestamp_millis().to_string()));
+            line.insert("level".to_string(), json!(record.level().as_str()));
+            line.insert("target".to_string(), json!(record.target()));
+            line.insert("msg".to_string(), json!(record.args().to_string()));
+            let _ = record.key_values().visit(&mut JsonFields(&mut line));
+            writeln!(buf, "{}", Value::Object(line))
+        });
+    }
+    builder.build()
+}
+
+// Fields of a record as keys of its JSON line, next to those every line has
+struct JsonFields<'a>(&'a mut Map<String, Value>);
+
+impl<'kvs> VisitSource<'kvs> for JsonFields<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: kv::Value<'kvs>) -> Result<(), kv::Error> {
+        let value = if let Some(n) = value.to_u64() {
+            json!(n)
+        } else if let Some(n) = value.to_i64() {
+            json!(n)
+        } else if let Some(b) = value.to_bool() {
+            json!(b)
+        } else {
+            json!(redact(&value.to_string()))
+        };
+        self.0.entry(key.as_str().to_string()).or_insert(value);
+        Ok(())
+    }
+}
+
+// Whether a debug scope is active. Traces are info records, which must then reach the logger
+// even if the filters are stricter.
+static TRACING: AtomicBool = AtomicBool::new(false);
+
+fn update_max_level(logger: &DynamicLogger) {
+    let level = logger.inner.read().unwrap().filter();
+    let level = if TRACING.load(Ordering::Relaxed) { level.max(LevelFilter::Info) } else { level };
+    log::set_max_level(level);
+}
+
+// Let trace records through while a debug scope is active
+fn set_tracing(tracing: bool) {
+    if TRACING.swap(tracing, Ordering::Relaxed) != tracing
+        && let Some(logger) = LOGGER.get()
+    {
+        update_max_level(logger);
+    }
+}
+
+// env_logger skips directives it can't parse with a note on stderr; reject them instead
+fn validate_filters(filters: &str) -> Result<(), String> {
+    // A `/regex` suffix filters messages, the directives come before it
+    let directives = filters.split('/').next().unwrap_or_default();
+    for directive in directives.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+        let level = match directive.split_once('=') {
+            Some((module, level)) if !module.trim().is_empty() => level.trim(),
+            Some(_) => return Err(format!("Invalid log directive `{}`", directive)),
+            // A bare module name enables all of its levels
+            None if LevelFilter::from_str(directive).is_err() => continue,
+            None => directive,
+        };
+        if LevelFilter::from_str(level).is_err() {
+            return Err(format!("Invalid log level `{}` in `{}`", level, directive));
+        }
+    }
+    Ok(())
+}
+
+// -----------------------------------------------------------------------------
+// Secret Redaction
+// -----------------------------------------------------------------------------
+
+const REDACTED: &str = "[REDACTED]";
+
+// Known values shorter than this could be ordinary words; masked by their labels only
+const MIN_SECRET_LEN: usize = 8;
+
+// Values masked in log lines
+struct KnownSecrets {
+    // Passwords, keys and endpoint tokens the middleware held since it started
+    held: Vec<String>,
+    // Plaintext tokens of the active secrets.yaml, replaced on every load
+    tokens: Vec<String>,
+    // Both, longest first
+    all: Vec<String>,
+}
+
+impl KnownSecrets {
+    fn sort(&mut self) {
+        self.all = self.held.iter().chain(&self.tokens).cloned().collect();
+        self.all.sort_by_key(|known| std::cmp::Reverse(known.len()));
+        self.all.dedup();
+    }
+}
+
+static SECRETS: RwLock<KnownSecrets> =
+    RwLock::new(KnownSecrets { held: Vec::new(), tokens: Vec::new(), all: Vec::new() });
+
+static PATTERNS: OnceLock<Vec<(Regex, &'static str)>> = OnceLock::new();
+
+// Mask a secret wherever it appears in log lines from now on. Values are never forgotten,
+// so rotated endpoint tokens and passwords stay masked as well.
+pub fn redact_secret(secret: &str) {
+    if secret.len() < MIN_SECRET_LEN || SECRETS.read().unwrap().held.iter().any(|known| known == secret) {
+        return;
+    }
+    let mut secrets = SECRETS.write().unwrap();
+    if !secrets.held.iter().any(|known| known == secret) {
+        secrets.held.push(secret.to_string());
+        secrets.sort();
+    }
+}
+
+// Mask the plaintext tokens of the active secrets instead of those of the previous load, so
+// revoked tokens are no longer searched for in every line
+pub fn redact_tokens<'a>(tokens: impl IntoIterator<Item = &'a str>) {
+    let mut tokens: Vec<String> =
+        tokens.into_iter().filter(|token| token.len() >= MIN_SECRET_LEN).map(str::to_string).collect();
+    tokens.sort();
+    tokens.dedup();
+    let mut secrets = SECRETS.write().unwrap();
+    if secrets.tokens != tokens {
+        secrets.tokens = tokens;
+        secrets.sort();
+    }
+}
+
+// A log line without secrets: known values, bearer tokens, values labelled as tokens,
+// passwords or keys (`access_token: "..."`, `"token":"..."`, `password=...`) and the
+// credentials of URLs
+pub fn redact(line: &str) -> Cow<'_, str> {
+    let mut line = Cow::Borrowed(line);
+    let patterns = PATTERNS.get_or_init(|| {
+        [
+            (r#"(?i)\b(bearer\s+)[^\s"',;]+"#, "${1}[REDACTED]"),
+            (
+                r#"(?i)\b((?:access_)?token|password|secret|api[_-]?key|authorization)("?\s*[:=]\s*"?)[^\s"',;&}\]]+"#,
+                "${1}${2}[REDACTED]",
+            ),
+            (r"(?i)\b([a-z][a-z0-9+.-]*://)[^/\s:@]*:[^/\s@]+@", "${1}[REDACTED]@"),
+        ]
+        .into_iter()
+        .map(|(pattern, replacement)| (Regex::new(pattern).unwrap(), replacement))
+        .collect()
+    });
+    for (pattern, replacement) in patterns {
+        if let Cow::Owned(replaced) = pattern.replace_all(&line, *replacement) {
+            line = Cow::Owned(replaced);
+        }
+    }
+    // After the patterns, which would mask the mask again
+    for secret in SECRETS.read().unwrap().all.iter() {
+        if line.contains(secret.as_str()) {
+            line = Cow::Owned(line.replace(secret.as_str(), REDACTED));
+        }
+    }
+    line
+}
+
+// Requests to these models or endpoints are traced until `until_ms`
+#[derive(Debug, Serialize, Clone)]
+pub struct DebugScope {
+    pub models: Vec<String>,
+    pub endpoints: Vec<String>,
+    pub until_ms: u64,
+}
+
+// Targeted request traces set via PUT /admin/logging
+#[derive(Default)]
+pub struct DebugTraces {
+    scope: Mutex<Option<DebugScope>>,
+}
+
+impl DebugTraces {
+    pub fn set(&self, scope: Option<DebugScope>) {
+        set_tracing(scope.is_some());
+        *self.scope.lock().unwrap() = scope;
+    }
+
+    // The active scope, None once it ran out
+    pub fn scope(&self) -> Option<DebugScope> {
+        let mut scope = self.scope.lock().unwrap();
+        if scope.as_ref().is_some_and(|scope| now_ms() >= scope.until_ms) {
+            *scope = None;
+            set_tracing(false);
+        }
+        scope.clone()
+    }
+
+    // Whether requests for the model to the endpoint are traced
+    pub fn traces(&self, model: &str, endpoint: &str) -> bool {
+        self.scope().is_some_and(|scope| {
+            scope.models.iter().any(|m| m == model) || scope.endpoints.iter().any(|e| e == endpoint)
+        })
+    }
+}
+
+// The start of a body for a trace
+pub fn trace_body(body: &str) -> &str {
+    if body.len() <= TRACE_BODY_BYTES {
+        return body;
+    }
+    let mut end = TRACE_BODY_BYTES;
+    while !body.is_char_boundary(end) {
+        end -= 1;
+    }
+    &body[..end]
+}
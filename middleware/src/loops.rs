@@ -0,0 +1,167 @@
+// External crates
+use log::{info, warn};
+use serde_json::{json, Value};
+
+// Standard library
+use std::collections::{HashMap, VecDeque};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// -----------------------------------------------------------------------------
+// Agent Loop Detection
+// -----------------------------------------------------------------------------
+
+// Prompts remembered per token, bounds memory for very busy tokens
+const MAX_RECENT_PER_TOKEN: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LoopAction {
+    // Reject repeats with 429 while the loop lasts
+    Throttle,
+    // Only log and notify the webhook
+    Flag,
+}
+
+pub enum LoopVerdict {
+    Pass,
+    // The prompt was repeated `repeats` times within the window
+    Loop { repeats: usize, throttle: bool },
+}
+
+// Spots tokens sending the same prompt over and over, as misconfigured agent frameworks do
+// when they spin on a failing prompt
+pub struct LoopDetector {
+    // Repeats within the window that count as a loop, 0 disables detection
+    threshold: usize,
+    pub window: Duration,
+    action: LoopAction,
+    webhook_url: Option<String>,
+    // Auth token -> (prompt hash, time) of recent requests
+    recent: Mutex<HashMap<String, VecDeque<(u64, Instant)>>>,
+    // (auth token, prompt hash) -> loops already reported, so each is reported once
+    reported: Mutex<HashMap<(String, u64), Instant>>,
+}
+
+impl LoopDetector {
+    // VLLM_COMPOSER_LOOP_THRESHOLD, _WINDOW_SECS, _ACTION ("throttle" / "flag"), _WEBHOOK_URL
+    pub fn from_env() -> Self {
+        let env_u64 = |name: &str, default: u64| {
+            std::env::var(name)
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(default)
+        };
+        let action = match std::env::var("VLLM_COMPOSER_LOOP_ACTION").as_deref() {
+            Ok("flag") => LoopAction::Flag,
+            _ => LoopAction::Throttle,
+        };
+        let detector = LoopDetector {
+            threshold: env_u64("VLLM_COMPOSER_LOOP_THRESHOLD", 0) as usize,
+            window: Duration::from_secs(env_u64("VLLM_COMPOSER_LOOP_WINDOW_SECS", 60)),
+            action,
+            webhook_url: std::env::var("VLLM_COMPOSER_LOOP_WEBHOOK_URL").ok().filter(|s| !s.is_empty()),
+            recent: Mutex::new(HashMap::new()),
+            reported: Mutex::new(HashMap::new()),
+        };
+        if detector.threshold > 0 {
+            info!(
+                "Agent loop detection: {} repeats within {:?}, action {:?}",
+                detector.threshold, detector.window, detector.action
+            );
+        }
+        detector
+    }
+
+    // Record a request of `token` and judge whether it is part of a loop
+    pub fn observe(&self, token: &str, body: &Value) -> LoopVerdict {
+        if self.threshold == 0 {
+            return LoopVerdict::Pass;
+        }
+        let hash = prompt_hash(body);
+        let now = Instant::now();
+
+        let repeats = {
+            let mut recent = self.recent.lock().unwrap();
+            let history = recent.entry(token.to_string()).or_default();
+            while history.front().is_some_and(|(_, t)| now.duration_since(*t) >= self.window) {
+                history.pop_front();
+            }
+            if history.len() >= MAX_RECENT_PER_TOKEN {
+                history.pop_front();
+            }
+            history.push_back((hash, now));
+            history.iter().filter(|(h, _)| *h == hash).count()
+        };
+        if repeats < self.threshold {
+            return LoopVerdict::Pass;
+        }
+        LoopVerdict::Loop {
+            repeats,
+            throttle: self.action == LoopAction::Throttle,
+        }
+    }
+
+    // Payload for the webhook if this loop has not been reported within the window
+    pub fn report(&self, token: &str, body: &Value, groups: &[String], repeats: usize) -> Option<(String, Value)> {
+        let hash = prompt_hash(body);
+        let now = Instant::now();
+        {
+            let mut reported = self.reported.lock().unwrap();
+            reported.retain(|_, t| now.duration_since(*t) < self.window);
+            if reported.contains_key(&(token.to_string(), hash)) {
+                return None;
+            }
+            reported.insert((token.to_string(), hash), now);
+        }
+        warn!(
+            "Agent loop: token of groups {:?} sent the same prompt {} times within {:?}",
+            groups, repeats, self.window
+        );
+        let url = self.webhook_url.clone()?;
+        Some((
+            url,
+            json!({
+                "event": "agent_loop",
+                "token_fingerprint": format!("{:016x}", hash_of(token)),
+                "groups": groups,
+                "model": body.get("model"),
+                "repeats": repeats,
+                "window_secs": self.window.as_secs(),
+                "action": if self.action == LoopAction::Throttle { "throttle" } else { "flag" },
+            }),
+        ))
+    }
+}
+
+fn hash_of(value: impl Hash) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+// Hash of the model and prompt, ignoring case and whitespace so near-identical
+// prompts (e.g. a changed trailing newline) collide
+fn prompt_hash(body: &Value) -> u64 {
+    let mut text = String::new();
+    for field in ["messages", "prompt", "input"] {
+        if let Some(value) = body.get(field) {
+            collect_strings(value, &mut text);
+        }
+    }
+    let normalized = text.to_lowercase().split_whitespace().collect::<Vec<_>>().join(" ");
+    hash_of((body.get("model").and_then(Value::as_str), normalized))
+}
+
+// All string leaves of a JSON value, separated by spaces
+fn collect_strings(value: &Value, out: &mut String) {
+    match value {
+        Value::String(s) => {
+            out.push_str(s);
+            out.push(' ');
+        }
+        Value::Array(items) => items.iter().for_each(|v| collect_strings(v, out)),
+        Value::Object(map) => map.values().for_each(|v| collect_strings(v, out)),
+        _ => {}
+    }
+}
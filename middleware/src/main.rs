@@ -1,107 +1,267 @@
 // External crates
-use actix_web::{web, App, HttpServer};
-use log::{debug, info};
+use actix_web::{App, HttpServer};
+use clap::Parser;
+use log::{debug, info, warn};
 
 // Standard library
-use std::collections::HashMap;
+use std::fs;
 use std::io;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::os::unix::net::UnixListener;
+use std::path::Path;
 use std::sync::{Arc, Mutex};
 
 // Internal modules
-mod auth;
-use auth::AuthMiddleware;
-
-mod routes;
-use routes::{
-    endpoints_handler,
-    health_status_handler,
-    reload_handler,
-    health_handler,
-    models_handler,
-    model_to_endpoints_handler,
-    chat_completions_handler,
-    embeddings_handler,
-    chat_completions_handler_legacy,
+use vllm_middleware::access_log::AccessLog;
+use vllm_middleware::auth::{self, AuthMiddleware};
+use vllm_middleware::canaries;
+use vllm_middleware::cli::{Cli, Command};
+use vllm_middleware::discovery::consul::{self, ConsulConfig};
+use vllm_middleware::discovery::dns;
+use vllm_middleware::discovery::docker::{self, DockerConfig};
+use vllm_middleware::discovery::etcd::{self, EtcdConfig};
+use vllm_middleware::discovery::kubernetes::{self, KubernetesConfig};
+use vllm_middleware::discovery::registration;
+use vllm_middleware::gc;
+use vllm_middleware::logging;
+use vllm_middleware::monitoring::{spawn_monitor, sync_shared_state};
+use vllm_middleware::otel;
+use vllm_middleware::reload;
+use vllm_middleware::reports::{self, ReportConfig};
+use vllm_middleware::routes::playground_enabled;
+use vllm_middleware::routing::RoutingStrategy;
+use vllm_middleware::shared::SharedStore;
+use vllm_middleware::shutdown;
+use vllm_middleware::state::{
+    AppState, load_auth_tokens_from_yaml, load_endpoints_from_yaml, set_config_paths,
 };
-
-mod state;
-use state::{
-    AppState,
-    load_endpoints_from_yaml,
-    load_auth_tokens_from_yaml,
-    partition_endpoints,
-};
-
-mod monitoring;
-use monitoring::monitor_endpoint;
+use vllm_middleware::tls::{self, ServerCert};
+use vllm_middleware::token_expiry;
+use vllm_middleware::validate::validate_config;
 
 // -----------------------------------------------------------------------------
 // Main
 // -----------------------------------------------------------------------------
 #[actix_web::main]
 async fn main() -> io::Result<()> {
-    env_logger::init();
+    let cli = Cli::parse();
+
+    // `vllm_middleware hash-token <token>` prints the hashed entry for secrets.yaml
+    if let Some(Command::HashToken { token }) = &cli.command {
+        println!("{}", auth::hash_token(token)?);
+        return Ok(());
+    }
+
+    // `vllm_middleware validate-config` checks the config files, e.g. in a deployment pipeline
+    if let Some(Command::ValidateConfig) = &cli.command {
+        match validate_config(&cli.endpoints_file, &cli.secrets_file) {
+            Ok(summary) => {
+                println!(
+                    "Config is valid: {} endpoints, {} groups, {} tokens",
+                    summary.endpoints, summary.groups, summary.tokens
+                );
+                return Ok(());
+            }
+            Err(issues) => {
+                for issue in &issues {
+                    eprintln!("{}", issue);
+                }
+                eprintln!("{} errors in the config files", issues.len());
+                std::process::exit(1);
+            }
+        }
+    }
+
+    logging::init(cli.log_level.clone(), cli.log_format);
     debug!("Logger activated.");
     info!("vllm_middleware started.");
 
-    // Load initial endpoints
-    let all_endpoints = load_endpoints_from_yaml().unwrap_or_else(|_| Vec::new());
-    let (gen_initial, emb_initial) = partition_endpoints(all_endpoints);
+    // Optional OpenTelemetry traces, flushed on shutdown
+    let tracer_provider = otel::init();
 
-    // Load auth tokens
-    let auth_tokens = load_auth_tokens_from_yaml().unwrap_or_else(|_| HashMap::new());
+    set_config_paths(cli.endpoints_file.clone(), cli.secrets_file.clone());
 
-    // Construct state
-    let state = Arc::new(AppState {
-        endpoints_generate: Mutex::new(gen_initial.clone()),
-        health_status_generate: Mutex::new(HashMap::new()),
-        endpoint_models_generate: Mutex::new(HashMap::new()),
-        model_to_endpoints_generate: Mutex::new(HashMap::new()),
-
-        endpoints_embed: Mutex::new(emb_initial.clone()),
-        health_status_embed: Mutex::new(HashMap::new()),
-        endpoint_models_embed: Mutex::new(HashMap::new()),
-        model_to_endpoints_embed: Mutex::new(HashMap::new()),
-
-        auth_tokens: Mutex::new(auth_tokens),
+    // Optional HTTPS, the certificate is reloaded when it is rotated
+    let server_cert = match (&cli.tls_cert, &cli.tls_key) {
+        (Some(cert), Some(key)) => Some(Arc::new(ServerCert::load(cert, key).map_err(io::Error::other)?)),
+        _ => None,
+    };
+    if server_cert.is_some() && cli.unix_socket().is_some() {
+        return Err(io::Error::other("TLS is not served on Unix domain sockets"));
+    }
+
+    // Load initial endpoints and auth tokens; the server starts without them, but isn't ready
+    // until a reload succeeds
+    let mut config_error = None;
+    let all_endpoints = load_endpoints_from_yaml().unwrap_or_else(|e| {
+        warn!("Failed to load endpoints: {}", e);
+        config_error = Some(format!("Failed to load endpoints: {}", e));
+        Vec::new()
+    });
+    let auth_config = load_auth_tokens_from_yaml().unwrap_or_else(|e| {
+        warn!("Failed to load auth tokens: {}", e);
+        config_error = Some(format!("Failed to load auth tokens: {}", e));
+        Default::default()
     });
 
-    // Spawn monitors for both sets
-    for endpoint in gen_initial {
-        let state_clone = Arc::clone(&state);
-        tokio::spawn(async move {
-            monitor_endpoint(endpoint, state_clone).await;
-        });
+    // Optional shared state for multiple replicas
+    let shared = match std::env::var("VLLM_COMPOSER_REDIS_URL") {
+        Ok(url) if !url.is_empty() => match SharedStore::redis(&url) {
+            Ok(store) => {
+                info!("Sharing state via Redis.");
+                store
+            }
+            Err(e) => {
+                warn!("Invalid Redis URL, using in-memory state: {}", e);
+                SharedStore::disabled()
+            }
+        },
+        _ => SharedStore::disabled(),
+    };
+
+    // Construct state
+    let mut state = AppState::new(all_endpoints, auth_config, shared);
+    state.config_error = Mutex::new(config_error);
+    let timeouts = cli.timeouts(state.http.timeouts);
+    if timeouts != state.http.timeouts {
+        info!("Upstream timeouts from the command line: {:?}", timeouts);
+        state.http.timeouts = timeouts;
+    }
+    let state = Arc::new(state);
+    if let Some(db) = &state.usage_db {
+        state.budgets.load(db);
     }
-    for endpoint in emb_initial {
+
+    if matches!(state.routing_strategy, RoutingStrategy::BackendLoad(_))
+        && state.monitor_intervals.metrics_scrape.is_none()
+    {
+        warn!("Backend load routing needs VLLM_COMPOSER_METRICS_SCRAPE_SECS, routing by local load only");
+    }
+
+    // Spawn monitors for all tasks, DNS templates are expanded by their own task
+    for endpoint in state.all_endpoints() {
+        spawn_monitor(endpoint, Arc::clone(&state));
+    }
+    let dns_templates = state.dns_templates.lock().unwrap().clone();
+    dns::log_templates(&dns_templates);
+    tokio::spawn(dns::run(Arc::clone(&state)));
+
+    // Optional dynamic endpoints from Kubernetes or a service registry
+    if let Some(config) = KubernetesConfig::from_env() {
+        tokio::spawn(kubernetes::run(config, Arc::clone(&state)));
+    }
+    if let Some(config) = DockerConfig::from_env() {
+        tokio::spawn(docker::run(config, Arc::clone(&state)));
+    }
+    if let Some(config) = ConsulConfig::from_env() {
+        tokio::spawn(consul::run(config, Arc::clone(&state)));
+    }
+    if let Some(config) = EtcdConfig::from_env() {
+        tokio::spawn(etcd::run(config, Arc::clone(&state)));
+    }
+    tokio::spawn(registration::run(Arc::clone(&state)));
+
+    // Optional reloads on changes to the config files
+    tokio::spawn(reload::watch(Arc::clone(&state)));
+
+    // Drops state left behind by removed endpoints
+    tokio::spawn(gc::run(Arc::clone(&state)));
+
+    // Warnings about tokens close to their expiry
+    tokio::spawn(token_expiry::run(Arc::clone(&state)));
+
+    // Optional scheduled usage reports
+    if let Some(config) = ReportConfig::from_env() {
+        tokio::spawn(reports::run(config, Arc::clone(&state)));
+    }
+
+    if state.shared.is_enabled() {
         let state_clone = Arc::clone(&state);
         tokio::spawn(async move {
-            monitor_endpoint(endpoint, state_clone).await;
+            sync_shared_state(state_clone).await;
         });
     }
 
-    // Get port from command line arguments or default to 8080
-    let port: u16 = std::env::args()
-        .nth(1)
-        .and_then(|arg| arg.parse().ok())
-        .unwrap_or(8080);
-    let bind_address = format!("0.0.0.0:{}", port);
+    let bind_address = cli.bind_address();
+
+    // Optional canary prompts, sent to this server like any client would
+    if !state.canaries.canaries.is_empty() {
+        match cli.local_url() {
+            Some(url) => {
+                tokio::spawn(canaries::run(Arc::clone(&state), url));
+            }
+            None => warn!("Canaries need a TCP port, they don't run on a Unix domain socket"),
+        }
+    }
+    let playground = playground_enabled();
+    if playground {
+        info!("Serving the playground at /playground");
+    }
+
+    let access_log = match &cli.access_log {
+        Some(target) => {
+            info!("Writing the access log to {}", if target == "-" { "stdout" } else { target });
+            AccessLog::open(target)
+                .map_err(|e| io::Error::other(format!("Failed to open the access log {}: {}", target, e)))?
+        }
+        None => AccessLog::disabled(),
+    };
 
-    HttpServer::new(move || {
+    // Signals are handled by `shutdown`, which lets streams finish
+    let app_state = Arc::clone(&state);
+    let server = HttpServer::new(move || {
         App::new()
             .wrap(AuthMiddleware)
-            .app_data(web::Data::new(state.clone()))
-            .route("/endpoints", web::get().to(endpoints_handler))
-            .route("/reload", web::get().to(reload_handler))
-            .route("/health-status", web::get().to(health_status_handler))
-            .route("/v1/models", web::get().to(models_handler))
-            .route("/model-to-endpoints", web::get().to(model_to_endpoints_handler))
-            .route("/health", web::get().to(health_handler))
-            .route("/v1/chat/completions", web::post().to(chat_completions_handler))
-            .route("/v1/embeddings", web::post().to(embeddings_handler))
-            .route("/v1/completions", web::get().to(chat_completions_handler_legacy))
+            .wrap(access_log.clone())
+            .configure(|cfg| vllm_middleware::configure(cfg, Arc::clone(&app_state), playground))
     })
-    .bind(bind_address)?
-    .run()
-    .await
-}
\ No newline at end of file
+    .disable_signals()
+    // A client closing its side of the connection has gone away; its handler is dropped,
+    // and with it the upstream request
+    .h1_allow_half_closed(false)
+    .shutdown_timeout(state.shutdown.deadline.as_secs());
+    let server = match server_cert {
+        None if let Some(path) = cli.unix_socket() => {
+            let listener = bind_unix_socket(path, cli.socket_mode)?;
+            info!("Listening on {}", cli.bind);
+            server.listen_uds(listener)?
+        }
+        Some(cert) => {
+            let config = cert.server_config().map_err(io::Error::other)?;
+            tokio::spawn(tls::watch(cert));
+            info!("Listening on {} with TLS", bind_address);
+            server.bind_rustls_0_23(bind_address, config)?
+        }
+        None => {
+            info!("Listening on {}", bind_address);
+            server.bind(bind_address)?
+        }
+    };
+    let server = server.run();
+    state.shutdown.set_server(server.handle());
+    tokio::spawn(shutdown::on_signals(state));
+    let result = server.await;
+    if let Some(provider) = tracer_provider {
+        // Off the runtime, which the exporter still needs to send the last spans
+        let flushed = tokio::task::spawn_blocking(move || provider.shutdown()).await;
+        if let Ok(Err(e)) = flushed {
+            warn!("Failed to export the last traces: {}", e);
+        }
+    }
+    result
+}
+
+// Bind the socket, replacing one left behind by an earlier run
+fn bind_unix_socket(path: &Path, mode: Option<u32>) -> io::Result<UnixListener> {
+    if let Ok(metadata) = fs::symlink_metadata(path) {
+        if !metadata.file_type().is_socket() {
+            return Err(io::Error::other(format!("{} exists and is not a socket", path.display())));
+        }
+        fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    if let Some(mode) = mode {
+        fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+    }
+    Ok(listener)
+}
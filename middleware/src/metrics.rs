@@ -0,0 +1,255 @@
+// External crates
+use prometheus::{
+    CounterVec, Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts, Registry,
+    TextEncoder,
+};
+
+// Internal modules
+use crate::state::AppState;
+
+// -----------------------------------------------------------------------------
+// Metrics
+// -----------------------------------------------------------------------------
+
+const NAMESPACE: &str = "vllm_composer";
+
+// Upstream latencies range from fast embeddings to long generations
+const LATENCY_BUCKETS: [f64; 14] = [
+    0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0,
+];
+
+pub struct Metrics {
+    registry: Registry,
+    // task, model, endpoint, mode ("stream" / "non_stream"), status (HTTP code or "error")
+    pub requests: IntCounterVec,
+    // task, endpoint, kind ("connect", "timeout", "request", "upstream_5xx", "stream",
+    // "malformed_stream")
+    pub upstream_errors: IntCounterVec,
+    // task, model, endpoint
+    pub requests_cancelled: IntCounterVec,
+    // task, endpoint, mode; until response headers for streams, until the full body otherwise
+    pub upstream_latency: HistogramVec,
+    // task, endpoint, result ("healthy" / "unhealthy")
+    pub health_checks: IntCounterVec,
+    // task, endpoint
+    pub health_check_latency: HistogramVec,
+    // task, endpoint, result ("pass" / "fail")
+    pub deep_checks: IntCounterVec,
+    // task, endpoint
+    pub deep_check_latency: HistogramVec,
+    // task, endpoint; refreshed from AppState on every scrape
+    endpoint_healthy: IntGaugeVec,
+    // model, endpoint, reason ("stop", "length", "tool_calls", ...)
+    pub finish_reasons: IntCounterVec,
+    // model, endpoint; 1 while the endpoint truncates unusually often
+    pub truncation_anomaly: IntGaugeVec,
+    // canary, model, result ("pass" / "fail")
+    pub canary_checks: IntCounterVec,
+    // canary, model; 1 while the canary's last run passed
+    pub canary_passing: IntGaugeVec,
+    // status ("expiring" / "expired"); tokens within the expiry warning window
+    pub tokens_by_expiry: IntGaugeVec,
+    // group, model, kind ("prompt" / "completion"); usage reported by the endpoints
+    pub tokens: IntCounterVec,
+    // group, model; usage by the prices in secrets.yaml, for models with a price
+    pub cost: CounterVec,
+    // task, zone; requests routed out of the composer's zone
+    pub zone_spills: IntCounterVec,
+    // map; refreshed from AppState on every scrape
+    state_map_entries: IntGaugeVec,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let registry = Registry::new();
+        let requests = IntCounterVec::new(
+            Opts::new("requests_total", "Proxied requests by model, endpoint, mode and status")
+                .namespace(NAMESPACE),
+            &["task", "model", "endpoint", "mode", "status"],
+        )
+        .unwrap();
+        let upstream_errors = IntCounterVec::new(
+            Opts::new("upstream_errors_total", "Failed upstream requests by kind").namespace(NAMESPACE),
+            &["task", "endpoint", "kind"],
+        )
+        .unwrap();
+        let requests_cancelled = IntCounterVec::new(
+            Opts::new("requests_cancelled_total", "Requests whose client disconnected before they completed")
+                .namespace(NAMESPACE),
+            &["task", "model", "endpoint"],
+        )
+        .unwrap();
+        let upstream_latency = HistogramVec::new(
+            HistogramOpts::new("upstream_latency_seconds", "Latency of upstream requests")
+                .namespace(NAMESPACE)
+                .buckets(LATENCY_BUCKETS.to_vec()),
+            &["task", "endpoint", "mode"],
+        )
+        .unwrap();
+        let health_checks = IntCounterVec::new(
+            Opts::new("health_checks_total", "Health checks by result").namespace(NAMESPACE),
+            &["task", "endpoint", "result"],
+        )
+        .unwrap();
+        let health_check_latency = HistogramVec::new(
+            HistogramOpts::new("health_check_latency_seconds", "Latency of health checks")
+                .namespace(NAMESPACE)
+                .buckets(LATENCY_BUCKETS.to_vec()),
+            &["task", "endpoint"],
+        )
+        .unwrap();
+        let deep_checks = IntCounterVec::new(
+            Opts::new("deep_checks_total", "Synthetic inference checks by result").namespace(NAMESPACE),
+            &["task", "endpoint", "result"],
+        )
+        .unwrap();
+        let deep_check_latency = HistogramVec::new(
+            HistogramOpts::new("deep_check_latency_seconds", "Latency of synthetic inference checks")
+                .namespace(NAMESPACE)
+                .buckets(LATENCY_BUCKETS.to_vec()),
+            &["task", "endpoint"],
+        )
+        .unwrap();
+        let endpoint_healthy = IntGaugeVec::new(
+            Opts::new("endpoint_healthy", "Whether the endpoint's health state lets requests through")
+                .namespace(NAMESPACE),
+            &["task", "endpoint"],
+        )
+        .unwrap();
+
+        let finish_reasons = IntCounterVec::new(
+            Opts::new("finish_reasons_total", "Finish reasons of generated choices").namespace(NAMESPACE),
+            &["model", "endpoint", "reason"],
+        )
+        .unwrap();
+        let truncation_anomaly = IntGaugeVec::new(
+            Opts::new(
+                "truncation_anomaly",
+                "Whether the endpoint hits the length limit far more often than the model's other endpoints",
+            )
+            .namespace(NAMESPACE),
+            &["model", "endpoint"],
+        )
+        .unwrap();
+
+        let canary_checks = IntCounterVec::new(
+            Opts::new("canary_checks_total", "Runs of synthetic canary prompts by result").namespace(NAMESPACE),
+            &["canary", "model", "result"],
+        )
+        .unwrap();
+        let canary_passing = IntGaugeVec::new(
+            Opts::new("canary_passing", "Whether the canary's last run got the expected answer")
+                .namespace(NAMESPACE),
+            &["canary", "model"],
+        )
+        .unwrap();
+
+        let tokens_by_expiry = IntGaugeVec::new(
+            Opts::new("tokens_by_expiry", "Tokens in secrets.yaml about to expire or expired").namespace(NAMESPACE),
+            &["status"],
+        )
+        .unwrap();
+
+        let tokens = IntCounterVec::new(
+            Opts::new("tokens_total", "Prompt and completion tokens used by group and model").namespace(NAMESPACE),
+            &["group", "model", "kind"],
+        )
+        .unwrap();
+        let cost = CounterVec::new(
+            Opts::new("cost_total", "Cost of the tokens used by group and model").namespace(NAMESPACE),
+            &["group", "model"],
+        )
+        .unwrap();
+        let zone_spills = IntCounterVec::new(
+            Opts::new("zone_spills_total", "Requests routed to another zone by task and zone").namespace(NAMESPACE),
+            &["task", "zone"],
+        )
+        .unwrap();
+
+        let state_map_entries = IntGaugeVec::new(
+            Opts::new("state_map_entries", "Entries in the composer's per-endpoint and per-model maps")
+                .namespace(NAMESPACE),
+            &["map"],
+        )
+        .unwrap();
+
+        registry.register(Box::new(requests.clone())).unwrap();
+        registry.register(Box::new(upstream_errors.clone())).unwrap();
+        registry.register(Box::new(requests_cancelled.clone())).unwrap();
+        registry.register(Box::new(upstream_latency.clone())).unwrap();
+        registry.register(Box::new(health_checks.clone())).unwrap();
+        registry.register(Box::new(health_check_latency.clone())).unwrap();
+        registry.register(Box::new(deep_checks.clone())).unwrap();
+        registry.register(Box::new(deep_check_latency.clone())).unwrap();
+        registry.register(Box::new(endpoint_healthy.clone())).unwrap();
+        registry.register(Box::new(finish_reasons.clone())).unwrap();
+        registry.register(Box::new(truncation_anomaly.clone())).unwrap();
+        registry.register(Box::new(canary_checks.clone())).unwrap();
+        registry.register(Box::new(canary_passing.clone())).unwrap();
+        registry.register(Box::new(tokens_by_expiry.clone())).unwrap();
+        registry.register(Box::new(tokens.clone())).unwrap();
+        registry.register(Box::new(cost.clone())).unwrap();
+        registry.register(Box::new(zone_spills.clone())).unwrap();
+        registry.register(Box::new(state_map_entries.clone())).unwrap();
+
+        Metrics {
+            registry,
+            requests,
+            upstream_errors,
+            requests_cancelled,
+            upstream_latency,
+            health_checks,
+            health_check_latency,
+            deep_checks,
+            deep_check_latency,
+            endpoint_healthy,
+            finish_reasons,
+            truncation_anomaly,
+            canary_checks,
+            canary_passing,
+            tokens_by_expiry,
+            tokens,
+            cost,
+            zone_spills,
+            state_map_entries,
+        }
+    }
+
+    // Prometheus text exposition of all metrics
+    pub fn render(&self, state: &AppState) -> String {
+        // Rebuild the health gauges so removed endpoints disappear
+        self.endpoint_healthy.reset();
+        for (task, task_state) in state.tasks() {
+            let endpoints = task_state.endpoints.lock().unwrap().clone();
+            let health_status = task_state.health_status.lock().unwrap();
+            for endpoint in endpoints {
+                let healthy = health_status
+                    .get(&endpoint.url)
+                    .is_some_and(|hs| hs.is_routable());
+                self.endpoint_healthy
+                    .with_label_values(&[task, &endpoint.url])
+                    .set(healthy as i64);
+            }
+        }
+
+        for (map, entries) in state.map_sizes() {
+            self.state_map_entries.with_label_values(&[map]).set(entries as i64);
+        }
+
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .unwrap();
+        String::from_utf8(buffer).unwrap()
+    }
+}
+
+pub fn mode_label(stream: bool) -> &'static str {
+    if stream { "stream" } else { "non_stream" }
+}
@@ -0,0 +1,198 @@
+// External crates
+use log::info;
+use serde_json::{json, Map, Value};
+
+// -----------------------------------------------------------------------------
+// Moderation
+// -----------------------------------------------------------------------------
+
+// Guard models like Llama Guard are served by vLLM as chat models. They answer a
+// conversation with "safe", or with "unsafe" and a line of the violated categories such as
+// "S1,S10". /v1/moderations asks them about each input and answers in OpenAI's schema.
+
+// Categories of OpenAI's moderation results
+const OPENAI_CATEGORIES: [&str; 13] = [
+    "harassment",
+    "harassment/threatening",
+    "hate",
+    "hate/threatening",
+    "illicit",
+    "illicit/violent",
+    "self-harm",
+    "self-harm/instructions",
+    "self-harm/intent",
+    "sexual",
+    "sexual/minors",
+    "violence",
+    "violence/graphic",
+];
+
+// Llama Guard 3 categories with an OpenAI counterpart. The others (defamation, specialized
+// advice, privacy, intellectual property, elections, code interpreter abuse) only show in
+// `guard_categories`.
+const GUARD_CATEGORIES: [(&str, &str); 8] = [
+    ("S1", "violence"),
+    ("S2", "illicit"),
+    ("S3", "sexual"),
+    ("S4", "sexual/minors"),
+    ("S9", "illicit/violent"),
+    ("S10", "hate"),
+    ("S11", "self-harm"),
+    ("S12", "sexual"),
+];
+
+// Screening of chat completions by a guard model before they are routed
+pub struct GuardConfig {
+    // Guard model screening requests and the default of /v1/moderations, None leaves
+    // requests unscreened
+    pub model: Option<String>,
+    // Groups whose requests are screened, all if empty
+    pub groups: Vec<String>,
+}
+
+impl GuardConfig {
+    // From VLLM_COMPOSER_GUARD_MODEL and VLLM_COMPOSER_GUARD_GROUPS, a list like "student,guest"
+    pub fn from_env() -> Self {
+        let model = std::env::var("VLLM_COMPOSER_GUARD_MODEL").ok().filter(|s| !s.is_empty());
+        let groups: Vec<String> = std::env::var("VLLM_COMPOSER_GUARD_GROUPS")
+            .unwrap_or_default()
+            .split(',')
+            .map(|g| g.trim().to_string())
+            .filter(|g| !g.is_empty())
+            .collect();
+        if let Some(model) = &model {
+            if groups.is_empty() {
+                info!("Screening chat completions with guard model {}", model);
+            } else {
+                info!("Screening chat completions of groups {:?} with guard model {}", groups, model);
+            }
+        }
+        GuardConfig { model, groups }
+    }
+
+    // The guard model screening requests of a caller in `groups`
+    pub fn screens(&self, groups: &[String]) -> Option<&str> {
+        let model = self.model.as_deref()?;
+        if self.groups.is_empty() || groups.iter().any(|g| self.groups.contains(g)) {
+            Some(model)
+        } else {
+            None
+        }
+    }
+}
+
+// The texts of a moderation request's `input`: a string, a list of strings, or a list of
+// content parts, which make up one input of their text parts
+pub fn moderation_inputs(body: &Value) -> Option<Vec<String>> {
+    match body.get("input")? {
+        Value::String(text) => Some(vec![text.clone()]),
+        Value::Array(items) if items.iter().all(Value::is_string) && !items.is_empty() => {
+            Some(items.iter().filter_map(Value::as_str).map(String::from).collect())
+        }
+        Value::Array(parts) if parts.iter().all(Value::is_object) && !parts.is_empty() => {
+            Some(vec![text_of(&Value::Array(parts.clone()))])
+        }
+        _ => None,
+    }
+}
+
+// The conversation of a chat request as a guard model reads it: the text of the user and
+// assistant turns, alternating and starting with the user as its chat template requires
+pub fn guard_conversation(body: &Value) -> Vec<Value> {
+    let mut turns: Vec<(&str, String)> = Vec::new();
+    for message in body.get("messages").and_then(Value::as_array).into_iter().flatten() {
+        let role = match message.get("role").and_then(Value::as_str) {
+            Some(role @ ("user" | "assistant")) => role,
+            _ => continue,
+        };
+        let text = message.get("content").map(text_of).unwrap_or_default();
+        if text.is_empty() || (turns.is_empty() && role == "assistant") {
+            continue;
+        }
+        match turns.last_mut() {
+            Some((last, joined)) if *last == role => {
+                joined.push('\n');
+                joined.push_str(&text);
+            }
+            _ => turns.push((role, text)),
+        }
+    }
+    turns
+        .into_iter()
+        .map(|(role, text)| json!({ "role": role, "content": text }))
+        .collect()
+}
+
+// Chat completion asking the guard model about a conversation
+pub fn guard_request(model: &str, messages: Vec<Value>) -> Value {
+    json!({
+        "model": model,
+        "messages": messages,
+        "temperature": 0,
+        "max_tokens": 32,
+    })
+}
+
+// Text of a message's content, a string or a list of parts
+fn text_of(content: &Value) -> String {
+    match content {
+        Value::String(text) => text.clone(),
+        Value::Array(parts) => parts
+            .iter()
+            .filter_map(|part| part.get("text").and_then(Value::as_str))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        _ => String::new(),
+    }
+}
+
+// A guard model's answer about one input
+pub struct Verdict {
+    pub flagged: bool,
+    // Codes of the violated categories, like "S1"
+    pub categories: Vec<String>,
+}
+
+impl Verdict {
+    // From the guard model's chat completion, None if it answered something else
+    pub fn from_completion(completion: &Value) -> Option<Self> {
+        let answer = completion
+            .pointer("/choices/0/message/content")
+            .and_then(Value::as_str)?
+            .trim();
+        let mut lines = answer.lines().map(str::trim);
+        let flagged = match lines.next()?.to_ascii_lowercase().as_str() {
+            "safe" => false,
+            "unsafe" => true,
+            _ => return None,
+        };
+        let categories = lines
+            .next()
+            .unwrap_or_default()
+            .split(',')
+            .map(|code| code.trim().to_ascii_uppercase())
+            .filter(|code| !code.is_empty())
+            .collect();
+        Some(Verdict { flagged, categories })
+    }
+
+    // An entry of `results` in OpenAI's moderation response; as guard models don't score
+    // categories, the scores are 1 for violated categories and 0 otherwise
+    pub fn to_result(&self) -> Value {
+        let mut categories = Map::new();
+        let mut scores = Map::new();
+        for category in OPENAI_CATEGORIES {
+            let violated = GUARD_CATEGORIES
+                .iter()
+                .any(|(code, mapped)| *mapped == category && self.categories.iter().any(|c| c == code));
+            categories.insert(category.to_string(), Value::Bool(violated));
+            scores.insert(category.to_string(), json!(if violated { 1.0 } else { 0.0 }));
+        }
+        json!({
+            "flagged": self.flagged,
+            "categories": categories,
+            "category_scores": scores,
+            "guard_categories": self.categories,
+        })
+    }
+}
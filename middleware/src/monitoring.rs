@@ -1,32 +1,107 @@
 // External crates
-use reqwest;
-use serde_json::Value;
-use tokio::time::sleep;
+use log::{debug, info, warn};
+use serde_json::{json, Value};
+use tokio::sync::Notify;
+use tokio::time::{sleep, timeout};
 
 // Standard library
-use std::collections::HashSet;
-use std::sync::Arc;
-use std::time::Duration;
+use std::collections::HashMap;
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
 
 // Internal modules
-use crate::state::{AppState, Endpoint, EndpointHealth};
+use crate::aliases::with_aliases;
+use crate::shared::MONITOR_LEASE_RENEW_INTERVAL;
+use crate::shared::now_ms;
+use crate::health::{EndpointHealth, HealthState, Probe};
+use crate::state::{AppState, BackendMetrics, DeepCheck, Endpoint, EndpointCapabilities};
+use crate::sanitize::request_fields_from_openapi;
+use crate::vision::detect_vision;
 
 // -----------------------------------------------------------------------------
 // Monitoring
 // -----------------------------------------------------------------------------
 
-pub async fn perform_health_check(url: &str) -> bool {
-    match reqwest::get(url).await {
-        Ok(resp) => resp.status().is_success(),
-        Err(_) => false,
+const SHARED_SYNC_INTERVAL: Duration = Duration::from_secs(2);
+
+// Delay of the next deep check after a failed one, so a broken endpoint leaves routing quickly,
+// and while there is nothing to check yet
+const DEEP_CHECK_RETRY: Duration = Duration::from_secs(2);
+
+// Tasks with an API small enough to deep check
+const DEEP_CHECKED_TASKS: [&str; 3] = ["generate", "moderate", "embed"];
+
+// Cadences of the two monitor loops of every endpoint
+#[derive(Debug, Clone, Copy)]
+pub struct MonitorIntervals {
+    // Health checks run every 0.5s after a status change and slow down to this
+    pub health_max: Duration,
+    // Model lists and capabilities rarely change, they are refreshed at this interval and
+    // whenever an endpoint becomes healthy
+    pub model_refresh: Duration,
+    // vLLM's /metrics of healthy endpoints are scraped at this interval, if set
+    pub metrics_scrape: Option<Duration>,
+    // Healthy endpoints get a tiny inference request at this interval, if set
+    pub deep_check: Option<Duration>,
+}
+
+impl MonitorIntervals {
+    // From VLLM_COMPOSER_HEALTH_INTERVAL_SECS (default 10), _MODEL_REFRESH_SECS (default 60),
+    // _METRICS_SCRAPE_SECS (default 0, off) and _DEEP_CHECK_SECS (default 0, off)
+    pub fn from_env() -> Self {
+        let secs = |name: &str, default: u64| {
+            Duration::from_secs(
+                std::env::var(name)
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(default),
+            )
+        };
+        let intervals = MonitorIntervals {
+            health_max: secs("VLLM_COMPOSER_HEALTH_INTERVAL_SECS", 10).max(Duration::from_millis(500)),
+            model_refresh: secs("VLLM_COMPOSER_MODEL_REFRESH_SECS", 60).max(Duration::from_secs(1)),
+            metrics_scrape: Some(secs("VLLM_COMPOSER_METRICS_SCRAPE_SECS", 0)).filter(|d| !d.is_zero()),
+            deep_check: Some(secs("VLLM_COMPOSER_DEEP_CHECK_SECS", 0)).filter(|d| !d.is_zero()),
+        };
+        info!("Monitor intervals: {:?}", intervals);
+        intervals
     }
 }
 
-pub async fn fetch_models(endpoint: &Endpoint) -> Result<Vec<Value>, Box<dyn std::error::Error>> {
-    let client = reqwest::Client::new();
+// Probe the endpoint's /health, with its token in case a proxy in front checks it. Probes
+// that don't pass come with their error.
+pub async fn probe_health(client: &reqwest::Client, endpoint: &Endpoint) -> (Probe, Option<String>) {
+    let resp = client
+        .get(format!("{}/health", endpoint.url))
+        .headers(endpoint.request_headers())
+        .send()
+        .await;
+    match resp {
+        Ok(resp) if resp.status().is_success() => (Probe::Passed, None),
+        Ok(resp) => {
+            let probe = if is_auth_rejection(resp.status()) { Probe::AuthRejected } else { Probe::Failed };
+            (probe, Some(format!("/health: status {}", resp.status().as_u16())))
+        }
+        Err(e) => (Probe::Failed, Some(format!("/health: {}", e))),
+    }
+}
+
+fn is_auth_rejection(status: reqwest::StatusCode) -> bool {
+    status == reqwest::StatusCode::UNAUTHORIZED || status == reqwest::StatusCode::FORBIDDEN
+}
+
+// The endpoint's model entries, kept whole: the listing passes on vLLM's max_model_len,
+// root (the weights a model was loaded from) and parent (the base model of a LoRA adapter)
+pub async fn fetch_models(
+    client: &reqwest::Client,
+    endpoint: &Endpoint,
+) -> Result<Vec<Value>, Box<dyn std::error::Error>> {
+    if endpoint.is_composer() {
+        return fetch_composer_models(client, endpoint).await;
+    }
     let resp = client
         .get(format!("{}/v1/models", endpoint.url))
-        .bearer_auth(&endpoint.access_token)
+        .headers(endpoint.request_headers())
         .send()
         .await?
         .error_for_status()?;
@@ -38,122 +113,539 @@ pub async fn fetch_models(endpoint: &Endpoint) -> Result<Vec<Value>, Box<dyn std
     }
 }
 
-// Single monitor function, picks generate vs embed data structures
-pub async fn monitor_endpoint(endpoint: Endpoint, state: Arc<AppState>) {
-    let mut interval = Duration::from_millis(500);
+// The models a remote composer routes for the endpoint's task: the entries of its detailed
+// listing whose tasks include it, if its /model-to-endpoints maps them to an endpoint
+async fn fetch_composer_models(
+    client: &reqwest::Client,
+    endpoint: &Endpoint,
+) -> Result<Vec<Value>, Box<dyn std::error::Error>> {
+    let get = |path: &str| client.get(format!("{}{}", endpoint.url, path)).headers(endpoint.request_headers());
+    let listing: Value = get("/v1/models?details=true").send().await?.error_for_status()?.json().await?;
+    let routed: HashMap<String, Vec<String>> =
+        get("/model-to-endpoints").send().await?.error_for_status()?.json().await?;
+    let models = listing
+        .get("data")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter(|model| {
+            let id = model.get("id").and_then(Value::as_str).unwrap_or_default();
+            let tasks = model.pointer("/capabilities/tasks").and_then(Value::as_array);
+            routed.get(id).is_some_and(|urls| !urls.is_empty())
+                && tasks.is_some_and(|tasks| tasks.iter().any(|task| task == endpoint.task.as_str()))
+        })
+        .map(|model| {
+            // Kept like the entries of vLLM, this composer lists its own details
+            let mut model = model.clone();
+            if let Value::Object(map) = &mut model {
+                map.remove("capabilities");
+                map.remove("lora_adapters");
+            }
+            model
+        })
+        .collect();
+    Ok(models)
+}
+
+// Read capabilities from the served OpenAPI schema: vLLM's guided decoding fields and
+// the request fields of each path
+pub async fn probe_openapi(client: &reqwest::Client, endpoint: &Endpoint) -> EndpointCapabilities {
+    let Some(schema) = fetch_openapi(client, endpoint).await else {
+        return EndpointCapabilities::default();
+    };
+    EndpointCapabilities {
+        guided_decoding: Some(schema.contains("\"guided_json\"")),
+        vision: None,
+        request_fields: serde_json::from_str(&schema)
+            .ok()
+            .map(|schema| request_fields_from_openapi(&schema)),
+    }
+}
+
+// Load figures from vLLM's Prometheus metrics, summed over the served models
+pub async fn scrape_metrics(
+    client: &reqwest::Client,
+    endpoint: &Endpoint,
+) -> Result<BackendMetrics, Box<dyn std::error::Error>> {
+    let text = client
+        .get(format!("{}/metrics", endpoint.url))
+        .headers(endpoint.request_headers())
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
+    Ok(parse_vllm_metrics(&text))
+}
+
+fn parse_vllm_metrics(text: &str) -> BackendMetrics {
+    let mut metrics = BackendMetrics { scraped_ms: now_ms(), ..Default::default() };
+    for line in text.lines().filter(|line| !line.starts_with('#')) {
+        // `name{labels} value [timestamp]`
+        let Some((series, rest)) = line.rsplit_once('}').or_else(|| line.split_once(' ')) else {
+            continue;
+        };
+        let name = series.split('{').next().unwrap_or_default().trim();
+        let Some(value) = rest.split_whitespace().next().and_then(|v| v.parse::<f64>().ok()) else {
+            continue;
+        };
+        match name {
+            "vllm:num_requests_running" => metrics.requests_running += value,
+            "vllm:num_requests_waiting" => metrics.requests_waiting += value,
+            "vllm:kv_cache_usage_perc" | "vllm:gpu_cache_usage_perc" => {
+                metrics.kv_cache_usage = metrics.kv_cache_usage.max(value);
+            }
+            _ => {}
+        }
+    }
+    metrics
+}
+
+async fn fetch_openapi(client: &reqwest::Client, endpoint: &Endpoint) -> Option<String> {
+    let resp = client
+        .get(format!("{}/openapi.json", endpoint.url))
+        .headers(endpoint.request_headers())
+        .send()
+        .await
+        .ok()?
+        .error_for_status()
+        .ok()?;
+    resp.text().await.ok()
+}
+
+// Run the monitors of an endpoint in the background until the endpoint is removed: a fast
+// health check loop, a slower model refresh loop and, if enabled, metrics scrape and deep
+// check loops
+pub fn spawn_monitor(endpoint: Endpoint, state: Arc<AppState>) {
+    // Owned by the health loop, the other loops end once it is gone
+    let models_due = Arc::new(Notify::new());
+    tokio::spawn(refresh_models(endpoint.clone(), Arc::clone(&state), Arc::downgrade(&models_due)));
+    // A composer's /metrics has no vLLM load figures
+    if let Some(interval) = state.monitor_intervals.metrics_scrape
+        && !endpoint.is_composer()
+    {
+        let health_loop = Arc::downgrade(&models_due);
+        tokio::spawn(scrape_metrics_loop(endpoint.clone(), Arc::clone(&state), interval, health_loop));
+    }
+    if let Some(interval) = state.monitor_intervals.deep_check
+        && DEEP_CHECKED_TASKS.contains(&endpoint.task.as_str())
+    {
+        let health_loop = Arc::downgrade(&models_due);
+        tokio::spawn(deep_check_loop(endpoint.clone(), Arc::clone(&state), interval, health_loop));
+    }
+    tokio::spawn(async move {
+        monitor_endpoint(endpoint, state, models_due).await;
+    });
+}
+
+// Health check loop, picks the data structures of the endpoint's task
+pub async fn monitor_endpoint(endpoint: Endpoint, state: Arc<AppState>, models_due: Arc<Notify>) {
+    let max_interval_ms = state.monitor_intervals.health_max.as_millis() as u64;
 
     loop {
-        // If endpoint is no longer in its relevant vector, exit the loop
+        let task_state = state.task(&endpoint.task);
+
+        // If endpoint is no longer in its relevant vector or was replaced, exit the loop
         {
-            let found = if endpoint.task == "generate" {
-                let endpoints = state.endpoints_generate.lock().unwrap();
-                endpoints.iter().any(|e| e.url == endpoint.url)
+            let endpoints = task_state.endpoints.lock().unwrap();
+            if !endpoints.contains(&endpoint) {
+                break;
+            }
+        }
+
+        // With shared state only the lease holder probes, the others sync its results
+        if !state.shared.hold_monitor_lease(&endpoint.task, &endpoint.url).await {
+            sleep(MONITOR_LEASE_RENEW_INTERVAL).await;
+            continue;
+        }
+
+        // Operators pause probing during maintenance
+        let in_maintenance = task_state
+            .health_status
+            .lock()
+            .unwrap()
+            .get(&endpoint.url)
+            .is_some_and(|health| health.admin_state == Some(HealthState::Maintenance));
+        if in_maintenance {
+            sleep(MONITOR_LEASE_RENEW_INTERVAL).await;
+            continue;
+        }
+
+        let check_started = Instant::now();
+        let (probe, error) = probe_health(&state.http.monitor_for(&endpoint), &endpoint).await;
+        let check_latency = check_started.elapsed();
+        if probe == Probe::Failed {
+            // Look the host up again in case the endpoint moved
+            state.http.dns.forget_url(&endpoint.url);
+        }
+        state
+            .metrics
+            .health_check_latency
+            .with_label_values(&[&endpoint.task, &endpoint.url])
+            .observe(check_latency.as_secs_f64());
+        let result = if probe == Probe::Passed { "healthy" } else { "unhealthy" };
+        state
+            .metrics
+            .health_checks
+            .with_label_values(&[&endpoint.task, &endpoint.url, result])
+            .inc();
+
+        let (health_state, changed, interval) = {
+            let mut health_map_lock = task_state.health_status.lock().unwrap();
+            let mut changed = false;
+            let entry = health_map_lock.entry(endpoint.url.clone()).or_insert_with(|| {
+                changed = true;
+                EndpointHealth::new(probe)
+            });
+            entry.checked(check_latency, error);
+            let was_routable = entry.state.is_routable();
+            if !changed && entry.observe(probe, &state.health_thresholds) {
+                info!("Endpoint {} is {} now", endpoint.url, entry.state.label());
+                changed = true;
+                // Back after an outage, its caches are cold
+                if !was_routable && entry.state.is_routable() {
+                    state.slow_start.begin(&endpoint.url);
+                }
+            }
+            if changed {
+                entry.check_interval = 500;
             } else {
-                let endpoints = state.endpoints_embed.lock().unwrap();
-                endpoints.iter().any(|e| e.url == endpoint.url)
-            };
-            if !found {
+                entry.check_interval = std::cmp::min(entry.check_interval + 500, max_interval_ms);
+            }
+            // Passing again after an auth error, see whether the token works by now
+            if entry.state == HealthState::AuthError
+                && probe == Probe::Passed
+                && entry.consecutive_checks == 1
+            {
+                models_due.notify_one();
+            }
+            (entry.state, changed, Duration::from_millis(entry.check_interval))
+        };
+
+        if health_state == HealthState::Healthy {
+            // Fetch models right away instead of waiting for the next refresh
+            if changed {
+                models_due.notify_one();
+            }
+        } else if changed && !health_state.keeps_models() {
+            task_state.clear_models(&endpoint.url);
+            // Re-probe capabilities once the endpoint comes back, it may have been upgraded
+            state.endpoint_capabilities.lock().unwrap().remove(&endpoint.url);
+        }
+
+        publish_endpoint(&state, &endpoint).await;
+
+        // Wait for the next check, keeping the lease alive meanwhile
+        let mut remaining = interval;
+        while !remaining.is_zero() {
+            let step = remaining.min(MONITOR_LEASE_RENEW_INTERVAL);
+            sleep(step).await;
+            remaining -= step;
+            if !remaining.is_zero()
+                && !state.shared.hold_monitor_lease(&endpoint.task, &endpoint.url).await
+            {
                 break;
             }
         }
+    }
 
-        let health_url = format!("{}/health", endpoint.url);
-        let is_healthy = perform_health_check(&health_url).await;
+    state.shared.release_monitor_lease(&endpoint.task, &endpoint.url).await;
+}
 
-        // Update the correct health map
-        let (health_map, endpoint_models, model_to_endpoints) = if endpoint.task == "generate" {
-            (
-                &state.health_status_generate,
-                &state.endpoint_models_generate,
-                &state.model_to_endpoints_generate,
-            )
-        } else {
-            (
-                &state.health_status_embed,
-                &state.endpoint_models_embed,
-                &state.model_to_endpoints_embed,
-            )
+// Model refresh loop: capabilities and served models of a healthy endpoint, at the model
+// refresh interval or when the health loop reports the endpoint as back
+async fn refresh_models(endpoint: Endpoint, state: Arc<AppState>, models_due: Weak<Notify>) {
+    while let Some(notify) = models_due.upgrade() {
+        let _ = timeout(state.monitor_intervals.model_refresh, notify.notified()).await;
+        drop(notify);
+
+        let task_state = state.task(&endpoint.task);
+        // Endpoints with an auth error are asked too, a successful answer ends the error
+        let is_answering = |url: &str| {
+            task_state.health_status.lock().unwrap().get(url).is_some_and(|health| {
+                health.state != HealthState::Unhealthy
+                    && health.admin_state != Some(HealthState::Maintenance)
+            })
         };
+        if models_due.strong_count() == 0 || !is_answering(&endpoint.url) {
+            continue;
+        }
+        if !state.shared.hold_monitor_lease(&endpoint.task, &endpoint.url).await {
+            continue;
+        }
 
-        {
-            let mut health_map_lock = health_map.lock().unwrap();
-            let entry = health_map_lock.entry(endpoint.url.clone()).or_insert(EndpointHealth {
-                current_status: is_healthy,
-                consecutive_checks: 0,
-                check_interval: interval.as_millis() as u64,
-            });
-            if entry.current_status == is_healthy {
-                entry.consecutive_checks += 1;
-                entry.check_interval = std::cmp::min(entry.check_interval + 500, 30_000);
+        // Probe capabilities once per healthy period. A composer serves no schema, its own
+        // endpoints' fields are checked where it forwards to them.
+        let probed = state.endpoint_capabilities.lock().unwrap().contains_key(&endpoint.url);
+        if !probed {
+            let capabilities = if endpoint.is_composer() {
+                EndpointCapabilities::default()
             } else {
-                entry.current_status = is_healthy;
-                entry.consecutive_checks = 1;
-                entry.check_interval = 500;
+                probe_openapi(&state.http.monitor_for(&endpoint), &endpoint).await
+            };
+            state
+                .endpoint_capabilities
+                .lock()
+                .unwrap()
+                .insert(endpoint.url.clone(), capabilities);
+        }
+
+        let models = match fetch_models(&state.http.monitor_for(&endpoint), &endpoint).await {
+            Ok(models) => Some(models),
+            Err(e) => {
+                if let Some(health) = task_state.health_status.lock().unwrap().get_mut(&endpoint.url) {
+                    health.failed(format!("/v1/models: {}", e));
+                }
+                let rejected = e
+                    .downcast_ref::<reqwest::Error>()
+                    .and_then(reqwest::Error::status)
+                    .is_some_and(is_auth_rejection);
+                if rejected {
+                    mark_auth_error(&state, &endpoint);
+                }
+                None
+            }
+        };
+        // The endpoint may have failed meanwhile
+        if let Some(models) = models
+            && is_answering(&endpoint.url)
+        {
+            let auth_restored = task_state
+                .health_status
+                .lock()
+                .unwrap()
+                .get_mut(&endpoint.url)
+                .is_some_and(EndpointHealth::auth_accepted);
+            if auth_restored {
+                info!("Endpoint {} accepts its access token again", endpoint.url);
+            }
+            // Served models may change without the endpoint going down
+            if let Some(caps) = state.endpoint_capabilities.lock().unwrap().get_mut(&endpoint.url) {
+                caps.vision = detect_vision(&models);
             }
-            interval = Duration::from_millis(entry.check_interval);
+            task_state.apply_models(&endpoint.url, with_aliases(&endpoint, models));
+            publish_endpoint(&state, &endpoint).await;
         }
+    }
+}
 
+// The endpoint rejected its access token on an authenticated request
+fn mark_auth_error(state: &AppState, endpoint: &Endpoint) {
+    let task_state = state.task(&endpoint.task);
+    let changed = task_state
+        .health_status
+        .lock()
+        .unwrap()
+        .get_mut(&endpoint.url)
+        .is_some_and(|health| health.observe(Probe::AuthRejected, &state.health_thresholds));
+    if changed {
+        warn!("Endpoint {} rejects its access token", endpoint.url);
+        task_state.clear_models(&endpoint.url);
+    }
+}
+
+// Metrics scrape loop: every replica scrapes on its own, the figures are only used locally
+async fn scrape_metrics_loop(
+    endpoint: Endpoint,
+    state: Arc<AppState>,
+    interval: Duration,
+    health_loop: Weak<Notify>,
+) {
+    while health_loop.strong_count() > 0 {
+        let is_healthy = state
+            .task(&endpoint.task)
+            .health_status
+            .lock()
+            .unwrap()
+            .get(&endpoint.url)
+            .is_some_and(|health| health.state.is_routable());
         if is_healthy {
-            if let Ok(models) = fetch_models(&endpoint).await {
-                // Two-way sync
-                let mut models_map = endpoint_models.lock().unwrap();
-                let mut model_to_endpoints_map = model_to_endpoints.lock().unwrap();
-
-                // Current known models
-                let current_models = models_map.get(&endpoint.url).cloned().unwrap_or_default();
-                let current_ids: HashSet<String> = current_models
-                    .iter()
-                    .filter_map(|m| m.get("id").and_then(|v| v.as_str()))
-                    .map(String::from)
-                    .collect();
-
-                // Freshly fetched models
-                let new_ids: HashSet<String> = models
-                    .iter()
-                    .filter_map(|m| m.get("id").and_then(|v| v.as_str()))
-                    .map(String::from)
-                    .collect();
-
-                // Identify add/remove
-                let to_add = new_ids.difference(&current_ids).cloned().collect::<HashSet<_>>();
-                let to_remove = current_ids.difference(&new_ids).cloned().collect::<HashSet<_>>();
-
-                // Update endpoint_models
-                models_map.insert(endpoint.url.clone(), models.clone());
-
-                // Add new associations
-                for model_id in to_add {
-                    let entry = model_to_endpoints_map.entry(model_id).or_insert_with(Vec::new);
-                    if !entry.contains(&endpoint.url) {
-                        entry.push(endpoint.url.clone());
-                    }
+            match scrape_metrics(&state.http.monitor_for(&endpoint), &endpoint).await {
+                Ok(metrics) => {
+                    state.backend_metrics.lock().unwrap().insert(endpoint.url.clone(), metrics);
                 }
-                // Remove stale associations
-                for model_id in to_remove {
-                    if let Some(urls) = model_to_endpoints_map.get_mut(&model_id) {
-                        urls.retain(|u| u != &endpoint.url);
-                        if urls.is_empty() {
-                            model_to_endpoints_map.remove(&model_id);
-                        }
-                    }
+                Err(e) => {
+                    debug!("Failed to scrape metrics of {}: {}", endpoint.url, e);
+                    state.backend_metrics.lock().unwrap().remove(&endpoint.url);
                 }
             }
         } else {
-            // Remove the endpoint's URL from the model_to_endpoints map
-            {
-                let mut map_lock = model_to_endpoints.lock().unwrap();
-                for urls in map_lock.values_mut() {
-                    urls.retain(|u| u != &endpoint.url);
+            state.backend_metrics.lock().unwrap().remove(&endpoint.url);
+        }
+        sleep(interval).await;
+    }
+}
+
+// The smallest real request of the task's API, None for tasks without deep checks
+fn deep_check_request(task: &str, model: &str) -> Option<(&'static str, Value)> {
+    match task {
+        "generate" | "moderate" => Some((
+            "/v1/chat/completions",
+            json!({ "model": model, "messages": [{ "role": "user", "content": "Hi" }], "max_tokens": 1 }),
+        )),
+        "embed" => Some(("/v1/embeddings", json!({ "model": model, "input": "Hi" }))),
+        _ => None,
+    }
+}
+
+// Send the request and check that the answer carries a choice or an embedding
+async fn deep_check(
+    client: &reqwest::Client,
+    endpoint: &Endpoint,
+    path: &str,
+    body: &Value,
+) -> Result<(), String> {
+    let resp = client
+        .post(format!("{}{}", endpoint.url, path))
+        .headers(endpoint.request_headers())
+        .json(body)
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    let status = resp.status();
+    if !status.is_success() {
+        return Err(format!("status {}", status.as_u16()));
+    }
+    let json: Value = resp.json().await.map_err(|e| e.to_string())?;
+    let answered = ["choices", "data"]
+        .iter()
+        .any(|field| json.get(field).and_then(Value::as_array).is_some_and(|items| !items.is_empty()));
+    if answered { Ok(()) } else { Err("empty answer".to_string()) }
+}
+
+// Deep check loop: `/health` passing doesn't mean the model generates, so healthy endpoints
+// get a tiny real request for their first model. Every replica checks on its own, endpoints
+// failing as many checks in a row as take them out of health leave routing.
+async fn deep_check_loop(
+    endpoint: Endpoint,
+    state: Arc<AppState>,
+    interval: Duration,
+    health_loop: Weak<Notify>,
+) {
+    while health_loop.strong_count() > 0 {
+        let task_state = state.task(&endpoint.task);
+        let is_healthy = task_state
+            .health_status
+            .lock()
+            .unwrap()
+            .get(&endpoint.url)
+            .is_some_and(|health| health.state.is_routable());
+        // Base models only, adapters and aliases answer from the same weights
+        let model = task_state.endpoint_models.lock().unwrap().get(&endpoint.url).and_then(|models| {
+            models
+                .iter()
+                .filter(|m| m.get("parent").is_none_or(Value::is_null))
+                .find_map(|m| m.get("id").and_then(Value::as_str).map(str::to_string))
+        });
+        let request = model.as_deref().and_then(|model| deep_check_request(&endpoint.task, model));
+        // Soon again after a failure, or until there is something to check
+        let mut next = interval.min(DEEP_CHECK_RETRY);
+        if is_healthy
+            && let Some(model) = model
+            && let Some((path, body)) = request
+        {
+            let started = Instant::now();
+            let result = deep_check(&state.http.monitor_for(&endpoint), &endpoint, path, &body).await;
+            let latency = started.elapsed();
+            state
+                .metrics
+                .deep_check_latency
+                .with_label_values(&[&endpoint.task, &endpoint.url])
+                .observe(latency.as_secs_f64());
+            let outcome = if result.is_ok() { "pass" } else { "fail" };
+            state
+                .metrics
+                .deep_checks
+                .with_label_values(&[&endpoint.task, &endpoint.url, outcome])
+                .inc();
+            let mut deep_checks = state.deep_checks.lock().unwrap();
+            let failures = deep_checks.get(&endpoint.url).map_or(0, |check| check.failures);
+            let check = match result {
+                Ok(()) => {
+                    next = interval;
+                    DeepCheck { passed: true, failures: 0, ..Default::default() }
                 }
-                map_lock.retain(|_, v| !v.is_empty());
-            }
-            {
-                // Remove from endpoint_models
-                let mut models_map = endpoint_models.lock().unwrap();
-                models_map.remove(&endpoint.url);
-            }
+                Err(error) => {
+                    warn!("Deep check of {} on {} failed: {}", model, endpoint.url, error);
+                    let failures = failures + 1;
+                    DeepCheck { passed: false, failures, error: Some(error), ..Default::default() }
+                }
+            };
+            deep_checks.insert(
+                endpoint.url.clone(),
+                DeepCheck { model, latency_ms: latency.as_millis() as u64, checked_ms: now_ms(), ..check },
+            );
+        } else if !is_healthy {
+            // Health decides meanwhile, the endpoint is checked afresh once it is back
+            state.deep_checks.lock().unwrap().remove(&endpoint.url);
         }
+        sleep(next).await;
+    }
+}
 
-        sleep(interval).await;
+// Let other replicas know
+async fn publish_endpoint(state: &AppState, endpoint: &Endpoint) {
+    if !state.shared.is_enabled() {
+        return;
+    }
+    let task_state = state.task(&endpoint.task);
+    let health_state = task_state.health_status.lock().unwrap().get(&endpoint.url).map(|h| h.state);
+    let Some(health_state) = health_state else {
+        return;
+    };
+    let models = task_state
+        .endpoint_models
+        .lock()
+        .unwrap()
+        .get(&endpoint.url)
+        .cloned()
+        .unwrap_or_default();
+    let capabilities = state.endpoint_capabilities.lock().unwrap().get(&endpoint.url).cloned();
+    state
+        .shared
+        .publish_endpoint(&endpoint.task, &endpoint.url, health_state, models, capabilities)
+        .await;
+}
+
+// Pull observations other replicas published to the shared backend into local state
+pub async fn sync_shared_state(state: Arc<AppState>) {
+    loop {
+        for (task, task_state) in state.tasks() {
+            let urls: Vec<String> = task_state
+                .endpoints
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|ep| ep.url.clone())
+                .collect();
+            let snapshots = state.shared.fetch_new_snapshots(task, &urls).await;
+            for (url, snapshot) in snapshots {
+                let snapshot_state = snapshot.health_state();
+                {
+                    let mut health_map = task_state.health_status.lock().unwrap();
+                    let entry = health_map
+                        .entry(url.clone())
+                        .or_insert_with(|| EndpointHealth::synced(snapshot_state));
+                    if entry.state != snapshot_state {
+                        if !entry.state.is_routable() && snapshot_state.is_routable() {
+                            state.slow_start.begin(&url);
+                        }
+                        entry.state = snapshot_state;
+                        entry.consecutive_checks = 0;
+                    }
+                }
+                if snapshot_state.keeps_models() {
+                    task_state.apply_models(&url, snapshot.models);
+                } else {
+                    task_state.clear_models(&url);
+                }
+                let mut capabilities = state.endpoint_capabilities.lock().unwrap();
+                match snapshot.capabilities {
+                    Some(caps) => capabilities.insert(url, caps),
+                    None => capabilities.remove(&url),
+                };
+            }
+        }
+        sleep(SHARED_SYNC_INTERVAL).await;
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,75 @@
+// External crates
+use actix_web::{HttpMessage, HttpRequest};
+
+// Standard library
+use std::ops::Range;
+
+// -----------------------------------------------------------------------------
+// Multipart Forms
+// -----------------------------------------------------------------------------
+
+// Uploads like audio files are forwarded as they arrive. Only the text fields needed to
+// route them are read from the start of the form, without parsing the rest.
+
+// Largest accepted upload, same as OpenAI's limit for audio files
+const DEFAULT_MAX_UPLOAD_BYTES: usize = 25 * 1024 * 1024;
+
+pub fn max_upload_bytes() -> usize {
+    std::env::var("VLLM_COMPOSER_MAX_UPLOAD_BYTES")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(DEFAULT_MAX_UPLOAD_BYTES)
+}
+
+// The request's Content-Type if it is multipart/form-data with a boundary
+pub fn form_data_content_type(req: &HttpRequest) -> Option<String> {
+    let mime = req.mime_type().ok()??;
+    if mime.type_() != "multipart" || mime.subtype() != "form-data" {
+        return None;
+    }
+    mime.get_param("boundary")?;
+    req.headers().get("Content-Type")?.to_str().ok().map(String::from)
+}
+
+// Finds a text field in a form received so far. Scanning resumes where it left off, so
+// feeding it the growing body chunk by chunk costs one pass over the body.
+pub struct FieldScanner {
+    // `name="..."` of the field's Content-Disposition header
+    needle: Vec<u8>,
+    scanned: usize,
+}
+
+impl FieldScanner {
+    pub fn new(name: &str) -> Self {
+        FieldScanner { needle: format!("name=\"{}\"", name).into_bytes(), scanned: 0 }
+    }
+
+    // Byte range of the field's value in `body`, once `body` holds all of it
+    pub fn scan(&mut self, body: &[u8]) -> Option<Range<usize>> {
+        loop {
+            let Some(at) = find(&body[self.scanned..], &self.needle).map(|i| self.scanned + i) else {
+                self.scanned = body.len().saturating_sub(self.needle.len() - 1).max(self.scanned);
+                return None;
+            };
+            self.scanned = at;
+            // Only in the header of a text part, not in a file name or anywhere in a file
+            let line_start = body[..at].iter().rposition(|b| *b == b'\n').map_or(0, |i| i + 1);
+            let line_end = find(&body[at..], b"\r\n").map(|i| at + i)?;
+            let line = body[line_start..line_end].to_ascii_lowercase();
+            let in_word = at > 0 && body[at - 1].is_ascii_alphanumeric();
+            if in_word || !line.starts_with(b"content-disposition:") || find(&line, b"filename=").is_some() {
+                self.scanned = at + 1;
+                continue;
+            }
+            // The value follows the blank line ending the part's headers, up to the delimiter
+            // of the next part
+            let start = find(&body[at..], b"\r\n\r\n").map(|i| at + i + 4)?;
+            let end = find(&body[start..], b"\r\n--").map(|i| start + i)?;
+            return Some(start..end);
+        }
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
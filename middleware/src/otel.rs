@@ -0,0 +1,124 @@
+// External crates
+use actix_web::dev::ServiceRequest;
+use actix_web::http::header::HeaderMap;
+use log::{info, warn};
+use opentelemetry::propagation::{Extractor, Injector};
+use opentelemetry::trace::TracerProvider;
+use opentelemetry::global;
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use opentelemetry_sdk::Resource;
+use tracing::field::Empty;
+use tracing::{info_span, Level, Span};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::layer::{Layer, SubscriberExt};
+
+// -----------------------------------------------------------------------------
+// OpenTelemetry Tracing
+// -----------------------------------------------------------------------------
+
+// Spans of proxied requests (request > auth, route, upstream until the first byte, stream)
+// are exported via OTLP, with the model and endpoint URL as attributes. Logging stays with
+// `log`; without an exporter the spans cost next to nothing.
+
+// Export spans to VLLM_COMPOSER_OTLP_ENDPOINT (gRPC, e.g. http://tempo:4317) if it is set.
+// The provider is returned to flush the last spans on shutdown.
+pub fn init() -> Option<SdkTracerProvider> {
+    let endpoint = std::env::var("VLLM_COMPOSER_OTLP_ENDPOINT").ok().filter(|s| !s.is_empty())?;
+    let exporter = match SpanExporter::builder().with_tonic().with_endpoint(&endpoint).build() {
+        Ok(exporter) => exporter,
+        Err(e) => {
+            warn!("Invalid OTLP endpoint {}, not exporting traces: {}", endpoint, e);
+            return None;
+        }
+    };
+    let service_name = std::env::var("OTEL_SERVICE_NAME").unwrap_or_else(|_| "vllm_composer".to_string());
+    // Sampling follows OTEL_TRACES_SAMPLER and OTEL_TRACES_SAMPLER_ARG
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(Resource::builder().with_service_name(service_name).build())
+        .build();
+    // Only our own spans, those of hyper and tonic (the exporter included) would be noise
+    let layer = tracing_opentelemetry::layer()
+        .with_tracer(provider.tracer("vllm_middleware"))
+        .with_location(false)
+        .with_threads(false)
+        .with_target(false)
+        .with_tracked_inactivity(false)
+        .with_filter(Targets::new().with_target("vllm_middleware", Level::INFO));
+    if let Err(e) = tracing::subscriber::set_global_default(tracing_subscriber::registry().with(layer)) {
+        warn!("Failed to install the trace exporter: {}", e);
+        return None;
+    }
+    // Traces continue those of callers and are continued by the backends (vLLM's
+    // --otlp-traces-endpoint) through W3C traceparent headers
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    info!("Exporting traces to {}", endpoint);
+    Some(provider)
+}
+
+// Root span of a request, child of the caller's trace if it sent one
+pub fn request_span(req: &ServiceRequest) -> Span {
+    let route = req.match_pattern().unwrap_or_else(|| req.path().to_string());
+    let span = info_span!(
+        "request",
+        otel.name = %format!("{} {}", req.method(), route),
+        otel.kind = "server",
+        otel.status_code = Empty,
+        http.request.method = %req.method(),
+        http.route = %route,
+        http.response.status_code = Empty,
+        model = Empty,
+        endpoint.url = Empty,
+    );
+    let parent =
+        global::get_text_map_propagator(|propagator| propagator.extract(&HeaderExtractor(req.headers())));
+    // Fails only for spans that are disabled anyway
+    let _ = span.set_parent(parent);
+    span
+}
+
+// Status of a finished request on its span, 5xx mark the trace as failed
+pub fn record_status(span: &Span, status: u16) {
+    span.record("http.response.status_code", i64::from(status));
+    if status >= 500 {
+        span.record("otel.status_code", "ERROR");
+    }
+}
+
+// traceparent header continuing `span` on the backend
+pub fn trace_headers(span: &Span) -> reqwest::header::HeaderMap {
+    let mut headers = reqwest::header::HeaderMap::new();
+    let context = span.context();
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&context, &mut HeaderInjector(&mut headers))
+    });
+    headers
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|name| name.as_str()).collect()
+    }
+}
+
+struct HeaderInjector<'a>(&'a mut reqwest::header::HeaderMap);
+
+impl Injector for HeaderInjector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        if let (Ok(name), Ok(value)) = (
+            reqwest::header::HeaderName::from_bytes(key.as_bytes()),
+            reqwest::header::HeaderValue::from_str(&value),
+        ) {
+            self.0.insert(name, value);
+        }
+    }
+}
@@ -0,0 +1,193 @@
+// External crates
+use log::{info, warn};
+use serde_json::{json, Value};
+
+// Standard library
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// -----------------------------------------------------------------------------
+// Outlier Ejection
+// -----------------------------------------------------------------------------
+
+// Longest ejection, as a multiple of the first
+const MAX_EJECTION_FACTOR: u32 = 10;
+
+// Outcomes of the requests sent to an endpoint in one second
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    second: u64,
+    requests: u32,
+    failures: u32,
+}
+
+#[derive(Debug, Default)]
+struct Outcomes {
+    // Oldest first, none older than the window
+    buckets: VecDeque<Bucket>,
+    ejected_until: Option<Instant>,
+    // Ejections in a row, lengthening each one; forgotten after a window without one
+    ejections: u32,
+    // Error rate that ejected the endpoint
+    ejected_at_rate: f64,
+}
+
+impl Outcomes {
+    fn totals(&self) -> (u32, u32) {
+        self.buckets
+            .iter()
+            .fold((0, 0), |(requests, failures), b| (requests + b.requests, failures + b.failures))
+    }
+}
+
+// Ejects endpoints whose proxied requests fail too often (5xx, transport errors, broken or
+// malformed streams) for a while, even if their /health passes. Unlike the circuit breaker,
+// which needs failures in a row, this catches endpoints failing part of their requests.
+pub struct OutlierDetector {
+    // Error rate over the window that ejects, None disables ejection
+    max_error_rate: Option<f64>,
+    // Requests within the window before the rate counts
+    min_requests: u32,
+    window: Duration,
+    // First ejection; later ones in a row last a multiple of it
+    eject_for: Duration,
+    started: Instant,
+    endpoints: Mutex<HashMap<String, Outcomes>>,
+}
+
+impl OutlierDetector {
+    // VLLM_COMPOSER_OUTLIER_ERROR_RATE (default 0.5, 0 disables),
+    // VLLM_COMPOSER_OUTLIER_MIN_REQUESTS (default 20), VLLM_COMPOSER_OUTLIER_WINDOW_SECS
+    // (default 60) and VLLM_COMPOSER_OUTLIER_EJECT_SECS (default 30)
+    pub fn from_env() -> Self {
+        let env = |name: &str| std::env::var(name).ok();
+        let max_error_rate = env("VLLM_COMPOSER_OUTLIER_ERROR_RATE")
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(0.5);
+        let env_secs = |name: &str, default: u64| {
+            Duration::from_secs(env(name).and_then(|s| s.parse().ok()).unwrap_or(default))
+        };
+        let detector = OutlierDetector::new(
+            (max_error_rate > 0.0).then_some(max_error_rate),
+            env("VLLM_COMPOSER_OUTLIER_MIN_REQUESTS").and_then(|s| s.parse().ok()).unwrap_or(20),
+            env_secs("VLLM_COMPOSER_OUTLIER_WINDOW_SECS", 60),
+            env_secs("VLLM_COMPOSER_OUTLIER_EJECT_SECS", 30),
+        );
+        if let Some(rate) = detector.max_error_rate {
+            info!(
+                "Ejecting endpoints failing over {:.0}% of at least {} requests within {:?}",
+                rate * 100.0,
+                detector.min_requests,
+                detector.window
+            );
+        }
+        detector
+    }
+
+    pub fn new(max_error_rate: Option<f64>, min_requests: u32, window: Duration, eject_for: Duration) -> Self {
+        OutlierDetector {
+            max_error_rate,
+            min_requests: min_requests.max(1),
+            window: window.max(Duration::from_secs(1)),
+            eject_for,
+            started: Instant::now(),
+            endpoints: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Count the outcome of a request to the endpoint, ejecting it if its error rate is too high
+    pub fn record(&self, url: &str, failed: bool) {
+        let Some(max_error_rate) = self.max_error_rate else {
+            return;
+        };
+        let now = Instant::now();
+        let second = now.duration_since(self.started).as_secs();
+        let window = self.window.as_secs();
+        let mut endpoints = self.endpoints.lock().unwrap();
+        let outcomes = endpoints.entry(url.to_string()).or_default();
+        // Outcomes of requests that were in flight when it was ejected don't count
+        if outcomes.ejected_until.is_some_and(|until| now < until) {
+            return;
+        }
+        if let Some(until) = outcomes.ejected_until
+            && now.duration_since(until) >= self.window
+        {
+            outcomes.ejected_until = None;
+            outcomes.ejections = 0;
+        }
+        while outcomes.buckets.front().is_some_and(|b| b.second + window <= second) {
+            outcomes.buckets.pop_front();
+        }
+        match outcomes.buckets.back_mut() {
+            Some(bucket) if bucket.second == second => {
+                bucket.requests += 1;
+                bucket.failures += failed as u32;
+            }
+            _ => outcomes.buckets.push_back(Bucket { second, requests: 1, failures: failed as u32 }),
+        }
+
+        let (requests, failures) = outcomes.totals();
+        let rate = failures as f64 / requests as f64;
+        if requests >= self.min_requests && rate > max_error_rate {
+            outcomes.ejections = (outcomes.ejections + 1).min(MAX_EJECTION_FACTOR);
+            let eject_for = self.eject_for * outcomes.ejections;
+            warn!(
+                "Ejecting endpoint {} for {:?}, {} of its last {} requests failed",
+                url, eject_for, failures, requests
+            );
+            outcomes.ejected_until = Some(now + eject_for);
+            outcomes.ejected_at_rate = rate;
+            // It starts over when it returns
+            outcomes.buckets.clear();
+        }
+    }
+
+    // Whether the endpoint is ejected right now
+    pub fn is_ejected(&self, url: &str) -> bool {
+        let endpoints = self.endpoints.lock().unwrap();
+        endpoints
+            .get(url)
+            .and_then(|outcomes| outcomes.ejected_until)
+            .is_some_and(|until| Instant::now() < until)
+    }
+
+    // The endpoint's error rate and ejection, for /health-status
+    pub fn status(&self, url: &str) -> Value {
+        let endpoints = self.endpoints.lock().unwrap();
+        let Some(outcomes) = endpoints.get(url) else {
+            return json!({ "ejected": false, "requests": 0, "error_rate": null });
+        };
+        let (requests, failures) = outcomes.totals();
+        let remaining = outcomes
+            .ejected_until
+            .map(|until| until.saturating_duration_since(Instant::now()))
+            .filter(|remaining| !remaining.is_zero());
+        let mut status = json!({
+            "ejected": remaining.is_some(),
+            "requests": requests,
+            "error_rate": (requests > 0).then(|| failures as f64 / requests as f64),
+        });
+        if let Some(remaining) = remaining {
+            status["ejected_for_secs"] = json!(remaining.as_secs() + 1);
+            status["ejected_at_error_rate"] = json!(outcomes.ejected_at_rate);
+        }
+        status
+    }
+
+    pub fn forget(&self, url: &str) {
+        self.endpoints.lock().unwrap().remove(url);
+    }
+
+    // Drop the outcomes of endpoints that are gone, returns how many
+    pub fn retain(&self, active: &HashSet<String>) -> usize {
+        let mut endpoints = self.endpoints.lock().unwrap();
+        let before = endpoints.len();
+        endpoints.retain(|url, _| active.contains(url));
+        before - endpoints.len()
+    }
+
+    pub fn entries(&self) -> usize {
+        self.endpoints.lock().unwrap().len()
+    }
+}
@@ -0,0 +1,140 @@
+// External crates
+use serde::Deserialize;
+use serde_json::Value;
+
+// Standard library
+use std::collections::BTreeMap;
+
+// -----------------------------------------------------------------------------
+// Parameter Policies
+// -----------------------------------------------------------------------------
+
+// What happens to a request exceeding a policy's bounds
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Violation {
+    // Rewrite the value to the nearest bound
+    #[default]
+    Clamp,
+    // Answer 400 naming the field and its bounds
+    Reject,
+}
+
+// Allowed range of a numeric request field, or whether a field may be sent at all
+#[derive(Debug, Clone, Deserialize)]
+pub struct Bounds {
+    // With false the field is removed, or rejected, whatever its value, e.g. guided_grammar
+    #[serde(default = "allowed_by_default")]
+    pub allowed: bool,
+    #[serde(default)]
+    pub min: Option<f64>,
+    #[serde(default)]
+    pub max: Option<f64>,
+    // Set when the request leaves the field out, e.g. to cap max_tokens
+    #[serde(default)]
+    pub default: Option<f64>,
+}
+
+// One entry of `policies` in secrets.yaml
+#[derive(Debug, Clone, Deserialize)]
+pub struct Policy {
+    // Groups the policy applies to, all if empty
+    #[serde(default)]
+    pub groups: Vec<String>,
+    // Models the policy applies to, all if empty; a trailing `*` matches a prefix
+    #[serde(default)]
+    pub models: Vec<String>,
+    #[serde(default)]
+    pub on_violation: Violation,
+    // Field name -> bounds
+    pub fields: BTreeMap<String, Bounds>,
+}
+
+fn allowed_by_default() -> bool {
+    true
+}
+
+impl Policy {
+    fn applies_to(&self, groups: &[String], model: &str) -> bool {
+        let group_matches = self.groups.is_empty() || self.groups.iter().any(|g| groups.contains(g));
+        let model_matches = self.models.is_empty() || self.models.iter().any(|pattern| model_matches(pattern, model));
+        group_matches && model_matches
+    }
+}
+
+// Whether a model name matches a pattern, a trailing `*` matches a prefix
+pub fn model_matches(pattern: &str, model: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => model.starts_with(prefix),
+        None => pattern == model,
+    }
+}
+
+// Apply every policy matching the caller's groups and the body's model in order.
+// Returns a description of each clamped value, or the first rejected violation.
+pub fn apply_policies(policies: &[Policy], body: &mut Value, groups: &[String]) -> Result<Vec<String>, String> {
+    let model = body.get("model").and_then(Value::as_str).unwrap_or_default().to_string();
+    let mut changes = Vec::new();
+    for policy in policies.iter().filter(|p| p.applies_to(groups, &model)) {
+        for (field, bounds) in &policy.fields {
+            let Some(obj) = body.as_object_mut() else {
+                return Ok(changes);
+            };
+            let Some(current) = obj.get(field) else {
+                if let Some(default) = bounds.default {
+                    obj.insert(field.clone(), number(default, default.fract() == 0.0));
+                    changes.push(format!("{} set to {}", field, default));
+                }
+                continue;
+            };
+            if !bounds.allowed {
+                if policy.on_violation == Violation::Reject {
+                    return Err(format!("`{}` is not allowed for model `{}`.", field, model));
+                }
+                obj.remove(field);
+                changes.push(format!("{} removed", field));
+                continue;
+            }
+            // Leave non-numeric values to the backend's validation
+            let Some(value) = current.as_f64() else {
+                continue;
+            };
+            let clamped = match (bounds.min, bounds.max) {
+                (Some(min), _) if value < min => min,
+                (_, Some(max)) if value > max => max,
+                _ => continue,
+            };
+            if policy.on_violation == Violation::Reject {
+                return Err(format!(
+                    "`{}` must be {} for model `{}`, got {}.",
+                    field,
+                    describe(bounds),
+                    model,
+                    value
+                ));
+            }
+            let integer = (current.is_i64() || current.is_u64()) && clamped.fract() == 0.0;
+            obj.insert(field.clone(), number(clamped, integer));
+            changes.push(format!("{} clamped from {} to {}", field, value, clamped));
+        }
+    }
+    Ok(changes)
+}
+
+// Keep integer fields like max_tokens integers
+fn number(value: f64, integer: bool) -> Value {
+    if integer {
+        Value::from(value as i64)
+    } else {
+        Value::from(value)
+    }
+}
+
+fn describe(bounds: &Bounds) -> String {
+    match (bounds.min, bounds.max) {
+        (Some(min), Some(max)) => format!("between {} and {}", min, max),
+        (Some(min), None) => format!("at least {}", min),
+        (None, Some(max)) => format!("at most {}", max),
+        (None, None) => "unbounded".to_string(),
+    }
+}
@@ -0,0 +1,71 @@
+// External crates
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+// -----------------------------------------------------------------------------
+// Prompt Prefixes
+// -----------------------------------------------------------------------------
+
+// Characters of a completion prompt that make up its prefix
+const PROMPT_PREFIX_CHARS: usize = 2048;
+
+// How much of a prompt names its prefix for prefix-cache-aware routing
+pub struct PrefixConfig {
+    // User turns included after the leading system messages
+    pub user_turns: usize,
+    // How many more requests per unit of weight the prefix's endpoint may have in flight than
+    // the least loaded one before the request goes by load instead
+    pub max_imbalance: u64,
+}
+
+impl PrefixConfig {
+    // VLLM_COMPOSER_PREFIX_USER_TURNS (default 1) and VLLM_COMPOSER_PREFIX_MAX_IMBALANCE
+    // (default 8)
+    pub fn from_env() -> Self {
+        let env_u64 = |name: &str, default: u64| {
+            std::env::var(name)
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(default)
+        };
+        PrefixConfig {
+            user_turns: env_u64("VLLM_COMPOSER_PREFIX_USER_TURNS", 1).max(1) as usize,
+            max_imbalance: env_u64("VLLM_COMPOSER_PREFIX_MAX_IMBALANCE", 8),
+        }
+    }
+
+    // Hash of the leading chat messages (system prompt and the first user turns) or of the
+    // start of a completion prompt, None if the body has neither
+    pub fn prefix_key(&self, body: &Value) -> Option<String> {
+        let mut hasher = Sha256::new();
+        if let Some(messages) = body.get("messages").and_then(Value::as_array) {
+            let mut user_turns = 0;
+            let mut hashed = 0;
+            for message in messages {
+                if user_turns == self.user_turns {
+                    break;
+                }
+                if message.get("role").and_then(Value::as_str) == Some("user") {
+                    user_turns += 1;
+                }
+                hasher.update(message.get("role").map(Value::to_string).unwrap_or_default());
+                hasher.update([0]);
+                hasher.update(message.get("content").map(Value::to_string).unwrap_or_default());
+                hasher.update([0]);
+                hashed += 1;
+            }
+            if hashed == 0 {
+                return None;
+            }
+        } else {
+            let prompt = body.get("prompt").and_then(Value::as_str).filter(|p| !p.is_empty())?;
+            let end = prompt
+                .char_indices()
+                .nth(PROMPT_PREFIX_CHARS)
+                .map_or(prompt.len(), |(i, _)| i);
+            hasher.update(&prompt[..end]);
+        }
+        let digest = hasher.finalize();
+        Some(digest.iter().map(|b| format!("{:02x}", b)).collect())
+    }
+}
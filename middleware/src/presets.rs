@@ -0,0 +1,57 @@
+// External crates
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+// Standard library
+use std::collections::BTreeMap;
+
+// Internal modules
+use crate::policies::model_matches;
+
+// -----------------------------------------------------------------------------
+// Sampling Presets
+// -----------------------------------------------------------------------------
+
+// One entry of `presets` in secrets.yaml
+#[derive(Debug, Clone, Deserialize)]
+pub struct Preset {
+    // Request fields the preset sets, e.g. temperature, top_p, seed
+    #[serde(default)]
+    pub params: Map<String, Value>,
+    // Extra fields per model pattern (a trailing `*` matches a prefix), e.g. stop sequences
+    #[serde(default)]
+    pub models: BTreeMap<String, Map<String, Value>>,
+}
+
+// Replace a request's `preset` field by the preset's parameters. Fields the request sets
+// itself win, then those of the model's entry. Returns the expanded preset's name, or an error for unknown presets.
+pub fn expand_preset(presets: &BTreeMap<String, Preset>, body: &mut Value) -> Result<Option<String>, String> {
+    let Some(obj) = body.as_object_mut() else {
+        return Ok(None);
+    };
+    let Some(requested) = obj.remove("preset") else {
+        return Ok(None);
+    };
+    let Some(name) = requested.as_str() else {
+        return Err("`preset` must be the name of a preset.".to_string());
+    };
+    let Some(preset) = presets.get(name) else {
+        let known: Vec<&str> = presets.keys().map(String::as_str).collect();
+        return Err(format!(
+            "The preset `{}` does not exist. Available presets: {}.",
+            name,
+            if known.is_empty() { "none".to_string() } else { known.join(", ") }
+        ));
+    };
+
+    let model = obj.get("model").and_then(Value::as_str).unwrap_or_default().to_string();
+    let model_params = preset
+        .models
+        .iter()
+        .filter(|(pattern, _)| model_matches(pattern, &model))
+        .flat_map(|(_, params)| params);
+    for (field, value) in model_params.chain(&preset.params) {
+        obj.entry(field.clone()).or_insert_with(|| value.clone());
+    }
+    Ok(Some(name.to_string()))
+}
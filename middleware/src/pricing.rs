@@ -0,0 +1,49 @@
+// External crates
+use serde::Deserialize;
+
+// Standard library
+use std::collections::BTreeMap;
+
+// Internal modules
+use crate::policies::model_matches;
+use crate::usage::UsageBucket;
+
+// -----------------------------------------------------------------------------
+// Pricing
+// -----------------------------------------------------------------------------
+
+// One entry of `pricing` in secrets.yaml, prices per 1000 tokens
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq)]
+pub struct Price {
+    #[serde(default)]
+    pub prompt: f64,
+    #[serde(default)]
+    pub completion: f64,
+}
+
+impl Price {
+    pub fn cost(&self, prompt_tokens: u64, completion_tokens: u64) -> f64 {
+        (prompt_tokens as f64 * self.prompt + completion_tokens as f64 * self.completion) / 1000.0
+    }
+}
+
+// The price of a model: its own entry, else the longest pattern matching it (a trailing `*`
+// matches a prefix, `*` alone every model)
+pub fn price_of<'a>(pricing: &'a BTreeMap<String, Price>, model: &str) -> Option<&'a Price> {
+    pricing.get(model).or_else(|| {
+        pricing
+            .iter()
+            .filter(|(pattern, _)| model_matches(pattern, model))
+            .max_by_key(|(pattern, _)| pattern.len())
+            .map(|(_, price)| price)
+    })
+}
+
+// Set the cost of each bucket whose model has a price
+pub fn price_buckets(pricing: &BTreeMap<String, Price>, buckets: &mut [UsageBucket]) {
+    for bucket in buckets {
+        let totals = &mut bucket.totals;
+        totals.cost = price_of(pricing, &bucket.model)
+            .map(|price| price.cost(totals.prompt_tokens, totals.completion_tokens));
+    }
+}
@@ -0,0 +1,83 @@
+// Standard library
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// Internal modules
+use crate::shared::SharedStore;
+
+// -----------------------------------------------------------------------------
+// Rate Limiting
+// -----------------------------------------------------------------------------
+
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+// Token buckets per bearer token: a full minute's worth of requests may burst, then
+// requests refill evenly over the minute
+pub struct RateLimiter {
+    buckets: Mutex<HashMap<String, Bucket>>,
+    last_pruned: Mutex<Instant>,
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        RateLimiter {
+            buckets: Mutex::new(HashMap::new()),
+            last_pruned: Mutex::new(Instant::now()),
+        }
+    }
+}
+
+impl RateLimiter {
+    // Like check, with the bucket shared by all replicas in the shared store if one is
+    // configured, keyed by the token's id. This replica's bucket stands in while the store
+    // is unreachable.
+    pub async fn check_shared(
+        &self,
+        shared: &SharedStore,
+        token: &str,
+        token_id: &str,
+        per_minute: u32,
+    ) -> Result<(), Duration> {
+        if let Some(result) = shared.take_from_bucket(&format!("ratelimit:{}", token_id), per_minute).await {
+            return result;
+        }
+        self.check(token, per_minute)
+    }
+
+    // Take one request from the token's bucket, or tell how long until one is available
+    pub fn check(&self, token: &str, per_minute: u32) -> Result<(), Duration> {
+        let capacity = per_minute.max(1) as f64;
+        let refill_per_sec = capacity / 60.0;
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+
+        // Buckets that refilled completely hold no information
+        {
+            let mut last_pruned = self.last_pruned.lock().unwrap();
+            if last_pruned.elapsed() >= PRUNE_INTERVAL {
+                buckets.retain(|_, bucket| now.duration_since(bucket.updated) < PRUNE_INTERVAL);
+                *last_pruned = now;
+            }
+        }
+
+        let bucket = buckets.entry(token.to_string()).or_insert(Bucket {
+            tokens: capacity,
+            updated: now,
+        });
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * refill_per_sec).min(capacity);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / refill_per_sec))
+        }
+    }
+}
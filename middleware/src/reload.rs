@@ -0,0 +1,165 @@
+// External crates
+use log::{info, warn};
+use notify::{RecursiveMode, Watcher};
+use tokio::sync::mpsc;
+use tokio::time::sleep;
+
+// Standard library
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+// Internal modules
+use crate::monitoring::spawn_monitor;
+use crate::state::{
+    endpoints_path,
+    load_auth_tokens_from_yaml,
+    load_endpoints_from_yaml,
+    partition_endpoints,
+    split_dns_templates,
+    secrets_path,
+    AppState,
+    TASKS,
+};
+
+// -----------------------------------------------------------------------------
+// Config Reload
+// -----------------------------------------------------------------------------
+
+// Changes are applied once the files have been quiet this long, editors and ConfigMap
+// updates write in several steps
+const WATCH_DEBOUNCE: Duration = Duration::from_secs(1);
+
+// Endpoint changes of a reload
+#[derive(Debug, Default)]
+pub struct ReloadSummary {
+    pub added: usize,
+    pub changed: usize,
+    pub removed: usize,
+    pub unchanged: usize,
+}
+
+impl fmt::Display for ReloadSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} added, {} changed, {} removed, {} unchanged",
+            self.added, self.changed, self.removed, self.unchanged
+        )
+    }
+}
+
+// Reread endpoints.yaml and secrets.yaml and apply them. Unchanged endpoints keep their
+// state and monitors; nothing changes if either file fails to load, the config in use stays
+// valid then.
+pub fn reload(state: &Arc<AppState>) -> Result<ReloadSummary, String> {
+    let endpoints = load_endpoints_from_yaml().map_err(|e| format!("Failed to load YAML: {}", e))?;
+    let auth_config =
+        load_auth_tokens_from_yaml().map_err(|e| format!("Failed to load auth tokens YAML: {}", e))?;
+
+    state.fallbacks.configure(&endpoints);
+    let (endpoints, dns_templates) = split_dns_templates(endpoints);
+    let mut partitioned = partition_endpoints(endpoints);
+    let mut to_monitor = Vec::new();
+    let mut summary = ReloadSummary::default();
+    for task in TASKS {
+        let changes = state.replace_endpoints(task, partitioned.remove(task).unwrap_or_default());
+        for url in &changes.removed {
+            info!("Reload removed {} endpoint {}", task, url);
+        }
+        for endpoint in &changes.changed {
+            info!("Reload updated {} endpoint {}", task, endpoint.url);
+        }
+        summary.added += changes.added.len();
+        summary.changed += changes.changed.len();
+        summary.removed += changes.removed.len();
+        summary.unchanged += changes.unchanged;
+        to_monitor.extend(changes.added);
+        to_monitor.extend(changes.changed);
+    }
+    *state.dns_templates.lock().unwrap() = dns_templates;
+
+    let version = state.replace_auth_tokens(auth_config);
+    info!("Activated auth tokens version {}", version);
+    *state.config_error.lock().unwrap() = None;
+
+    // Monitors of unchanged endpoints keep running
+    for endpoint in to_monitor {
+        spawn_monitor(endpoint, Arc::clone(state));
+    }
+    Ok(summary)
+}
+
+// With VLLM_COMPOSER_WATCH_CONFIG (true/1), reload whenever endpoints.yaml or secrets.yaml
+// change on disk
+pub async fn watch(state: Arc<AppState>) {
+    let enabled = std::env::var("VLLM_COMPOSER_WATCH_CONFIG")
+        .map(|v| v == "true" || v == "1")
+        .unwrap_or(false);
+    if !enabled {
+        return;
+    }
+    info!(
+        "Reloading on changes to {} and {}",
+        endpoints_path().display(),
+        secrets_path().display()
+    );
+    let files = vec![endpoints_path().to_path_buf(), secrets_path().to_path_buf()];
+    watch_files(files, || match reload(&state) {
+        Ok(summary) => info!("Config files changed, reloaded endpoints: {}", summary),
+        Err(e) => warn!("Config files changed, but the reload failed: {}", e),
+    })
+    .await;
+}
+
+// Call `on_change` whenever the contents of `files` change, until the watch fails
+pub async fn watch_files(files: Vec<PathBuf>, mut on_change: impl FnMut()) {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let mut watcher = match notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        if event.is_ok_and(|event| !event.kind.is_access()) {
+            let _ = tx.send(());
+        }
+    }) {
+        Ok(watcher) => watcher,
+        Err(e) => {
+            warn!("Failed to watch {:?}: {}", files, e);
+            return;
+        }
+    };
+    // The directories are watched, files replaced by a rename (or a ConfigMap's symlink
+    // swap) would lose a watch on the file itself
+    let mut directories: Vec<&Path> = files
+        .iter()
+        .filter_map(|path| path.parent())
+        // A bare file name is in the working directory
+        .map(|directory| if directory.as_os_str().is_empty() { Path::new(".") } else { directory })
+        .collect();
+    directories.dedup();
+    for directory in directories {
+        if let Err(e) = watcher.watch(directory, RecursiveMode::NonRecursive) {
+            warn!("Failed to watch {}: {}", directory.display(), e);
+            return;
+        }
+    }
+
+    let contents = || -> Vec<Option<Vec<u8>>> { files.iter().map(|path| std::fs::read(path).ok()).collect() };
+    let mut last_seen = contents();
+    while rx.recv().await.is_some() {
+        // Wait for the writes to settle
+        loop {
+            sleep(WATCH_DEBOUNCE).await;
+            if rx.try_recv().is_err() {
+                break;
+            }
+            while rx.try_recv().is_ok() {}
+        }
+        // Other files in the directories change too
+        let current = contents();
+        if current == last_seen {
+            continue;
+        }
+        last_seen = current;
+        on_change();
+    }
+}
@@ -0,0 +1,255 @@
+// External crates
+use log::{info, warn};
+use serde::Serialize;
+use serde_json::{json, Value};
+use tokio::time::sleep;
+
+// Standard library
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+// Internal modules
+use crate::pricing::price_buckets;
+use crate::shared::now_ms;
+use crate::state::AppState;
+use crate::usage::{UsageBucket, UsageTotals, Window, DAY_SECS};
+
+// -----------------------------------------------------------------------------
+// Scheduled Usage Reports
+// -----------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReportFormat {
+    Json,
+    Csv,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Period {
+    // The previous UTC day, reported after midnight
+    Daily,
+    // The previous Monday to Sunday, reported after midnight on Mondays
+    Weekly,
+}
+
+impl Period {
+    fn label(self) -> &'static str {
+        match self {
+            Period::Daily => "daily",
+            Period::Weekly => "weekly",
+        }
+    }
+
+    fn days(self) -> u64 {
+        match self {
+            Period::Daily => 1,
+            Period::Weekly => 7,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ReportConfig {
+    // Directory the report files are written to
+    pub dir: Option<PathBuf>,
+    // URL the reports are posted to as JSON
+    pub webhook_url: Option<String>,
+    // Format of the report files
+    pub format: ReportFormat,
+    daily: bool,
+    weekly: bool,
+}
+
+impl ReportConfig {
+    // VLLM_COMPOSER_REPORT_DIR and/or VLLM_COMPOSER_REPORT_WEBHOOK_URL turn reports on,
+    // VLLM_COMPOSER_REPORT_PERIODS ("daily", "weekly" or both, default daily) and
+    // VLLM_COMPOSER_REPORT_FORMAT ("json" or "csv") shape them
+    pub fn from_env() -> Option<Self> {
+        let non_empty = |name: &str| std::env::var(name).ok().filter(|s| !s.is_empty());
+        let dir = non_empty("VLLM_COMPOSER_REPORT_DIR").map(PathBuf::from);
+        let webhook_url = non_empty("VLLM_COMPOSER_REPORT_WEBHOOK_URL");
+        if dir.is_none() && webhook_url.is_none() {
+            return None;
+        }
+        let periods = non_empty("VLLM_COMPOSER_REPORT_PERIODS").unwrap_or_else(|| "daily".to_string());
+        let periods: Vec<&str> = periods.split(',').map(str::trim).collect();
+        let format = match non_empty("VLLM_COMPOSER_REPORT_FORMAT").as_deref() {
+            Some("csv") => ReportFormat::Csv,
+            _ => ReportFormat::Json,
+        };
+        Some(ReportConfig {
+            dir,
+            webhook_url,
+            format,
+            daily: periods.contains(&"daily"),
+            weekly: periods.contains(&"weekly"),
+        })
+    }
+}
+
+// Usage of one group over a report period
+#[derive(Debug, Serialize, Default)]
+struct GroupReport {
+    #[serde(flatten)]
+    totals: UsageTotals,
+    models: BTreeMap<String, UsageTotals>,
+}
+
+// Write and post the reports of every period that ended, waking up after each UTC midnight
+pub async fn run(config: ReportConfig, state: Arc<AppState>) {
+    info!(
+        "Usage reports (daily: {}, weekly: {}) to {}{}",
+        config.daily,
+        config.weekly,
+        config.dir.as_ref().map_or("-".to_string(), |dir| dir.display().to_string()),
+        config.webhook_url.as_ref().map_or(String::new(), |url| format!(" and {}", url)),
+    );
+    if let Some(dir) = &config.dir
+        && let Err(e) = tokio::fs::create_dir_all(dir).await
+    {
+        warn!("Failed to create report directory {}: {}", dir.display(), e);
+    }
+    loop {
+        let now = now_ms() / 1000;
+        let midnight = now - now % DAY_SECS + DAY_SECS;
+        // A little past midnight so requests of the last second are booked
+        sleep(Duration::from_secs(midnight - now + 5)).await;
+
+        if config.daily {
+            report(&config, &state, Period::Daily, midnight).await;
+        }
+        if config.weekly && weekday(midnight / DAY_SECS) == 0 {
+            report(&config, &state, Period::Weekly, midnight).await;
+        }
+    }
+}
+
+// Build the report of the period ending at `until` and deliver it
+async fn report(config: &ReportConfig, state: &Arc<AppState>, period: Period, until: u64) {
+    let since = until - period.days() * DAY_SECS;
+    let mut buckets = match usage_buckets(state, since, until).await {
+        Ok(buckets) => buckets,
+        Err(e) => {
+            warn!("Failed to query usage for the {} report: {}", period.label(), e);
+            return;
+        }
+    };
+    price_buckets(&state.auth_tokens.load().pricing, &mut buckets);
+    let mut groups: BTreeMap<String, GroupReport> = BTreeMap::new();
+    for bucket in buckets {
+        let group = groups.entry(bucket.group).or_default();
+        group.totals.merge(&bucket.totals);
+        group.models.entry(bucket.model).or_default().merge(&bucket.totals);
+    }
+    let date = format_date(since / DAY_SECS);
+    let payload = json!({
+        "event": "usage_report",
+        "period": period.label(),
+        "date": date,
+        "since": since,
+        "until": until,
+        "groups": groups,
+    });
+
+    if let Some(dir) = &config.dir {
+        let (extension, contents) = match config.format {
+            ReportFormat::Json => ("json", serde_json::to_string_pretty(&payload).unwrap()),
+            ReportFormat::Csv => ("csv", to_csv(&groups)),
+        };
+        let path = dir.join(format!("usage-{}-{}.{}", period.label(), date, extension));
+        match tokio::fs::write(&path, contents).await {
+            Ok(()) => info!("Wrote {} usage report {}", period.label(), path.display()),
+            Err(e) => warn!("Failed to write usage report {}: {}", path.display(), e),
+        }
+    }
+    if let Some(url) = &config.webhook_url
+        && let Err(e) = post(state, url, &payload).await
+    {
+        warn!("Failed to post {} usage report: {}", period.label(), e);
+    }
+}
+
+// Daily usage per group and model, from the database if usage is persisted
+async fn usage_buckets(
+    state: &Arc<AppState>,
+    since: u64,
+    until: u64,
+) -> Result<Vec<UsageBucket>, Box<dyn std::error::Error>> {
+    if state.usage_db.is_none() {
+        return Ok(state.usage.rollup(Window::Day, since, until, None));
+    }
+    let state = Arc::clone(state);
+    let buckets = tokio::task::spawn_blocking(move || {
+        state.usage_db.as_ref().unwrap().rollup(Window::Day, since, until, None)
+    })
+    .await??;
+    Ok(buckets)
+}
+
+async fn post(state: &AppState, url: &str, payload: &Value) -> Result<(), reqwest::Error> {
+    state
+        .http
+        .monitor
+        .post(url)
+        .json(payload)
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(())
+}
+
+// One row per group and model
+fn to_csv(groups: &BTreeMap<String, GroupReport>) -> String {
+    let mut csv = String::from("group,model,requests,prompt_tokens,completion_tokens,total_tokens,cost\n");
+    for (group, report) in groups {
+        for (model, totals) in &report.models {
+            csv.push_str(&format!(
+                "{},{},{},{},{},{},{}\n",
+                csv_field(group),
+                csv_field(model),
+                totals.requests,
+                totals.prompt_tokens,
+                totals.completion_tokens,
+                totals.total_tokens,
+                totals.cost.map(|cost| cost.to_string()).unwrap_or_default()
+            ));
+        }
+    }
+    csv
+}
+
+// Quote fields that would break the row
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+// 0 for Monday; day 0 of the epoch was a Thursday
+fn weekday(days: u64) -> u64 {
+    (days + 3) % 7
+}
+
+// YYYY-MM-DD of a day since the epoch (civil calendar, UTC)
+fn format_date(days: u64) -> String {
+    let (year, month, day) = civil_date(days);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+// Year, month and day of a day since the epoch (civil calendar, UTC)
+pub fn civil_date(days: u64) -> (i64, i64, i64) {
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
@@ -0,0 +1,134 @@
+// External crates
+use log::{info, warn};
+use hyper::client::connect::dns::Name;
+use reqwest::dns::{Addrs, Resolve, Resolving};
+
+// Standard library
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// -----------------------------------------------------------------------------
+// Upstream DNS Cache
+// -----------------------------------------------------------------------------
+
+struct CachedAddrs {
+    addrs: Vec<SocketAddr>,
+    resolved: Instant,
+    // Resolve again on the next connection regardless of the TTL
+    forgotten: bool,
+}
+
+// Addresses of endpoint hostnames, re-resolved after the TTL or once a host fails to
+// connect. Backends move to other IPs when their nodes are rescheduled.
+pub struct DnsCache {
+    ttl: Duration,
+    entries: Mutex<HashMap<String, CachedAddrs>>,
+    // Set when a host moved to other addresses, so pooled connections get dropped
+    changed: AtomicBool,
+}
+
+impl DnsCache {
+    // TTL from VLLM_COMPOSER_DNS_TTL_SECS (default 30, 0 resolves every new connection)
+    pub fn from_env() -> Self {
+        let ttl = Duration::from_secs(
+            std::env::var("VLLM_COMPOSER_DNS_TTL_SECS")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(30),
+        );
+        info!("Upstream DNS cache TTL: {:?}", ttl);
+        DnsCache {
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+            changed: AtomicBool::new(false),
+        }
+    }
+
+    async fn lookup(&self, host: &str) -> std::io::Result<Vec<SocketAddr>> {
+        if let Some(cached) = self.entries.lock().unwrap().get(host)
+            && !cached.forgotten
+            && cached.resolved.elapsed() < self.ttl
+        {
+            return Ok(cached.addrs.clone());
+        }
+
+        let resolved = tokio::net::lookup_host((host, 0)).await.map(|addrs| {
+            let mut addrs: Vec<SocketAddr> = addrs.collect();
+            addrs.sort();
+            addrs.dedup();
+            addrs
+        });
+        let mut entries = self.entries.lock().unwrap();
+        match resolved {
+            Ok(addrs) if !addrs.is_empty() => {
+                if let Some(previous) = entries.get(host)
+                    && previous.addrs != addrs
+                {
+                    info!("{} moved from {:?} to {:?}", host, previous.addrs, addrs);
+                    self.changed.store(true, Ordering::Relaxed);
+                }
+                entries.insert(
+                    host.to_string(),
+                    CachedAddrs { addrs: addrs.clone(), resolved: Instant::now(), forgotten: false },
+                );
+                Ok(addrs)
+            }
+            result => {
+                // Better a stale address than none while the DNS server is unavailable
+                if let Some(stale) = entries.get(host) {
+                    warn!("Failed to re-resolve {}, using stale addresses {:?}", host, stale.addrs);
+                    return Ok(stale.addrs.clone());
+                }
+                result.and_then(|_| {
+                    Err(std::io::Error::new(
+                        std::io::ErrorKind::NotFound,
+                        format!("{} has no addresses", host),
+                    ))
+                })
+            }
+        }
+    }
+
+    // Resolve the host of `url` again on its next connection
+    pub fn forget_url(&self, url: &str) {
+        if let Some(host) = reqwest::Url::parse(url).ok().and_then(|u| u.host_str().map(str::to_string))
+            && let Some(cached) = self.entries.lock().unwrap().get_mut(&host)
+        {
+            // Keep the addresses to detect a move and as a fallback
+            cached.forgotten = true;
+        }
+    }
+
+    // Drop hosts not in use that were not resolved within the TTL, returns how many
+    pub fn retain(&self, hosts: &HashSet<String>) -> usize {
+        let mut entries = self.entries.lock().unwrap();
+        let before = entries.len();
+        entries.retain(|host, cached| hosts.contains(host) || cached.resolved.elapsed() < self.ttl);
+        before - entries.len()
+    }
+
+    pub fn entries(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    // Whether a host moved since the last call
+    pub fn take_changed(&self) -> bool {
+        self.changed.swap(false, Ordering::Relaxed)
+    }
+}
+
+// Resolver of the upstream clients backed by the cache
+pub struct CachingResolver(pub Arc<DnsCache>);
+
+impl Resolve for CachingResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let cache = Arc::clone(&self.0);
+        Box::pin(async move {
+            let addrs = cache.lookup(name.as_str()).await?;
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
@@ -0,0 +1,478 @@
+// External crates
+use serde_json::{json, Map, Value};
+
+// Internal modules
+use crate::shared::now_ms;
+
+// -----------------------------------------------------------------------------
+// OpenAI Responses
+// -----------------------------------------------------------------------------
+
+// /v1/responses takes requests of OpenAI's Responses API, which newer SDK code paths use,
+// and runs them as chat completions on the vLLM endpoints. Responses are not stored: the
+// conversation comes with each request in `input`.
+
+// Request fields the response object repeats, with their defaults
+fn echoed_defaults() -> [(&'static str, Value); 9] {
+    [
+        ("instructions", Value::Null),
+        ("max_output_tokens", Value::Null),
+        ("metadata", json!({})),
+        ("parallel_tool_calls", json!(true)),
+        ("temperature", Value::Null),
+        ("tool_choice", json!("auto")),
+        ("tools", json!([])),
+        ("top_p", Value::Null),
+        ("user", Value::Null),
+    ]
+}
+
+// A Responses request as a chat completion request
+pub fn to_chat_request(body: &Value) -> Result<Value, String> {
+    if body.get("previous_response_id").is_some_and(|id| !id.is_null()) {
+        return Err("`previous_response_id` is not supported, send the conversation in `input`.".to_string());
+    }
+    let mut messages = Vec::new();
+    if let Some(instructions) = body.get("instructions").and_then(Value::as_str) {
+        messages.push(json!({ "role": "system", "content": instructions }));
+    }
+    match body.get("input") {
+        Some(Value::String(text)) => messages.push(json!({ "role": "user", "content": text })),
+        Some(Value::Array(items)) => {
+            for item in items {
+                input_item(item, &mut messages)?;
+            }
+        }
+        _ => return Err("`input` must be a string or a list of input items.".to_string()),
+    }
+
+    let mut chat = Map::new();
+    chat.insert("messages".to_string(), Value::Array(messages));
+    for field in ["model", "temperature", "top_p", "stream", "user", "parallel_tool_calls"] {
+        if let Some(value) = body.get(field) {
+            chat.insert(field.to_string(), value.clone());
+        }
+    }
+    if let Some(max_tokens) = body.get("max_output_tokens") {
+        chat.insert("max_tokens".to_string(), max_tokens.clone());
+    }
+    if let Some(tools) = body.get("tools").and_then(Value::as_array) {
+        let mut chat_tools = Vec::new();
+        for tool in tools {
+            if tool.get("type").and_then(Value::as_str) != Some("function") {
+                return Err("Only tools of type `function` are supported.".to_string());
+            }
+            let mut function = tool.clone();
+            if let Some(function) = function.as_object_mut() {
+                function.remove("type");
+            }
+            chat_tools.push(json!({ "type": "function", "function": function }));
+        }
+        chat.insert("tools".to_string(), Value::Array(chat_tools));
+    }
+    if let Some(choice) = body.get("tool_choice") {
+        let choice = match choice.get("type").and_then(Value::as_str) {
+            Some("function") => json!({ "type": "function", "function": { "name": choice.get("name") } }),
+            _ => choice.clone(),
+        };
+        chat.insert("tool_choice".to_string(), choice);
+    }
+    if let Some(format) = body.pointer("/text/format") {
+        match format.get("type").and_then(Value::as_str) {
+            Some("json_schema") => {
+                let mut schema = format.clone();
+                if let Some(schema) = schema.as_object_mut() {
+                    schema.remove("type");
+                }
+                chat.insert(
+                    "response_format".to_string(),
+                    json!({ "type": "json_schema", "json_schema": schema }),
+                );
+            }
+            Some("json_object") => {
+                chat.insert("response_format".to_string(), json!({ "type": "json_object" }));
+            }
+            _ => {}
+        }
+    }
+    Ok(Value::Object(chat))
+}
+
+// Append the chat messages of an input item. Consecutive function calls become the tool
+// calls of one assistant message; reasoning items are left out.
+fn input_item(item: &Value, messages: &mut Vec<Value>) -> Result<(), String> {
+    match item.get("type").and_then(Value::as_str) {
+        None | Some("message") => {
+            let role = match item.get("role").and_then(Value::as_str) {
+                Some("developer") => "system",
+                Some(role @ ("user" | "assistant" | "system")) => role,
+                _ => {
+                    let msg = "Each message needs a `role` of `user`, `assistant`, `system` or `developer`.";
+                    return Err(msg.to_string());
+                }
+            };
+            messages.push(json!({ "role": role, "content": message_content(item.get("content")) }));
+        }
+        Some("function_call") => {
+            let call = json!({
+                "id": item.get("call_id"),
+                "type": "function",
+                "function": { "name": item.get("name"), "arguments": item.get("arguments") },
+            });
+            match messages.last_mut() {
+                Some(last) if last["role"] == "assistant" && last.get("tool_calls").is_some() => {
+                    if let Some(calls) = last["tool_calls"].as_array_mut() {
+                        calls.push(call);
+                    }
+                }
+                _ => messages.push(json!({ "role": "assistant", "content": null, "tool_calls": [call] })),
+            }
+        }
+        Some("function_call_output") => {
+            let output = match item.get("output") {
+                Some(Value::String(text)) => text.clone(),
+                Some(output) => output.to_string(),
+                None => String::new(),
+            };
+            messages.push(json!({ "role": "tool", "tool_call_id": item.get("call_id"), "content": output }));
+        }
+        Some("reasoning") => {}
+        Some(other) => return Err(format!("Input items of type `{}` are not supported.", other)),
+    }
+    Ok(())
+}
+
+// Chat content of a message's content, a string or a list of input and output parts
+fn message_content(content: Option<&Value>) -> Value {
+    let Some(Value::Array(parts)) = content else {
+        return content.cloned().unwrap_or(Value::Null);
+    };
+    let mut chat_parts = Vec::new();
+    for part in parts {
+        match part.get("type").and_then(Value::as_str) {
+            Some("input_text" | "output_text" | "text") => {
+                chat_parts.push(json!({ "type": "text", "text": part.get("text") }));
+            }
+            Some("input_image") => {
+                let image_url = json!({ "url": part.get("image_url"), "detail": part.get("detail") });
+                chat_parts.push(json!({ "type": "image_url", "image_url": image_url }));
+            }
+            _ => {}
+        }
+    }
+    // Plain text reads better to text-only chat templates
+    if chat_parts.iter().all(|part| part["type"] == "text") {
+        let texts: Vec<&str> = chat_parts.iter().filter_map(|part| part["text"].as_str()).collect();
+        return json!(texts.join("\n"));
+    }
+    Value::Array(chat_parts)
+}
+
+// The request fields a response object repeats
+pub fn echoed_fields(body: &Value) -> Map<String, Value> {
+    echoed_defaults()
+        .into_iter()
+        .map(|(field, default)| {
+            let value = body.get(field).filter(|value| !value.is_null()).cloned();
+            (field.to_string(), value.unwrap_or(default))
+        })
+        .collect()
+}
+
+fn suffix(completion_id: Option<&str>) -> &str {
+    let id = completion_id.unwrap_or_default();
+    id.strip_prefix("chatcmpl-").unwrap_or(id)
+}
+
+// A response object around its output items
+fn response_object(
+    id: &str,
+    created: &Value,
+    model: &str,
+    echo: &Map<String, Value>,
+    status: &str,
+    output: Vec<Value>,
+    usage: Value,
+) -> Value {
+    let mut response = echo.clone();
+    let incomplete_details = match status {
+        "incomplete" => json!({ "reason": "max_output_tokens" }),
+        _ => Value::Null,
+    };
+    for (field, value) in [
+        ("id", json!(id)),
+        ("object", json!("response")),
+        ("created_at", if created.is_null() { json!(now_ms() / 1000) } else { created.clone() }),
+        ("model", json!(model)),
+        ("status", json!(status)),
+        ("error", Value::Null),
+        ("incomplete_details", incomplete_details),
+        ("output", Value::Array(output)),
+        ("usage", usage),
+    ] {
+        response.insert(field.to_string(), value);
+    }
+    Value::Object(response)
+}
+
+fn message_item(id: &str, text: &str, status: &str) -> Value {
+    json!({
+        "type": "message",
+        "id": id,
+        "status": status,
+        "role": "assistant",
+        "content": if status == "completed" { json!([output_text(text)]) } else { json!([]) },
+    })
+}
+
+fn output_text(text: &str) -> Value {
+    json!({ "type": "output_text", "text": text, "annotations": [] })
+}
+
+fn function_call_item(call: &Value, arguments: &str, status: &str) -> Value {
+    let call_id = call.get("id").and_then(Value::as_str).unwrap_or_default();
+    json!({
+        "type": "function_call",
+        "id": format!("fc_{}", call_id),
+        "call_id": call_id,
+        "name": call.pointer("/function/name"),
+        "arguments": arguments,
+        "status": status,
+    })
+}
+
+fn usage_of(completion: &Value) -> Value {
+    let tokens = |pointer: &str| completion.pointer(pointer).and_then(Value::as_u64).unwrap_or(0);
+    json!({
+        "input_tokens": tokens("/usage/prompt_tokens"),
+        "input_tokens_details": { "cached_tokens": tokens("/usage/prompt_tokens_details/cached_tokens") },
+        "output_tokens": tokens("/usage/completion_tokens"),
+        "output_tokens_details": { "reasoning_tokens": 0 },
+        "total_tokens": tokens("/usage/total_tokens"),
+    })
+}
+
+// A chat completion as a response object, named after the requested model
+pub fn from_chat_response(completion: &Value, model: &str, echo: &Map<String, Value>) -> Value {
+    let id = suffix(completion.get("id").and_then(Value::as_str));
+    let choice = completion.pointer("/choices/0").unwrap_or(&Value::Null);
+    let message = choice.get("message").unwrap_or(&Value::Null);
+    let mut output = Vec::new();
+    if let Some(text) = message.get("content").and_then(Value::as_str).filter(|text| !text.is_empty()) {
+        output.push(message_item(&format!("msg_{}", id), text, "completed"));
+    }
+    for call in message.get("tool_calls").and_then(Value::as_array).into_iter().flatten() {
+        let arguments = call.pointer("/function/arguments").and_then(Value::as_str).unwrap_or("{}");
+        output.push(function_call_item(call, arguments, "completed"));
+    }
+    let status = match choice.get("finish_reason").and_then(Value::as_str) {
+        Some("length") => "incomplete",
+        _ => "completed",
+    };
+    let created = completion.get("created").cloned().unwrap_or(Value::Null);
+    response_object(&format!("resp_{}", id), &created, model, echo, status, output, usage_of(completion))
+}
+
+// The output item of a translated stream that is still being written
+struct OpenItem {
+    // Index of the tool call in the chat completion's deltas, None for the message
+    tool_call: Option<u64>,
+    item: Value,
+    // Text of the message or arguments of the call so far
+    text: String,
+}
+
+// Turns the server-sent events of a streamed chat completion into the events of a streamed
+// response. Chunks may end anywhere, incomplete lines wait for the next one.
+pub struct StreamTranslator {
+    model: String,
+    echo: Map<String, Value>,
+    pending: Vec<u8>,
+    sequence: u64,
+    // Suffix of the ids, set with the first chunk
+    id: Option<String>,
+    created: Value,
+    output: Vec<Value>,
+    open: Option<OpenItem>,
+    incomplete: bool,
+    usage: Value,
+    finished: bool,
+}
+
+impl StreamTranslator {
+    pub fn new(model: &str, echo: Map<String, Value>) -> Self {
+        StreamTranslator {
+            model: model.to_string(),
+            echo,
+            pending: Vec::new(),
+            sequence: 0,
+            id: None,
+            created: Value::Null,
+            output: Vec::new(),
+            open: None,
+            incomplete: false,
+            usage: Value::Null,
+            finished: false,
+        }
+    }
+
+    // Events for a chunk of the chat completion stream
+    pub fn feed(&mut self, chunk: &[u8]) -> String {
+        self.pending.extend_from_slice(chunk);
+        let mut events = String::new();
+        while let Some(end) = self.pending.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.pending.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            let Some(data) = line.trim().strip_prefix("data:").map(str::trim) else {
+                continue;
+            };
+            if data == "[DONE]" {
+                self.close(&mut events);
+            } else if let Ok(chunk) = serde_json::from_str::<Value>(data) {
+                self.on_chunk(&chunk, &mut events);
+            }
+        }
+        events
+    }
+
+    // Closing events of a stream that ended without `[DONE]`
+    pub fn finish(&mut self) -> String {
+        let mut events = String::new();
+        self.close(&mut events);
+        events
+    }
+
+    fn response(&self, status: &str) -> Value {
+        let id = format!("resp_{}", self.id.as_deref().unwrap_or_default());
+        let output = self.output.clone();
+        response_object(&id, &self.created, &self.model, &self.echo, status, output, self.usage.clone())
+    }
+
+    fn on_chunk(&mut self, chunk: &Value, events: &mut String) {
+        if let Some(error) = chunk.get("error") {
+            let error = json!({ "code": error.get("code"), "message": error.get("message"), "param": null });
+            self.push(events, "error", error);
+            return;
+        }
+        if self.id.is_none() {
+            self.id = Some(suffix(chunk.get("id").and_then(Value::as_str)).to_string());
+            self.created = chunk.get("created").cloned().unwrap_or(Value::Null);
+            let response = self.response("in_progress");
+            self.push(events, "response.created", json!({ "response": response }));
+            self.push(events, "response.in_progress", json!({ "response": response }));
+        }
+        if chunk.get("usage").is_some_and(Value::is_object) {
+            self.usage = usage_of(chunk);
+        }
+        let Some(choice) = chunk.pointer("/choices/0") else {
+            return;
+        };
+        let delta = choice.get("delta").unwrap_or(&Value::Null);
+        if let Some(text) = delta.get("content").and_then(Value::as_str).filter(|text| !text.is_empty()) {
+            if self.open.as_ref().is_none_or(|open| open.tool_call.is_some()) {
+                let id = format!("msg_{}", self.id.as_deref().unwrap_or_default());
+                self.open_item(None, message_item(&id, "", "in_progress"), events);
+            }
+            self.push_delta("response.output_text.delta", text, events);
+        }
+        for call in delta.get("tool_calls").and_then(Value::as_array).into_iter().flatten() {
+            let index = call.get("index").and_then(Value::as_u64).unwrap_or(0);
+            if self.open.as_ref().is_none_or(|open| open.tool_call != Some(index)) {
+                self.open_item(Some(index), function_call_item(call, "", "in_progress"), events);
+            }
+            if let Some(arguments) = call.pointer("/function/arguments").and_then(Value::as_str)
+                && !arguments.is_empty()
+            {
+                self.push_delta("response.function_call_arguments.delta", arguments, events);
+            }
+        }
+        if choice.get("finish_reason").and_then(Value::as_str) == Some("length") {
+            self.incomplete = true;
+        }
+    }
+
+    fn open_item(&mut self, tool_call: Option<u64>, item: Value, events: &mut String) {
+        self.close_item(events);
+        let output_index = self.output.len();
+        let added = json!({ "output_index": output_index, "item": item });
+        self.push(events, "response.output_item.added", added);
+        if tool_call.is_none() {
+            let part = json!({
+                "item_id": item["id"],
+                "output_index": output_index,
+                "content_index": 0,
+                "part": output_text(""),
+            });
+            self.push(events, "response.content_part.added", part);
+        }
+        self.open = Some(OpenItem { tool_call, item, text: String::new() });
+    }
+
+    fn push_delta(&mut self, name: &str, delta: &str, events: &mut String) {
+        let output_index = self.output.len();
+        let Some(open) = self.open.as_mut() else {
+            return;
+        };
+        open.text.push_str(delta);
+        let mut fields = json!({ "item_id": open.item["id"], "output_index": output_index, "delta": delta });
+        if open.tool_call.is_none() {
+            fields["content_index"] = json!(0);
+        }
+        self.push(events, name, fields);
+    }
+
+    fn close_item(&mut self, events: &mut String) {
+        let Some(OpenItem { tool_call, mut item, text }) = self.open.take() else {
+            return;
+        };
+        let output_index = self.output.len();
+        let item_id = item["id"].clone();
+        item["status"] = json!("completed");
+        if tool_call.is_none() {
+            let done = json!({
+                "item_id": item_id,
+                "output_index": output_index,
+                "content_index": 0,
+                "text": text,
+            });
+            self.push(events, "response.output_text.done", done);
+            let part = json!({
+                "item_id": item_id,
+                "output_index": output_index,
+                "content_index": 0,
+                "part": output_text(&text),
+            });
+            self.push(events, "response.content_part.done", part);
+            item["content"] = json!([output_text(&text)]);
+        } else {
+            let done = json!({ "item_id": item_id, "output_index": output_index, "arguments": text });
+            self.push(events, "response.function_call_arguments.done", done);
+            item["arguments"] = json!(text);
+        }
+        self.push(events, "response.output_item.done", json!({ "output_index": output_index, "item": item }));
+        self.output.push(item);
+    }
+
+    fn close(&mut self, events: &mut String) {
+        if self.finished || self.id.is_none() {
+            return;
+        }
+        self.finished = true;
+        self.close_item(events);
+        let (name, status) = if self.incomplete {
+            ("response.incomplete", "incomplete")
+        } else {
+            ("response.completed", "completed")
+        };
+        let response = self.response(status);
+        self.push(events, name, json!({ "response": response }));
+    }
+
+    // An event with its type and sequence number added to `fields`
+    fn push(&mut self, events: &mut String, name: &str, mut fields: Value) {
+        fields["type"] = json!(name);
+        fields["sequence_number"] = json!(self.sequence);
+        self.sequence += 1;
+        events.push_str(&format!("event: {}\ndata: {}\n\n", name, fields));
+    }
+}
@@ -1,44 +1,59 @@
 // External crates
+use actix_web::http::StatusCode;
 use actix_web::{web, HttpMessage, HttpRequest, HttpResponse, Responder};
+use log::info;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
 
 // Standard library
 use std::collections::HashMap;
 use std::sync::Arc;
 
 // Internal modules
-use crate::auth::AuthInfo;
+use crate::auth::{caller_is_operator, AuthInfo};
+use crate::body::JsonBody;
+use crate::errors::ApiError;
+use crate::concurrency::RouteClass;
+use crate::health::HealthState;
 use crate::state::{
     AppState,
-    load_endpoints_from_yaml,
-    load_auth_tokens_from_yaml,
-    partition_endpoints,
+    normalize_url,
+    validate_endpoint,
+    Endpoint,
+    TASKS,
 };
-use crate::monitoring::monitor_endpoint;
+use crate::monitoring::spawn_monitor;
+use crate::reload::reload;
+use crate::shutdown;
 
 // -----------------------------------------------------------------------------
 // Handlers
 // -----------------------------------------------------------------------------
 
-// -- Handler: /endpoints (returns both generate and embed) --------------------
+// -- Handler: /endpoints (returns endpoints of all tasks) ---------------------
 pub async fn endpoints_handler(req: HttpRequest, state: web::Data<Arc<AppState>>) -> impl Responder {
     let auth_info = match req.extensions().get::<AuthInfo>() {
         Some(info) => info.clone(),
-        None => return HttpResponse::Unauthorized().finish(),
+        None => return ApiError::unauthorized().into_response(),
     };
     let user_groups = &auth_info.groups;
 
-    let endpoints_generate = state.endpoints_generate.lock().unwrap().clone();
-    let endpoints_embed = state.endpoints_embed.lock().unwrap().clone();
-
-    let filtered_endpoints: Vec<serde_json::Value> = endpoints_generate
+    let filtered_endpoints: Vec<serde_json::Value> = state
+        .all_endpoints()
         .into_iter()
-        .chain(endpoints_embed.into_iter())
         .filter(|ep| ep.groups.iter().any(|g| user_groups.contains(g)))
         .map(|ep| {
-            // Convert to JSON, remove the "access_tokens" field, and return the modified JSON.
+            // Convert to JSON, remove the "access_tokens" field, the proxy password and the values of
+            // the endpoint's headers, and return the modified JSON.
             let mut value = serde_json::to_value(ep).unwrap();
             if let serde_json::Value::Object(ref mut map) = value {
                 map.remove("access_token");
+                if let Some(serde_json::Value::Object(proxy)) = map.get_mut("proxy") {
+                    proxy.remove("password");
+                }
+                if let Some(serde_json::Value::Object(headers)) = map.get_mut("headers") {
+                    headers.values_mut().for_each(|value| *value = serde_json::Value::from("[REDACTED]"));
+                }
             }
             value
         })
@@ -51,33 +66,62 @@ pub async fn endpoints_handler(req: HttpRequest, state: web::Data<Arc<AppState>>
 pub async fn health_status_handler(req: HttpRequest, state: web::Data<Arc<AppState>>) -> impl Responder {
     let auth_info = match req.extensions().get::<AuthInfo>() {
         Some(info) => info.clone(),
-        None => return HttpResponse::Unauthorized().finish(),
+        None => return ApiError::unauthorized().into_response(),
     };
     let user_groups = &auth_info.groups;
 
-    // Lock endpoints
-    let endpoints_generate = state.endpoints_generate.lock().unwrap().clone();
-    let endpoints_embed = state.endpoints_embed.lock().unwrap().clone();
-
-    let health_status_generate = state.health_status_generate.lock().unwrap();
-    let health_status_embed = state.health_status_embed.lock().unwrap();
-
     let mut combined_status = HashMap::new();
 
-    // Process generate-based endpoints
-    for endpoint in endpoints_generate {
-        if endpoint.groups.iter().any(|g| user_groups.contains(g)) {
-            if let Some(hs) = health_status_generate.get(&endpoint.url) {
-                combined_status.insert(endpoint.url.clone(), hs);
-            }
-        }
-    }
-
-    // Process embed-based endpoints
-    for endpoint in endpoints_embed {
-        if endpoint.groups.iter().any(|g| user_groups.contains(g)) {
-            if let Some(hs) = health_status_embed.get(&endpoint.url) {
-                combined_status.insert(endpoint.url.clone(), hs);
+    // Process the endpoints of each task
+    for (_, task_state) in state.tasks() {
+        let endpoints = task_state.endpoints.lock().unwrap().clone();
+        let endpoint_models = task_state.endpoint_models.lock().unwrap().clone();
+        let health_status = task_state.health_status.lock().unwrap();
+        for endpoint in endpoints {
+            if endpoint.groups.iter().any(|g| user_groups.contains(g))
+                && let Some(hs) = health_status.get(&endpoint.url)
+            {
+                let mut status = serde_json::to_value(hs).unwrap();
+                status["routable"] = json!(hs.is_routable());
+                let truncating = state.truncation.flagged_models(&endpoint.url);
+                if !truncating.is_empty() {
+                    status["truncation_anomaly"] = json!(truncating);
+                }
+                status["circuit"] = json!(state.breakers.state_label(&endpoint.url));
+                status["outlier"] = state.outliers.status(&endpoint.url);
+                status["slow_start"] = state.slow_start.status(&endpoint.url);
+                if let Some(check) = state.deep_checks.lock().unwrap().get(&endpoint.url) {
+                    status["deep_check"] = json!(check);
+                }
+                status["in_flight"] = json!(state.model_stats.endpoint_in_flight(&endpoint.url));
+                // Served models, with the moving averages of the requests timed for them
+                let models: Vec<&str> = endpoint_models
+                    .get(&endpoint.url)
+                    .map(|models| models.iter().filter_map(|m| m.get("id")?.as_str()).collect())
+                    .unwrap_or_default();
+                let latencies: Map<String, Value> = models
+                    .iter()
+                    .map(|model| (*model, state.model_stats.latency(&endpoint.url, model)))
+                    .filter(|(_, latency)| latency.ttft_ms.is_some() || latency.total_ms.is_some())
+                    .map(|(model, latency)| {
+                        (model.to_string(), json!({ "ttft_ms": latency.ttft_ms, "total_ms": latency.total_ms }))
+                    })
+                    .collect();
+                status["models"] = json!(models);
+                status["inference_latency_ms"] = Value::Object(latencies);
+                if let Some(pools) = &endpoint.pools {
+                    let pool = |class: RouteClass| {
+                        json!({
+                            "in_flight": state.endpoint_pools.in_flight(&endpoint.url, class),
+                            "limit": pools.limit(class),
+                        })
+                    };
+                    status["pools"] = json!({
+                        "interactive": pool(RouteClass::Interactive),
+                        "batch": pool(RouteClass::Batch),
+                    });
+                }
+                combined_status.insert(endpoint.url.clone(), status);
             }
         }
     }
@@ -85,68 +129,251 @@ pub async fn health_status_handler(req: HttpRequest, state: web::Data<Arc<AppSta
     HttpResponse::Ok().json(combined_status)
 }
 
-// -- Handler: /reload (resets and reapplies both sets) ------------------------
+// -- Handler: /reload (reapplies endpoints.yaml and secrets.yaml) --------------
 pub async fn reload_handler(req: HttpRequest, state: web::Data<Arc<AppState>>) -> impl Responder {
     // Auth check
     let auth_info = match req.extensions().get::<AuthInfo>() {
         Some(info) => info.clone(),
-        None => return HttpResponse::Unauthorized().finish(),
+        None => return ApiError::unauthorized().into_response(),
     };
-    if !auth_info.groups.contains(&"admin".to_string())
-        && !auth_info.groups.contains(&"staff".to_string())
-    {
-        return HttpResponse::Forbidden().finish();
+    if !auth_info.is_operator() {
+        return ApiError::forbidden().into_response();
     }
 
-    match load_endpoints_from_yaml() {
-        Ok(new_endpoints) => {
-            let (new_generate, new_embed) = partition_endpoints(new_endpoints.clone());
-            {
-                let mut gen_lock = state.endpoints_generate.lock().unwrap();
-                *gen_lock = new_generate;
-                let mut emb_lock = state.endpoints_embed.lock().unwrap();
-                *emb_lock = new_embed;
-            }
-            {
-                state.health_status_generate.lock().unwrap().clear();
-                state.health_status_embed.lock().unwrap().clear();
-            }
-            {
-                state.endpoint_models_generate.lock().unwrap().clear();
-                state.endpoint_models_embed.lock().unwrap().clear();
-            }
-            {
-                state.model_to_endpoints_generate.lock().unwrap().clear();
-                state.model_to_endpoints_embed.lock().unwrap().clear();
-            }
+    match reload(state.get_ref()) {
+        Ok(summary) => HttpResponse::Ok().body(format!("Reloaded endpoints: {}", summary)),
+        Err(msg) => ApiError::internal(msg).into_response(),
+    }
+}
 
-            // Reload auth tokens
-            match load_auth_tokens_from_yaml() {
-                Ok(new_auth_tokens) => {
-                    let mut auth_tokens = state.auth_tokens.lock().unwrap();
-                    *auth_tokens = new_auth_tokens;
-                }
-                Err(e) => {
-                    return HttpResponse::InternalServerError()
-                        .body(format!("Failed to load auth tokens YAML: {}", e));
-                }
-            }
+// -- Handler: /health ---------------------------------------------------------
+pub async fn health_handler(state: web::Data<Arc<AppState>>) -> impl Responder {
+    // Load balancers take a draining replica out of rotation
+    if state.shutdown.is_draining() {
+        return ApiError::unavailable("The server is shutting down.").code("shutting_down").into_response();
+    }
+    HttpResponse::Ok().finish()
+}
 
-            // Spin up monitors again
-            for endpoint in new_endpoints {
-                let state_clone = state.get_ref().clone();
-                tokio::spawn(async move {
-                    monitor_endpoint(endpoint, state_clone).await;
-                });
+// -- Handler: /ready ----------------------------------------------------------
+// Unlike /health, fails while this replica can't serve: its config didn't load or a task with
+// endpoints has none healthy, so orchestrators stop sending it traffic
+pub async fn ready_handler(state: web::Data<Arc<AppState>>) -> impl Responder {
+    let mut reasons = Vec::new();
+    if state.shutdown.is_draining() {
+        reasons.push("The server is shutting down.".to_string());
+    }
+    if let Some(error) = state.config_error.lock().unwrap().clone() {
+        reasons.push(error);
+    }
+
+    let mut tasks = Map::new();
+    for (task, task_state) in state.tasks() {
+        let endpoints = task_state.endpoints.lock().unwrap().len();
+        if endpoints == 0 {
+            continue;
+        }
+        let health_status = task_state.health_status.lock().unwrap();
+        let is_routable = |url: &String| health_status.get(url).is_some_and(|health| health.is_routable());
+        let healthy = health_status.iter().filter(|(url, _)| is_routable(url)).count();
+        let mut models: Vec<&String> = Vec::new();
+        let model_to_endpoints = task_state.model_to_endpoints.lock().unwrap();
+        for (model, urls) in model_to_endpoints.iter() {
+            if urls.iter().any(is_routable) {
+                models.push(model);
             }
+        }
+        models.sort();
+        if healthy == 0 {
+            reasons.push(format!("No healthy {} endpoint.", task));
+        }
+        tasks.insert(
+            task.to_string(),
+            json!({ "endpoints": endpoints, "healthy_endpoints": healthy, "models": models }),
+        );
+    }
+    if tasks.is_empty() {
+        reasons.push("No endpoints configured.".to_string());
+    }
+
+    let status = if reasons.is_empty() { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    HttpResponse::build(status).json(json!({
+        "ready": reasons.is_empty(),
+        "reasons": reasons,
+        "tasks": tasks,
+    }))
+}
 
-            HttpResponse::Ok().body("Reloaded endpoints and reset all statuses")
+// -- Handler: /metrics (Prometheus) -------------------------------------------
+pub async fn metrics_handler(req: HttpRequest, state: web::Data<Arc<AppState>>) -> impl Responder {
+    let auth_info = match req.extensions().get::<AuthInfo>() {
+        Some(info) => info.clone(),
+        None => return ApiError::unauthorized().into_response(),
+    };
+    // Metrics name every endpoint, so only operators and scrapers get them
+    if !auth_info.reads_metrics() {
+        return ApiError::forbidden().into_response();
+    }
+
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(state.metrics.render(&state))
+}
+
+// -- Handler: /admin/auth-version (active secrets) ----------------------------
+pub async fn auth_version_handler(req: HttpRequest, state: web::Data<Arc<AppState>>) -> impl Responder {
+    let auth_info = match req.extensions().get::<AuthInfo>() {
+        Some(info) => info.clone(),
+        None => return ApiError::unauthorized().into_response(),
+    };
+    if !auth_info.is_operator() {
+        return ApiError::forbidden().into_response();
+    }
+
+    // Compare the fingerprint across replicas to confirm a rotation reached all of them
+    let auth_tokens = state.auth_tokens.load();
+    HttpResponse::Ok().json(json!({
+        "version": auth_tokens.version,
+        "fingerprint": auth_tokens.fingerprint,
+        "loaded_at_ms": auth_tokens.loaded_ms,
+        "groups": auth_tokens.groups.len(),
+    }))
+}
+
+// What POST /admin/endpoint-state expects
+#[derive(Debug, Deserialize)]
+pub struct EndpointStateRequest {
+    url: String,
+    // "draining", "maintenance" or "active"
+    state: String,
+}
+
+// -- Handler: /admin/endpoint-state (drain or pause an endpoint) --------------
+pub async fn endpoint_state_handler(
+    req: HttpRequest,
+    state: web::Data<Arc<AppState>>,
+    body: JsonBody,
+) -> impl Responder {
+    let auth_info = match req.extensions().get::<AuthInfo>() {
+        Some(info) => info.clone(),
+        None => return ApiError::unauthorized().into_response(),
+    };
+    if !auth_info.is_operator() {
+        return ApiError::forbidden().into_response();
+    }
+
+    let JsonBody(body) = body;
+    let request: EndpointStateRequest = match serde_json::from_value(body) {
+        Ok(request) => request,
+        Err(e) => return ApiError::bad_request(format!("Invalid request: {}", e)).into_response(),
+    };
+    let admin_state = match request.state.as_str() {
+        "draining" => Some(HealthState::Draining),
+        "maintenance" => Some(HealthState::Maintenance),
+        "active" => None,
+        _ => {
+            return ApiError::bad_request("`state` must be \"draining\", \"maintenance\" or \"active\".")
+                .param("state")
+                .into_response();
         }
-        Err(e) => HttpResponse::InternalServerError().body(format!("Failed to load YAML: {}", e)),
+    };
+    match state.set_admin_state(&request.url, admin_state) {
+        Ok(()) => HttpResponse::Ok().json(json!({
+            "url": request.url,
+            "state": request.state,
+            // A draining endpoint can be taken down once this reaches 0
+            "in_flight": state.model_stats.endpoint_in_flight(&normalize_url(&request.url).unwrap_or_default()),
+        })),
+        Err(msg) => ApiError::not_found(msg).into_response(),
     }
 }
 
-// -- Handler: /health ---------------------------------------------------------
-pub async fn health_handler() -> impl Responder {
-    HttpResponse::Ok().finish()
-}
\ No newline at end of file
+// -- Handler: POST /admin/endpoints (add an endpoint) -------------------------
+pub async fn add_endpoint_handler(
+    req: HttpRequest,
+    state: web::Data<Arc<AppState>>,
+    body: JsonBody,
+) -> impl Responder {
+    match caller_is_operator(&req) {
+        None => return ApiError::unauthorized().into_response(),
+        Some(false) => return ApiError::forbidden().into_response(),
+        Some(true) => {}
+    }
+    let JsonBody(body) = body;
+    let mut endpoint: Endpoint = match serde_json::from_value(body) {
+        Ok(endpoint) => endpoint,
+        Err(e) => return ApiError::bad_request(format!("Invalid endpoint: {}", e)).into_response(),
+    };
+    if let Err(msg) = validate_endpoint(&mut endpoint) {
+        return ApiError::bad_request(msg).into_response();
+    }
+    if endpoint.resolve.is_some() {
+        let msg = "DNS templates (`resolve`) are configured in endpoints.yaml.";
+        return ApiError::bad_request(msg).param("resolve").into_response();
+    }
+    if !state.add_endpoint(endpoint.clone()) {
+        let msg = format!("A {} endpoint {} already exists.", endpoint.task, endpoint.url);
+        return ApiError::new(StatusCode::CONFLICT, msg).into_response();
+    }
+    info!("Added {} endpoint {} via the admin API", endpoint.task, endpoint.url);
+    let created = json!({ "url": endpoint.url, "task": endpoint.task });
+    spawn_monitor(endpoint, state.get_ref().clone());
+    HttpResponse::Created().json(created)
+}
+
+// What DELETE /admin/endpoints expects
+#[derive(Debug, Deserialize)]
+pub struct RemoveEndpointQuery {
+    url: String,
+    // All tasks if left out
+    #[serde(default)]
+    task: Option<String>,
+}
+
+// -- Handler: DELETE /admin/endpoints?url=...[&task=...] (remove an endpoint) -
+pub async fn remove_endpoint_handler(
+    req: HttpRequest,
+    state: web::Data<Arc<AppState>>,
+    query: web::Query<RemoveEndpointQuery>,
+) -> impl Responder {
+    match caller_is_operator(&req) {
+        None => return ApiError::unauthorized().into_response(),
+        Some(false) => return ApiError::forbidden().into_response(),
+        Some(true) => {}
+    }
+    let url = match normalize_url(&query.url) {
+        Ok(url) => url,
+        Err(msg) => return ApiError::bad_request(msg).into_response(),
+    };
+    let tasks: Vec<&str> = match &query.task {
+        Some(task) if TASKS.contains(&task.as_str()) => vec![task.as_str()],
+        Some(task) => {
+            return ApiError::bad_request(format!("Invalid task value: {}", task)).param("task").into_response();
+        }
+        None => TASKS.to_vec(),
+    };
+    // Their monitors exit on their own, in-flight requests finish
+    let removed: Vec<&str> = tasks.into_iter().filter(|task| state.remove_endpoint(task, &url)).collect();
+    if removed.is_empty() {
+        return ApiError::not_found(format!("No endpoint {}.", url)).into_response();
+    }
+    info!("Removed {:?} endpoint {} via the admin API", removed, url);
+    HttpResponse::NoContent().finish()
+}
+
+// -- Handler: POST /drain (shut down once requests in flight are done) --------
+pub async fn drain_handler(req: HttpRequest, state: web::Data<Arc<AppState>>) -> impl Responder {
+    match caller_is_operator(&req) {
+        None => return ApiError::unauthorized().into_response(),
+        Some(false) => return ApiError::forbidden().into_response(),
+        Some(true) => {}
+    }
+    // Repeated calls report progress, the drain started first keeps its deadline
+    let already_draining = !shutdown::start_drain(state.get_ref(), "/drain");
+    HttpResponse::Accepted().json(json!({
+        "draining": true,
+        "already_draining": already_draining,
+        "in_flight": state.model_stats.total_in_flight(),
+        "deadline_secs": state.shutdown.deadline.as_secs(),
+    }))
+}
@@ -0,0 +1,120 @@
+// External crates
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use log::info;
+use serde::Deserialize;
+use serde_json::json;
+
+// Standard library
+use std::sync::Arc;
+
+// Internal modules
+use crate::auth::caller_is_operator;
+use crate::body::JsonBody;
+use crate::errors::ApiError;
+use crate::logging::{self, DebugScope};
+use crate::shared::now_ms;
+use crate::state::{normalize_url, AppState};
+
+// How long a debug scope lasts unless the request says otherwise
+const DEFAULT_DEBUG_SECS: u64 = 900;
+
+// What PUT /admin/logging expects; fields left out stay as they are
+#[derive(Debug, Deserialize)]
+pub struct LoggingRequest {
+    // env_logger filters, e.g. "info,vllm_middleware::routing=debug"
+    #[serde(default)]
+    filters: Option<String>,
+    // Trace requests of these models or endpoints, null ends tracing
+    #[serde(default, deserialize_with = "present")]
+    debug: Option<Option<DebugRequest>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DebugRequest {
+    #[serde(default)]
+    models: Vec<String>,
+    #[serde(default)]
+    endpoints: Vec<String>,
+    #[serde(default)]
+    duration_secs: Option<u64>,
+}
+
+// Tell a null field apart from a missing one
+fn present<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Option<Option<DebugRequest>>, D::Error> {
+    Option::<DebugRequest>::deserialize(deserializer).map(Some)
+}
+
+fn logging_status(state: &AppState) -> HttpResponse {
+    HttpResponse::Ok().json(json!({
+        "filters": logging::filters(),
+        "debug": state.debug_traces.scope(),
+    }))
+}
+
+// -- Handler: GET /admin/logging (active filters and debug scope) -------------
+pub async fn get_logging_handler(req: HttpRequest, state: web::Data<Arc<AppState>>) -> impl Responder {
+    match caller_is_operator(&req) {
+        None => return ApiError::unauthorized().into_response(),
+        Some(false) => return ApiError::forbidden().into_response(),
+        Some(true) => {}
+    }
+    logging_status(&state)
+}
+
+// -- Handler: PUT /admin/logging (change filters, trace requests) -------------
+pub async fn put_logging_handler(
+    req: HttpRequest,
+    state: web::Data<Arc<AppState>>,
+    body: JsonBody,
+) -> impl Responder {
+    match caller_is_operator(&req) {
+        None => return ApiError::unauthorized().into_response(),
+        Some(false) => return ApiError::forbidden().into_response(),
+        Some(true) => {}
+    }
+    let JsonBody(body) = body;
+    let request: LoggingRequest = match serde_json::from_value(body) {
+        Ok(request) => request,
+        Err(e) => return ApiError::bad_request(format!("Invalid request: {}", e)).into_response(),
+    };
+
+    // Validate everything before changing anything
+    let scope = match request.debug {
+        Some(Some(debug)) => {
+            if debug.models.is_empty() && debug.endpoints.is_empty() {
+                return ApiError::bad_request("`debug` must name `models` or `endpoints`.").into_response();
+            }
+            let endpoints: Result<Vec<String>, String> =
+                debug.endpoints.iter().map(|url| normalize_url(url)).collect();
+            let endpoints = match endpoints {
+                Ok(endpoints) => endpoints,
+                Err(msg) => return ApiError::bad_request(msg).into_response(),
+            };
+            let duration_secs = debug.duration_secs.unwrap_or(DEFAULT_DEBUG_SECS);
+            Some(Some(DebugScope {
+                models: debug.models,
+                endpoints,
+                until_ms: now_ms() + duration_secs * 1000,
+            }))
+        }
+        Some(None) => Some(None),
+        None => None,
+    };
+    if let Some(filters) = &request.filters {
+        if let Err(msg) = logging::set_filters(filters) {
+            return ApiError::bad_request(msg).into_response();
+        }
+        info!("Log filters set to `{}`", filters);
+    }
+    if let Some(scope) = scope {
+        match &scope {
+            Some(scope) => info!(
+                "Tracing requests of models {:?} and endpoints {:?}",
+                scope.models, scope.endpoints
+            ),
+            None => info!("Request tracing ended"),
+        }
+        state.debug_traces.set(scope);
+    }
+    logging_status(&state)
+}
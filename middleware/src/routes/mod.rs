@@ -1,21 +1,61 @@
 pub mod endpoints;
+pub mod logging;
 pub mod models;
+pub mod playground;
 pub mod proxy;
+pub mod register;
+pub mod telemetry;
+pub mod tokens;
+pub mod usage;
 
 pub use endpoints::{
     endpoints_handler,
     health_status_handler,
     reload_handler,
     health_handler,
+    ready_handler,
+    metrics_handler,
+    auth_version_handler,
+    endpoint_state_handler,
+    add_endpoint_handler,
+    remove_endpoint_handler,
+    drain_handler,
 };
 
 pub use models::{
     models_handler,
+    admin_models_handler,
     model_to_endpoints_handler,
 };
 
 pub use proxy::{
     chat_completions_handler,
+    messages_handler,
+    responses_handler,
     embeddings_handler,
     chat_completions_handler_legacy,
-};
\ No newline at end of file
+    pooling_handler,
+    classify_handler,
+    tokenize_handler,
+    detokenize_handler,
+    score_handler,
+    rerank_handler,
+    rerank_v2_handler,
+    transcriptions_handler,
+    translations_handler,
+    image_generations_handler,
+    moderations_handler,
+    websocket_handler,
+};
+
+pub use usage::{budgets_handler, usage_handler};
+
+pub use playground::{playground_enabled, playground_handler};
+
+pub use register::register_handler;
+
+pub use telemetry::telemetry_handler;
+
+pub use tokens::{create_token_handler, list_tokens_handler, revoke_token_handler};
+
+pub use logging::{get_logging_handler, put_logging_handler};
@@ -1,123 +1,297 @@
 // External crates
 use actix_web::{HttpRequest, HttpResponse, Responder, web, HttpMessage};
+use serde::Deserialize;
 use serde_json::{json, Value};
 
 // Standard library
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::sync::Arc;
 
 // Internal modules
 use crate::auth::AuthInfo;
-use crate::state::{AppState, Endpoint};
+use crate::errors::ApiError;
+use crate::state::{AppState, Endpoint, TaskState};
+use crate::vision::supports_vision;
 
-// -- Handler: /v1/models (combined list from both generate and embed) ----------------
-pub async fn models_handler(req: HttpRequest, state: web::Data<Arc<AppState>>) -> impl Responder {
+#[derive(Debug, Deserialize)]
+pub struct ModelsQuery {
+    // Append live load stats to each entry
+    #[serde(default)]
+    stats: bool,
+    // Append vLLM's model fields and what the model can be used for
+    #[serde(default)]
+    details: bool,
+}
+
+// A model's entries of all endpoints and tasks as one
+#[derive(Default)]
+struct MergedModel {
+    // The earliest of the entries
+    created: Option<u64>,
+    // That of the first entry naming one
+    owned_by: Option<String>,
+    tasks: Vec<&'static str>,
+    // The smallest of the entries, the longest context every endpoint takes
+    max_model_len: Option<u64>,
+    // Those of the first entry
+    root: Option<String>,
+    parent: Option<String>,
+    tools: Capability,
+    vision: Capability,
+}
+
+// Support of a feature across the endpoints of a model: true if any endpoint supports it,
+// false if all of them are known not to
+#[derive(Default)]
+struct Capability {
+    endpoints: usize,
+    supported: Option<bool>,
+}
+
+impl Capability {
+    fn add(&mut self, supported: Option<bool>) {
+        self.supported = match (self.endpoints, self.supported, supported) {
+            (0, _, supported) => supported,
+            (_, Some(true), _) | (_, _, Some(true)) => Some(true),
+            (_, Some(false), Some(false)) => Some(false),
+            _ => None,
+        };
+        self.endpoints += 1;
+    }
+}
+
+// -- Handler: /v1/models (combined list from all tasks) ----------------------------
+// One entry per model id in OpenAI's shape, however many endpoints and tasks serve it
+pub async fn models_handler(
+    req: HttpRequest,
+    state: web::Data<Arc<AppState>>,
+    query: web::Query<ModelsQuery>,
+) -> impl Responder {
     // Retrieve AuthInfo
     let auth_info = match req.extensions().get::<AuthInfo>() {
         Some(info) => info.clone(),
-        None => return HttpResponse::Unauthorized().finish(),
+        None => return ApiError::unauthorized().into_response(),
     };
     let user_groups = &auth_info.groups;
+    let may_use = |ep: &Endpoint| ep.groups.iter().any(|g| user_groups.contains(g));
+    let capabilities = state.endpoint_capabilities.lock().unwrap().clone();
 
-    // Lock endpoints for group checks
-    let endpoints_generate = state.endpoints_generate.lock().unwrap().clone();
-    let endpoints_embed = state.endpoints_embed.lock().unwrap().clone();
-
-    let endpoint_models_generate = state.endpoint_models_generate.lock().unwrap();
-    let endpoint_models_embed = state.endpoint_models_embed.lock().unwrap();
+    let mut merged: BTreeMap<String, MergedModel> = BTreeMap::new();
+    for (task, task_state) in state.tasks() {
+        for (endpoint, model) in served_models(task_state, &may_use) {
+            let Some(id) = model.get("id").and_then(Value::as_str) else {
+                continue;
+            };
+            let entry = merged.entry(id.to_string()).or_default();
+            if let Some(created) = model.get("created").and_then(Value::as_u64) {
+                entry.created = Some(entry.created.map_or(created, |c| c.min(created)));
+            }
+            if entry.owned_by.is_none() {
+                entry.owned_by = model.get("owned_by").and_then(Value::as_str).map(String::from);
+            }
+            if !entry.tasks.contains(&task) {
+                entry.tasks.push(task);
+            }
+            if let Some(len) = model.get("max_model_len").and_then(Value::as_u64) {
+                entry.max_model_len = Some(entry.max_model_len.map_or(len, |l| l.min(len)));
+            }
+            if entry.root.is_none() {
+                entry.root = model.get("root").and_then(Value::as_str).map(String::from);
+            }
+            if entry.parent.is_none() {
+                entry.parent = model.get("parent").and_then(Value::as_str).map(String::from);
+            }
+            entry.tools.add(endpoint.tools);
+            entry.vision.add(supports_vision(&endpoint, &capabilities));
+        }
+    }
 
-    let mut all_models = Vec::new();
+    // vLLM lists LoRA adapters as models with their base model as parent
+    let mut lora_adapters: HashMap<String, Vec<String>> = HashMap::new();
+    for (id, entry) in &merged {
+        if let Some(parent) = entry.parent.as_ref().filter(|parent| *parent != id) {
+            lora_adapters.entry(parent.clone()).or_default().push(id.clone());
+        }
+    }
 
-    // Combine generate models
-    for (endpoint_url, models) in endpoint_models_generate.iter() {
-        if let Some(endpoint) = endpoints_generate.iter().find(|ep| ep.url == *endpoint_url) {
-            if endpoint.groups.iter().any(|g| user_groups.contains(g)) {
-                for model in models {
-                    let mut model_with_url = model.clone();
-                    if let Value::Object(ref mut map) = model_with_url {
-                        map.insert("endpoint_url".to_string(), Value::String(endpoint_url.clone()));
-                        map.insert("task".to_string(), Value::String(endpoint.task.clone()));
-                    }
-                    all_models.push(model_with_url);
-                }
+    let data: Vec<Value> = merged
+        .into_iter()
+        .map(|(id, entry)| {
+            let mut model = json!({
+                "id": id,
+                "object": "model",
+                "created": entry.created.unwrap_or(0),
+                "owned_by": entry.owned_by.unwrap_or_else(|| "vllm".to_string()),
+            });
+            if query.stats {
+                model["composer_stats"] = model_stats(&state, &entry.tasks, &id, &may_use);
             }
-        }
+            if query.details {
+                model["max_model_len"] = json!(entry.max_model_len);
+                model["root"] = json!(entry.root);
+                model["parent"] = json!(entry.parent);
+                model["lora_adapters"] = json!(lora_adapters.remove(&id).unwrap_or_default());
+                model["capabilities"] =
+                    model_capabilities(&entry.tasks, entry.tools.supported, entry.vision.supported);
+            }
+            model
+        })
+        .collect();
+    HttpResponse::Ok().json(json!({
+        "object": "list",
+        "data": data
+    }))
+}
+
+// -- Handler: GET /admin/models (every endpoint's models) ---------------------------
+pub async fn admin_models_handler(
+    req: HttpRequest,
+    state: web::Data<Arc<AppState>>,
+    query: web::Query<ModelsQuery>,
+) -> impl Responder {
+    let auth_info = match req.extensions().get::<AuthInfo>() {
+        Some(info) => info.clone(),
+        None => return ApiError::unauthorized().into_response(),
+    };
+    if !auth_info.is_operator() {
+        return ApiError::forbidden().into_response();
     }
+    let any = |_: &Endpoint| true;
+    let capabilities = state.endpoint_capabilities.lock().unwrap().clone();
 
-    // Combine embed models
-    for (endpoint_url, models) in endpoint_models_embed.iter() {
-        if let Some(endpoint) = endpoints_embed.iter().find(|ep| ep.url == *endpoint_url) {
-            if endpoint.groups.iter().any(|g| user_groups.contains(g)) {
-                for model in models {
-                    let mut model_with_url = model.clone();
-                    if let Value::Object(ref mut map) = model_with_url {
-                        map.insert("endpoint_url".to_string(), Value::String(endpoint_url.clone()));
-                        map.insert("task".to_string(), Value::String(endpoint.task.clone()));
-                    }
-                    all_models.push(model_with_url);
+    // The entries as the endpoints list them, with where they come from
+    let mut all_models = Vec::new();
+    for (_, task_state) in state.tasks() {
+        for (endpoint, mut model) in served_models(task_state, &any) {
+            if let Value::Object(ref mut map) = model {
+                map.insert("endpoint_url".to_string(), Value::String(endpoint.url.clone()));
+                map.insert("task".to_string(), Value::String(endpoint.task.clone()));
+                if query.stats
+                    && let Some(model_id) = map.get("id").and_then(Value::as_str)
+                {
+                    let mut stats = model_stats(&state, &[endpoint.task.as_str()], model_id, &any);
+                    // This endpoint's share, what latency-aware routing goes by
+                    let latency = state.model_stats.latency(&endpoint.url, model_id);
+                    stats["endpoint_ttft_ms"] = json!(latency.ttft_ms.map(|ms| ms.round()));
+                    stats["endpoint_latency_ms"] = json!(latency.total_ms.map(|ms| ms.round()));
+                    map.insert("composer_stats".to_string(), stats);
+                }
+                if query.details {
+                    let vision = supports_vision(&endpoint, &capabilities);
+                    let caps = model_capabilities(&[endpoint.task.as_str()], endpoint.tools, vision);
+                    map.insert("capabilities".to_string(), caps);
                 }
             }
+            all_models.push(model);
         }
     }
-
-    let output = json!({
+    HttpResponse::Ok().json(json!({
         "object": "list",
         "data": all_models
-    });
-    HttpResponse::Ok().json(output)
+    }))
+}
+
+// The models of a task's endpoints that pass `may_use`, with their endpoint
+fn served_models(task_state: &TaskState, may_use: &dyn Fn(&Endpoint) -> bool) -> Vec<(Endpoint, Value)> {
+    let endpoints = task_state.endpoints.lock().unwrap().clone();
+    let endpoint_models = task_state.endpoint_models.lock().unwrap();
+    let mut served = Vec::new();
+    for (endpoint_url, models) in endpoint_models.iter() {
+        if let Some(endpoint) = endpoints.iter().find(|ep| ep.url == *endpoint_url)
+            && may_use(endpoint)
+        {
+            served.extend(models.iter().map(|model| (endpoint.clone(), model.clone())));
+        }
+    }
+    served
+}
+
+// What a model can be used for, so clients can pick one programmatically
+fn model_capabilities(tasks: &[&str], tools: Option<bool>, vision: Option<bool>) -> Value {
+    json!({
+        "tasks": tasks,
+        "chat": tasks.contains(&"generate"),
+        "embeddings": tasks.contains(&"embed"),
+        "transcriptions": tasks.contains(&"transcribe"),
+        "images": tasks.contains(&"image"),
+        "moderations": tasks.contains(&"moderate"),
+        "tools": tools,
+        "vision": vision,
+    })
 }
 
-// -- Handler: /model-to-endpoints (combines generate and embed) --------------------
+// Live load of a model for client-side schedulers choosing among equivalent models
+fn model_stats(
+    state: &AppState,
+    tasks: &[&str],
+    model_id: &str,
+    may_use: &dyn Fn(&Endpoint) -> bool,
+) -> Value {
+    let healthy_endpoints: usize = tasks
+        .iter()
+        .map(|task| {
+            let task_state = state.task(task);
+            let urls = task_state
+                .model_to_endpoints
+                .lock()
+                .unwrap()
+                .get(model_id)
+                .cloned()
+                .unwrap_or_default();
+            let endpoints = task_state.endpoints.lock().unwrap();
+            let health_status = task_state.health_status.lock().unwrap();
+            urls.iter()
+                .filter(|url| {
+                    endpoints.iter().any(|ep| &ep.url == *url && may_use(ep))
+                        && health_status.get(*url).is_some_and(|hs| hs.is_routable())
+                        && state.breakers.is_routable(url)
+                        && !state.outliers.is_ejected(url)
+                        && !state.fails_deep_checks(url)
+                })
+                .count()
+        })
+        .sum();
+    let load = state.model_stats.get(model_id);
+    json!({
+        "healthy_endpoints": healthy_endpoints,
+        "queue_depth": load.in_flight,
+        "avg_ttft_ms": load.avg_ttft_ms.map(|ms| ms.round()),
+    })
+}
+
+// -- Handler: /model-to-endpoints (combines all tasks) -----------------------------
 pub async fn model_to_endpoints_handler(
     req: HttpRequest,
     state: web::Data<Arc<AppState>>,
 ) -> impl Responder {
     let auth_info = match req.extensions().get::<AuthInfo>() {
         Some(info) => info.clone(),
-        None => return HttpResponse::Unauthorized().finish(),
+        None => return ApiError::unauthorized().into_response(),
     };
     let user_groups = &auth_info.groups;
 
-    // Build separate endpoint maps for generate and embed
-    let endpoints_generate = state.endpoints_generate.lock().unwrap().clone();
-    let endpoints_embed = state.endpoints_embed.lock().unwrap().clone();
-    let gen_map: HashMap<String, Endpoint> = endpoints_generate
-        .into_iter()
-        .map(|ep| (ep.url.clone(), ep))
-        .collect();
-    let emb_map: HashMap<String, Endpoint> = endpoints_embed
-        .into_iter()
-        .map(|ep| (ep.url.clone(), ep))
-        .collect();
-
-    let model_to_endpoints_generate = state.model_to_endpoints_generate.lock().unwrap();
-    let model_to_endpoints_embed = state.model_to_endpoints_embed.lock().unwrap();
-
-    // We'll combine them into a single HashMap for the final result
+    // We'll combine all tasks into a single HashMap for the final result
     let mut combined: HashMap<String, HashSet<String>> = HashMap::new();
 
-    // Fill from generate side
-    for (model_id, endpoint_list) in model_to_endpoints_generate.iter() {
-        for url in endpoint_list {
-            if let Some(ep) = gen_map.get(url) {
-                if ep.groups.iter().any(|g| user_groups.contains(g)) {
-                    combined
-                        .entry(model_id.clone())
-                        .or_insert_with(HashSet::new)
-                        .insert(url.clone());
-                }
-            }
-        }
-    }
+    for (_, task_state) in state.tasks() {
+        // Build an endpoint map per task
+        let endpoint_map: HashMap<String, Endpoint> = task_state
+            .endpoints
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|ep| (ep.url.clone(), ep.clone()))
+            .collect();
 
-    // Fill from embed side
-    for (model_id, endpoint_list) in model_to_endpoints_embed.iter() {
-        for url in endpoint_list {
-            if let Some(ep) = emb_map.get(url) {
-                if ep.groups.iter().any(|g| user_groups.contains(g)) {
+        let model_to_endpoints = task_state.model_to_endpoints.lock().unwrap();
+        for (model_id, endpoint_list) in model_to_endpoints.iter() {
+            for url in endpoint_list {
+                if let Some(ep) = endpoint_map.get(url)
+                    && ep.groups.iter().any(|g| user_groups.contains(g))
+                {
                     combined
                         .entry(model_id.clone())
-                        .or_insert_with(HashSet::new)
+                        .or_default()
                         .insert(url.clone());
                 }
             }
@@ -0,0 +1,20 @@
+// External crates
+use actix_web::{HttpResponse, Responder};
+
+// Built into the binary so the playground needs no files at runtime
+const PLAYGROUND_HTML: &str = include_str!("../../static/playground.html");
+
+// Whether /playground is served, off unless VLLM_COMPOSER_PLAYGROUND is true or 1
+pub fn playground_enabled() -> bool {
+    matches!(
+        std::env::var("VLLM_COMPOSER_PLAYGROUND").as_deref(),
+        Ok("true") | Ok("1")
+    )
+}
+
+// -- Handler: /playground (chat page using the caller's token) ---------------
+pub async fn playground_handler() -> impl Responder {
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(PLAYGROUND_HTML)
+}
@@ -1,43 +1,99 @@
 // External crates
 use actix_web::{web, HttpMessage, HttpRequest, HttpResponse, Responder};
+use actix_web::body::{to_bytes, BodyStream, BoxBody, MessageBody};
+use actix_web::http::header::{self, HeaderName, HeaderValue};
+use actix_web::http::{ConnectionType, StatusCode};
 use futures_util::{Stream, StreamExt};
-use log::info;
+use log::{info, warn};
 use reqwest;
-use serde_json::Value;
-use bytes::Bytes;
+use futures::channel::mpsc;
+use futures::future::{join_all, poll_fn, ready};
+use futures::SinkExt;
+use serde_json::{json, Map, Value};
+use bytes::{Bytes, BytesMut};
+use tokio::io::{split, AsyncReadExt, AsyncWriteExt, ReadHalf, WriteHalf};
 use tokio::time::timeout;
 use async_stream::try_stream;
+use tracing::field::Empty;
+use tracing::{info_span, Instrument, Span};
 
 
 // Standard library
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::io::{Error as IoError, ErrorKind};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 // Internal modules
-use crate::auth::AuthInfo;
+use crate::access_log::UpstreamInfo;
+use crate::admission::Ticket;
+use crate::anthropic::{self, error_body};
+use crate::aliases::{rename_model_in_json, rename_model_in_sse, resolve_alias};
+use crate::auth::{AuthInfo, TOKEN_SUBPROTOCOL};
+use crate::body::{JsonBody, ProxyBody};
+use crate::canaries::SYNTHETIC_GROUP;
+use crate::clients::Transport;
+use crate::concurrency::{GroupSlot, RouteClass};
+use crate::conversations::{conversation_id, ConversationKey, CONVERSATION_FIELD};
+use crate::errors::ApiError;
+use crate::logging::{trace_body, DEBUG_TARGET};
+use crate::loops::LoopVerdict;
+use crate::otel;
+use crate::policies::apply_policies;
+use crate::presets::expand_preset;
+use crate::pricing::price_of;
+use crate::responses;
+use crate::routing::{route_request, select_endpoint, select_guard_endpoint, Route, RouteError};
+use crate::sanitize::sanitize_request;
+use crate::metrics::mode_label;
+use crate::moderation::{guard_conversation, guard_request, moderation_inputs, Verdict};
+use crate::multipart::{form_data_content_type, max_upload_bytes, FieldScanner};
 use crate::state::{AppState, Endpoint};
+use crate::shared::now_ms;
+use crate::stats::InFlight;
+use crate::truncation::finish_reasons_from_body;
+use crate::usage_db::RequestRow;
+use crate::usage::{stream_usage_requested, usage_from_body, SseUsageScanner, Usage};
 
 
 // Helpers
-fn stream_with_read_timeout<S>(
+// Relay `upstream`, failing with a TimedOut error when no chunk comes within `chunk_timeout`
+// or the whole stream takes longer than `total`
+fn stream_with_read_timeout<S, F>(
     upstream: S,
+    chunk_timeout: Duration,
+    total: Option<Duration>,
+    on_error: F,
 ) -> impl Stream<Item = Result<Bytes, IoError>>
 where
     S: Stream<Item = reqwest::Result<Bytes>> + Unpin,
+    F: Fn(),
 {
     try_stream! {
         let mut resp_stream = upstream;
+        let deadline = total.map(|total| (Instant::now() + total, total));
 
-        // Loop over each chunk, applying a 30s timeout per chunk
+        // Loop over each chunk, applying the timeout per chunk
         loop {
-            // Wait up to 30s for the next chunk
-            let next_chunk = match timeout(Duration::from_secs(30), resp_stream.next()).await {
+            // Wait up to `chunk_timeout` for the next chunk, less if the deadline comes first
+            let remaining = deadline.map(|(at, _)| at.saturating_duration_since(Instant::now()));
+            let wait = remaining.map_or(chunk_timeout, |remaining| remaining.min(chunk_timeout));
+            let next_chunk = match timeout(wait, resp_stream.next()).await {
                 Ok(res) => res,                 // We got either Some(...) or None from the stream
-                Err(_) => {
-                    // Timed out waiting for the chunk
-                    Err(IoError::new(ErrorKind::TimedOut, "Read timed out"))?
-                }
+                Err(_) => match deadline {
+                    // The endpoint is still sending, it isn't counted as broken
+                    Some((at, total)) if Instant::now() >= at => {
+                        let message = format!("The stream exceeded its limit of {}s.", total.as_secs());
+                        Err(IoError::new(ErrorKind::TimedOut, message))?
+                    }
+                    _ => {
+                        // Timed out waiting for the chunk
+                        on_error();
+                        let message = format!("The endpoint sent nothing for {}s.", chunk_timeout.as_secs());
+                        Err(IoError::new(ErrorKind::TimedOut, message))?
+                    }
+                },
             };
 
             match next_chunk {
@@ -47,7 +103,8 @@ where
                 }
                 Some(Err(e)) => {
                     // Convert reqwest error into IoError
-                    Err(IoError::new(ErrorKind::Other, e))?;
+                    on_error();
+                    Err(IoError::other(e))?;
                 }
                 None => {
                     // Stream ended
@@ -58,342 +115,2256 @@ where
     }
 }
 
-// -- Handler: /v1/chat/completions (for generate) ----------------------------
-pub async fn chat_completions_handler(
+// The end of a stream that timed out: an OpenAI error event in place of the missing chunks,
+// after an empty line that ends an event cut short, and the [DONE] clients wait for
+fn timeout_event(message: &str) -> Bytes {
+    let error = json!({
+        "error": {"message": message, "type": "timeout_error", "param": null, "code": "stream_timeout"}
+    });
+    Bytes::from(format!("\ndata: {}\n\ndata: [DONE]\n\n", error))
+}
+
+// An SSE comment, a whole event that SSE clients ignore
+const HEARTBEAT: &[u8] = b": ping\n\n";
+
+// Send heartbeats every `interval` until the first chunk of a stream, so proxies between the
+// client and the composer don't close the connection while a long prompt is prefilled. Only
+// coming before the first event, they can't split one.
+fn with_heartbeats<S>(upstream: S, interval: Option<Duration>) -> impl Stream<Item = Result<Bytes, IoError>>
+where
+    S: Stream<Item = Result<Bytes, IoError>>,
+{
+    try_stream! {
+        let mut upstream = Box::pin(upstream.fuse());
+        if let Some(interval) = interval {
+            loop {
+                match timeout(interval, upstream.next()).await {
+                    Ok(Some(chunk)) => {
+                        yield chunk?;
+                        break;
+                    }
+                    Ok(None) => break,
+                    Err(_) => yield Bytes::from_static(HEARTBEAT),
+                }
+            }
+        }
+        while let Some(chunk) = upstream.next().await {
+            yield chunk?;
+        }
+    }
+}
+
+// Pass a stream through, handing the usage of its final chunk to `on_usage`, and the finish
+// reasons of its choices and whether any event was malformed to `on_end`. The request
+// counts as in flight and `span` stays open until the stream ends. `rename` maps the served model name back to
+// the requested alias.
+fn tap_usage<S, F, G>(
+    upstream: S,
+    mut in_flight: InFlight,
+    span: Span,
+    strip_usage_events: bool,
+    rename: Option<(String, String)>,
+    on_usage: F,
+    on_end: G,
+) -> impl Stream<Item = Result<Bytes, IoError>>
+where
+    S: Stream<Item = Result<Bytes, IoError>>,
+    F: FnOnce(Usage),
+    G: FnOnce(Vec<String>, bool),
+{
+    try_stream! {
+        let mut resp_stream = Box::pin(upstream);
+        let mut scanner = SseUsageScanner::new(strip_usage_events);
+        // i64 as OTLP exports u64 values as strings
+        let mut chunks = 0i64;
+        while let Some(chunk) = resp_stream.next().await {
+            in_flight.on_chunk();
+            chunks += 1;
+            let chunk = match chunk {
+                Ok(chunk) => chunk,
+                // Ended with an error event, a partial line held back is dropped
+                Err(e) if e.kind() == ErrorKind::TimedOut => {
+                    in_flight.complete();
+                    warn!("Stream timed out: {}", e);
+                    yield timeout_event(&e.to_string());
+                    break;
+                }
+                // Broken by the endpoint, not cancelled by the client
+                Err(e) => {
+                    in_flight.complete();
+                    Err(e)?
+                }
+            };
+            let mut lines = scanner.feed(&chunk);
+            if let Some((served, alias)) = &rename {
+                lines = rename_model_in_sse(&lines, served, alias);
+            }
+            if !lines.is_empty() {
+                yield Bytes::from(lines);
+            }
+        }
+        let (mut rest, usage) = scanner.finish();
+        if let Some((served, alias)) = &rename {
+            rest = rename_model_in_sse(&rest, served, alias);
+        }
+        if !rest.is_empty() {
+            yield Bytes::from(rest);
+        }
+        in_flight.finish();
+        span.record("chunks", chunks);
+        if let Some(usage) = usage {
+            span.record("completion_tokens", usage.completion_tokens as i64);
+            on_usage(usage);
+        }
+        on_end(scanner.finish_reasons(), scanner.malformed());
+    }
+}
+
+// Static description of a proxied route
+struct ProxyRoute {
+    // Task whose endpoints serve the route
+    task: &'static str,
+    // Upstream path the request body is forwarded to
+    path: &'static str,
+    // Whether `"stream": true` requests are relayed as streams
+    streaming: bool,
+}
+
+impl ProxyRoute {
+    // Tokenizing costs next to nothing, only inference counts against budgets and loops
+    fn runs_inference(&self) -> bool {
+        !matches!(self.path, "/tokenize" | "/detokenize")
+    }
+}
+
+// Where a request goes upstream, also used to label metrics
+struct UpstreamRequest<'a> {
+    endpoint: &'a Endpoint,
+    task: &'a str,
+    // Model as requested by the client
+    model: &'a str,
+    // Name the endpoint serves `model` under if it is an alias
+    served_model: Option<&'a str>,
+    path: &'a str,
+    // Caller the response's usage is booked to
+    caller: Option<&'a AuthInfo>,
+    // Conversation whose budget the response's usage counts against
+    conversation: Option<&'a ConversationKey>,
+    // Usage was requested from the backend on the caller's behalf, don't relay it
+    strip_stream_usage: bool,
+    // Where a successful response goes in the response cache
+    cache_key: Option<&'a str>,
+    // Client headers and the caller's header passed on
+    forwarded: &'a reqwest::header::HeaderMap,
+}
+
+impl UpstreamRequest<'_> {
+    // Book the usage of a response, also callable after the request is gone (streams)
+    fn usage_recorder(&self, state: &Arc<AppState>, started: Instant) -> impl FnOnce(Usage) + 'static {
+        let state = Arc::clone(state);
+        let caller = self.caller.map(booked_caller);
+        let model = self.model.to_string();
+        let endpoint = Some(self.endpoint.url.clone());
+        let conversation = self.conversation.cloned();
+        move |usage: Usage| book_usage(&state, caller, &model, endpoint, started, conversation.as_ref(), usage)
+    }
+
+    // Count the finish reasons of a response towards truncation detection
+    fn finish_recorder(&self, state: &Arc<AppState>) -> impl FnOnce(Vec<String>) + 'static {
+        let state = Arc::clone(state);
+        let model = self.model.to_string();
+        let endpoint = self.endpoint.url.clone();
+        move |reasons: Vec<String>| {
+            state.truncation.record(&state.metrics, &model, &endpoint, &reasons);
+        }
+    }
+
+    // Span of the call to the endpoint
+    fn span(&self, url: &str) -> Span {
+        info_span!(
+            "upstream",
+            otel.kind = "client",
+            otel.status_code = Empty,
+            endpoint.url = %self.endpoint.url,
+            model = self.model,
+            url.full = url,
+            http.response.status_code = Empty,
+            error = Empty,
+        )
+    }
+
+    // Span from the first to the last chunk of a streamed response
+    fn stream_span(&self) -> Span {
+        info_span!(
+            "stream",
+            endpoint.url = %self.endpoint.url,
+            model = self.model,
+            chunks = Empty,
+            completion_tokens = Empty,
+        )
+    }
+
+    fn record_error(&self, state: &AppState, error: &reqwest::Error) {
+        let kind = if error.is_timeout() {
+            "timeout"
+        } else if error.is_connect() {
+            // The endpoint may have moved to another address
+            state.http.dns.forget_url(&self.endpoint.url);
+            "connect"
+        } else {
+            "request"
+        };
+        state
+            .metrics
+            .upstream_errors
+            .with_label_values(&[self.task, &self.endpoint.url, kind])
+            .inc();
+    }
+
+    // Name the endpoint and its status on the response for the access log
+    fn answered(&self, mut resp: HttpResponse, status: Option<u16>) -> HttpResponse {
+        resp.extensions_mut().insert(UpstreamInfo { endpoint: self.endpoint.url.clone(), status });
+        resp
+    }
+
+    fn record_response(&self, state: &AppState, stream: bool, status: Option<u16>, started: Instant) {
+        let mode = mode_label(stream);
+        let status = status.map_or_else(|| "error".to_string(), |s| s.to_string());
+        // Streams are answered once their headers arrive, they go on until their last chunk
+        let latency_ms = started.elapsed().as_millis() as u64;
+        info!(
+            task = self.task,
+            model = self.model,
+            endpoint = self.endpoint.url.as_str(),
+            group = caller_groups(self.caller).as_str(),
+            stream,
+            status = status.as_str(),
+            latency_ms;
+            "endpoint {} answered {} request for model {} with {} after {}ms",
+            self.endpoint.url, self.task, self.model, status, latency_ms
+        );
+        state
+            .metrics
+            .requests
+            .with_label_values(&[self.task, self.model, &self.endpoint.url, mode, &status])
+            .inc();
+        state.telemetry.record(self.task, &status);
+        state
+            .metrics
+            .upstream_latency
+            .with_label_values(&[self.task, &self.endpoint.url, mode])
+            .observe(started.elapsed().as_secs_f64());
+        if status.starts_with('5') {
+            state
+                .metrics
+                .upstream_errors
+                .with_label_values(&[self.task, &self.endpoint.url, "upstream_5xx"])
+                .inc();
+        }
+        // Transport errors and 5xx count towards the endpoint's circuit
+        let failed = status == "error" || status.starts_with('5');
+        if failed {
+            state.breakers.record_failure(&self.endpoint.url);
+        } else {
+            state.breakers.record_success(&self.endpoint.url);
+        }
+        // and its error rate; streams count when they end
+        if failed || !stream || !status.starts_with('2') {
+            state.outliers.record(&self.endpoint.url, failed);
+        }
+    }
+}
+
+// Resolve the model of a JSON request to an endpoint of the route's task and forward
+async fn forward_json(
     req: HttpRequest,
     state: web::Data<Arc<AppState>>,
-    body: web::Json<Value>,
-) -> impl Responder {
-    // 1. Check auth
-    let auth_info = match req.extensions().get::<AuthInfo>() {
-        Some(info) => info.clone(),
-        None => return HttpResponse::Unauthorized().finish(),
+    mut body: ProxyBody,
+    route: ProxyRoute,
+) -> HttpResponse {
+    if let Err(resp) = check_draining(&state) {
+        return *resp;
+    }
+    if let Err(resp) = check_budget(&req, &state).await {
+        return *resp;
+    }
+    if let Err(resp) = check_rate_limit(&req, &state).await {
+        return *resp;
+    }
+    let slot = match acquire_concurrency_slot(&req, &state) {
+        Ok(slot) => slot,
+        Err(resp) => return *resp,
     };
-    let user_groups = &auth_info.groups;
-
-    // 2. Extract model
-    let model_id = match body.get("model").and_then(Value::as_str) {
-        Some(m) => m,
-        None => return HttpResponse::NotFound().body("The model `` does not exist."),
+    let auth_info = req.extensions().get::<AuthInfo>().cloned();
+    let conversation = if route.runs_inference() {
+        // The conversation's ID is taken out of the body
+        if body.get(CONVERSATION_FIELD).is_some() {
+            body.rewritten();
+        }
+        match check_conversation_budget(&req, &state, &mut body.json) {
+            Ok(conversation) => conversation,
+            Err(resp) => return *resp,
+        }
+    } else {
+        None
     };
+    if route.runs_inference()
+        && let Err(resp) = check_agent_loop(&req, &state, &body)
+    {
+        return *resp;
+    }
+    // Presets are expanded first so policies bound their values as well
+    match apply_preset(&state, &mut body.json) {
+        Ok(true) => body.rewritten(),
+        Ok(false) => {}
+        Err(resp) => return *resp,
+    }
+    match enforce_policies(&req, &state, &mut body.json) {
+        Ok(true) => body.rewritten(),
+        Ok(false) => {}
+        Err(resp) => return *resp,
+    }
+    if route.path == "/v1/chat/completions"
+        && let Err(resp) = screen_chat(&req, &state, &body).await
+    {
+        return *resp;
+    }
 
-    // 3. Check whether user wants streaming
-    let stream_requested = body.get("stream").and_then(Value::as_bool).unwrap_or(false);
+    // Check whether user wants streaming
+    let stream_requested =
+        route.streaming && body.get("stream").and_then(Value::as_bool).unwrap_or(false);
 
-    // 4. Look in generate's model->endpoints map
-    let model_to_endpoints_generate = state.model_to_endpoints_generate.lock().unwrap();
-    let endpoints_for_model = match model_to_endpoints_generate.get(model_id) {
-        Some(eps) => eps.clone(),
-        None => {
-            return HttpResponse::NotFound()
-                .body(format!("The model `{}` does not exist.", model_id));
+    // Deterministic chat completions answered before come from the cache, without taking a
+    // place in the queue or an endpoint's slot
+    let cache_key = match &auth_info {
+        Some(info) if route.path == "/v1/chat/completions" && !stream_requested => {
+            state.response_cache.key(&req, &body, &info.groups)
         }
+        _ => None,
     };
-    drop(model_to_endpoints_generate);
+    if let Some(cached) = cache_key.as_deref().and_then(|key| state.response_cache.get(key)) {
+        let model_id = body.get("model").and_then(Value::as_str).unwrap_or_default().to_string();
+        info!(
+            task = route.task,
+            model = model_id.as_str(),
+            group = caller_groups(auth_info.as_ref()).as_str();
+            "answered request for model {} from the cache",
+            model_id
+        );
+        let usage = serde_json::from_str::<Value>(&cached).ok().and_then(|c| Usage::from_value(&c["usage"]));
+        if let Some(usage) = usage {
+            let caller = auth_info.as_ref().map(booked_caller);
+            book_usage(&state, caller, &model_id, None, Instant::now(), conversation.as_ref(), usage);
+        }
+        return HttpResponse::Ok()
+            .content_type("application/json")
+            .insert_header(("X-Cache", "hit"))
+            .body(cached);
+    }
 
-    // 5. Filter endpoints by group
-    let endpoints = state.endpoints_generate.lock().unwrap();
-    let endpoints_list = endpoints_for_model
-        .iter()
-        .filter_map(|url| endpoints.iter().find(|e| &e.url == url))
-        .filter(|ep| ep.groups.iter().any(|g| user_groups.contains(g)))
-        .cloned()
-        .collect::<Vec<Endpoint>>();
+    let class = RouteClass::of(route.task, &body);
+    let (Route { endpoint: target_endpoint, fallback_from, .. }, pool_slot) =
+        match admit(&req, &state, &mut body.json, route.task, class).await {
+            Ok(admitted) => admitted,
+            Err(resp) => return *resp,
+        };
+    let model_id = body.get("model").and_then(Value::as_str).unwrap_or_default().to_string();
+    if fallback_from.is_some() {
+        body.set_model(&model_id);
+    }
+
+    let served_model = resolve_alias(&target_endpoint, &model_id);
+    if let Some(served) = served_model {
+        body.set_model(served);
+    }
+    // Streams only report usage on request
+    let strip_stream_usage = stream_requested && !stream_usage_requested(&body);
+    if stream_requested {
+        body.request_stream_usage();
+    }
+
+    // Log the forwarded request details
+    let kind = if stream_requested {
+        "streaming ".to_string()
+    } else if route.task == "generate" {
+        String::new()
+    } else {
+        format!("{} ", route.task)
+    };
+    info!(
+        task = route.task,
+        model = model_id.as_str(),
+        endpoint = target_endpoint.url.as_str(),
+        group = caller_groups(auth_info.as_ref()).as_str(),
+        stream = stream_requested;
+        "forwarded {}request for model {} to endpoint {}",
+        kind, model_id, target_endpoint.url
+    );
 
-    // 6. If no authorized endpoints remain, 404
-    if endpoints_list.is_empty() {
-        return HttpResponse::NotFound()
-            .body(format!("The model `{}` does not exist.", model_id));
+    // Drop fields the endpoint would reject
+    let changes = {
+        let capabilities = state.endpoint_capabilities.lock().unwrap();
+        sanitize_request(&mut body.json, &target_endpoint, &capabilities, route.path)
+    };
+    if !changes.is_empty() {
+        body.rewritten();
+        info!(
+            "adjusted request for endpoint {}: {}",
+            target_endpoint.url,
+            changes.join(", ")
+        );
     }
 
-    // 7. Pick the first one and rotate
-    let target_endpoint = &endpoints_list[0];
+    // The caller named in place of the client's own `user`, which vLLM logs
+    if matches!(route.task, "generate" | "embed")
+        && let Some(user) = state.forwarding.body_user(&req)
     {
-        let mut map_lock = state.model_to_endpoints_generate.lock().unwrap();
-        if let Some(urls) = map_lock.get_mut(model_id) {
-            if let Some(pos) = urls.iter().position(|url| url == &target_endpoint.url) {
-                let url = urls.remove(pos);
-                urls.push(url);
+        body.json["user"] = Value::String(user);
+        body.rewritten();
+    }
+
+    let forwarded = state.forwarding.headers_of(&req);
+    let upstream = UpstreamRequest {
+        endpoint: &target_endpoint,
+        task: route.task,
+        model: &model_id,
+        served_model,
+        path: route.path,
+        caller: auth_info.as_ref(),
+        conversation: conversation.as_ref(),
+        strip_stream_usage,
+        cache_key: cache_key.as_deref(),
+        forwarded: &forwarded,
+    };
+    let slots = slot.into_iter().chain(pool_slot).collect();
+    let resp = match state.embedding_split.chunks(&body).filter(|_| route.task == "embed") {
+        Some(chunks) => forward_embeddings_split(&req, &state, &upstream, &body, chunks, slots).await,
+        None => relay(&state, &upstream, body.to_bytes(), stream_requested, slots).await,
+    };
+    annotate_fallback(resp, fallback_from.as_deref())
+}
+
+// Token id and groups a caller's usage is booked to
+fn booked_caller(info: &AuthInfo) -> (String, Vec<String>) {
+    if info.synthetic {
+        (SYNTHETIC_GROUP.to_string(), vec![SYNTHETIC_GROUP.to_string()])
+    } else {
+        (info.token_id(), info.groups.clone())
+    }
+}
+
+// Book the usage of a response to the caller's usage, budgets and the conversation. The
+// endpoint is None for answers from the response cache, which are stored as cached.
+fn book_usage(
+    state: &Arc<AppState>,
+    caller: Option<(String, Vec<String>)>,
+    model: &str,
+    endpoint: Option<String>,
+    started: Instant,
+    conversation: Option<&ConversationKey>,
+    usage: Usage,
+) {
+    if let Some((token_id, groups)) = caller {
+        state.usage.record(&token_id, &groups, model, &usage);
+        state.budgets.record(&token_id, &groups, usage.total());
+        if state.shared.is_enabled() {
+            let (state, token_id, groups) = (Arc::clone(state), token_id.clone(), groups.clone());
+            let tokens = usage.total();
+            tokio::spawn(async move {
+                state.budgets.record_shared(&state.shared, &token_id, &groups, tokens).await;
+            });
+        }
+        let price = price_of(&state.auth_tokens.load().pricing, model).copied();
+        for group in &groups {
+            let tokens = &state.metrics.tokens;
+            tokens.with_label_values(&[group, model, "prompt"]).inc_by(usage.prompt_tokens);
+            tokens.with_label_values(&[group, model, "completion"]).inc_by(usage.completion_tokens);
+            if let Some(price) = price {
+                let cost = price.cost(usage.prompt_tokens, usage.completion_tokens);
+                state.metrics.cost.with_label_values(&[group, model]).inc_by(cost);
             }
         }
+        if let Some(db) = &state.usage_db {
+            db.insert(RequestRow {
+                ts: now_ms() / 1000,
+                token_hash: token_id,
+                groups,
+                model: model.to_string(),
+                cached: endpoint.is_none(),
+                endpoint: endpoint.unwrap_or_default(),
+                latency_ms: started.elapsed().as_millis() as u64,
+                usage,
+            });
+        }
+    }
+    if let Some(key) = conversation {
+        state.conversations.add(key, usage.total_tokens);
     }
+}
 
-    // Log the forwarded request details
-    if stream_requested {
-        info!(
-            "forwarded streaming request for model {} to endpoint {}",
-            model_id, target_endpoint.url
-        );
+// Route the request and take a slot of the endpoint's pool for the class. While the pools of
+// every endpoint of the model are full, the request waits its turn in the model's queue.
+async fn admit(
+    req: &HttpRequest,
+    state: &AppState,
+    body: &mut Value,
+    task: &str,
+    class: RouteClass,
+) -> Result<(Route, Option<GroupSlot>), Box<HttpResponse>> {
+    let model = body.get("model").and_then(Value::as_str).unwrap_or_default().to_string();
+    let queue = format!("{} {} {}", task, class.label(), model);
+    let priority = match req.extensions().get::<AuthInfo>() {
+        Some(info) => state.auth_tokens.load().priority_of(&info.groups),
+        None => 0,
+    };
+    let deadline = Instant::now() + state.admission.max_wait;
+    let mut ticket: Option<Ticket> = None;
+    loop {
+        let has_turn = match &ticket {
+            Some(ticket) => ticket.is_first(),
+            None => !state.admission.has_waiting(&queue),
+        };
+        if let Some(ticket) = &ticket
+            && ticket.was_shed()
+        {
+            return Err(Box::new(
+                ApiError::too_many_requests(format!(
+                    "Requests of a higher priority took your place in the queue for model `{}`, \
+                     try again shortly.",
+                    model
+                ))
+                .code("queue_full")
+                .retry_after(1)
+                .into_response(),
+            ));
+        }
+        if has_turn {
+            match traced_route(req, state, body, task) {
+                Ok(route) => match acquire_pool_slot(state, &route.endpoint, class) {
+                    Ok(pool_slot) => {
+                        route.dispatch(state, task);
+                        return Ok((route, pool_slot));
+                    }
+                    Err(resp) if !state.admission.is_enabled() => return Err(resp),
+                    Err(_) => {}
+                },
+                Err(RouteError::Busy(..)) => {}
+                Err(error) => return Err(Box::new(error.into_response())),
+            }
+        }
+        let busy = || Box::new(RouteError::Busy(model.clone(), class).into_response());
+        if !state.admission.is_enabled() {
+            return Err(busy());
+        }
+        if ticket.is_none() {
+            let Some(joined) = state.admission.join(&queue, priority) else {
+                return Err(Box::new(
+                    ApiError::too_many_requests(format!(
+                        "Too many requests are waiting for model `{}`, try again shortly.",
+                        model
+                    ))
+                    .code("queue_full")
+                    .retry_after(1)
+                    .into_response(),
+                ));
+            };
+            ticket = Some(joined);
+        }
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(busy());
+        }
+        state.admission.wait(state.endpoint_pools.released(), remaining).await;
+    }
+}
+
+// Pick the endpoint in a span of its own; the model and endpoint go on the request's span
+fn traced_route(
+    req: &HttpRequest,
+    state: &AppState,
+    body: &mut Value,
+    task: &str,
+) -> Result<Route, RouteError> {
+    let model = body.get("model").and_then(Value::as_str).unwrap_or_default().to_string();
+    let request_span = Span::current();
+    request_span.record("model", model.as_str());
+    let span = info_span!("route", task, model, endpoint.url = Empty, fallback_from = Empty);
+    let route = span.in_scope(|| route_request(req, state, body, task))?;
+    span.record("endpoint.url", route.endpoint.url.as_str());
+    if let Some(requested) = &route.fallback_from {
+        span.record("fallback_from", requested.as_str());
+    }
+    request_span.record("endpoint.url", route.endpoint.url.as_str());
+    Ok(route)
+}
+
+// Groups of the caller for logs, comma-separated
+fn caller_groups(caller: Option<&AuthInfo>) -> String {
+    caller.map(|info| info.groups.join(",")).unwrap_or_default()
+}
+
+// Name the requested model on responses a fallback model produced
+fn annotate_fallback(mut resp: HttpResponse, fallback_from: Option<&str>) -> HttpResponse {
+    if let Some(requested) = fallback_from
+        && let Ok(value) = HeaderValue::from_str(requested)
+    {
+        resp.headers_mut().insert(HeaderName::from_static("x-fallback-from"), value);
+    }
+    resp
+}
+
+// New requests are turned away while the server drains, clients retry on another replica
+fn check_draining(state: &AppState) -> Result<(), Box<HttpResponse>> {
+    if !state.shutdown.is_draining() {
+        return Ok(());
+    }
+    let mut resp = ApiError::unavailable("The server is shutting down. Please try again.")
+        .code("shutting_down")
+        .retry_after(1)
+        .into_response();
+    resp.head_mut().set_connection_type(ConnectionType::Close);
+    Err(Box::new(resp))
+}
+
+// Take a request from the caller's rate limit, OpenAI-style 429 once it is exceeded
+async fn check_rate_limit(req: &HttpRequest, state: &AppState) -> Result<(), Box<HttpResponse>> {
+    let Some(auth_info) = req.extensions().get::<AuthInfo>().cloned() else {
+        return Ok(());
+    };
+    let Some(per_minute) = state.auth_tokens.load().rate_limit_of(&auth_info.groups) else {
+        return Ok(());
+    };
+    let token_id = auth_info.token_id();
+    let limited = state.rate_limiter.check_shared(&state.shared, &auth_info.token, &token_id, per_minute);
+    if let Err(retry_after) = limited.await {
+        let retry_secs = retry_after.as_secs_f64().ceil() as u64;
+        return Err(Box::new(
+            ApiError::too_many_requests(format!(
+                "Rate limit of {} requests per minute reached. Please try again in {}s.",
+                per_minute, retry_secs
+            ))
+            .code("rate_limit_exceeded")
+            .retry_after(retry_secs.max(1))
+            .into_response(),
+        ));
+    }
+    Ok(())
+}
+
+// Turn the caller away with an OpenAI-style 429 once its token budget for the day or month
+// is used up. With a shared store, the usage of all replicas counts.
+async fn check_budget(req: &HttpRequest, state: &AppState) -> Result<(), Box<HttpResponse>> {
+    let Some(auth_info) = req.extensions().get::<AuthInfo>().cloned() else {
+        return Ok(());
+    };
+    let auth_tokens = state.auth_tokens.load_full();
+    if auth_tokens.budgets.is_empty() {
+        return Ok(());
+    }
+    let token_id = auth_info.token_id();
+    state.budgets.sync_shared(&state.shared, &token_id, &auth_info.groups).await;
+    let Err(exhausted) = state.budgets.check(&auth_tokens.budgets, &token_id, &auth_info.groups) else {
+        return Ok(());
+    };
+    let whose = if exhausted.per_token {
+        format!("Your token's {} budget in group `{}`", exhausted.period.label(), exhausted.group)
     } else {
+        format!("The {} budget of your group `{}`", exhausted.period.label(), exhausted.group)
+    };
+    info!("{} is used up", whose);
+    Err(Box::new(
+        ApiError::too_many_requests(format!(
+            "{} of {} tokens is used up ({} used). It resets in {}s.",
+            whose, exhausted.limit, exhausted.used, exhausted.resets_in_secs
+        ))
+        .code("budget_exceeded")
+        .retry_after(exhausted.resets_in_secs.max(1))
+        .into_response(),
+    ))
+}
+
+// Take a slot of the caller's group for the duration of the request, OpenAI-style 429 when
+// the group already has its limit of requests in flight
+fn acquire_concurrency_slot(
+    req: &HttpRequest,
+    state: &AppState,
+) -> Result<Option<GroupSlot>, Box<HttpResponse>> {
+    let Some(auth_info) = req.extensions().get::<AuthInfo>().cloned() else {
+        return Ok(None);
+    };
+    let Some((group, limit)) = state.auth_tokens.load().concurrency_limit_of(&auth_info.groups) else {
+        return Ok(None);
+    };
+    match state.concurrency.try_acquire(&group, limit) {
+        Some(slot) => Ok(Some(slot)),
+        None => {
+            info!("group {} reached its limit of {} concurrent requests", group, limit);
+            Err(Box::new(
+                ApiError::too_many_requests(format!(
+                    "Your group `{}` already has {} requests in progress. Please try again once one of them has finished.",
+                    group, limit
+                ))
+                .code("concurrency_limit_exceeded")
+                .retry_after(1)
+                .into_response(),
+            ))
+        }
+    }
+}
+
+// Expand the `preset` a request names into its sampling parameters, 400 for unknown presets.
+// True if the body changed.
+fn apply_preset(state: &AppState, body: &mut Value) -> Result<bool, Box<HttpResponse>> {
+    match expand_preset(&state.auth_tokens.load().presets, body) {
+        Ok(Some(name)) => {
+            info!("expanded preset {}", name);
+            Ok(true)
+        }
+        Ok(None) => Ok(false),
+        Err(msg) => Err(Box::new(ApiError::bad_request(msg).into_response())),
+    }
+}
+
+// Clamp request parameters to the bounds of the caller's policies, 400 if a policy rejects them.
+// True if the body changed.
+fn enforce_policies(req: &HttpRequest, state: &AppState, body: &mut Value) -> Result<bool, Box<HttpResponse>> {
+    let Some(auth_info) = req.extensions().get::<AuthInfo>().cloned() else {
+        return Ok(false);
+    };
+    match apply_policies(&state.auth_tokens.load().policies, body, &auth_info.groups) {
+        Ok(changes) => {
+            if !changes.is_empty() {
+                info!("applied parameter policies: {}", changes.join(", "));
+            }
+            Ok(!changes.is_empty())
+        }
+        Err(msg) => Err(Box::new(ApiError::bad_request(msg).into_response())),
+    }
+}
+
+// A slot of the endpoint's pool for the request's class. Routing skips full pools, this
+// only fails if another request took the last slot in between.
+fn acquire_pool_slot(
+    state: &AppState,
+    endpoint: &Endpoint,
+    class: RouteClass,
+) -> Result<Option<GroupSlot>, Box<HttpResponse>> {
+    let Some(limit) = endpoint.pools.as_ref().and_then(|pools| pools.limit(class)) else {
+        return Ok(None);
+    };
+    match state.endpoint_pools.try_acquire(&endpoint.url, class, limit) {
+        Some(slot) => Ok(Some(slot)),
+        None => Err(Box::new(
+            ApiError::unavailable(format!(
+                "Endpoint {} is busy with {} requests, try again shortly.",
+                endpoint.url,
+                class.label()
+            ))
+            .code("model_busy")
+            .retry_after(1)
+            .into_response(),
+        )),
+    }
+}
+
+// Report requests repeating the same prompt in a loop, 429 if loops are throttled
+fn check_agent_loop(req: &HttpRequest, state: &AppState, body: &Value) -> Result<(), Box<HttpResponse>> {
+    let Some(auth_info) = req.extensions().get::<AuthInfo>().cloned() else {
+        return Ok(());
+    };
+    let LoopVerdict::Loop { repeats, throttle } = state.loops.observe(&auth_info.token, body) else {
+        return Ok(());
+    };
+    if let Some((url, payload)) = state.loops.report(&auth_info.token, body, &auth_info.groups, repeats) {
+        let client = state.http.monitor.clone();
+        tokio::spawn(async move {
+            if let Err(e) = client.post(&url).json(&payload).send().await {
+                warn!("Failed to notify agent loop webhook: {}", e);
+            }
+        });
+    }
+    if throttle {
+        return Err(Box::new(
+            ApiError::too_many_requests(format!(
+                "The same prompt was sent {} times within {} seconds, this looks like an agent loop. Try again later.",
+                repeats,
+                state.loops.window.as_secs()
+            ))
+            .code("agent_loop_detected")
+            .retry_after(state.loops.window.as_secs())
+            .into_response(),
+        ));
+    }
+    Ok(())
+}
+
+// The conversation a request belongs to if its budget is tracked, 429 once it is used up
+fn check_conversation_budget(
+    req: &HttpRequest,
+    state: &AppState,
+    body: &mut Value,
+) -> Result<Option<ConversationKey>, Box<HttpResponse>> {
+    let Some(id) = conversation_id(req, body) else {
+        return Ok(None);
+    };
+    let Some(auth_info) = req.extensions().get::<AuthInfo>().cloned() else {
+        return Ok(None);
+    };
+    let Some(limit) = state.conversations.limit_for(&auth_info.groups) else {
+        return Ok(None);
+    };
+    let key = (auth_info.token, id);
+    let used = state.conversations.used(&key);
+    if used >= limit {
+        info!("conversation {} exhausted its budget of {} tokens", key.1, limit);
+        return Err(Box::new(
+            ApiError::too_many_requests(format!(
+                "The token budget of {} for conversation `{}` is used up ({} tokens used).",
+                limit, key.1, used
+            ))
+            .kind("tokens")
+            .code("conversation_budget_exceeded")
+            .into_response(),
+        ));
+    }
+    Ok(Some(key))
+}
+
+// Have the guard model screen a chat request of a caller whose groups are screened, 400 if
+// it flags the conversation
+async fn screen_chat(
+    req: &HttpRequest,
+    state: &Arc<AppState>,
+    body: &Value,
+) -> Result<(), Box<HttpResponse>> {
+    let Some(auth_info) = req.extensions().get::<AuthInfo>().cloned() else {
+        return Ok(());
+    };
+    let Some(model) = state.guard.screens(&auth_info.groups) else {
+        return Ok(());
+    };
+    let conversation = guard_conversation(body);
+    if conversation.is_empty() {
+        return Ok(());
+    }
+    let Some(endpoint) = select_guard_endpoint(state, model) else {
+        warn!("no endpoint of guard model {} is available to screen a request", model);
+        return Err(Box::new(
+            ApiError::unavailable("Requests can't be screened right now, try again later.")
+                .code("moderation_unavailable")
+                .retry_after(1)
+                .into_response(),
+        ));
+    };
+    let forwarded = state.forwarding.headers_of(req);
+    let upstream = UpstreamRequest {
+        endpoint: &endpoint,
+        task: "moderate",
+        model,
+        served_model: resolve_alias(&endpoint, model),
+        path: "/v1/chat/completions",
+        caller: Some(&auth_info),
+        conversation: None,
+        strip_stream_usage: false,
+        cache_key: None,
+        forwarded: &forwarded,
+    };
+    let _in_flight = InFlight::start(state, model, &endpoint.url);
+    let verdict = ask_guard(state, &upstream, conversation).await.and_then(|completion| {
+        Verdict::from_completion(&completion).ok_or_else(unexpected_guard_answer)
+    })?;
+    if !verdict.flagged {
+        return Ok(());
+    }
+    info!(
+        model,
+        group = caller_groups(Some(&auth_info)).as_str();
+        "guard model {} flagged a request ({})",
+        model,
+        verdict.categories.join(",")
+    );
+    Err(Box::new(
+        ApiError::bad_request(format!(
+            "This request was flagged by the moderation model ({}).",
+            verdict.categories.join(", ")
+        ))
+        .code("content_policy_violation")
+        .into_response(),
+    ))
+}
+
+// Ask a guard model about a conversation, returns its chat completion. Its usage is booked
+// to the caller.
+async fn ask_guard(
+    state: &Arc<AppState>,
+    upstream: &UpstreamRequest<'_>,
+    messages: Vec<Value>,
+) -> Result<Value, Box<HttpResponse>> {
+    let endpoint = upstream.endpoint;
+    let started = Instant::now();
+    let forward_url = format!("{}{}", endpoint.url, upstream.path);
+    let timeouts = state.http.timeouts.for_endpoint(endpoint);
+    let upstream_span = upstream.span(&forward_url);
+    let request = state
+        .http
+        .upstream(timeouts.connect, &Transport::of(endpoint))
+        .post(forward_url)
+        .headers(upstream.forwarded.clone())
+        .headers(endpoint.request_headers())
+        .headers(otel::trace_headers(&upstream_span))
+        .json(&guard_request(upstream.served_model.unwrap_or(upstream.model), messages))
+        .timeout(timeouts.request);
+    let resp = match send_traced(request, upstream_span).await {
+        Ok(resp) => resp,
+        Err(e) => {
+            upstream.record_error(state, &e);
+            upstream.record_response(state, false, None, started);
+            let resp = ApiError::internal(format!("Forward request failed: {}", e))
+                .code("upstream_error")
+                .into_response();
+            return Err(Box::new(upstream.answered(resp, None)));
+        }
+    };
+    let status = resp.status();
+    upstream.record_response(state, false, Some(status.as_u16()), started);
+    let text = resp.text().await.unwrap_or_default();
+    if !status.is_success() {
+        let resp = HttpResponse::build(status).content_type("application/json").body(text);
+        return Err(Box::new(upstream.answered(resp, Some(status.as_u16()))));
+    }
+    let completion: Value = serde_json::from_str(&text).map_err(|_| unexpected_guard_answer())?;
+    if let Some(usage) = Usage::from_value(&completion["usage"]) {
+        upstream.usage_recorder(state, started)(usage);
+    }
+    Ok(completion)
+}
+
+fn unexpected_guard_answer() -> Box<HttpResponse> {
+    Box::new(
+        ApiError::new(StatusCode::BAD_GATEWAY, "The moderation model gave an unexpected answer.")
+            .code("upstream_error")
+            .into_response(),
+    )
+}
+
+// Send a JSON body to an endpoint and relay its response, streamed if requested
+async fn relay(
+    state: &Arc<AppState>,
+    upstream: &UpstreamRequest<'_>,
+    body: Bytes,
+    stream_requested: bool,
+    slots: Vec<GroupSlot>,
+) -> HttpResponse {
+    let endpoint = upstream.endpoint;
+    let started = Instant::now();
+    let in_flight = InFlight::start(state, upstream.model, &endpoint.url)
+        .holding(slots)
+        .cancellable(upstream.task);
+    let traced = state.debug_traces.traces(upstream.model, &endpoint.url);
+    if traced {
         info!(
-            "forwarded request for model {} to endpoint {}",
-            model_id, target_endpoint.url
+            target: DEBUG_TARGET,
+            "{} {}{} (caller {}): {}",
+            upstream.model,
+            endpoint.url,
+            upstream.path,
+            upstream.caller.map_or_else(|| "-".to_string(), AuthInfo::token_id),
+            trace_body(&String::from_utf8_lossy(&body))
         );
     }
 
-    // 8. Forward the entire request body
-    let forward_url = format!("{}/v1/chat/completions", target_endpoint.url);
+    // Forward the entire request body
+    let forward_url = format!("{}{}", endpoint.url, upstream.path);
 
-    // Set up the client
-    let client_builder = reqwest::Client::builder()
-        .connect_timeout(Duration::from_secs(5));
-    let client = if stream_requested {
-        client_builder
-            .build()
-            .unwrap()
-    } else {
-        client_builder
-            // For non-streaming block for a maximum of 90 seconds.
-            .timeout(Duration::from_secs(90))
-            .build()    
-            .unwrap()
-    };
-    let forward_resp = client
+    let timeouts = state.http.timeouts.for_endpoint(endpoint);
+    // Lasts until the response headers arrive, the time to first byte
+    let upstream_span = upstream.span(&forward_url);
+    let mut request = state
+        .http
+        .upstream(timeouts.connect, &Transport::of(endpoint))
         .post(forward_url)
-        .bearer_auth(&target_endpoint.access_token)
-        .json(&*body)
-        .send()
-        .await;
+        .headers(upstream.forwarded.clone())
+        .headers(endpoint.request_headers())
+        .headers(otel::trace_headers(&upstream_span))
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(body);
+    if !stream_requested {
+        // For non-streaming block for a maximum of the request timeout
+        request = request.timeout(timeouts.request);
+    }
+    let forward_resp = send_traced(request, upstream_span).await;
+    relay_response(state, upstream, forward_resp, stream_requested, in_flight, started, traced).await
+}
+
+fn content_type_of(resp: &reqwest::Response, default: &str) -> String {
+    resp.headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or(default)
+        .to_string()
+}
 
-    // 9. Handle streaming vs non-streaming response
+// Send a request in its upstream span, which ends with the response headers
+async fn send_traced(
+    request: reqwest::RequestBuilder,
+    upstream_span: Span,
+) -> reqwest::Result<reqwest::Response> {
+    let forward_resp = request.send().instrument(upstream_span.clone()).await;
+    match &forward_resp {
+        Ok(resp) => otel::record_status(&upstream_span, resp.status().as_u16()),
+        Err(e) => {
+            upstream_span.record("otel.status_code", "ERROR");
+            upstream_span.record("error", e.to_string().as_str());
+        }
+    }
+    forward_resp
+}
+
+// Relay an endpoint's response, streamed if requested; the request stays in flight until
+// the response is done
+async fn relay_response(
+    state: &Arc<AppState>,
+    upstream: &UpstreamRequest<'_>,
+    forward_resp: reqwest::Result<reqwest::Response>,
+    stream_requested: bool,
+    mut in_flight: InFlight,
+    started: Instant,
+    traced: bool,
+) -> HttpResponse {
+    let endpoint = upstream.endpoint;
+    let timeouts = state.http.timeouts.for_endpoint(endpoint);
     match forward_resp {
         Ok(resp) => {
             let status = resp.status();
+            if !status.is_success() {
+                in_flight.untimed();
+            }
+            if traced && stream_requested {
+                info!(
+                    target: DEBUG_TARGET,
+                    "{} {}: status {} after {:?}, streaming",
+                    upstream.model,
+                    endpoint.url,
+                    status.as_u16(),
+                    started.elapsed()
+                );
+            }
             if stream_requested {
-                let content_type = resp
-                    .headers()
-                    .get(reqwest::header::CONTENT_TYPE)
-                    .and_then(|v| v.to_str().ok())
-                    .unwrap_or("application/octet-stream")
-                    .to_string();
+                let content_type = content_type_of(&resp, "application/octet-stream");
+                upstream.record_response(state, true, Some(status.as_u16()), started);
                 let byte_stream = resp.bytes_stream();
+                // Count broken streams
+                let stream_state = Arc::clone(state);
+                let (task, url) = (upstream.task.to_string(), endpoint.url.clone());
+                let on_error = move || {
+                    stream_state
+                        .metrics
+                        .upstream_errors
+                        .with_label_values(&[&task, &url, "stream"])
+                        .inc();
+                    stream_state.breakers.record_failure(&url);
+                    stream_state.outliers.record(&url, true);
+                };
                 // Wrap the original stream per-chunk timeout logic
-                let timed_stream = stream_with_read_timeout(byte_stream);
-                HttpResponse::build(status)
+                let timed_stream = stream_with_read_timeout(
+                    byte_stream,
+                    timeouts.stream_chunk,
+                    timeouts.stream_total,
+                    on_error,
+                );
+                let rename = upstream
+                    .served_model
+                    .map(|served| (served.to_string(), upstream.model.to_string()));
+                // A stream that started fine counts towards the endpoint's error rate once it ends
+                let record_finish = upstream.finish_recorder(state);
+                let end_state = Arc::clone(state);
+                let (task, url, counted) = (upstream.task.to_string(), endpoint.url.clone(), status.is_success());
+                let on_end = move |reasons: Vec<String>, malformed: bool| {
+                    record_finish(reasons);
+                    if malformed {
+                        warn!("Endpoint {} sent malformed stream events", url);
+                        end_state
+                            .metrics
+                            .upstream_errors
+                            .with_label_values(&[&task, &url, "malformed_stream"])
+                            .inc();
+                    }
+                    if counted {
+                        end_state.outliers.record(&url, malformed);
+                    }
+                };
+                let tapped_stream = tap_usage(
+                    timed_stream,
+                    in_flight,
+                    upstream.stream_span(),
+                    upstream.strip_stream_usage,
+                    rename,
+                    upstream.usage_recorder(state, started),
+                    on_end,
+                );
+                let heartbeat = timeouts.stream_heartbeat.filter(|_| content_type.starts_with("text/event-stream"));
+                let resp = HttpResponse::build(status)
                     .content_type(content_type)
-                    // Pass the *new* timed_stream to Actix
-                    .streaming(timed_stream)
+                    // Pass the *new* stream to Actix
+                    .streaming(with_heartbeats(tapped_stream, heartbeat));
+                upstream.answered(resp, Some(status.as_u16()))
+            } else if upstream.task == "image" {
+                // Generated images are large base64 bodies without token usage, they are passed
+                // on as they arrive instead of being read and parsed
+                if traced {
+                    info!(
+                        target: DEBUG_TARGET,
+                        "{} {}: status {} after {:?}, relaying images",
+                        upstream.model,
+                        endpoint.url,
+                        status.as_u16(),
+                        started.elapsed()
+                    );
+                }
+                upstream.record_response(state, false, Some(status.as_u16()), started);
+                let content_type = content_type_of(&resp, "application/json");
+                let body = relayed_in_full(resp.bytes_stream(), in_flight);
+                let resp = HttpResponse::build(status).content_type(content_type).streaming(body);
+                upstream.answered(resp, Some(status.as_u16()))
             } else {
-                let text = resp.text().await.unwrap_or_default();
-                HttpResponse::build(status)
-                    .content_type("application/json")
-                    .body(text)
+                // Transcriptions may be plain text or subtitles
+                let content_type = content_type_of(&resp, "application/json");
+                let mut text = resp.text().await.unwrap_or_default();
+                in_flight.finish();
+                if traced {
+                    info!(
+                        target: DEBUG_TARGET,
+                        "{} {}: status {} after {:?}: {}",
+                        upstream.model,
+                        endpoint.url,
+                        status.as_u16(),
+                        started.elapsed(),
+                        trace_body(&text)
+                    );
+                }
+                upstream.record_response(state, false, Some(status.as_u16()), started);
+                if let Some(usage) = usage_from_body(&text) {
+                    upstream.usage_recorder(state, started)(usage);
+                }
+                upstream.finish_recorder(state)(finish_reasons_from_body(&text));
+                if let Some(renamed) = upstream
+                    .served_model
+                    .and_then(|served| rename_model_in_json(&text, served, upstream.model))
+                {
+                    text = renamed;
+                }
+                let mut resp = HttpResponse::build(status);
+                resp.content_type(content_type);
+                if let Some(key) = upstream.cache_key {
+                    resp.insert_header(("X-Cache", "miss"));
+                    if status.is_success() {
+                        state.response_cache.insert(key, text.clone());
+                    }
+                }
+                upstream.answered(resp.body(text), Some(status.as_u16()))
             }
         }
-        Err(e) => HttpResponse::InternalServerError().body(format!("Forward request failed: {}", e)),
+        Err(e) => {
+            if traced {
+                info!(
+                    target: DEBUG_TARGET,
+                    "{} {}: failed after {:?}: {:?}",
+                    upstream.model,
+                    endpoint.url,
+                    started.elapsed(),
+                    e
+                );
+            }
+            in_flight.complete();
+            upstream.record_error(state, &e);
+            upstream.record_response(state, stream_requested, None, started);
+            let resp = ApiError::internal(format!("Forward request failed: {}", e))
+                .code("upstream_error")
+                .into_response();
+            upstream.answered(resp, None)
+        }
     }
 }
 
+// Pass a response body through; the request is in flight until its last chunk is sent
+fn relayed_in_full<S>(upstream: S, mut in_flight: InFlight) -> impl Stream<Item = Result<Bytes, IoError>>
+where
+    S: Stream<Item = reqwest::Result<Bytes>>,
+{
+    try_stream! {
+        let mut upstream = Box::pin(upstream);
+        while let Some(chunk) = upstream.next().await {
+            match chunk {
+                Ok(chunk) => yield chunk,
+                Err(e) => {
+                    in_flight.complete();
+                    Err(IoError::other(e))?
+                }
+            }
+        }
+        in_flight.finish();
+    }
+}
 
-// -- Handler: /v1/embeddings (for embed) -------------------------------              
-pub async fn embeddings_handler(
+// -- Handler: /v1/chat/completions (for generate) ----------------------------
+pub async fn chat_completions_handler(
+    req: HttpRequest,
+    state: web::Data<Arc<AppState>>,
+    body: ProxyBody,
+) -> impl Responder {
+    let route = ProxyRoute { task: "generate", path: "/v1/chat/completions", streaming: true };
+    forward_json(req, state, body, route).await
+}
+
+// -- Handler: /v1/messages (Anthropic, for generate) -------------------------
+pub async fn messages_handler(
     req: HttpRequest,
     state: web::Data<Arc<AppState>>,
-    body: web::Json<Value>,
+    body: Result<JsonBody, actix_web::Error>,
 ) -> impl Responder {
-    // 1. Check auth
-    let auth_info = match req.extensions().get::<AuthInfo>() {
-        Some(info) => info.clone(),
-        None => return HttpResponse::Unauthorized().finish(),
+    // Errors of the body answer in Anthropic's schema as well
+    let JsonBody(body) = match body {
+        Ok(body) => body,
+        Err(e) => return anthropic_error(e.error_response()).await,
     };
-    let user_groups = &auth_info.groups;
+    let chat = match anthropic::to_chat_request(&body) {
+        Ok(chat) => chat,
+        Err(msg) => return anthropic_error(ApiError::bad_request(msg).into_response()).await,
+    };
+    let model = chat.get("model").and_then(Value::as_str).unwrap_or_default().to_string();
+    let translator = anthropic::StreamTranslator::new(&model);
+    let resp = forward_translated(req, state, chat, translator, |completion| {
+        anthropic::from_chat_response(completion, &model)
+    })
+    .await;
+    if !resp.status().is_success() {
+        return anthropic_error(resp).await;
+    }
+    resp
+}
 
-    // 2. Extract model
-    let model_id = match body.get("model").and_then(Value::as_str) {
-        Some(m) => m,
-        None => return HttpResponse::NotFound().body("The model `` does not exist."),
+// -- Handler: /v1/responses (OpenAI Responses, for generate) -----------------
+pub async fn responses_handler(
+    req: HttpRequest,
+    state: web::Data<Arc<AppState>>,
+    body: JsonBody,
+) -> impl Responder {
+    let chat = match responses::to_chat_request(&body) {
+        Ok(chat) => chat,
+        Err(msg) => return ApiError::bad_request(msg).into_response(),
     };
+    let model = chat.get("model").and_then(Value::as_str).unwrap_or_default().to_string();
+    let echo = responses::echoed_fields(&body);
+    let translator = responses::StreamTranslator::new(&model, echo.clone());
+    forward_translated(req, state, chat, translator, |completion| {
+        responses::from_chat_response(completion, &model, &echo)
+    })
+    .await
+}
 
-    // 3. Look in embed's model->endpoints map
-    let model_to_endpoints_embed = state.model_to_endpoints_embed.lock().unwrap();
-    let endpoints_for_model = match model_to_endpoints_embed.get(model_id) {
-        Some(eps) => eps.clone(),
-        None => {
-            return HttpResponse::NotFound()
-                .body(format!("The model `{}` does not exist.", model_id));
+// Streams of the API facades, translated from chat completion events
+trait EventTranslator {
+    fn feed(&mut self, chunk: &[u8]) -> String;
+    fn finish(&mut self) -> String;
+}
+
+impl EventTranslator for anthropic::StreamTranslator {
+    fn feed(&mut self, chunk: &[u8]) -> String {
+        anthropic::StreamTranslator::feed(self, chunk)
+    }
+
+    fn finish(&mut self) -> String {
+        anthropic::StreamTranslator::finish(self)
+    }
+}
+
+impl EventTranslator for responses::StreamTranslator {
+    fn feed(&mut self, chunk: &[u8]) -> String {
+        responses::StreamTranslator::feed(self, chunk)
+    }
+
+    fn finish(&mut self) -> String {
+        responses::StreamTranslator::finish(self)
+    }
+}
+
+// Run the chat completion an API facade translated a request to and translate its answer
+// back, streamed by `translator` or whole by `translate`. Failed responses are relayed.
+async fn forward_translated(
+    req: HttpRequest,
+    state: web::Data<Arc<AppState>>,
+    mut chat: Value,
+    translator: impl EventTranslator + 'static,
+    translate: impl FnOnce(&Value) -> Value,
+) -> HttpResponse {
+    if chat.get("stream").and_then(Value::as_bool).unwrap_or(false) {
+        // The final events carry the usage
+        chat["stream_options"] = json!({ "include_usage": true });
+    }
+    let route = ProxyRoute { task: "generate", path: "/v1/chat/completions", streaming: true };
+    let resp = forward_json(req, state, ProxyBody::from(chat), route).await;
+    if !resp.status().is_success() {
+        return resp;
+    }
+    let streamed = resp
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("text/event-stream"));
+    let (resp, body) = resp.into_parts();
+    if streamed {
+        let events = translated_events(body, translator);
+        return resp.set_body(BoxBody::new(BodyStream::new(events)));
+    }
+    let completion = to_bytes(body)
+        .await
+        .ok()
+        .and_then(|bytes| serde_json::from_slice::<Value>(&bytes).ok());
+    match completion {
+        Some(completion) => resp.set_body(BoxBody::new(translate(&completion).to_string())),
+        None => ApiError::new(StatusCode::BAD_GATEWAY, "Invalid chat completion of the endpoint.")
+            .code("upstream_error")
+            .into_response(),
+    }
+}
+
+// A relayed chat completion stream as the events of a facade
+fn translated_events(
+    body: BoxBody,
+    mut translator: impl EventTranslator,
+) -> impl Stream<Item = Result<Bytes, IoError>> {
+    try_stream! {
+        let mut body = Box::pin(body);
+        while let Some(chunk) = poll_fn(|cx| body.as_mut().poll_next(cx)).await {
+            let chunk = chunk.map_err(|e| IoError::other(e.to_string()))?;
+            // Heartbeats are comments to any SSE client
+            if chunk.as_ref() == HEARTBEAT {
+                yield chunk;
+                continue;
+            }
+            let events = translator.feed(&chunk);
+            if !events.is_empty() {
+                yield Bytes::from(events);
+            }
+        }
+        let rest = translator.finish();
+        if !rest.is_empty() {
+            yield Bytes::from(rest);
         }
+    }
+}
+
+// A failed response with its body in Anthropic's error schema
+async fn anthropic_error(resp: HttpResponse) -> HttpResponse {
+    let status = resp.status();
+    let (resp, body) = resp.into_parts();
+    let body = to_bytes(body).await.unwrap_or_default();
+    let mut resp = resp.set_body(BoxBody::new(error_body(status, &body).to_string()));
+    resp.headers_mut().insert(header::CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    resp
+}
+
+// -- Handler: /v1/embeddings (for embed) -------------------------------------
+pub async fn embeddings_handler(
+    req: HttpRequest,
+    state: web::Data<Arc<AppState>>,
+    body: ProxyBody,
+) -> impl Responder {
+    let route = ProxyRoute { task: "embed", path: "/v1/embeddings", streaming: false };
+    forward_json(req, state, body, route).await
+}
+
+// -- Handler: /v1/completions (legacy) ---------------------------------------
+pub async fn chat_completions_handler_legacy(
+    req: HttpRequest,
+    state: web::Data<Arc<AppState>>,
+    body: ProxyBody,
+) -> impl Responder {
+    let route = ProxyRoute { task: "generate", path: "/v1/completions", streaming: true };
+    forward_json(req, state, body, route).await
+}
+
+// -- Handler: /pooling (for pooling) -----------------------------------------
+pub async fn pooling_handler(
+    req: HttpRequest,
+    state: web::Data<Arc<AppState>>,
+    body: ProxyBody,
+) -> impl Responder {
+    let route = ProxyRoute { task: "pooling", path: "/pooling", streaming: false };
+    forward_json(req, state, body, route).await
+}
+
+// -- Handler: /classify (for pooling) ----------------------------------------
+pub async fn classify_handler(
+    req: HttpRequest,
+    state: web::Data<Arc<AppState>>,
+    body: ProxyBody,
+) -> impl Responder {
+    let route = ProxyRoute { task: "pooling", path: "/classify", streaming: false };
+    forward_json(req, state, body, route).await
+}
+
+// -- Handler: /tokenize (for generate) ---------------------------------------
+pub async fn tokenize_handler(
+    req: HttpRequest,
+    state: web::Data<Arc<AppState>>,
+    body: ProxyBody,
+) -> impl Responder {
+    let route = ProxyRoute { task: "generate", path: "/tokenize", streaming: false };
+    forward_json(req, state, body, route).await
+}
+
+// -- Handler: /detokenize (for generate) -------------------------------------
+pub async fn detokenize_handler(
+    req: HttpRequest,
+    state: web::Data<Arc<AppState>>,
+    body: ProxyBody,
+) -> impl Responder {
+    let route = ProxyRoute { task: "generate", path: "/detokenize", streaming: false };
+    forward_json(req, state, body, route).await
+}
+
+// -- Handler: /v1/rerank and /rerank (for score) -----------------------------
+pub async fn rerank_handler(
+    req: HttpRequest,
+    state: web::Data<Arc<AppState>>,
+    body: ProxyBody,
+) -> impl Responder {
+    let route = ProxyRoute { task: "score", path: "/v1/rerank", streaming: false };
+    forward_json(req, state, body, route).await
+}
+
+// -- Handler: /v2/rerank (for score) -----------------------------------------
+pub async fn rerank_v2_handler(
+    req: HttpRequest,
+    state: web::Data<Arc<AppState>>,
+    body: ProxyBody,
+) -> impl Responder {
+    let route = ProxyRoute { task: "score", path: "/v2/rerank", streaming: false };
+    forward_json(req, state, body, route).await
+}
+
+// -- Handler: /v1/images/generations (for image) -----------------------------
+pub async fn image_generations_handler(
+    req: HttpRequest,
+    state: web::Data<Arc<AppState>>,
+    body: ProxyBody,
+) -> impl Responder {
+    let route = ProxyRoute { task: "image", path: "/v1/images/generations", streaming: false };
+    forward_json(req, state, body, route).await
+}
+
+// -- Handler: /v1/moderations (for moderate) ---------------------------------
+pub async fn moderations_handler(
+    req: HttpRequest,
+    state: web::Data<Arc<AppState>>,
+    body: JsonBody,
+) -> impl Responder {
+    forward_moderation(req, state, body).await
+}
+
+// Ask the guard model about each input of a moderation request and answer in OpenAI's schema
+async fn forward_moderation(
+    req: HttpRequest,
+    state: web::Data<Arc<AppState>>,
+    body: JsonBody,
+) -> HttpResponse {
+    if let Err(resp) = check_draining(&state) {
+        return *resp;
+    }
+    if let Err(resp) = check_budget(&req, &state).await {
+        return *resp;
+    }
+    if let Err(resp) = check_rate_limit(&req, &state).await {
+        return *resp;
+    }
+    let slot = match acquire_concurrency_slot(&req, &state) {
+        Ok(slot) => slot,
+        Err(resp) => return *resp,
+    };
+    let JsonBody(mut body) = body;
+    let Some(inputs) = moderation_inputs(&body) else {
+        let msg = "`input` must be a string, a list of strings or a list of content parts.";
+        return ApiError::bad_request(msg)
+            .param("input")
+            .into_response();
     };
-    drop(model_to_endpoints_embed);
+    // The model is optional as with OpenAI, the guard model is the default
+    if body.get("model").and_then(Value::as_str).is_none() {
+        let Some(guard) = &state.guard.model else {
+            return ApiError::bad_request("A `model` is required, there is no default moderation model.")
+                .param("model")
+                .into_response();
+        };
+        body["model"] = Value::String(guard.clone());
+    }
+    let auth_info = req.extensions().get::<AuthInfo>().cloned();
+    let (Route { endpoint: target_endpoint, fallback_from, .. }, pool_slot) =
+        match admit(&req, &state, &mut body, "moderate", RouteClass::Batch).await {
+            Ok(admitted) => admitted,
+            Err(resp) => return *resp,
+        };
+    let model_id = body.get("model").and_then(Value::as_str).unwrap_or_default().to_string();
+    info!(
+        task = "moderate",
+        model = model_id.as_str(),
+        endpoint = target_endpoint.url.as_str(),
+        group = caller_groups(auth_info.as_ref()).as_str();
+        "forwarded moderation request of {} inputs for model {} to endpoint {}",
+        inputs.len(), model_id, target_endpoint.url
+    );
 
-    // 4. Filter endpoints by group
-    let endpoints = state.endpoints_embed.lock().unwrap();
-    let endpoints_list = endpoints_for_model
-        .iter()
-        .filter_map(|url| endpoints.iter().find(|e| &e.url == url))
-        .filter(|ep| ep.groups.iter().any(|g| user_groups.contains(g)))
-        .cloned()
-        .collect::<Vec<Endpoint>>();
+    let forwarded = state.forwarding.headers_of(&req);
+    let upstream = UpstreamRequest {
+        endpoint: &target_endpoint,
+        task: "moderate",
+        model: &model_id,
+        served_model: resolve_alias(&target_endpoint, &model_id),
+        path: "/v1/chat/completions",
+        caller: auth_info.as_ref(),
+        conversation: None,
+        strip_stream_usage: false,
+        cache_key: None,
+        forwarded: &forwarded,
+    };
+    let in_flight =
+        InFlight::start(&state, &model_id, &target_endpoint.url).holding(slot.into_iter().chain(pool_slot));
+    let completions = join_all(inputs.into_iter().map(|input| {
+        ask_guard(&state, &upstream, vec![json!({ "role": "user", "content": input })])
+    }))
+    .await;
+    drop(in_flight);
 
-    // 5. If no authorized endpoints remain, 404
-    if endpoints_list.is_empty() {
-        return HttpResponse::NotFound()
-            .body(format!("The model `{}` does not exist.", model_id));
+    let mut results = Vec::new();
+    let mut id = None;
+    for completion in completions {
+        let completion = match completion {
+            Ok(completion) => completion,
+            Err(resp) => return annotate_fallback(*resp, fallback_from.as_deref()),
+        };
+        let Some(verdict) = Verdict::from_completion(&completion) else {
+            return *unexpected_guard_answer();
+        };
+        results.push(verdict.to_result());
+        id = id.or_else(|| completion.get("id").and_then(Value::as_str).map(String::from));
     }
+    let id = id.unwrap_or_default();
+    let resp = HttpResponse::Ok().json(json!({
+        "id": format!("modr-{}", id.strip_prefix("chatcmpl-").unwrap_or(&id)),
+        "model": model_id,
+        "results": results,
+    }));
+    annotate_fallback(upstream.answered(resp, Some(200)), fallback_from.as_deref())
+}
 
-    // 6. Pick the first one and rotate
-    let target_endpoint = &endpoints_list[0];
-    {
-        let mut map_lock = state.model_to_endpoints_embed.lock().unwrap();
-        if let Some(urls) = map_lock.get_mut(model_id) {
-            if let Some(pos) = urls.iter().position(|url| url == &target_endpoint.url) {
-                let url = urls.remove(pos);
-                urls.push(url);
+// -- Handler: /v1/audio/transcriptions (for transcribe) ----------------------
+pub async fn transcriptions_handler(
+    req: HttpRequest,
+    state: web::Data<Arc<AppState>>,
+    payload: web::Payload,
+) -> impl Responder {
+    let route = ProxyRoute { task: "transcribe", path: "/v1/audio/transcriptions", streaming: false };
+    forward_form(req, state, payload, route).await
+}
+
+// -- Handler: /v1/audio/translations (for transcribe) ------------------------
+pub async fn translations_handler(
+    req: HttpRequest,
+    state: web::Data<Arc<AppState>>,
+    payload: web::Payload,
+) -> impl Responder {
+    let route = ProxyRoute { task: "transcribe", path: "/v1/audio/translations", streaming: false };
+    forward_form(req, state, payload, route).await
+}
+
+// Route a multipart/form-data upload by its `model` field and forward it as it arrives. Only
+// the start of the form is held back, until the `model` field is complete.
+async fn forward_form(
+    req: HttpRequest,
+    state: web::Data<Arc<AppState>>,
+    mut payload: web::Payload,
+    route: ProxyRoute,
+) -> HttpResponse {
+    if let Err(resp) = check_draining(&state) {
+        return *resp;
+    }
+    if let Err(resp) = check_budget(&req, &state).await {
+        return *resp;
+    }
+    if let Err(resp) = check_rate_limit(&req, &state).await {
+        return *resp;
+    }
+    let slot = match acquire_concurrency_slot(&req, &state) {
+        Ok(slot) => slot,
+        Err(resp) => return *resp,
+    };
+    let Some(content_type) = form_data_content_type(&req) else {
+        let content_type = req.headers().get("Content-Type").and_then(|h| h.to_str().ok()).unwrap_or("none");
+        let msg = format!("Expected a `multipart/form-data` body, got content type: {}.", content_type);
+        return ApiError::new(StatusCode::UNSUPPORTED_MEDIA_TYPE, msg).into_response();
+    };
+
+    let limit = max_upload_bytes();
+    let too_large = || {
+        let msg = format!("Request body is larger than the limit of {} bytes.", limit);
+        ApiError::new(StatusCode::PAYLOAD_TOO_LARGE, msg).into_response()
+    };
+    let mut head = BytesMut::new();
+    let mut scanner = FieldScanner::new("model");
+    let model_range = loop {
+        if let Some(range) = scanner.scan(&head) {
+            break range;
+        }
+        match payload.next().await {
+            Some(Ok(chunk)) if head.len() + chunk.len() > limit => return too_large(),
+            Some(Ok(chunk)) => head.extend_from_slice(&chunk),
+            Some(Err(e)) => {
+                return ApiError::bad_request(format!("Failed to read the form: {}.", e)).into_response();
+            }
+            None => {
+                return ApiError::bad_request("The form has no `model` field.").param("model").into_response();
             }
         }
+    };
+    let requested_model = String::from_utf8_lossy(&head[model_range.clone()]).into_owned();
+
+    let mut body = json!({ "model": requested_model });
+    let (Route { endpoint: target_endpoint, fallback_from, .. }, pool_slot) =
+        match admit(&req, &state, &mut body, route.task, RouteClass::Batch).await {
+            Ok(admitted) => admitted,
+            Err(resp) => return *resp,
+        };
+    let model_id = body.get("model").and_then(Value::as_str).unwrap_or_default().to_string();
+    let served_model = resolve_alias(&target_endpoint, &model_id);
+    // A fallback or the served name of an alias goes upstream in place of the requested name
+    let forwarded_model = served_model.unwrap_or(&model_id);
+    if forwarded_model != requested_model {
+        let mut spliced = BytesMut::with_capacity(head.len() + forwarded_model.len());
+        spliced.extend_from_slice(&head[..model_range.start]);
+        spliced.extend_from_slice(forwarded_model.as_bytes());
+        spliced.extend_from_slice(&head[model_range.end..]);
+        head = spliced;
     }
 
-    // 7. Forward the entire request body
+    let auth_info = req.extensions().get::<AuthInfo>().cloned();
     info!(
-        "forwarded embed request for model {} to endpoint {}",
-        model_id, target_endpoint.url
+        task = route.task,
+        model = model_id.as_str(),
+        endpoint = target_endpoint.url.as_str(),
+        group = caller_groups(auth_info.as_ref()).as_str(),
+        stream = false;
+        "forwarded {} request for model {} to endpoint {}",
+        route.task, model_id, target_endpoint.url
     );
-    let forward_url = format!("{}/v1/embeddings", target_endpoint.url);
-    let client = reqwest::Client::builder()
-            .connect_timeout(Duration::from_secs(5))
-            .timeout(Duration::from_secs(90))
-            .build()
-            .unwrap();
-    let forward_resp = client
+    let forwarded = state.forwarding.headers_of(&req);
+    let upstream = UpstreamRequest {
+        endpoint: &target_endpoint,
+        task: route.task,
+        model: &model_id,
+        served_model,
+        path: route.path,
+        caller: auth_info.as_ref(),
+        conversation: None,
+        strip_stream_usage: false,
+        cache_key: None,
+        forwarded: &forwarded,
+    };
+
+    // The rest of the form is passed on by a task of its own, the request body sent upstream
+    // has to be Send unlike the payload
+    let exceeded = Arc::new(AtomicBool::new(false));
+    let (sender, receiver) = mpsc::channel(8);
+    actix_web::rt::spawn(pump_upload(payload, sender, head.len(), limit, Arc::clone(&exceeded)));
+    let upload = futures::stream::once(ready(Ok(head.freeze()))).chain(receiver);
+
+    let started = Instant::now();
+    let slots: Vec<GroupSlot> = slot.into_iter().chain(pool_slot).collect();
+    let mut in_flight = InFlight::start(&state, &model_id, &target_endpoint.url)
+        .holding(slots)
+        .cancellable(route.task);
+    let traced = state.debug_traces.traces(&model_id, &target_endpoint.url);
+    if traced {
+        info!(
+            target: DEBUG_TARGET,
+            "{} {}{} (caller {}): {} upload",
+            model_id,
+            target_endpoint.url,
+            route.path,
+            upstream.caller.map_or_else(|| "-".to_string(), AuthInfo::token_id),
+            content_type
+        );
+    }
+    let forward_url = format!("{}{}", target_endpoint.url, route.path);
+    let timeouts = state.http.timeouts.for_endpoint(&target_endpoint);
+    let upstream_span = upstream.span(&forward_url);
+    let request = state
+        .http
+        .upstream(timeouts.connect, &Transport::of(&target_endpoint))
         .post(forward_url)
-        .bearer_auth(&target_endpoint.access_token)
-        .json(&*body)
-        .send()
-        .await;
+        .headers(upstream.forwarded.clone())
+        .headers(target_endpoint.request_headers())
+        .headers(otel::trace_headers(&upstream_span))
+        .header(reqwest::header::CONTENT_TYPE, content_type)
+        .body(reqwest::Body::wrap_stream(upload))
+        .timeout(timeouts.request);
+    let forward_resp = send_traced(request, upstream_span).await;
+    if exceeded.load(Ordering::Relaxed) {
+        in_flight.complete();
+        return too_large();
+    }
+    let resp = relay_response(&state, &upstream, forward_resp, false, in_flight, started, traced).await;
+    annotate_fallback(resp, fallback_from.as_deref())
+}
 
-    match forward_resp {
-        Ok(resp) => {
-            let status = resp.status();
-            let text = resp.text().await.unwrap_or_default();
-            HttpResponse::build(status)
-                .content_type("application/json")
-                .body(text)
+// Pass the rest of an upload on as it arrives, up to `limit` bytes in all
+async fn pump_upload(
+    mut payload: web::Payload,
+    mut sender: mpsc::Sender<Result<Bytes, IoError>>,
+    mut received: usize,
+    limit: usize,
+    exceeded: Arc<AtomicBool>,
+) {
+    while let Some(chunk) = payload.next().await {
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(e) => {
+                let _ = sender.send(Err(IoError::other(e.to_string()))).await;
+                return;
+            }
+        };
+        received += chunk.len();
+        if received > limit {
+            exceeded.store(true, Ordering::Relaxed);
+            let _ = sender.send(Err(IoError::other("upload over the limit"))).await;
+            return;
+        }
+        // The upstream request failed
+        if sender.send(Ok(chunk)).await.is_err() {
+            return;
         }
-        Err(e) => HttpResponse::InternalServerError().body(format!("Forward request failed: {}", e)),
     }
 }
 
-// -- Handler: /v1/completions (legacy) ----------------------------
-pub async fn chat_completions_handler_legacy(
+// -- Handler: /v1/realtime (WebSocket) ---------------------------------------
+// Realtime and other interactive backends speak WebSocket. The handshake goes to the endpoint
+// serving the model and once it switches protocols, the bytes of both directions are tunnelled
+// as they are, frames pass through untouched.
+pub async fn websocket_handler(
     req: HttpRequest,
     state: web::Data<Arc<AppState>>,
-    body: web::Json<Value>,
-) -> impl Responder {
-    // 1. Check auth
-    let auth_info = match req.extensions().get::<AuthInfo>() {
-        Some(info) => info.clone(),
-        None => return HttpResponse::Unauthorized().finish(),
+    payload: web::Payload,
+) -> HttpResponse {
+    let upgrade = req.headers().get(header::UPGRADE).and_then(|h| h.to_str().ok()).unwrap_or_default();
+    if !upgrade.eq_ignore_ascii_case("websocket") {
+        return ApiError::new(StatusCode::UPGRADE_REQUIRED, "Expected a WebSocket upgrade request.")
+            .into_response();
+    }
+    if let Err(resp) = check_draining(&state) {
+        return *resp;
+    }
+    if let Err(resp) = check_budget(&req, &state).await {
+        return *resp;
+    }
+    if let Err(resp) = check_rate_limit(&req, &state).await {
+        return *resp;
+    }
+    let slot = match acquire_concurrency_slot(&req, &state) {
+        Ok(slot) => slot,
+        Err(resp) => return *resp,
+    };
+    let offered = offered_subprotocols(&req);
+    let query_model = web::Query::<HashMap<String, String>>::from_query(req.query_string())
+        .ok()
+        .and_then(|query| query.get("model").cloned());
+    let Some(requested_model) = query_model.or_else(|| {
+        offered.iter().find_map(|protocol| protocol.strip_prefix(MODEL_SUBPROTOCOL).map(String::from))
+    }) else {
+        return ApiError::bad_request("Name the model in the `model` query parameter or a `model.<name>` subprotocol.")
+            .param("model")
+            .into_response();
     };
-    let user_groups = &auth_info.groups;
 
-    // 2. Extract model
-    let model_id = match body.get("model").and_then(Value::as_str) {
-        Some(m) => m,
-        None => return HttpResponse::NotFound().body("The model `` does not exist."),
+    // The task of the endpoints serving the model, generate if none does
+    let task = state
+        .tasks()
+        .into_iter()
+        .find(|(_, endpoints)| endpoints.model_to_endpoints.lock().unwrap().contains_key(&requested_model))
+        .map_or("generate", |(task, _)| task);
+    let mut body = json!({ "model": requested_model });
+    // Someone is at the other end of the connection
+    let (Route { endpoint: target_endpoint, fallback_from, .. }, pool_slot) =
+        match admit(&req, &state, &mut body, task, RouteClass::Interactive).await {
+            Ok(admitted) => admitted,
+            Err(resp) => return *resp,
+        };
+    let model_id = body.get("model").and_then(Value::as_str).unwrap_or_default().to_string();
+    let served_model = resolve_alias(&target_endpoint, &model_id);
+    let forwarded_model = served_model.unwrap_or(&model_id);
+    // /ws/{path} reaches any path of the endpoint
+    let path = req.match_info().get("path").map_or_else(|| req.path().to_string(), |path| format!("/{}", path));
+    let Some(forward_url) = websocket_url(&target_endpoint.url, &path, req.query_string(), forwarded_model) else {
+        return ApiError::bad_request(format!("Invalid WebSocket path: {}", path)).into_response();
     };
 
-    // 3. Check whether user wants streaming
-    let stream_requested = body.get("stream").and_then(Value::as_bool).unwrap_or(false);
+    let auth_info = req.extensions().get::<AuthInfo>().cloned();
+    info!(
+        task,
+        model = model_id.as_str(),
+        endpoint = target_endpoint.url.as_str(),
+        group = caller_groups(auth_info.as_ref()).as_str(),
+        stream = true;
+        "forwarded WebSocket connection for model {} to endpoint {}",
+        model_id, target_endpoint.url
+    );
+    let forwarded = state.forwarding.headers_of(&req);
+    let upstream = UpstreamRequest {
+        endpoint: &target_endpoint,
+        task,
+        model: &model_id,
+        served_model,
+        path: &path,
+        caller: auth_info.as_ref(),
+        conversation: None,
+        strip_stream_usage: false,
+        cache_key: None,
+        forwarded: &forwarded,
+    };
 
-    // 4. Look in generate's model->endpoints map
-    let model_to_endpoints_generate = state.model_to_endpoints_generate.lock().unwrap();
-    let endpoints_for_model = match model_to_endpoints_generate.get(model_id) {
-        Some(eps) => eps.clone(),
-        None => {
-            return HttpResponse::NotFound()
-                .body(format!("The model `{}` does not exist.", model_id));
+    let started = Instant::now();
+    let slots: Vec<GroupSlot> = slot.into_iter().chain(pool_slot).collect();
+    let in_flight = InFlight::start(&state, &model_id, &target_endpoint.url).holding(slots);
+    let traced = state.debug_traces.traces(&model_id, &target_endpoint.url);
+    let timeouts = state.http.timeouts.for_endpoint(&target_endpoint);
+    let upstream_span = upstream.span(&forward_url);
+    // The caller's token and the model are for the composer, other subprotocols are the
+    // backend's to pick from
+    let protocols: Vec<&str> = offered
+        .iter()
+        .map(String::as_str)
+        .filter(|protocol| !protocol.starts_with(TOKEN_SUBPROTOCOL) && !protocol.starts_with(MODEL_SUBPROTOCOL))
+        .collect();
+    let mut request = state
+        .http
+        .upstream(timeouts.connect, &Transport::of(&target_endpoint))
+        .get(forward_url)
+        .version(reqwest::Version::HTTP_11)
+        .headers(upstream.forwarded.clone())
+        .headers(target_endpoint.request_headers())
+        .headers(otel::trace_headers(&upstream_span))
+        .header(reqwest::header::CONNECTION, "Upgrade")
+        .header(reqwest::header::UPGRADE, "websocket");
+    for name in ["Sec-WebSocket-Key", "Sec-WebSocket-Version", "Sec-WebSocket-Extensions"] {
+        if let Some(value) = req.headers().get(name).and_then(|h| h.to_str().ok()) {
+            request = request.header(name, value);
+        }
+    }
+    if !protocols.is_empty() {
+        request = request.header("Sec-WebSocket-Protocol", protocols.join(", "));
+    }
+    // Only the handshake is bounded by the request timeout, the connection lasts as long as
+    // both sides keep it open
+    let forward_resp = match timeout(timeouts.request, send_traced(request, upstream_span)).await {
+        Ok(forward_resp) => forward_resp,
+        Err(_) => {
+            upstream.record_response(&state, true, None, started);
+            let resp = ApiError::new(StatusCode::GATEWAY_TIMEOUT, "The endpoint did not answer the handshake.")
+                .code("upstream_error")
+                .into_response();
+            return upstream.answered(resp, None);
+        }
+    };
+    let resp = match forward_resp {
+        Ok(resp) if resp.status() == reqwest::StatusCode::SWITCHING_PROTOCOLS => resp,
+        // Refused handshakes and failed requests are relayed like any response
+        other => {
+            let resp = relay_response(&state, &upstream, other, false, in_flight, started, traced).await;
+            return annotate_fallback(resp, fallback_from.as_deref());
         }
     };
-    drop(model_to_endpoints_generate);
+    upstream.record_response(&state, true, Some(101), started);
 
-    // 5. Filter endpoints by group
-    let endpoints = state.endpoints_generate.lock().unwrap();
-    let endpoints_list = endpoints_for_model
-        .iter()
-        .filter_map(|url| endpoints.iter().find(|e| &e.url == url))
-        .filter(|ep| ep.groups.iter().any(|g| user_groups.contains(g)))
-        .cloned()
-        .collect::<Vec<Endpoint>>();
+    let mut switching = HttpResponse::SwitchingProtocols();
+    switching.upgrade("websocket");
+    let upstream_header = |name: &str| resp.headers().get(name).and_then(|h| h.to_str().ok()).map(String::from);
+    for name in ["Sec-WebSocket-Accept", "Sec-WebSocket-Extensions"] {
+        if let Some(value) = upstream_header(name) {
+            switching.insert_header((name, value));
+        }
+    }
+    // Browsers close connections whose offered subprotocols all go unanswered; if the backend
+    // picked none, the first one it was offered or the model subprotocol is accepted on its
+    // behalf, never the one carrying the caller's token
+    let fallback_protocol = protocols.first().map(|protocol| protocol.to_string()).or_else(|| {
+        offered.iter().find(|protocol| protocol.starts_with(MODEL_SUBPROTOCOL)).cloned()
+    });
+    if let Some(protocol) = upstream_header("Sec-WebSocket-Protocol").or(fallback_protocol) {
+        switching.insert_header(("Sec-WebSocket-Protocol", protocol));
+    }
+    let upgraded = match resp.upgrade().await {
+        Ok(upgraded) => upgraded,
+        Err(e) => {
+            upstream.record_error(&state, &e);
+            let resp = ApiError::internal(format!("Forward request failed: {}", e))
+                .code("upstream_error")
+                .into_response();
+            return upstream.answered(resp, None);
+        }
+    };
+    let (upstream_reader, upstream_writer) = split(upgraded);
+    // The client's frames are passed on by a task of its own, the backend's are the body of
+    // the response
+    actix_web::rt::spawn(pump_frames(payload, upstream_writer));
+    let resp = switching.body(BodyStream::new(tunnelled_frames(upstream_reader, in_flight)));
+    annotate_fallback(upstream.answered(resp, Some(101)), fallback_from.as_deref())
+}
+
+// Subprotocol naming the model of a WebSocket connection, like "model.gpt-realtime"
+const MODEL_SUBPROTOCOL: &str = "model.";
+
+// Subprotocols of a WebSocket handshake in the client's order of preference
+fn offered_subprotocols(req: &HttpRequest) -> Vec<String> {
+    req.headers()
+        .get_all("Sec-WebSocket-Protocol")
+        .filter_map(|h| h.to_str().ok())
+        .flat_map(|h| h.split(','))
+        .map(str::trim)
+        .filter(|protocol| !protocol.is_empty())
+        .map(String::from)
+        .collect()
+}
 
-    // 6. If no authorized endpoints remain, 404
-    if endpoints_list.is_empty() {
-        return HttpResponse::NotFound()
-            .body(format!("The model `{}` does not exist.", model_id));
+// URL of the endpoint's WebSocket, with the query's model renamed to the one it serves
+fn websocket_url(base: &str, path: &str, query: &str, model: &str) -> Option<String> {
+    let mut url = reqwest::Url::parse(&format!("{}{}", base, path)).ok()?;
+    if !query.is_empty() {
+        url.set_query(Some(query));
+        let pairs: Vec<(String, String)> = url
+            .query_pairs()
+            .map(|(key, value)| {
+                let value = if key == "model" { model.to_string() } else { value.into_owned() };
+                (key.into_owned(), value)
+            })
+            .collect();
+        url.query_pairs_mut().clear().extend_pairs(pairs);
     }
+    Some(url.to_string())
+}
 
-    // 7. Pick the first one and rotate
-    let target_endpoint = &endpoints_list[0];
-    {
-        let mut map_lock = state.model_to_endpoints_generate.lock().unwrap();
-        if let Some(urls) = map_lock.get_mut(model_id) {
-            if let Some(pos) = urls.iter().position(|url| url == &target_endpoint.url) {
-                let url = urls.remove(pos);
-                urls.push(url);
-            }
+// Pass the client's frames on until either side closes the connection
+async fn pump_frames(mut payload: web::Payload, mut writer: WriteHalf<reqwest::Upgraded>) {
+    while let Some(Ok(chunk)) = payload.next().await {
+        if writer.write_all(&chunk).await.is_err() {
+            return;
         }
     }
+    let _ = writer.shutdown().await;
+}
 
-    // Log the forwarded request details
-    if stream_requested {
-        info!(
-            "forwarded streaming request for model {} to endpoint {}",
-            model_id, target_endpoint.url
-        );
-    } else {
-        info!(
-            "forwarded request for model {} to endpoint {}",
-            model_id, target_endpoint.url
-        );
+// The backend's frames as they arrive; the connection stays in flight until it closes
+fn tunnelled_frames(
+    mut reader: ReadHalf<reqwest::Upgraded>,
+    in_flight: InFlight,
+) -> impl Stream<Item = Result<Bytes, IoError>> {
+    try_stream! {
+        let _in_flight = in_flight;
+        let mut buf = BytesMut::new();
+        loop {
+            buf.reserve(16 * 1024);
+            if reader.read_buf(&mut buf).await? == 0 {
+                break;
+            }
+            yield buf.split().freeze();
+        }
     }
+}
 
-    // 8. Forward the entire request body
-    let forward_url = format!("{}/v1/completions", target_endpoint.url);
+// -- Handler: /v1/score (for score) ------------------------------------------
+pub async fn score_handler(
+    req: HttpRequest,
+    state: web::Data<Arc<AppState>>,
+    mut body: ProxyBody,
+) -> impl Responder {
+    if let Err(resp) = check_draining(&state) {
+        return *resp;
+    }
+    if let Err(resp) = check_budget(&req, &state).await {
+        return *resp;
+    }
+    if let Err(resp) = check_rate_limit(&req, &state).await {
+        return *resp;
+    }
+    let slot = match acquire_concurrency_slot(&req, &state) {
+        Ok(slot) => slot,
+        Err(resp) => return *resp,
+    };
+    let (Route { endpoint: target_endpoint, fallback_from, .. }, pool_slot) =
+        match admit(&req, &state, &mut body.json, "score", RouteClass::Batch).await {
+            Ok(admitted) => admitted,
+            Err(resp) => return *resp,
+        };
+    let slots: Vec<GroupSlot> = slot.into_iter().chain(pool_slot).collect();
+    let model_id = body.get("model").and_then(Value::as_str).unwrap_or_default().to_string();
+    if fallback_from.is_some() {
+        body.set_model(&model_id);
+    }
+    let served_model = resolve_alias(&target_endpoint, &model_id);
+    if let Some(served) = served_model {
+        body.set_model(served);
+    }
+    let auth_info = req.extensions().get::<AuthInfo>().cloned();
+    info!(
+        task = "score",
+        model = model_id.as_str(),
+        endpoint = target_endpoint.url.as_str(),
+        group = caller_groups(auth_info.as_ref()).as_str(),
+        stream = false;
+        "forwarded score request for model {} to endpoint {}",
+        model_id, target_endpoint.url
+    );
 
-    // Set up the client
-    let client_builder = reqwest::Client::builder()
-        .connect_timeout(Duration::from_secs(5));
-    let client = if stream_requested {
-        client_builder
-            .build()
-            .unwrap()
-    } else {
-        client_builder
-            // For non-streaming block for a maximum of 90 seconds.
-            .timeout(Duration::from_secs(90))
-            .build()    
-            .unwrap()
+    // Split document lists the backend can't take at once
+    let document_count = body.get("text_2").and_then(Value::as_array).map_or(0, Vec::len);
+    let forwarded = state.forwarding.headers_of(&req);
+    let upstream = UpstreamRequest {
+        endpoint: &target_endpoint,
+        task: "score",
+        model: &model_id,
+        served_model,
+        path: "/v1/score",
+        caller: auth_info.as_ref(),
+        conversation: None,
+        strip_stream_usage: false,
+        cache_key: None,
+        forwarded: &forwarded,
     };
-    let forward_resp = client
-        .post(forward_url)
-        .bearer_auth(&target_endpoint.access_token)
-        .json(&*body)
-        .send()
-        .await;
+    let resp = match target_endpoint.max_batch_size.filter(|size| *size > 0) {
+        Some(batch_size) if document_count > batch_size => {
+            let started = Instant::now();
+            let _in_flight = InFlight::start(&state, &model_id, &target_endpoint.url).holding(slots);
+            let resp = forward_score_batched(&state, &upstream, &body, batch_size).await;
+            let status = resp.status().as_u16();
+            upstream.record_response(&state, false, Some(status), started);
+            upstream.answered(resp, Some(status))
+        }
+        _ => relay(&state, &upstream, body.to_bytes(), false, slots).await,
+    };
+    annotate_fallback(resp, fallback_from.as_deref())
+}
 
-    // 9. Handle streaming vs non-streaming response
-    match forward_resp {
-        Ok(resp) => {
-            let status = resp.status();
-            if stream_requested {
-                let content_type = resp
-                    .headers()
-                    .get(reqwest::header::CONTENT_TYPE)
-                    .and_then(|v| v.to_str().ok())
-                    .unwrap_or("application/octet-stream")
-                    .to_string();
-                let byte_stream = resp.bytes_stream();
-                // Wrap the original stream per-chunk timeout logic
-                let timed_stream = stream_with_read_timeout(byte_stream);
-                HttpResponse::build(status)
-                    .content_type(content_type)
-                    // Pass the *new* timed_stream to Actix
-                    .streaming(timed_stream)
-            } else {
-                let text = resp.text().await.unwrap_or_default();
-                HttpResponse::build(status)
-                    .content_type("application/json")
-                    .body(text)
+// Score `text_2` in chunks of `batch_size` concurrently and merge the chunks in document order
+async fn forward_score_batched(
+    state: &Arc<AppState>,
+    upstream: &UpstreamRequest<'_>,
+    body: &Value,
+    batch_size: usize,
+) -> HttpResponse {
+    let endpoint = upstream.endpoint;
+    let started = Instant::now();
+    let documents = body["text_2"].as_array().cloned().unwrap_or_default();
+    // A list of queries pairs up with the documents one-to-one and is split alongside them
+    let queries = body
+        .get("text_1")
+        .and_then(Value::as_array)
+        .filter(|queries| queries.len() == documents.len())
+        .cloned();
+
+    let timeouts = state.http.timeouts.for_endpoint(endpoint);
+    let client = state.http.upstream(timeouts.connect, &Transport::of(endpoint));
+    let forward_url = format!("{}{}", endpoint.url, upstream.path);
+    // One span for all chunks, sent at once
+    let upstream_span = upstream.span(&forward_url);
+    let trace_headers = otel::trace_headers(&upstream_span);
+    let requests = documents.chunks(batch_size).enumerate().map(|(i, chunk)| {
+        let mut chunk_body = body.clone();
+        chunk_body["text_2"] = Value::Array(chunk.to_vec());
+        if let Some(queries) = &queries {
+            let start = i * batch_size;
+            chunk_body["text_1"] = Value::Array(queries[start..start + chunk.len()].to_vec());
+        }
+        client
+            .post(&forward_url)
+            .headers(upstream.forwarded.clone())
+            .headers(endpoint.request_headers())
+            .headers(trace_headers.clone())
+            .json(&chunk_body)
+            .timeout(timeouts.request)
+            .send()
+    });
+    let responses = join_all(requests).instrument(upstream_span).await;
+
+    let mut chunks = Vec::new();
+    for (i, forward_resp) in responses.into_iter().enumerate() {
+        let resp = match forward_resp {
+            Ok(resp) => resp,
+            Err(e) => {
+                upstream.record_error(state, &e);
+                return ApiError::internal(format!("Forward request failed: {}", e))
+                    .code("upstream_error")
+                    .into_response();
             }
+        };
+        let status = resp.status();
+        let text = resp.text().await.unwrap_or_default();
+        // Relay the first failing chunk as is
+        if !status.is_success() {
+            return HttpResponse::build(status)
+                .content_type("application/json")
+                .body(text);
         }
-        Err(e) => HttpResponse::InternalServerError().body(format!("Forward request failed: {}", e)),
+        let chunk: Value = match serde_json::from_str(&text) {
+            Ok(chunk) => chunk,
+            Err(e) => {
+                let msg = format!("Invalid score response: {}", e);
+                return ApiError::new(StatusCode::BAD_GATEWAY, msg).code("upstream_error").into_response();
+            }
+        };
+
+        chunks.push((i * batch_size, chunk));
+    }
+
+    let mut merged = merge_chunks(chunks);
+    if upstream.served_model.is_some() {
+        merged["model"] = Value::String(upstream.model.to_string());
     }
-}
\ No newline at end of file
+    if let Some(usage) = Usage::from_value(&merged["usage"]) {
+        upstream.usage_recorder(state, started)(usage);
+    }
+    HttpResponse::Ok().json(merged)
+}
+
+// Embed the chunks of a long input list concurrently and merge them in input order. The
+// first chunk goes to the endpoint the request was routed to, the others are routed on
+// their own and so spread over the model's endpoints.
+async fn forward_embeddings_split(
+    req: &HttpRequest,
+    state: &Arc<AppState>,
+    upstream: &UpstreamRequest<'_>,
+    body: &Value,
+    chunks: Vec<Vec<Value>>,
+    slots: Vec<GroupSlot>,
+) -> HttpResponse {
+    let started = Instant::now();
+    let route_body = json!({ "model": upstream.model });
+    let mut endpoints = vec![upstream.endpoint.clone()];
+    // Started as the chunks are routed so load-based routing sees the earlier chunks
+    let mut in_flight = vec![InFlight::start(state, upstream.model, &upstream.endpoint.url).holding(slots)];
+    for _ in 1..chunks.len() {
+        // Endpoints that went away meanwhile leave their chunks to the first
+        let endpoint = match select_endpoint(req, state, &route_body, upstream.task) {
+            Ok(route) => {
+                route.dispatch(state, upstream.task);
+                route.endpoint
+            }
+            Err(_) => upstream.endpoint.clone(),
+        };
+        let pool_slot = acquire_pool_slot(state, &endpoint, RouteClass::Batch).ok().flatten();
+        in_flight.push(InFlight::start(state, upstream.model, &endpoint.url).holding(pool_slot));
+        endpoints.push(endpoint);
+    }
+    info!(
+        "split embedding request for model {} into {} chunks over {} endpoints",
+        upstream.model,
+        chunks.len(),
+        endpoints.iter().map(|ep| &ep.url).collect::<HashSet<_>>().len()
+    );
+
+    // One span for all chunks, sent at once
+    let upstream_span = upstream.span(&format!("{}{}", upstream.endpoint.url, upstream.path));
+    let trace_headers = otel::trace_headers(&upstream_span);
+    let capabilities = state.endpoint_capabilities.lock().unwrap().clone();
+    let requests = chunks.iter().zip(&endpoints).map(|(chunk, endpoint)| {
+        let mut chunk_body = body.clone();
+        chunk_body["input"] = Value::Array(chunk.clone());
+        let served = resolve_alias(endpoint, upstream.model).unwrap_or(upstream.model);
+        chunk_body["model"] = Value::String(served.to_string());
+        sanitize_request(&mut chunk_body, endpoint, &capabilities, upstream.path);
+        let timeouts = state.http.timeouts.for_endpoint(endpoint);
+        state
+            .http
+            .upstream(timeouts.connect, &Transport::of(endpoint))
+            .post(format!("{}{}", endpoint.url, upstream.path))
+            .headers(upstream.forwarded.clone())
+            .headers(endpoint.request_headers())
+            .headers(trace_headers.clone())
+            .json(&chunk_body)
+            .timeout(timeouts.request)
+            .send()
+    });
+    let responses = join_all(requests).instrument(upstream_span).await;
+
+    let mut merged = Vec::new();
+    let mut offset = 0;
+    for ((forward_resp, endpoint), chunk) in responses.into_iter().zip(&endpoints).zip(&chunks) {
+        let chunk_upstream = UpstreamRequest {
+            endpoint,
+            served_model: resolve_alias(endpoint, upstream.model),
+            ..*upstream
+        };
+        let resp = match forward_resp {
+            Ok(resp) => resp,
+            Err(e) => {
+                chunk_upstream.record_error(state, &e);
+                chunk_upstream.record_response(state, false, None, started);
+                let resp = ApiError::internal(format!("Forward request failed: {}", e))
+                    .code("upstream_error")
+                    .into_response();
+                return chunk_upstream.answered(resp, None);
+            }
+        };
+        let status = resp.status();
+        chunk_upstream.record_response(state, false, Some(status.as_u16()), started);
+        let text = resp.text().await.unwrap_or_default();
+        // Relay the first failing chunk as is
+        if !status.is_success() {
+            let resp = HttpResponse::build(status)
+                .content_type("application/json")
+                .body(text);
+            return chunk_upstream.answered(resp, Some(status.as_u16()));
+        }
+        match serde_json::from_str(&text) {
+            Ok(chunk_resp) => merged.push((offset, chunk_resp)),
+            Err(e) => {
+                let msg = format!("Invalid embedding response: {}", e);
+                return ApiError::new(StatusCode::BAD_GATEWAY, msg).code("upstream_error").into_response();
+            }
+        }
+        offset += chunk.len();
+    }
+    drop(in_flight);
+
+    let mut merged = merge_chunks(merged);
+    merged["model"] = Value::String(upstream.model.to_string());
+    if let Some(usage) = Usage::from_value(&merged["usage"]) {
+        upstream.usage_recorder(state, started)(usage);
+    }
+    upstream.answered(HttpResponse::Ok().json(merged), Some(200))
+}
+
+// Merge the responses to chunks of a list, given with the offset of their chunk: the `data`
+// items in list order with their indices shifted by the offset, token counts summed up
+fn merge_chunks(chunks: Vec<(usize, Value)>) -> Value {
+    let mut merged: Option<Value> = None;
+    let mut data = Vec::new();
+    let mut usage = Map::new();
+    for (offset, chunk) in chunks {
+        for item in chunk.get("data").and_then(Value::as_array).into_iter().flatten() {
+            let mut item = item.clone();
+            if let Some(index) = item.get("index").and_then(Value::as_u64) {
+                item["index"] = Value::from(index + offset as u64);
+            }
+            data.push(item);
+        }
+        for (key, count) in chunk.get("usage").and_then(Value::as_object).into_iter().flatten() {
+            if let Some(count) = count.as_u64() {
+                let total = usage.get(key).and_then(Value::as_u64).unwrap_or(0) + count;
+                usage.insert(key.clone(), Value::from(total));
+            }
+        }
+        merged.get_or_insert(chunk);
+    }
+    data.sort_by_key(|item| item.get("index").and_then(Value::as_u64).unwrap_or(u64::MAX));
+    let mut merged = merged.unwrap_or_else(|| json!({ "object": "list" }));
+    merged["data"] = Value::Array(data);
+    merged["usage"] = Value::Object(usage);
+    merged
+}
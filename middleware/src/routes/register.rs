@@ -0,0 +1,38 @@
+// External crates
+use actix_web::{web, HttpMessage, HttpRequest, HttpResponse, Responder};
+
+// Standard library
+use std::sync::Arc;
+
+// Internal modules
+use crate::auth::AuthInfo;
+use crate::body::JsonBody;
+use crate::discovery::registration::Registration;
+use crate::errors::ApiError;
+use crate::state::AppState;
+
+// -- Handler: /register (backends announce themselves) ------------------------
+pub async fn register_handler(
+    req: HttpRequest,
+    state: web::Data<Arc<AppState>>,
+    body: JsonBody,
+) -> impl Responder {
+    let auth_info = match req.extensions().get::<AuthInfo>() {
+        Some(info) => info.clone(),
+        None => return ApiError::unauthorized().into_response(),
+    };
+    // Only tokens handed out to backends may add endpoints
+    if !auth_info.groups.contains(&state.registrations.group) {
+        return ApiError::forbidden().into_response();
+    }
+
+    let JsonBody(body) = body;
+    let registration: Registration = match serde_json::from_value(body) {
+        Ok(registration) => registration,
+        Err(e) => return ApiError::bad_request(format!("Invalid registration: {}", e)).into_response(),
+    };
+    match state.registrations.register(&state, &registration) {
+        Ok(accepted) => HttpResponse::Ok().json(accepted),
+        Err(msg) => ApiError::bad_request(msg).into_response(),
+    }
+}
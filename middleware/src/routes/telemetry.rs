@@ -0,0 +1,24 @@
+// External crates
+use actix_web::{web, HttpMessage, HttpRequest, HttpResponse, Responder};
+
+// Standard library
+use std::sync::Arc;
+
+// Internal modules
+use crate::auth::AuthInfo;
+use crate::errors::ApiError;
+use crate::state::AppState;
+
+// -- Handler: /telemetry (anonymous operational statistics) -------------------
+pub async fn telemetry_handler(req: HttpRequest, state: web::Data<Arc<AppState>>) -> impl Responder {
+    let auth_info = match req.extensions().get::<AuthInfo>() {
+        Some(info) => info.clone(),
+        None => return ApiError::unauthorized().into_response(),
+    };
+    // Collected by the same scrapers as /metrics
+    if !auth_info.reads_metrics() {
+        return ApiError::forbidden().into_response();
+    }
+
+    HttpResponse::Ok().json(state.telemetry.report(&state))
+}
@@ -0,0 +1,88 @@
+// External crates
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use serde::Deserialize;
+use serde_json::json;
+
+// Standard library
+use std::sync::Arc;
+
+// Internal modules
+use crate::auth::caller_is_operator;
+use crate::body::JsonBody;
+use crate::errors::ApiError;
+use crate::state::AppState;
+use crate::token_admin::{list_tokens, TokenMetadata};
+
+// What POST /admin/tokens expects
+#[derive(Debug, Deserialize)]
+pub struct CreateTokenRequest {
+    groups: Vec<String>,
+    #[serde(flatten)]
+    metadata: TokenMetadata,
+}
+
+// -- Handler: GET /admin/tokens (ids and groups of all tokens) ----------------
+pub async fn list_tokens_handler(req: HttpRequest, state: web::Data<Arc<AppState>>) -> impl Responder {
+    match caller_is_operator(&req) {
+        None => return ApiError::unauthorized().into_response(),
+        Some(false) => return ApiError::forbidden().into_response(),
+        Some(true) => {}
+    }
+    HttpResponse::Ok().json(list_tokens(&state.auth_tokens.load().groups))
+}
+
+// -- Handler: POST /admin/tokens (create a token) -----------------------------
+pub async fn create_token_handler(
+    req: HttpRequest,
+    state: web::Data<Arc<AppState>>,
+    body: JsonBody,
+) -> impl Responder {
+    match caller_is_operator(&req) {
+        None => return ApiError::unauthorized().into_response(),
+        Some(false) => return ApiError::forbidden().into_response(),
+        Some(true) => {}
+    }
+    let JsonBody(body) = body;
+    let request: CreateTokenRequest = match serde_json::from_value(body) {
+        Ok(request) => request,
+        Err(e) => return ApiError::bad_request(format!("Invalid request: {}", e)).into_response(),
+    };
+    let state = Arc::clone(&state);
+    let result = web::block(move || {
+        state
+            .token_admin
+            .create(&state, &request.groups, &request.metadata)
+            .map(|created| (created, request.groups))
+    })
+    .await;
+    match result {
+        // The token is only ever shown here, secrets.yaml keeps its hash
+        Ok(Ok(((token, id), groups))) => {
+            HttpResponse::Created().json(json!({ "id": id, "token": token, "groups": groups }))
+        }
+        Ok(Err(msg)) => ApiError::bad_request(msg).into_response(),
+        Err(e) => ApiError::internal(e.to_string()).into_response(),
+    }
+}
+
+// -- Handler: DELETE /admin/tokens/{id} (revoke a token) ----------------------
+pub async fn revoke_token_handler(
+    req: HttpRequest,
+    state: web::Data<Arc<AppState>>,
+    id: web::Path<String>,
+) -> impl Responder {
+    match caller_is_operator(&req) {
+        None => return ApiError::unauthorized().into_response(),
+        Some(false) => return ApiError::forbidden().into_response(),
+        Some(true) => {}
+    }
+    let id = id.into_inner();
+    let state = Arc::clone(&state);
+    let revoke_id = id.clone();
+    match web::block(move || state.token_admin.revoke(&state, &revoke_id)).await {
+        Ok(Ok(true)) => HttpResponse::NoContent().finish(),
+        Ok(Ok(false)) => ApiError::not_found(format!("No token with id `{}`.", id)).into_response(),
+        Ok(Err(msg)) => ApiError::internal(msg).into_response(),
+        Err(e) => ApiError::internal(e.to_string()).into_response(),
+    }
+}
@@ -0,0 +1,142 @@
+// External crates
+use actix_web::{web, HttpMessage, HttpRequest, HttpResponse, Responder};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+
+// Standard library
+use std::collections::HashMap;
+use std::sync::Arc;
+
+// Internal modules
+use crate::auth::AuthInfo;
+use crate::errors::ApiError;
+use crate::pricing::price_buckets;
+use crate::shared::now_ms;
+use crate::state::AppState;
+use crate::usage::{UsageTotals, Window};
+
+#[derive(Debug, Deserialize)]
+pub struct UsageQuery {
+    // "hour" (default) or "day"
+    window: Option<String>,
+    // Unix seconds; the last 24 hours (hourly) or 30 days (daily) by default
+    since: Option<u64>,
+    until: Option<u64>,
+}
+
+// -- Handler: /usage (requests and tokens per window, group and model) --------
+pub async fn usage_handler(
+    req: HttpRequest,
+    state: web::Data<Arc<AppState>>,
+    query: web::Query<UsageQuery>,
+) -> impl Responder {
+    let auth_info = match req.extensions().get::<AuthInfo>() {
+        Some(info) => info.clone(),
+        None => return ApiError::unauthorized().into_response(),
+    };
+    let is_operator = auth_info.is_operator();
+
+    let window = match query.window.as_deref().map(Window::parse) {
+        None => Window::Hour,
+        Some(Some(window)) => window,
+        Some(None) => {
+            return ApiError::bad_request("`window` must be \"hour\" or \"day\".").into_response();
+        }
+    };
+    let now = now_ms() / 1000;
+    let default_span = match window {
+        Window::Hour => 24 * window.secs(),
+        Window::Day => 30 * window.secs(),
+    };
+    let until = query.until.unwrap_or(now + 1);
+    let since = query.since.unwrap_or(until.saturating_sub(default_span));
+    if since >= until {
+        return ApiError::bad_request("`since` must be before `until`.").into_response();
+    }
+
+    // Admins see all groups, everyone else only their own
+    let groups = (!is_operator).then(|| auth_info.groups.clone());
+    // The database also knows about requests before the last restart
+    let (mut buckets, token_totals) = if state.usage_db.is_some() {
+        let state = Arc::clone(&state);
+        let result = web::block(move || {
+            let db = state.usage_db.as_ref().unwrap();
+            let buckets = db.rollup(window, since, until, groups.as_deref())?;
+            let token_totals = if is_operator { db.token_totals()? } else { HashMap::new() };
+            Ok::<_, rusqlite::Error>((buckets, token_totals))
+        })
+        .await;
+        match result {
+            Ok(Ok(usage)) => usage,
+            Ok(Err(e)) => {
+                return ApiError::internal(format!("Failed to query usage database: {}", e)).into_response();
+            }
+            Err(e) => return ApiError::internal(e.to_string()).into_response(),
+        }
+    } else {
+        (
+            state.usage.rollup(window, since, until, groups.as_deref()),
+            if is_operator { state.usage.token_totals() } else { HashMap::new() },
+        )
+    };
+
+    price_buckets(&state.auth_tokens.load().pricing, &mut buckets);
+
+    let mut by_group: HashMap<&str, UsageTotals> = HashMap::new();
+    let mut by_model: HashMap<&str, UsageTotals> = HashMap::new();
+    for bucket in &buckets {
+        by_group.entry(&bucket.group).or_default().merge(&bucket.totals);
+        by_model.entry(&bucket.model).or_default().merge(&bucket.totals);
+    }
+
+    let mut output = json!({
+        "window": if window == Window::Hour { "hour" } else { "day" },
+        "since": since,
+        "until": until,
+        "by_group": by_group,
+        "by_model": by_model,
+        "buckets": buckets,
+    });
+    // Per token over all recorded usage, tokens identified by their id only
+    if is_operator {
+        output["by_token"] = json!(token_totals);
+    }
+    HttpResponse::Ok().json(output)
+}
+
+// -- Handler: /budgets (token budgets of groups and their usage this day and month) --
+pub async fn budgets_handler(req: HttpRequest, state: web::Data<Arc<AppState>>) -> impl Responder {
+    let auth_info = match req.extensions().get::<AuthInfo>() {
+        Some(info) => info.clone(),
+        None => return ApiError::unauthorized().into_response(),
+    };
+    let is_operator = auth_info.is_operator();
+    let token_id = auth_info.token_id();
+    state.budgets.sync_shared(&state.shared, &token_id, &auth_info.groups).await;
+
+    // Admins see all budgets, everyone else those of their groups and their own token's share
+    let auth_tokens = state.auth_tokens.load();
+    let groups: Map<String, Value> = auth_tokens
+        .budgets
+        .iter()
+        .filter(|(group, _)| is_operator || auth_info.groups.contains(group))
+        .map(|(group, budget)| {
+            let token = auth_info.groups.contains(group).then_some(token_id.as_str());
+            (group.clone(), state.budgets.status(budget, group, token))
+        })
+        .collect();
+    let mut output = json!({ "groups": groups });
+    if let Err(exhausted) = state.budgets.check(&auth_tokens.budgets, &token_id, &auth_info.groups) {
+        output["exhausted"] = json!({
+            "group": exhausted.group,
+            "per_token": exhausted.per_token,
+            "period": exhausted.period.label(),
+            "resets_in_secs": exhausted.resets_in_secs,
+        });
+    }
+    // Tokens identified by their id only
+    if is_operator {
+        output["by_token"] = state.budgets.token_usage();
+    }
+    HttpResponse::Ok().json(output)
+}
@@ -0,0 +1,742 @@
+// External crates
+use actix_web::{HttpMessage, HttpRequest, HttpResponse};
+use log::{debug, info, warn};
+use serde_json::Value;
+
+// Standard library
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+// Internal modules
+use crate::affinity::pick_by_session;
+use crate::auth::AuthInfo;
+use crate::concurrency::RouteClass;
+use crate::errors::ApiError;
+use crate::prefix::PrefixConfig;
+use crate::guided::{filter_guided_capable, requested_guided_params, validate_guided_params};
+use crate::shared::now_ms;
+use crate::state::{AppState, BackendMetrics, Endpoint, TaskState};
+use crate::stats::EndpointLatency;
+use crate::vision::{filter_vision_capable, request_has_images};
+
+// -----------------------------------------------------------------------------
+// Routing
+// -----------------------------------------------------------------------------
+
+// Endpoints come back after a few passing probes, half a second apart
+const UNAVAILABLE_RETRY_AFTER_SECS: u64 = 5;
+
+// Why no endpoint was picked for a request
+pub enum RouteError {
+    Unauthorized,
+    // No endpoint the caller may use serves the model, or did before going down
+    UnknownModel(String),
+    // All endpoints of the model the caller may use are failing or down
+    Unavailable(String),
+    // The request needs a feature no endpoint of the model has
+    BadRequest(String),
+    // The pools of the request's class are full on every endpoint of the model
+    Busy(String, RouteClass),
+}
+
+impl RouteError {
+    pub fn into_response(self) -> HttpResponse {
+        match self {
+            RouteError::Unauthorized => ApiError::unauthorized(),
+            RouteError::UnknownModel(model) => {
+                ApiError::not_found(format!("The model `{}` does not exist.", model))
+                    .param("model")
+                    .code("model_not_found")
+            }
+            RouteError::Unavailable(model) => ApiError::unavailable(format!(
+                "No endpoint of model `{}` is available right now, try again later.",
+                model
+            ))
+            .code("model_unavailable")
+            .retry_after(UNAVAILABLE_RETRY_AFTER_SECS),
+            RouteError::BadRequest(msg) => ApiError::bad_request(msg),
+            RouteError::Busy(model, class) => ApiError::unavailable(format!(
+                "All endpoints of model `{}` are busy with {} requests, try again shortly.",
+                model,
+                class.label()
+            ))
+            .code("model_busy")
+            .retry_after(1),
+        }
+        .into_response()
+    }
+
+    // Whether a fallback model may serve the request instead
+    fn allows_fallback(&self) -> bool {
+        matches!(self, RouteError::UnknownModel(_) | RouteError::Unavailable(_))
+    }
+}
+
+// Ordered fallback models per model
+pub struct Fallbacks {
+    // The `fallbacks` of the endpoints in endpoints.yaml, replaced on reload
+    configured: Mutex<HashMap<String, Vec<String>>>,
+    // Chains of VLLM_COMPOSER_MODEL_FALLBACKS, which win over the file's
+    overrides: HashMap<String, Vec<String>>,
+}
+
+impl Fallbacks {
+    // VLLM_COMPOSER_MODEL_FALLBACKS is a list like "llama-70b=llama-8b|llama-3b,qwen-72b=qwen-7b"
+    pub fn from_env() -> Self {
+        let overrides: HashMap<String, Vec<String>> = std::env::var("VLLM_COMPOSER_MODEL_FALLBACKS")
+            .unwrap_or_default()
+            .split(',')
+            .filter_map(|entry| {
+                let (model, chain) = entry.split_once('=')?;
+                let chain: Vec<String> = chain
+                    .split('|')
+                    .map(str::trim)
+                    .filter(|m| !m.is_empty())
+                    .map(String::from)
+                    .collect();
+                (!chain.is_empty()).then(|| (model.trim().to_string(), chain))
+            })
+            .collect();
+        if !overrides.is_empty() {
+            info!("Model fallbacks from the environment: {:?}", overrides);
+        }
+        Fallbacks { configured: Mutex::new(HashMap::new()), overrides }
+    }
+
+    // Take the chains of the endpoints' `fallbacks`. Chains of a model on several endpoints
+    // are joined in file order.
+    pub fn configure(&self, endpoints: &[Endpoint]) {
+        let mut chains: HashMap<String, Vec<String>> = HashMap::new();
+        for fallbacks in endpoints.iter().filter_map(|endpoint| endpoint.fallbacks.as_ref()) {
+            for (model, fallbacks) in fallbacks {
+                let chain = chains.entry(model.clone()).or_default();
+                for fallback in fallbacks {
+                    if !chain.contains(fallback) && fallback != model {
+                        chain.push(fallback.clone());
+                    }
+                }
+            }
+        }
+        *self.configured.lock().unwrap() = chains;
+    }
+
+    pub fn chain(&self, model: &str) -> Vec<String> {
+        match self.overrides.get(model) {
+            Some(chain) => chain.clone(),
+            None => self.configured.lock().unwrap().get(model).cloned().unwrap_or_default(),
+        }
+    }
+}
+
+// Smooth weighted round-robin: every pick adds each candidate's weight to its credit and
+// takes the candidate with the most credit, which then pays the total weight. Endpoints are
+// picked in proportion to their weights and interleaved rather than in bursts.
+#[derive(Default)]
+pub struct WeightedRoundRobin {
+    // (task, model) -> endpoint URL -> credit, in thousandths of a weight
+    credits: Mutex<HashMap<(String, String), HashMap<String, i64>>>,
+}
+
+// The credit changes of a round-robin pick, booked once the request is dispatched
+pub struct CreditUpdate {
+    key: (String, String),
+    // Each candidate's weight, in thousandths
+    weights: Vec<(String, i64)>,
+    chosen: String,
+}
+
+impl WeightedRoundRobin {
+    // The candidate to take next, leaving the credits alone until the pick is booked
+    pub fn pick(
+        &self,
+        task: &str,
+        model: &str,
+        candidates: Vec<Endpoint>,
+        weight: impl Fn(&Endpoint) -> f64,
+    ) -> Option<(Endpoint, CreditUpdate)> {
+        let key = (task.to_string(), model.to_string());
+        let credits = self.credits.lock().unwrap();
+        let model_credits = credits.get(&key);
+        let weight = |ep: &Endpoint| ((weight(ep) * 1000.0) as i64).max(1);
+        let mut weights = Vec::with_capacity(candidates.len());
+        let mut best: Option<(i64, Endpoint)> = None;
+        for endpoint in candidates {
+            let credit = model_credits.and_then(|c| c.get(&endpoint.url)).copied().unwrap_or(0);
+            let credit = credit + weight(&endpoint);
+            weights.push((endpoint.url.clone(), weight(&endpoint)));
+            if best.as_ref().is_none_or(|(best_credit, _)| credit > *best_credit) {
+                best = Some((credit, endpoint));
+            }
+        }
+        let (_, chosen) = best?;
+        let update = CreditUpdate { key, weights, chosen: chosen.url.clone() };
+        Some((chosen, update))
+    }
+
+    // Book a pick: every candidate earns its weight, the chosen one pays the total
+    pub fn book(&self, update: &CreditUpdate) {
+        let mut credits = self.credits.lock().unwrap();
+        let model_credits = credits.entry(update.key.clone()).or_default();
+        // Endpoints that are no longer candidates start over when they return
+        model_credits.retain(|url, _| update.weights.iter().any(|(candidate, _)| candidate == url));
+        for (url, weight) in &update.weights {
+            *model_credits.entry(url.clone()).or_insert(0) += weight;
+        }
+        let total: i64 = update.weights.iter().map(|(_, weight)| weight).sum();
+        if let Some(credit) = model_credits.get_mut(&update.chosen) {
+            *credit -= total;
+        }
+    }
+
+    // Drop the credits of models no longer served and of endpoints that are gone, returns
+    // how many entries went
+    pub fn retain(&self, served: &HashSet<(String, String)>, active: &HashSet<String>) -> usize {
+        let mut credits = self.credits.lock().unwrap();
+        let before = credit_entries(&credits);
+        credits.retain(|key, model_credits| {
+            model_credits.retain(|url, _| active.contains(url));
+            served.contains(key) && !model_credits.is_empty()
+        });
+        before - credit_entries(&credits)
+    }
+
+    pub fn entries(&self) -> usize {
+        credit_entries(&self.credits.lock().unwrap())
+    }
+}
+
+// Credits of all models and endpoints, models without credits count once
+fn credit_entries(credits: &HashMap<(String, String), HashMap<String, i64>>) -> usize {
+    credits.values().map(|model_credits| model_credits.len().max(1)).sum()
+}
+
+// How a model's endpoints share its requests
+pub enum RoutingStrategy {
+    // Smooth weighted round-robin
+    Weighted,
+    // Fewest requests in flight per unit of weight
+    LeastLoaded,
+    // Identical prompt prefixes go to the same endpoint so they hit its prefix cache,
+    // requests without one or whose endpoint is overloaded go by load
+    Prefix(PrefixConfig),
+    // Avoid endpoints whose scraped vLLM metrics show a deep queue or a nearly full KV cache
+    BackendLoad(BackendLoadConfig),
+    // Prefer the endpoints with the shortest time to first token for the model
+    Latency(LatencyConfig),
+}
+
+// Endpoints within this share of the fastest one count as just as fast and go by load, so
+// the fastest endpoint isn't piled up with every request
+const LATENCY_TOLERANCE: f64 = 0.2;
+
+// How latency-aware routing explores
+pub struct LatencyConfig {
+    // Every nth request goes by weighted round-robin, so the averages of slower endpoints
+    // stay current; 0 never explores
+    pub explore_every: u64,
+    picks: AtomicU64,
+}
+
+impl LatencyConfig {
+    // VLLM_COMPOSER_LATENCY_EXPLORE_EVERY (default 10)
+    pub fn from_env() -> Self {
+        let explore_every = std::env::var("VLLM_COMPOSER_LATENCY_EXPLORE_EVERY")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(10);
+        LatencyConfig::new(explore_every)
+    }
+
+    pub fn new(explore_every: u64) -> Self {
+        LatencyConfig { explore_every, picks: AtomicU64::new(0) }
+    }
+
+    fn explores(&self) -> bool {
+        let picks = self.picks.fetch_add(1, Ordering::Relaxed) + 1;
+        self.explore_every > 0 && picks.is_multiple_of(self.explore_every)
+    }
+}
+
+// When an endpoint counts as pressured by its scraped metrics
+pub struct BackendLoadConfig {
+    // Requests waiting per unit of weight
+    pub max_waiting: f64,
+    // KV cache usage, 0 to 1
+    pub max_kv_cache_usage: f64,
+}
+
+impl BackendLoadConfig {
+    // VLLM_COMPOSER_MAX_QUEUE_DEPTH (default 4) and VLLM_COMPOSER_MAX_KV_CACHE_USAGE
+    // (default 0.9)
+    fn from_env() -> Self {
+        let env_f64 = |name: &str, default: f64| {
+            std::env::var(name)
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(default)
+        };
+        BackendLoadConfig {
+            max_waiting: env_f64("VLLM_COMPOSER_MAX_QUEUE_DEPTH", 4.0),
+            max_kv_cache_usage: env_f64("VLLM_COMPOSER_MAX_KV_CACHE_USAGE", 0.9),
+        }
+    }
+}
+
+impl RoutingStrategy {
+    // VLLM_COMPOSER_ROUTING_STRATEGY: "weighted" (default), "least_loaded", "prefix",
+    // "backend_load" or "latency"
+    pub fn from_env() -> Self {
+        let strategy = match std::env::var("VLLM_COMPOSER_ROUTING_STRATEGY").as_deref() {
+            Ok("least_loaded") => RoutingStrategy::LeastLoaded,
+            Ok("prefix") => RoutingStrategy::Prefix(PrefixConfig::from_env()),
+            Ok("backend_load") => RoutingStrategy::BackendLoad(BackendLoadConfig::from_env()),
+            Ok("latency") => RoutingStrategy::Latency(LatencyConfig::from_env()),
+            Ok("weighted") | Err(_) => RoutingStrategy::Weighted,
+            Ok(other) => {
+                warn!("Unknown routing strategy `{}`, using weighted round-robin", other);
+                RoutingStrategy::Weighted
+            }
+        };
+        match &strategy {
+            RoutingStrategy::Weighted => {}
+            RoutingStrategy::LeastLoaded => info!("Routing to the least loaded endpoint"),
+            RoutingStrategy::Prefix(config) => info!(
+                "Routing by prompt prefix over the system prompt and {} user turn(s)",
+                config.user_turns
+            ),
+            RoutingStrategy::BackendLoad(config) => info!(
+                "Routing around endpoints with over {} waiting requests or {:.0}% KV cache usage",
+                config.max_waiting,
+                config.max_kv_cache_usage * 100.0
+            ),
+            RoutingStrategy::Latency(config) => info!(
+                "Routing to the endpoints with the shortest time to first token, exploring every {}th request",
+                config.explore_every
+            ),
+        }
+        strategy
+    }
+
+    // Pick one of the non-empty candidates for the body
+    fn pick(
+        &self,
+        state: &AppState,
+        task: &str,
+        model: &str,
+        body: &Value,
+        candidates: Vec<Endpoint>,
+    ) -> Option<Route> {
+        match self {
+            RoutingStrategy::Weighted => round_robin(state, task, model, candidates),
+            RoutingStrategy::LeastLoaded => pick_least_loaded(state, task, model, candidates),
+            RoutingStrategy::Prefix(config) => {
+                let Some(key) = config.prefix_key(body) else {
+                    return pick_least_loaded(state, task, model, candidates);
+                };
+                let min_load = candidates.iter().map(|ep| load(state, ep)).fold(f64::MAX, f64::min);
+                let target = pick_by_session(&key, candidates.clone())?;
+                if load(state, &target) > min_load + config.max_imbalance as f64 {
+                    debug!("prefix endpoint {} overloaded, routing by load", target.url);
+                    return pick_least_loaded(state, task, model, candidates);
+                }
+                Some(Route::to(target))
+            }
+            RoutingStrategy::BackendLoad(config) => {
+                pick_by_backend_load(state, task, model, config, candidates)
+            }
+            RoutingStrategy::Latency(config) => pick_by_latency(state, task, model, config, candidates),
+        }
+    }
+}
+
+// The least loaded of the candidates about as fast as the fastest, by their average time to
+// first token, or to the whole response where no stream was timed. Idle candidates never
+// timed for the model are tried first, and every so often a request explores by round-robin.
+fn pick_by_latency(
+    state: &AppState,
+    task: &str,
+    model: &str,
+    config: &LatencyConfig,
+    candidates: Vec<Endpoint>,
+) -> Option<Route> {
+    if config.explores() {
+        return round_robin(state, task, model, candidates);
+    }
+    let latencies: Vec<EndpointLatency> =
+        candidates.iter().map(|ep| state.model_stats.latency(&ep.url, model)).collect();
+    // Streams and whole responses aren't compared with each other
+    let streamed = latencies.iter().any(|latency| latency.ttft_ms.is_some());
+    let timed: Vec<Option<f64>> = latencies
+        .iter()
+        .map(|latency| if streamed { latency.ttft_ms } else { latency.total_ms })
+        .collect();
+    let untimed: Vec<Endpoint> = candidates
+        .iter()
+        .zip(&timed)
+        .filter(|(ep, ms)| ms.is_none() && state.model_stats.endpoint_in_flight(&ep.url) == 0)
+        .map(|(ep, _)| ep.clone())
+        .collect();
+    if !untimed.is_empty() {
+        return round_robin(state, task, model, untimed);
+    }
+    let timed: Vec<(Endpoint, Option<f64>)> = candidates.into_iter().zip(timed).collect();
+    if timed.iter().all(|(_, ms)| ms.is_none()) {
+        return pick_least_loaded(state, task, model, timed.into_iter().map(|(ep, _)| ep).collect());
+    }
+    let fastest = timed.iter().filter_map(|(_, ms)| *ms).fold(f64::MAX, f64::min);
+    let fast = timed
+        .into_iter()
+        .filter(|(_, ms)| ms.is_some_and(|ms| ms <= fastest * (1.0 + LATENCY_TOLERANCE)))
+        .map(|(ep, _)| ep)
+        .collect();
+    pick_least_loaded(state, task, model, fast)
+}
+
+// The least loaded of the candidates that are not pressured; if all are, the one with the
+// shortest queue. Endpoints without recent metrics count as not pressured.
+fn pick_by_backend_load(
+    state: &AppState,
+    task: &str,
+    model: &str,
+    config: &BackendLoadConfig,
+    candidates: Vec<Endpoint>,
+) -> Option<Route> {
+    let fresh_after = state
+        .monitor_intervals
+        .metrics_scrape
+        .map_or(0, |interval| now_ms().saturating_sub(3 * interval.as_millis() as u64));
+    let metrics: Vec<Option<BackendMetrics>> = {
+        let scraped = state.backend_metrics.lock().unwrap();
+        candidates
+            .iter()
+            .map(|ep| scraped.get(&ep.url).copied().filter(|m| m.scraped_ms >= fresh_after))
+            .collect()
+    };
+    let waiting = |ep: &Endpoint, m: &BackendMetrics| {
+        m.requests_waiting / ep.weight.unwrap_or(1).max(1) as f64
+    };
+    let is_pressured = |ep: &Endpoint, m: &BackendMetrics| {
+        waiting(ep, m) > config.max_waiting || m.kv_cache_usage > config.max_kv_cache_usage
+    };
+
+    let (relaxed, pressured): (Vec<_>, Vec<_>) = candidates
+        .into_iter()
+        .zip(metrics)
+        .partition(|(ep, m)| m.is_none_or(|m| !is_pressured(ep, &m)));
+    if !relaxed.is_empty() {
+        let relaxed = relaxed.into_iter().map(|(ep, _)| ep).collect();
+        return pick_least_loaded(state, task, model, relaxed);
+    }
+    debug!("all endpoints of {} are pressured, picking the shortest queue", model);
+    pressured
+        .into_iter()
+        .filter_map(|(ep, m)| m.map(|m| (waiting(&ep, &m), m.kv_cache_usage, ep)))
+        .min_by(|a, b| a.0.total_cmp(&b.0).then(a.1.total_cmp(&b.1)))
+        .map(|(_, _, ep)| Route::to(ep))
+}
+
+// The endpoint's routing weight, ramped up while it slow-starts
+fn weight(state: &AppState, endpoint: &Endpoint) -> f64 {
+    endpoint.weight.unwrap_or(1).max(1) as f64 * state.slow_start.share(&endpoint.url)
+}
+
+// Weighted round-robin over the candidates
+fn round_robin(state: &AppState, task: &str, model: &str, candidates: Vec<Endpoint>) -> Option<Route> {
+    let (endpoint, credits) = state.round_robin.pick(task, model, candidates, |ep| weight(state, ep))?;
+    Some(Route { credits: Some(credits), ..Route::to(endpoint) })
+}
+
+// Requests in flight at the endpoint per unit of weight
+fn load(state: &AppState, endpoint: &Endpoint) -> f64 {
+    state.model_stats.endpoint_in_flight(&endpoint.url) as f64 / weight(state, endpoint)
+}
+
+// The candidate with the least load; ties are broken by weighted round-robin so idle
+// endpoints still share the traffic
+fn pick_least_loaded(
+    state: &AppState,
+    task: &str,
+    model: &str,
+    candidates: Vec<Endpoint>,
+) -> Option<Route> {
+    let loads: Vec<f64> = candidates.iter().map(|ep| load(state, ep)).collect();
+    let min_load = loads.iter().copied().fold(f64::MAX, f64::min);
+    let least_loaded = candidates
+        .into_iter()
+        .zip(loads)
+        .filter(|(_, load)| *load <= min_load)
+        .map(|(ep, _)| ep)
+        .collect();
+    round_robin(state, task, model, least_loaded)
+}
+
+// Which endpoints count as nearby. Endpoints without a zone count as in the composer's own.
+pub struct ZonePreference {
+    // The composer's zone, no preference if unset
+    pub zone: Option<String>,
+    // Requests in flight per unit of weight at which an endpoint counts as saturated
+    pub spill_load: f64,
+}
+
+impl ZonePreference {
+    // VLLM_COMPOSER_ZONE and VLLM_COMPOSER_ZONE_SPILL_LOAD (default 16)
+    pub fn from_env() -> Self {
+        let zone = std::env::var("VLLM_COMPOSER_ZONE").ok().filter(|s| !s.is_empty());
+        let spill_load = std::env::var("VLLM_COMPOSER_ZONE_SPILL_LOAD")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(16.0);
+        if let Some(zone) = &zone {
+            info!("Preferring endpoints in zone {} up to {} requests in flight each", zone, spill_load);
+        }
+        ZonePreference::new(zone, spill_load)
+    }
+
+    pub fn new(zone: Option<String>, spill_load: f64) -> Self {
+        ZonePreference { zone, spill_load }
+    }
+
+    fn is_remote(&self, endpoint: &Endpoint) -> bool {
+        matches!((&self.zone, &endpoint.zone), (Some(own), Some(zone)) if own != zone)
+    }
+}
+
+// The candidates in the composer's zone unless all of them are saturated, then the unsaturated
+// ones of other zones, or all candidates if every one is. Unhealthy endpoints and those with
+// full pools are no candidates to begin with.
+fn prefer_own_zone(state: &AppState, candidates: Vec<Endpoint>) -> Vec<Endpoint> {
+    let zones = &state.zones;
+    let (local, remote): (Vec<_>, Vec<_>) = candidates.into_iter().partition(|ep| !zones.is_remote(ep));
+    let saturated = |ep: &Endpoint| load(state, ep) >= zones.spill_load;
+    if remote.is_empty() || (!local.is_empty() && !local.iter().all(saturated)) {
+        return if local.is_empty() { remote } else { local };
+    }
+    let spill: Vec<Endpoint> = remote.iter().filter(|ep| !saturated(ep)).cloned().collect();
+    let chosen = if spill.is_empty() { local.into_iter().chain(remote).collect() } else { spill };
+    debug!("no unsaturated endpoint in zone {:?}, spilling over", zones.zone);
+    chosen
+}
+
+// Count a request routed out of the composer's zone
+fn note_spill(state: &AppState, task: &str, endpoint: &Endpoint) {
+    if state.zones.is_remote(endpoint) {
+        let zone = endpoint.zone.as_deref().unwrap_or_default();
+        state.metrics.zone_spills.with_label_values(&[task, zone]).inc();
+    }
+}
+
+// Where a request was routed. Nothing is booked for the endpoint until `dispatch`, so a
+// request can be routed again while it waits for room.
+pub struct Route {
+    pub endpoint: Endpoint,
+    // The requested model if a fallback serves the request
+    pub fallback_from: Option<String>,
+    credits: Option<CreditUpdate>,
+}
+
+impl Route {
+    fn to(endpoint: Endpoint) -> Self {
+        Route { endpoint, fallback_from: None, credits: None }
+    }
+
+    // Commit to the endpoint as the request is sent: book the round-robin pick, count a spill
+    // out of the zone and let the request probe an open circuit
+    pub fn dispatch(&self, state: &AppState, task: &str) {
+        if let Some(credits) = &self.credits {
+            state.round_robin.book(credits);
+        }
+        note_spill(state, task, &self.endpoint);
+        state.breakers.on_dispatch(&self.endpoint.url);
+    }
+}
+
+// Pick an endpoint for the body's model, falling back along the model's chain when it has
+// no healthy endpoint the caller may use. A fallback replaces the body's model.
+pub fn route_request(
+    req: &HttpRequest,
+    state: &AppState,
+    body: &mut Value,
+    task: &str,
+) -> Result<Route, RouteError> {
+    let error = match select_endpoint(req, state, body, task) {
+        Ok(route) => return Ok(route),
+        Err(error) if error.allows_fallback() => error,
+        Err(error) => return Err(error),
+    };
+
+    let requested = body.get("model").and_then(Value::as_str).unwrap_or_default().to_string();
+    let chain = state.fallbacks.chain(&requested);
+    for fallback in &chain {
+        body["model"] = Value::String(fallback.clone());
+        if let Ok(route) = select_endpoint(req, state, body, task) {
+            info!("model {} unavailable, falling back to {}", requested, fallback);
+            return Ok(Route { fallback_from: Some(requested), ..route });
+        }
+    }
+    if !chain.is_empty() {
+        body["model"] = Value::String(requested);
+    }
+    Err(error)
+}
+
+// Pick an authorized endpoint of `task` serving the body's model, by session if sticky routing
+// is on and the request names one, by the routing strategy otherwise
+pub fn select_endpoint(
+    req: &HttpRequest,
+    state: &AppState,
+    body: &Value,
+    task: &str,
+) -> Result<Route, RouteError> {
+    // 1. Check auth
+    let auth_info = match req.extensions().get::<AuthInfo>() {
+        Some(info) => info.clone(),
+        None => return Err(RouteError::Unauthorized),
+    };
+    let user_groups = &auth_info.groups;
+
+    // 2. Extract model
+    let model_id = match body.get("model").and_then(Value::as_str) {
+        Some(m) => m,
+        None => return Err(RouteError::UnknownModel(String::new())),
+    };
+
+    // 3. Look in the task's model->endpoints map
+    let task_state = state.task(task);
+    let endpoints_for_model = task_state.model_to_endpoints.lock().unwrap().get(model_id).cloned();
+    let Some(endpoints_for_model) = endpoints_for_model else {
+        return Err(unserved_model(task_state, model_id, user_groups));
+    };
+
+    // 4. Filter endpoints by group
+    let endpoints_list = {
+        let endpoints = task_state.endpoints.lock().unwrap();
+        endpoints_for_model
+            .iter()
+            .filter_map(|url| endpoints.iter().find(|e| &e.url == url))
+            .filter(|ep| ep.groups.iter().any(|g| user_groups.contains(g)))
+            .cloned()
+            .collect::<Vec<Endpoint>>()
+    };
+
+    // 5. If no authorized endpoints remain, 404 unless one of them is down
+    if endpoints_list.is_empty() {
+        return Err(unserved_model(task_state, model_id, user_groups));
+    }
+
+    // 6. Guided decoding needs an endpoint that understands the guided_* fields. Only
+    // generation knows them; other tasks pass them on like any other field.
+    let guided_params = if task == "generate" { requested_guided_params(body) } else { Vec::new() };
+    let endpoints_list = if guided_params.is_empty() {
+        endpoints_list
+    } else {
+        validate_guided_params(body).map_err(RouteError::BadRequest)?;
+        let capabilities = state.endpoint_capabilities.lock().unwrap();
+        let capable = filter_guided_capable(endpoints_list, &capabilities);
+        if capable.is_empty() {
+            return Err(RouteError::BadRequest(format!(
+                "The model `{}` is not served by any endpoint supporting guided decoding ({}).",
+                model_id,
+                guided_params.join(", ")
+            )));
+        }
+        capable
+    };
+
+    // 7. Images need an endpoint hosting a vision-language model
+    let endpoints_list = if request_has_images(body) {
+        let capabilities = state.endpoint_capabilities.lock().unwrap();
+        let capable = filter_vision_capable(endpoints_list, &capabilities);
+        if capable.is_empty() {
+            return Err(RouteError::BadRequest(format!(
+                "The model `{}` is not served by any endpoint accepting image input.",
+                model_id
+            )));
+        }
+        capable
+    } else {
+        endpoints_list
+    };
+
+    // 8. Skip endpoints whose circuit is open or that are draining or in maintenance.
+    // Registered endpoints are routable before their first probe.
+    let endpoints_list: Vec<Endpoint> = {
+        let health_status = task_state.health_status.lock().unwrap();
+        endpoints_list
+            .into_iter()
+            .filter(|ep| health_status.get(&ep.url).is_none_or(|health| health.is_routable()))
+            .filter(|ep| state.breakers.is_routable(&ep.url))
+            .collect()
+    };
+    if endpoints_list.is_empty() {
+        return Err(RouteError::Unavailable(model_id.to_string()));
+    }
+    let endpoints_list = without_failing(state, endpoints_list);
+
+    // 9. Skip endpoints whose pool for the request's class is full
+    let class = RouteClass::of(task, body);
+    let endpoints_list: Vec<Endpoint> = endpoints_list
+        .into_iter()
+        .filter(|ep| state.endpoint_pools.has_room(ep.pools.as_ref(), &ep.url, class))
+        .collect();
+    if endpoints_list.is_empty() {
+        return Err(RouteError::Busy(model_id.to_string(), class));
+    }
+
+    // 10. Stay in the composer's zone while it has capacity
+    let endpoints_list = prefer_own_zone(state, endpoints_list);
+
+    // 11. Keep sessions on one endpoint, spread the rest by the routing strategy
+    let route = match state.affinity.session_key(req, body) {
+        Some(key) => pick_by_session(&key, endpoints_list).map(Route::to),
+        None => state.routing_strategy.pick(state, task, model_id, body, endpoints_list),
+    }
+    .expect("candidates are not empty");
+    Ok(route)
+}
+
+// The candidates neither ejected for their error rate nor failing deep checks, or all of them
+// if every one is: a model is better served by failing endpoints than not at all
+fn without_failing(state: &AppState, candidates: Vec<Endpoint>) -> Vec<Endpoint> {
+    let failing = |ep: &Endpoint| state.outliers.is_ejected(&ep.url) || state.fails_deep_checks(&ep.url);
+    if candidates.iter().all(failing) {
+        return candidates;
+    }
+    candidates.into_iter().filter(|ep| !failing(ep)).collect()
+}
+
+// Pick a routable endpoint of the guard model screening a request. Screening is not up to
+// the caller, so the groups of the endpoints don't matter.
+pub fn select_guard_endpoint(state: &AppState, model: &str) -> Option<Endpoint> {
+    let task_state = state.task("moderate");
+    let urls = task_state.model_to_endpoints.lock().unwrap().get(model).cloned()?;
+    let candidates: Vec<Endpoint> = {
+        let endpoints = task_state.endpoints.lock().unwrap();
+        let health_status = task_state.health_status.lock().unwrap();
+        urls.iter()
+            .filter_map(|url| endpoints.iter().find(|e| &e.url == url))
+            .filter(|ep| health_status.get(&ep.url).is_none_or(|health| health.is_routable()))
+            .filter(|ep| state.breakers.is_routable(&ep.url))
+            .cloned()
+            .collect()
+    };
+    let candidates = prefer_own_zone(state, without_failing(state, candidates));
+    let route = round_robin(state, "moderate", model, candidates)?;
+    route.dispatch(state, "moderate");
+    Some(route.endpoint)
+}
+
+// No healthy endpoint the caller may use serves the model. If one served it before going
+// down the model is unavailable, otherwise it does not exist as far as the caller can tell.
+fn unserved_model(task_state: &TaskState, model_id: &str, user_groups: &[String]) -> RouteError {
+    let last_served = task_state.last_served_by(model_id);
+    let endpoints = task_state.endpoints.lock().unwrap();
+    let was_served = endpoints.iter().any(|ep| {
+        last_served.contains(&ep.url) && ep.groups.iter().any(|g| user_groups.contains(g))
+    });
+    if was_served {
+        RouteError::Unavailable(model_id.to_string())
+    } else {
+        RouteError::UnknownModel(model_id.to_string())
+    }
+}
@@ -0,0 +1,93 @@
+// External crates
+use serde_json::Value;
+
+// Standard library
+use std::collections::HashMap;
+
+// Internal modules
+use crate::state::{Endpoint, EndpointCapabilities};
+
+// -----------------------------------------------------------------------------
+// Request Sanitizing
+// -----------------------------------------------------------------------------
+
+// Largest logit_bias map forwarded unless an endpoint sets its own limit
+const DEFAULT_MAX_LOGIT_BIAS: usize = 300;
+
+// Top-level fields of the JSON body each POST path of an OpenAPI schema accepts
+pub fn request_fields_from_openapi(schema: &Value) -> HashMap<String, Vec<String>> {
+    let mut fields = HashMap::new();
+    for (path, item) in schema.get("paths").and_then(Value::as_object).into_iter().flatten() {
+        let Some(body_schema) = item.pointer("/post/requestBody/content/application~1json/schema") else {
+            continue;
+        };
+        let mut names = Vec::new();
+        collect_properties(schema, body_schema, &mut names, 0);
+        if !names.is_empty() {
+            names.sort();
+            names.dedup();
+            fields.insert(path.clone(), names);
+        }
+    }
+    fields
+}
+
+// Property names of a schema, following $ref and unions (anyOf/oneOf/allOf)
+fn collect_properties(root: &Value, schema: &Value, names: &mut Vec<String>, depth: usize) {
+    if depth > 8 {
+        return;
+    }
+    if let Some(reference) = schema.get("$ref").and_then(Value::as_str) {
+        if let Some(target) = reference.strip_prefix('#').and_then(|ptr| root.pointer(ptr)) {
+            collect_properties(root, target, names, depth + 1);
+        }
+        return;
+    }
+    for key in ["anyOf", "oneOf", "allOf"] {
+        for sub in schema.get(key).and_then(Value::as_array).into_iter().flatten() {
+            collect_properties(root, sub, names, depth + 1);
+        }
+    }
+    if let Some(properties) = schema.get("properties").and_then(Value::as_object) {
+        names.extend(properties.keys().cloned());
+    }
+}
+
+// Drop what the endpoint would reject: fields missing from its request schema and
+// oversized logit_bias maps. Returns a description of each change.
+pub fn sanitize_request(
+    body: &mut Value,
+    endpoint: &Endpoint,
+    capabilities: &HashMap<String, EndpointCapabilities>,
+    path: &str,
+) -> Vec<String> {
+    let mut changes = Vec::new();
+    let Some(obj) = body.as_object_mut() else {
+        return changes;
+    };
+
+    let max_logit_bias = endpoint.max_logit_bias.unwrap_or(DEFAULT_MAX_LOGIT_BIAS);
+    if let Some(size) = obj.get("logit_bias").and_then(Value::as_object).map(|bias| bias.len())
+        && size > max_logit_bias
+    {
+        obj.remove("logit_bias");
+        changes.push(format!("removed logit_bias with {} entries (limit {})", size, max_logit_bias));
+    }
+
+    let known_fields = capabilities
+        .get(&endpoint.url)
+        .and_then(|caps| caps.request_fields.as_ref())
+        .and_then(|fields| fields.get(path));
+    if let Some(known_fields) = known_fields {
+        let unknown: Vec<String> = obj
+            .keys()
+            .filter(|key| !known_fields.contains(key))
+            .cloned()
+            .collect();
+        for key in unknown {
+            obj.remove(&key);
+            changes.push(format!("removed unsupported field {}", key));
+        }
+    }
+    changes
+}
@@ -0,0 +1,365 @@
+// External crates
+use log::{info, warn};
+use redis::aio::ConnectionManager;
+use redis::AsyncCommands;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::time::timeout;
+
+// Standard library
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// Internal modules
+use crate::health::HealthState;
+use crate::state::EndpointCapabilities;
+
+// -----------------------------------------------------------------------------
+// Shared State
+// -----------------------------------------------------------------------------
+
+const KEY_PREFIX: &str = "vllm_composer";
+// Snapshots of endpoints nobody monitors anymore expire after this
+const SNAPSHOT_TTL_SECS: u64 = 60;
+const RECONNECT_BACKOFF: Duration = Duration::from_secs(5);
+// Monitor leases expire unless renewed, letting another replica take over
+pub const MONITOR_LEASE_TTL: Duration = Duration::from_secs(15);
+pub const MONITOR_LEASE_RENEW_INTERVAL: Duration = Duration::from_secs(5);
+
+// Take the lease if it is free, renew it if we hold it
+const ACQUIRE_LEASE_SCRIPT: &str = r"
+local holder = redis.call('GET', KEYS[1])
+if not holder then
+    redis.call('SET', KEYS[1], ARGV[1], 'PX', ARGV[2])
+    return 1
+elseif holder == ARGV[1] then
+    redis.call('PEXPIRE', KEYS[1], ARGV[2])
+    return 1
+end
+return 0
+";
+
+// Drop the lease only if we hold it
+const RELEASE_LEASE_SCRIPT: &str = r"
+if redis.call('GET', KEYS[1]) == ARGV[1] then
+    return redis.call('DEL', KEYS[1])
+end
+return 0
+";
+
+// Token bucket refilling `ARGV[1]` requests per minute, timed by the Redis server's clock.
+// Returns 0 if a request was taken, else the milliseconds until one is available.
+const TAKE_FROM_BUCKET_SCRIPT: &str = r"
+local capacity = tonumber(ARGV[1])
+local refill_per_ms = capacity / 60000
+local time = redis.call('TIME')
+local now = tonumber(time[1]) * 1000 + math.floor(tonumber(time[2]) / 1000)
+local bucket = redis.call('HMGET', KEYS[1], 'tokens', 'updated')
+local tokens = tonumber(bucket[1]) or capacity
+local updated = tonumber(bucket[2]) or now
+tokens = math.min(capacity, tokens + math.max(0, now - updated) * refill_per_ms)
+local wait = 0
+if tokens >= 1 then
+    tokens = tokens - 1
+else
+    wait = math.ceil((1 - tokens) / refill_per_ms)
+end
+redis.call('HSET', KEYS[1], 'tokens', tostring(tokens), 'updated', now)
+redis.call('PEXPIRE', KEYS[1], 60000)
+return wait
+";
+
+// What a replica observed about an endpoint, as stored in Redis
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct EndpointSnapshot {
+    // Whether the endpoint keeps its models, for replicas predating `state`
+    pub healthy: bool,
+    #[serde(default)]
+    pub state: Option<HealthState>,
+    pub models: Vec<Value>,
+    #[serde(default)]
+    pub capabilities: Option<EndpointCapabilities>,
+    pub updated_ms: u64,
+}
+
+impl EndpointSnapshot {
+    pub fn health_state(&self) -> HealthState {
+        self.state.unwrap_or(if self.healthy { HealthState::Healthy } else { HealthState::Unhealthy })
+    }
+}
+
+// Optional Redis backend that lets several composer replicas share what they know.
+// Every operation degrades to a no-op while Redis is unreachable; the in-memory maps
+// in AppState stay authoritative for routing.
+pub struct SharedStore {
+    client: Option<redis::Client>,
+    // Identifies this replica as monitor lease holder
+    replica_id: String,
+    conn: tokio::sync::Mutex<Option<ConnectionManager>>,
+    available: AtomicBool,
+    last_connect_attempt: Mutex<Option<Instant>>,
+    // Newest snapshot version applied or published per endpoint key
+    seen_versions: Mutex<HashMap<String, u64>>,
+}
+
+pub fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+impl SharedStore {
+    // In-memory only
+    pub fn disabled() -> Self {
+        SharedStore {
+            client: None,
+            replica_id: format!(
+                "{}-{}-{}",
+                std::env::var("HOSTNAME").unwrap_or_else(|_| "composer".to_string()),
+                std::process::id(),
+                now_ms()
+            ),
+            conn: tokio::sync::Mutex::new(None),
+            available: AtomicBool::new(false),
+            last_connect_attempt: Mutex::new(None),
+            seen_versions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Use the Redis server at `url`; the connection is established lazily
+    pub fn redis(url: &str) -> Result<Self, redis::RedisError> {
+        let client = redis::Client::open(url)?;
+        Ok(SharedStore {
+            client: Some(client),
+            // Assume reachable so the first failure gets reported
+            available: AtomicBool::new(true),
+            ..SharedStore::disabled()
+        })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.client.is_some()
+    }
+
+    // A live connection, or None while Redis is down
+    async fn connection(&self) -> Option<ConnectionManager> {
+        let client = self.client.as_ref()?;
+        let mut conn = self.conn.lock().await;
+        if conn.is_none() {
+            // Don't hammer a Redis that is down
+            {
+                let mut last_attempt = self.last_connect_attempt.lock().unwrap();
+                if last_attempt.is_some_and(|t| t.elapsed() < RECONNECT_BACKOFF) {
+                    return None;
+                }
+                *last_attempt = Some(Instant::now());
+            }
+            match timeout(Duration::from_secs(2), ConnectionManager::new(client.clone())).await {
+                Ok(Ok(manager)) => *conn = Some(manager),
+                Ok(Err(e)) => {
+                    self.mark_unavailable(&e.to_string());
+                    return None;
+                }
+                Err(_) => {
+                    self.mark_unavailable("connection timed out");
+                    return None;
+                }
+            }
+        }
+        conn.clone()
+    }
+
+    fn mark_available(&self) {
+        if !self.available.swap(true, Ordering::Relaxed) {
+            info!("Shared state backend connected.");
+        }
+    }
+
+    fn mark_unavailable(&self, reason: &str) {
+        if self.available.swap(false, Ordering::Relaxed) {
+            warn!("Shared state backend unavailable, using in-memory state: {}", reason);
+        }
+    }
+
+    fn endpoint_key(task: &str, url: &str) -> String {
+        format!("{}:endpoint:{}:{}", KEY_PREFIX, task, url)
+    }
+
+    fn lease_key(task: &str, url: &str) -> String {
+        format!("{}:monitor:{}:{}", KEY_PREFIX, task, url)
+    }
+
+    fn limit_key(name: &str) -> String {
+        format!("{}:{}", KEY_PREFIX, name)
+    }
+
+    // Take a request from a token bucket all replicas share, None while the backend is
+    // unreachable (or not configured) so the caller falls back to its own bucket
+    pub async fn take_from_bucket(&self, name: &str, per_minute: u32) -> Option<Result<(), Duration>> {
+        let mut conn = self.connection().await?;
+        let result: redis::RedisResult<u64> = redis::Script::new(TAKE_FROM_BUCKET_SCRIPT)
+            .key(Self::limit_key(name))
+            .arg(per_minute.max(1))
+            .invoke_async(&mut conn)
+            .await;
+        match result {
+            Ok(wait_ms) => {
+                self.mark_available();
+                Some(if wait_ms == 0 { Ok(()) } else { Err(Duration::from_millis(wait_ms)) })
+            }
+            Err(e) => {
+                self.mark_unavailable(&e.to_string());
+                None
+            }
+        }
+    }
+
+    // Add to counters all replicas share, each expiring its TTL in seconds after it was
+    // last added to
+    pub async fn add_to_counters(&self, counters: &[(String, u64)], amount: u64) {
+        let Some(mut conn) = self.connection().await else {
+            return;
+        };
+        let mut pipe = redis::pipe();
+        for (name, ttl_secs) in counters {
+            let key = Self::limit_key(name);
+            pipe.incr(&key, amount).ignore().expire(&key, *ttl_secs as i64).ignore();
+        }
+        let result: redis::RedisResult<()> = pipe.query_async(&mut conn).await;
+        match result {
+            Ok(()) => self.mark_available(),
+            Err(e) => self.mark_unavailable(&e.to_string()),
+        }
+    }
+
+    // Values of shared counters, missing ones as 0; None while the backend is unreachable
+    pub async fn counters(&self, names: &[String]) -> Option<Vec<u64>> {
+        let mut conn = self.connection().await?;
+        let keys: Vec<String> = names.iter().map(|name| Self::limit_key(name)).collect();
+        let result: redis::RedisResult<Vec<Option<u64>>> =
+            redis::cmd("MGET").arg(&keys).query_async(&mut conn).await;
+        match result {
+            Ok(values) => {
+                self.mark_available();
+                Some(values.into_iter().map(Option::unwrap_or_default).collect())
+            }
+            Err(e) => {
+                self.mark_unavailable(&e.to_string());
+                None
+            }
+        }
+    }
+
+    // Whether this replica should monitor the endpoint, acquiring or renewing its lease.
+    // Without a reachable backend every replica monitors everything itself.
+    pub async fn hold_monitor_lease(&self, task: &str, url: &str) -> bool {
+        let Some(mut conn) = self.connection().await else {
+            return true;
+        };
+        let result: redis::RedisResult<i64> = redis::Script::new(ACQUIRE_LEASE_SCRIPT)
+            .key(Self::lease_key(task, url))
+            .arg(&self.replica_id)
+            .arg(MONITOR_LEASE_TTL.as_millis() as u64)
+            .invoke_async(&mut conn)
+            .await;
+        match result {
+            Ok(held) => {
+                self.mark_available();
+                held == 1
+            }
+            Err(e) => {
+                self.mark_unavailable(&e.to_string());
+                true
+            }
+        }
+    }
+
+    // Hand the endpoint over to other replicas right away
+    pub async fn release_monitor_lease(&self, task: &str, url: &str) {
+        let Some(mut conn) = self.connection().await else {
+            return;
+        };
+        let result: redis::RedisResult<i64> = redis::Script::new(RELEASE_LEASE_SCRIPT)
+            .key(Self::lease_key(task, url))
+            .arg(&self.replica_id)
+            .invoke_async(&mut conn)
+            .await;
+        if let Err(e) = result {
+            self.mark_unavailable(&e.to_string());
+        }
+    }
+
+    // Publish this replica's latest observation of an endpoint
+    pub async fn publish_endpoint(
+        &self,
+        task: &str,
+        url: &str,
+        state: HealthState,
+        models: Vec<Value>,
+        capabilities: Option<EndpointCapabilities>,
+    ) {
+        let Some(mut conn) = self.connection().await else {
+            return;
+        };
+        let key = Self::endpoint_key(task, url);
+        let snapshot = EndpointSnapshot {
+            healthy: state.keeps_models(),
+            state: Some(state),
+            models,
+            capabilities,
+            updated_ms: now_ms(),
+        };
+        let payload = serde_json::to_string(&snapshot).unwrap();
+        let result: redis::RedisResult<()> = conn.set_ex(&key, payload, SNAPSHOT_TTL_SECS).await;
+        match result {
+            Ok(()) => {
+                self.mark_available();
+                self.seen_versions.lock().unwrap().insert(key, snapshot.updated_ms);
+            }
+            Err(e) => self.mark_unavailable(&e.to_string()),
+        }
+    }
+
+    // Snapshots of the given endpoints that are newer than anything seen so far
+    pub async fn fetch_new_snapshots(
+        &self,
+        task: &str,
+        urls: &[String],
+    ) -> HashMap<String, EndpointSnapshot> {
+        let mut fresh = HashMap::new();
+        if urls.is_empty() {
+            return fresh;
+        }
+        let Some(mut conn) = self.connection().await else {
+            return fresh;
+        };
+        let keys: Vec<String> = urls.iter().map(|url| Self::endpoint_key(task, url)).collect();
+        // Explicit MGET, the `mget` helper sends GET for a single key
+        let payloads: Vec<Option<String>> = match redis::cmd("MGET").arg(&keys).query_async(&mut conn).await {
+            Ok(payloads) => {
+                self.mark_available();
+                payloads
+            }
+            Err(e) => {
+                self.mark_unavailable(&e.to_string());
+                return fresh;
+            }
+        };
+
+        let mut seen = self.seen_versions.lock().unwrap();
+        for ((url, key), payload) in urls.iter().zip(keys).zip(payloads) {
+            let Some(snapshot) = payload.and_then(|p| serde_json::from_str::<EndpointSnapshot>(&p).ok()) else {
+                continue;
+            };
+            if seen.get(&key).is_some_and(|v| *v >= snapshot.updated_ms) {
+                continue;
+            }
+            seen.insert(key, snapshot.updated_ms);
+            fresh.insert(url.clone(), snapshot);
+        }
+        fresh
+    }
+}
@@ -0,0 +1,112 @@
+// External crates
+use actix_web::dev::ServerHandle;
+use log::{info, warn};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::time::sleep;
+
+// Standard library
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+
+// Internal modules
+use crate::state::AppState;
+
+// -----------------------------------------------------------------------------
+// Graceful Shutdown
+// -----------------------------------------------------------------------------
+
+// On SIGTERM, SIGINT or POST /drain the composer turns new requests away, waits for the
+// requests in flight (streams until their last chunk) and then stops the server.
+pub struct Shutdown {
+    draining: AtomicBool,
+    // Longest wait for requests in flight, those still running then are cut off
+    pub deadline: Duration,
+    server: OnceLock<ServerHandle>,
+}
+
+impl Shutdown {
+    // Deadline from VLLM_COMPOSER_SHUTDOWN_DEADLINE_SECS (default 300)
+    pub fn from_env() -> Self {
+        let deadline_secs = std::env::var("VLLM_COMPOSER_SHUTDOWN_DEADLINE_SECS")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(300u64);
+        Shutdown {
+            draining: AtomicBool::new(false),
+            deadline: Duration::from_secs(deadline_secs),
+            server: OnceLock::new(),
+        }
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::Relaxed)
+    }
+
+    // The running server, stopped once drained
+    pub fn set_server(&self, server: ServerHandle) {
+        let _ = self.server.set(server);
+    }
+}
+
+// Start draining unless the server already is; false if it was. New requests are turned
+// away from now on, the server stops once those in flight are done.
+pub fn start_drain(state: &Arc<AppState>, reason: &'static str) -> bool {
+    if state.shutdown.draining.swap(true, Ordering::Relaxed) {
+        return false;
+    }
+    tokio::spawn(drain(Arc::clone(state), reason));
+    true
+}
+
+async fn drain(state: Arc<AppState>, reason: &'static str) {
+    let deadline = state.shutdown.deadline;
+    info!(
+        "Draining on {}: turning new requests away, waiting up to {}s for {} in flight",
+        reason,
+        deadline.as_secs(),
+        state.model_stats.total_in_flight()
+    );
+    let started = Instant::now();
+    let drained = loop {
+        let in_flight = state.model_stats.total_in_flight();
+        if in_flight == 0 {
+            info!("Drained, no requests in flight");
+            break true;
+        }
+        if started.elapsed() >= deadline {
+            warn!("Shutdown deadline reached, cutting off {} requests in flight", in_flight);
+            break false;
+        }
+        sleep(Duration::from_millis(200)).await;
+    };
+    if let Some(server) = state.shutdown.server.get() {
+        server.stop(drained).await;
+    }
+}
+
+// Drain on SIGTERM and SIGINT, in place of actix's own handling that cuts streams off. A
+// second signal stops the server at once.
+pub async fn on_signals(state: Arc<AppState>) {
+    let mut terminate = match signal(SignalKind::terminate()) {
+        Ok(terminate) => terminate,
+        Err(e) => {
+            warn!("Failed to listen for SIGTERM: {}", e);
+            return;
+        }
+    };
+    loop {
+        let reason = tokio::select! {
+            _ = terminate.recv() => "SIGTERM",
+            _ = tokio::signal::ctrl_c() => "SIGINT",
+        };
+        if start_drain(&state, reason) {
+            continue;
+        }
+        warn!("{} while draining, stopping now", reason);
+        if let Some(server) = state.shutdown.server.get() {
+            server.stop(false).await;
+        }
+        return;
+    }
+}
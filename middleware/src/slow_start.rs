@@ -0,0 +1,97 @@
+// External crates
+use log::info;
+use serde_json::{json, Value};
+
+// Standard library
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// -----------------------------------------------------------------------------
+// Slow Start
+// -----------------------------------------------------------------------------
+
+// Share of its weight an endpoint gets right after it returned
+const MIN_SHARE: f64 = 0.1;
+
+// Ramps the routing weight of endpoints that just became healthy again, so their cold caches
+// aren't hit by a full share of the traffic at once
+pub struct SlowStart {
+    // How long the ramp to the full weight takes, None disables it
+    window: Option<Duration>,
+    // Endpoint URL -> when it became healthy
+    ramping: Mutex<HashMap<String, Instant>>,
+}
+
+impl SlowStart {
+    // VLLM_COMPOSER_SLOW_START_SECS (default 30, 0 disables)
+    pub fn from_env() -> Self {
+        let secs = std::env::var("VLLM_COMPOSER_SLOW_START_SECS")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(30);
+        let slow_start = SlowStart::new((secs > 0).then(|| Duration::from_secs(secs)));
+        if let Some(window) = slow_start.window {
+            info!("Ramping recovered endpoints up to their full weight over {:?}", window);
+        }
+        slow_start
+    }
+
+    pub fn new(window: Option<Duration>) -> Self {
+        SlowStart { window, ramping: Mutex::new(HashMap::new()) }
+    }
+
+    // The endpoint became healthy again, start its ramp
+    pub fn begin(&self, url: &str) {
+        if self.window.is_some() {
+            self.ramping.lock().unwrap().insert(url.to_string(), Instant::now());
+        }
+    }
+
+    // Share of its weight the endpoint gets, from MIN_SHARE when it returned to 1 at the end of
+    // the window
+    pub fn share(&self, url: &str) -> f64 {
+        let Some(window) = self.window else {
+            return 1.0;
+        };
+        let mut ramping = self.ramping.lock().unwrap();
+        let Some(since) = ramping.get(url) else {
+            return 1.0;
+        };
+        let ramped = since.elapsed().as_secs_f64() / window.as_secs_f64();
+        if ramped >= 1.0 {
+            ramping.remove(url);
+            return 1.0;
+        }
+        MIN_SHARE + (1.0 - MIN_SHARE) * ramped
+    }
+
+    // The endpoint's ramp for /health-status, null once it is at full weight
+    pub fn status(&self, url: &str) -> Value {
+        let share = self.share(url);
+        let (Some(window), Some(since)) = (self.window, self.ramping.lock().unwrap().get(url).copied())
+        else {
+            return Value::Null;
+        };
+        json!({
+            "share": share,
+            "remaining_secs": window.saturating_sub(since.elapsed()).as_secs() + 1,
+        })
+    }
+
+    pub fn forget(&self, url: &str) {
+        self.ramping.lock().unwrap().remove(url);
+    }
+
+    // Drop the ramps of endpoints that are gone, returns how many
+    pub fn retain(&self, active: &HashSet<String>) -> usize {
+        let mut ramping = self.ramping.lock().unwrap();
+        let before = ramping.len();
+        ramping.retain(|url, _| active.contains(url));
+        before - ramping.len()
+    }
+
+    pub fn entries(&self) -> usize {
+        self.ramping.lock().unwrap().len()
+    }
+}
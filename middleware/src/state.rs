@@ -1,121 +1,1294 @@
 // External crates
+use arc_swap::ArcSwap;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use sha2::{Digest, Sha256};
 use log::info;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION};
 
 // Standard library
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs;
 use std::io;
-use std::sync::Mutex;
-use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+use std::path::{Path, PathBuf};
+
+// Internal modules
+use crate::affinity::SessionAffinity;
+use crate::auth::{token_matches, validate_token_entry, HASHED_TOKEN_PREFIX};
+use crate::body::BodyPolicy;
+use crate::breaker::CircuitBreakers;
+use crate::budgets::{BudgetTracker, GroupBudget};
+use crate::cache::ResponseCache;
+use crate::canaries::Canaries;
+use crate::clients::HttpClients;
+use crate::conversations::ConversationBudgets;
+use crate::embeddings::EmbeddingSplit;
+use crate::forwarding::Forwarding;
+use crate::logging::{redact_secret, redact_tokens, DebugTraces};
+use crate::loops::LoopDetector;
+use crate::ratelimit::RateLimiter;
+use crate::concurrency::{ConcurrencyLimiter, EndpointPools, PoolLimiter};
+use crate::health::{EndpointHealth, HealthState, HealthThresholds};
+use crate::policies::Policy;
+use crate::presets::Preset;
+use crate::pricing::Price;
+use crate::admission::AdmissionQueue;
+use crate::monitoring::MonitorIntervals;
+use crate::outliers::OutlierDetector;
+use crate::routing::{Fallbacks, RoutingStrategy, WeightedRoundRobin, ZonePreference};
+use crate::discovery::docker::DockerConfig;
+use crate::discovery::kubernetes::KubernetesConfig;
+use crate::discovery::registration::Registrations;
+use crate::slow_start::SlowStart;
+use crate::stats::ModelStats;
+use crate::telemetry::Telemetry;
+use crate::truncation::TruncationDetector;
+use crate::token_admin::TokenAdmin;
+use crate::token_expiry::parse_expiry;
+use crate::usage::UsageLedger;
+use crate::usage_db::UsageDb;
+use crate::metrics::Metrics;
+use crate::moderation::GuardConfig;
+use crate::shared::{now_ms, SharedStore};
+use crate::shutdown::Shutdown;
 
 // -----------------------------------------------------------------------------
 // Structures
 // -----------------------------------------------------------------------------
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+// Valid values of `Endpoint::task`
+pub const TASKS: [&str; 7] = [
+    "generate", "embed", "pooling", "score", "transcribe", "image", "moderate",
+];
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq)]
 pub struct Endpoint {
     pub url: String,
     pub access_token: String,
     pub groups: Vec<String>,
-    // "generate" or "embed"
+    // "generate", "embed", "pooling", "score", "transcribe", "image" or "moderate"
+    #[serde(default = "default_task")]
     pub task: String,
+    // Overrides the probed guided decoding support when set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub guided_decoding: Option<bool>,
+    // Largest document list sent upstream in one score request; larger lists are split
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_batch_size: Option<usize>,
+    // Resolve the URL's host via DNS, one endpoint per record: "all" (A/AAAA) or "srv"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resolve: Option<String>,
+    // Largest logit_bias map forwarded, larger ones are removed (default 300)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_logit_bias: Option<usize>,
+    // Overrides the probed vision (image input) support when set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vision: Option<bool>,
+    // Whether the endpoint's vLLM calls tools (--enable-auto-tool-choice), only listed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tools: Option<bool>,
+    // Overrides of the global upstream timeouts
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeouts: Option<EndpointTimeouts>,
+    // Alias -> served model; aliases are listed and accepted like the model itself
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aliases: Option<HashMap<String, String>>,
+    // Served model -> models that serve its requests, in order, when it has no healthy endpoint
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fallbacks: Option<BTreeMap<String, Vec<String>>>,
+    // Share of the traffic relative to the model's other endpoints, e.g. its GPU count (default 1)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub weight: Option<u32>,
+    // HTTP(S) or SOCKS5 proxy all requests to the endpoint go through, probes included
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<EndpointProxy>,
+    // Requests of each route class taken at once, so batch work can't crowd out streams
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pools: Option<EndpointPools>,
+    // CA bundle and client certificate for https:// endpoints, probes included
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls: Option<EndpointTls>,
+    // How the access token is sent (default bearer)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth_style: Option<AuthStyle>,
+    // Sent with every request to the endpoint, probes included, e.g. `X-Scope-OrgID`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub headers: Option<BTreeMap<String, String>>,
+    // Zone or datacenter of the endpoint, see routing::ZonePreference
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub zone: Option<String>,
+    // What serves the endpoint (default vllm)
+    #[serde(default, rename = "type", skip_serializing_if = "Option::is_none")]
+    pub kind: Option<EndpointKind>,
+}
+
+// What serves an endpoint
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum EndpointKind {
+    #[default]
+    Vllm,
+    // Another composer, e.g. one per cluster under a top-level instance. Its models of the
+    // endpoint's task are served with the access token, a service token of the remote composer.
+    Composer,
+}
+
+// How an endpoint expects its access token
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum AuthStyle {
+    // `Authorization: Bearer <token>`, like vLLM's --api-key
+    #[default]
+    Bearer,
+    // `api-key: <token>`, like Azure OpenAI
+    ApiKey,
+    // No token, e.g. when `headers` carry the credentials
+    None,
+}
+
+impl Endpoint {
+    pub fn is_composer(&self) -> bool {
+        self.kind == Some(EndpointKind::Composer)
+    }
+
+    // Headers authenticating a request to the endpoint, followed by its own
+    pub fn request_headers(&self) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        let token = match self.auth_style.unwrap_or_default() {
+            AuthStyle::Bearer => Some((AUTHORIZATION, format!("Bearer {}", self.access_token))),
+            AuthStyle::ApiKey => Some((HeaderName::from_static("api-key"), self.access_token.clone())),
+            AuthStyle::None => None,
+        };
+        if let Some((name, value)) = token
+            && let Ok(mut value) = HeaderValue::from_str(&value)
+        {
+            value.set_sensitive(true);
+            headers.insert(name, value);
+        }
+        // Checked when the endpoint is read
+        for (name, value) in self.headers.iter().flatten() {
+            if let (Ok(name), Ok(value)) = (HeaderName::from_bytes(name.as_bytes()), HeaderValue::from_str(value)) {
+                headers.insert(name, value);
+            }
+        }
+        headers
+    }
+}
+
+// Per-endpoint timeouts in seconds; unset fields use the global value
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq)]
+pub struct EndpointTimeouts {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connect_secs: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_secs: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stream_chunk_secs: Option<u64>,
+    // 0 lifts a global limit
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stream_total_secs: Option<u64>,
+}
+
+// Proxy in front of an endpoint, e.g. a bastion host. Validated when the endpoint is read.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, Hash)]
+#[serde(try_from = "ProxyConfig")]
+pub struct EndpointProxy {
+    // http://, https://, socks5:// or socks5h:// (host names resolved by the proxy)
+    pub url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ProxyConfig {
+    url: String,
+    #[serde(default)]
+    username: Option<String>,
+    #[serde(default)]
+    password: Option<String>,
+}
+
+impl TryFrom<ProxyConfig> for EndpointProxy {
+    type Error = String;
+
+    fn try_from(config: ProxyConfig) -> Result<Self, String> {
+        let parsed = reqwest::Url::parse(config.url.trim())
+            .map_err(|e| format!("Invalid proxy url {}: {}", config.url, e))?;
+        if !matches!(parsed.scheme(), "http" | "https" | "socks5" | "socks5h") || parsed.host_str().is_none() {
+            return Err(format!(
+                "Invalid proxy url {}: expected http, https, socks5 or socks5h://host:port",
+                config.url
+            ));
+        }
+        // Credentials go into their own fields, so /endpoints can leave them out
+        if !parsed.username().is_empty() {
+            return Err(format!("Invalid proxy url {}: set username and password separately", config.url));
+        }
+        if config.password.is_some() && config.username.is_none() {
+            return Err("Proxy password without username".to_string());
+        }
+        Ok(EndpointProxy {
+            url: parsed.as_str().trim_end_matches('/').to_string(),
+            username: config.username,
+            password: config.password,
+        })
+    }
+}
+
+// TLS settings of an endpoint, e.g. behind an internal CA or requiring client certificates.
+// The files are read and checked when the endpoint is read; a changed file applies on the
+// next reload.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, Hash)]
+#[serde(try_from = "TlsConfig")]
+pub struct EndpointTls {
+    // PEM file of CA certificates trusted in addition to the system roots
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ca_bundle: Option<String>,
+    // PEM files of the client certificate and its PKCS#8 key
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_cert: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_key: Option<String>,
+    // Accept any server certificate, for testing only
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub insecure_skip_verify: bool,
+    // Contents of the files
+    #[serde(skip)]
+    pub ca_pem: Option<Vec<u8>>,
+    #[serde(skip)]
+    pub identity_pem: Option<(Vec<u8>, Vec<u8>)>,
+}
+
+#[derive(Deserialize)]
+struct TlsConfig {
+    #[serde(default)]
+    ca_bundle: Option<String>,
+    #[serde(default)]
+    client_cert: Option<String>,
+    #[serde(default)]
+    client_key: Option<String>,
+    #[serde(default)]
+    insecure_skip_verify: bool,
+}
+
+impl TryFrom<TlsConfig> for EndpointTls {
+    type Error = String;
+
+    fn try_from(config: TlsConfig) -> Result<Self, String> {
+        let read = |path: &str| fs::read(path).map_err(|e| format!("Failed to read {}: {}", path, e));
+        let ca_pem = match &config.ca_bundle {
+            Some(path) => {
+                let pem = read(path)?;
+                let certs = reqwest::Certificate::from_pem_bundle(&pem)
+                    .map_err(|e| format!("Invalid CA bundle {}: {}", path, e))?;
+                if certs.is_empty() {
+                    return Err(format!("No certificate in CA bundle {}", path));
+                }
+                Some(pem)
+            }
+            None => None,
+        };
+        let identity_pem = match (&config.client_cert, &config.client_key) {
+            (Some(cert_path), Some(key_path)) => {
+                let (cert, key) = (read(cert_path)?, read(key_path)?);
+                reqwest::Identity::from_pkcs8_pem(&cert, &key).map_err(|e| {
+                    format!("Invalid client certificate {} or PKCS#8 key {}: {}", cert_path, key_path, e)
+                })?;
+                Some((cert, key))
+            }
+            (None, None) => None,
+            _ => return Err("client_cert and client_key must be set together".to_string()),
+        };
+        Ok(EndpointTls {
+            ca_bundle: config.ca_bundle,
+            client_cert: config.client_cert,
+            client_key: config.client_key,
+            insecure_skip_verify: config.insecure_skip_verify,
+            ca_pem,
+            identity_pem,
+        })
+    }
+}
+
+fn default_task() -> String {
+    "generate".to_string()
+}
+
+// Features detected by probing an endpoint. `None` means the probe gave no answer.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct EndpointCapabilities {
+    pub guided_decoding: Option<bool>,
+    // Guessed from the served models
+    #[serde(default)]
+    pub vision: Option<bool>,
+    // Upstream path -> body fields its OpenAPI schema accepts
+    #[serde(default)]
+    pub request_fields: Option<HashMap<String, Vec<String>>>,
+}
+
+// Load an endpoint reports on its Prometheus /metrics
+#[derive(Debug, Serialize, Clone, Copy, Default)]
+pub struct BackendMetrics {
+    // vllm:num_requests_running
+    pub requests_running: f64,
+    // vllm:num_requests_waiting
+    pub requests_waiting: f64,
+    // vllm:kv_cache_usage_perc (vllm:gpu_cache_usage_perc before v1), 0 to 1
+    pub kv_cache_usage: f64,
+    // Unix time in ms of the scrape
+    pub scraped_ms: u64,
+}
+
+// Outcome of the last synthetic inference request to an endpoint
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct DeepCheck {
+    pub passed: bool,
+    // Failed checks in a row
+    pub failures: u32,
+    pub model: String,
+    pub latency_ms: u64,
+    // Unix time in ms of the check
+    pub checked_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+// A token of a group in secrets.yaml
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TokenEntry {
+    // The token itself or its salted hash
+    pub token: String,
+    // As written in secrets.yaml, a date or a UTC time
+    pub expires: Option<String>,
+    // Unix time in seconds the token stops working
+    pub expires_at: Option<u64>,
+    pub description: Option<String>,
+    pub owner: Option<String>,
+}
+
+impl TokenEntry {
+    pub fn is_expired(&self, now_secs: u64) -> bool {
+        self.expires_at.is_some_and(|expires_at| now_secs >= expires_at)
+    }
+}
+
+// A token entry as written in secrets.yaml: the token alone or with metadata
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum TokenSpec {
+    Plain(String),
+    Detailed {
+        token: String,
+        #[serde(default)]
+        expires: Option<String>,
+        #[serde(default)]
+        description: Option<String>,
+        #[serde(default)]
+        owner: Option<String>,
+    },
+}
+
+impl TryFrom<TokenSpec> for TokenEntry {
+    type Error = String;
+
+    fn try_from(spec: TokenSpec) -> Result<Self, String> {
+        match spec {
+            TokenSpec::Plain(token) => Ok(TokenEntry {
+                token,
+                ..Default::default()
+            }),
+            TokenSpec::Detailed { token, expires, description, owner } => Ok(TokenEntry {
+                token,
+                expires_at: expires.as_deref().map(parse_expiry).transpose()?,
+                expires,
+                description,
+                owner,
+            }),
+        }
+    }
+}
+
+// What secrets.yaml configures
+#[derive(Debug, Clone, Default)]
+pub struct AuthConfig {
+    // Group -> tokens
+    pub groups: HashMap<String, Vec<TokenEntry>>,
+    // Group -> requests per minute allowed per token
+    pub rate_limits: HashMap<String, u32>,
+    // Group -> requests the group may have in flight at once
+    pub concurrency_limits: HashMap<String, u32>,
+    // Group -> priority of its queued requests, higher first (default 0)
+    pub priorities: HashMap<String, u32>,
+    // Group -> tokens it and each of its tokens may use per day and month
+    pub budgets: HashMap<String, GroupBudget>,
+    // Bounds of request parameters per group and model
+    pub policies: Vec<Policy>,
+    // Sampling parameter bundles clients select by name
+    pub presets: BTreeMap<String, Preset>,
+    // Model pattern -> price per 1000 prompt and completion tokens
+    pub pricing: BTreeMap<String, Price>,
 }
 
-#[derive(Debug, Serialize)]
-pub struct EndpointHealth {
-    pub current_status: bool,
-    pub consecutive_checks: u32,
-    pub check_interval: u64,
+// One loaded generation of auth tokens, swapped as a whole on reload so lookups never block
+#[derive(Debug, Default)]
+pub struct AuthTokens {
+    // Group -> tokens
+    pub groups: HashMap<String, Vec<TokenEntry>>,
+    // Group -> requests per minute allowed per token
+    pub rate_limits: HashMap<String, u32>,
+    // Group -> requests the group may have in flight at once
+    pub concurrency_limits: HashMap<String, u32>,
+    // Group -> priority of its queued requests, higher first (default 0)
+    pub priorities: HashMap<String, u32>,
+    // Group -> tokens it and each of its tokens may use per day and month
+    pub budgets: HashMap<String, GroupBudget>,
+    // Bounds of request parameters per group and model
+    pub policies: Vec<Policy>,
+    // Sampling parameter bundles clients select by name
+    pub presets: BTreeMap<String, Preset>,
+    // Model pattern -> price per 1000 prompt and completion tokens
+    pub pricing: BTreeMap<String, Price>,
+    // Counts the loads of this replica, starting at 1
+    pub version: u64,
+    // Hash of the secrets; equal on replicas that loaded the same secrets
+    pub fingerprint: String,
+    // Unix time in ms of the load
+    pub loaded_ms: u64,
+}
+
+impl AuthTokens {
+    pub fn new(config: AuthConfig, version: u64) -> Self {
+        let fingerprint = fingerprint(&config);
+        // Hashed entries are no secret, plaintext ones are kept out of logs
+        redact_tokens(
+            config
+                .groups
+                .values()
+                .flatten()
+                .map(|entry| entry.token.as_str())
+                .filter(|token| !token.starts_with(HASHED_TOKEN_PREFIX)),
+        );
+        AuthTokens {
+            groups: config.groups,
+            rate_limits: config.rate_limits,
+            concurrency_limits: config.concurrency_limits,
+            priorities: config.priorities,
+            budgets: config.budgets,
+            policies: config.policies,
+            presets: config.presets,
+            pricing: config.pricing,
+            version,
+            fingerprint,
+            loaded_ms: now_ms(),
+        }
+    }
+
+    // The most generous rate limit among the groups, None if none of them is limited
+    pub fn rate_limit_of(&self, groups: &[String]) -> Option<u32> {
+        groups.iter().filter_map(|g| self.rate_limits.get(g)).max().copied()
+    }
+
+    // The group with the most generous concurrency limit and its limit, None if none of them
+    // is limited
+    pub fn concurrency_limit_of(&self, groups: &[String]) -> Option<(String, u32)> {
+        groups
+            .iter()
+            .filter_map(|g| self.concurrency_limits.get(g).map(|limit| (g.clone(), *limit)))
+            .max_by_key(|(_, limit)| *limit)
+    }
+
+    // The highest priority among the groups, 0 if none of them has one
+    pub fn priority_of(&self, groups: &[String]) -> u32 {
+        groups.iter().filter_map(|g| self.priorities.get(g)).max().copied().unwrap_or(0)
+    }
+
+    // Groups a token belongs to, leaving out the groups its entry expired in
+    pub fn groups_of(&self, token: &str) -> Vec<String> {
+        let now = now_ms() / 1000;
+        self.groups
+            .iter()
+            .filter(|(_, tokens)| {
+                tokens
+                    .iter()
+                    .any(|entry| !entry.is_expired(now) && token_matches(&entry.token, token))
+            })
+            .map(|(group, _)| group.clone())
+            .collect()
+    }
+
+    // Owner of the token's first unexpired entry that names one
+    pub fn owner_of(&self, token: &str) -> Option<String> {
+        let now = now_ms() / 1000;
+        self.groups
+            .values()
+            .flatten()
+            .filter(|entry| !entry.is_expired(now) && token_matches(&entry.token, token))
+            .find_map(|entry| entry.owner.clone())
+    }
+
+    // Whether the token has an entry, all of which expired
+    pub fn is_expired(&self, token: &str) -> bool {
+        let now = now_ms() / 1000;
+        let mut entries = self
+            .groups
+            .values()
+            .flatten()
+            .filter(|entry| token_matches(&entry.token, token))
+            .peekable();
+        entries.peek().is_some() && entries.all(|entry| entry.is_expired(now))
+    }
+}
+
+// Order-independent hash of the secrets
+fn fingerprint(config: &AuthConfig) -> String {
+    let mut groups: Vec<(&String, Vec<&TokenEntry>)> = config
+        .groups
+        .iter()
+        .map(|(group, tokens)| {
+            let mut tokens: Vec<&TokenEntry> = tokens.iter().collect();
+            tokens.sort_by(|a, b| a.token.cmp(&b.token));
+            (group, tokens)
+        })
+        .collect();
+    groups.sort_by(|a, b| a.0.cmp(b.0));
+    let mut rate_limits: Vec<(&String, &u32)> = config.rate_limits.iter().collect();
+    rate_limits.sort();
+    let mut concurrency_limits: Vec<(&String, &u32)> = config.concurrency_limits.iter().collect();
+    concurrency_limits.sort();
+    let mut priorities: Vec<(&String, &u32)> = config.priorities.iter().collect();
+    priorities.sort();
+    let mut budgets: Vec<(&String, &GroupBudget)> = config.budgets.iter().collect();
+    budgets.sort_by(|a, b| a.0.cmp(b.0));
+    let digest = Sha256::digest(
+        format!(
+            "{:?}{:?}{:?}{:?}{:?}{:?}{:?}{:?}",
+            groups,
+            rate_limits,
+            concurrency_limits,
+            priorities,
+            budgets,
+            config.policies,
+            config.presets,
+            config.pricing
+        )
+        .as_bytes(),
+    );
+    digest[..8].iter().map(|b| format!("{:02x}", b)).collect()
 }
 
 #[derive(Debug, Deserialize)]
 pub struct Secrets {
-    pub groups: Vec<HashMap<String, Vec<String>>>,
+    pub groups: Vec<HashMap<String, Vec<TokenSpec>>>,
+    // Group -> requests per minute per token
+    #[serde(default)]
+    pub rate_limits: HashMap<String, u32>,
+    // Group -> requests in flight at once
+    #[serde(default)]
+    pub concurrency_limits: HashMap<String, u32>,
+    // Group -> priority of queued requests, higher first
+    #[serde(default)]
+    pub priorities: HashMap<String, u32>,
+    // Group -> daily and monthly token budgets
+    #[serde(default)]
+    pub budgets: HashMap<String, GroupBudget>,
+    // Request parameter bounds, applied in order
+    #[serde(default)]
+    pub policies: Vec<Policy>,
+    // Preset name -> sampling parameters
+    #[serde(default)]
+    pub presets: BTreeMap<String, Preset>,
+    // Model pattern -> price per 1000 prompt and completion tokens
+    #[serde(default)]
+    pub pricing: BTreeMap<String, Price>,
 }
 
 // -----------------------------------------------------------------------------
 // YAML Loading Functions
 // -----------------------------------------------------------------------------
-pub fn load_auth_tokens_from_yaml() -> Result<HashMap<String, Vec<String>>, Box<dyn std::error::Error>> {
-    let path = Path::new("/workspace/secrets.yaml");
+pub const DEFAULT_SECRETS_PATH: &str = "/workspace/secrets.yaml";
+pub const DEFAULT_ENDPOINTS_PATH: &str = "/workspace/endpoints.yaml";
+
+// Endpoints and secrets files, set once at startup (--endpoints-file, --secrets-file)
+static CONFIG_PATHS: OnceLock<(PathBuf, PathBuf)> = OnceLock::new();
+
+pub fn set_config_paths(endpoints: PathBuf, secrets: PathBuf) {
+    let _ = CONFIG_PATHS.set((endpoints, secrets));
+}
+
+pub fn endpoints_path() -> &'static Path {
+    CONFIG_PATHS.get().map_or(Path::new(DEFAULT_ENDPOINTS_PATH), |(endpoints, _)| endpoints)
+}
+
+pub fn secrets_path() -> &'static Path {
+    CONFIG_PATHS.get().map_or(Path::new(DEFAULT_SECRETS_PATH), |(_, secrets)| secrets)
+}
+
+pub fn load_auth_tokens_from_yaml() -> Result<AuthConfig, Box<dyn std::error::Error>> {
+    let path = secrets_path();
     info!("Load secrets from: {}", path.display());
-    let contents = fs::read_to_string(&path)?;
+    let contents = fs::read_to_string(path)?;
     let secrets: Secrets = serde_yaml::from_str(&contents)?;
     let mut tokens = HashMap::new();
+    let mut plaintext = 0;
     for group_map in secrets.groups {
-        for (group, tokens_list) in group_map {
-            tokens.insert(group, tokens_list);
+        for (group, specs) in group_map {
+            let mut entries = Vec::with_capacity(specs.len());
+            for spec in specs {
+                let entry = TokenEntry::try_from(spec).map_err(|e| format!("Group {}: {}", group, e))?;
+                validate_token_entry(&entry.token).map_err(|e| format!("Group {}: {}", group, e))?;
+                plaintext += usize::from(!entry.token.starts_with(HASHED_TOKEN_PREFIX));
+                entries.push(entry);
+            }
+            tokens.insert(group, entries);
         }
     }
-    Ok(tokens)
+    if plaintext > 0 {
+        info!("{} tokens in secrets are stored in plaintext", plaintext);
+    }
+    Ok(AuthConfig {
+        groups: tokens,
+        rate_limits: secrets.rate_limits,
+        concurrency_limits: secrets.concurrency_limits,
+        priorities: secrets.priorities,
+        budgets: secrets.budgets,
+        policies: secrets.policies,
+        presets: secrets.presets,
+        pricing: secrets.pricing,
+    })
 }
 
 pub fn load_endpoints_from_yaml() -> io::Result<Vec<Endpoint>> {
-    let path = Path::new("/workspace/endpoints.yaml");
+    let path = endpoints_path();
     info!("Load endpoints from: {}", path.display());
-    let contents = fs::read_to_string(&path)?;
-    let raw_endpoints: Vec<serde_yaml::Value> = serde_yaml::from_str(&contents).map_err(|e| {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        // Discovered endpoints can stand in for the file
+        Err(e)
+            if e.kind() == io::ErrorKind::NotFound
+                && (KubernetesConfig::is_enabled() || DockerConfig::is_enabled()) =>
+        {
+            info!("No {}, taking endpoints from discovery only", path.display());
+            return Ok(Vec::new());
+        }
+        Err(e) => return Err(e),
+    };
+    // If "task" is missing, it defaults to "generate".
+    let mut endpoints: Vec<Endpoint> = serde_yaml::from_str(&contents).map_err(|e| {
         io::Error::new(io::ErrorKind::InvalidData, format!("YAML parse error: {}", e))
     })?;
-
-    let mut endpoints = Vec::new();
-    for mut raw in raw_endpoints {
-        // If "task" is missing, default to "generate".
-        if let Some(mapping) = raw.as_mapping_mut() {
-            let key = serde_yaml::Value::String("task".to_string());
-            if !mapping.contains_key(&key) {
-                mapping.insert(key, serde_yaml::Value::String("generate".to_string()));
-            }
-        }
-        let endpoint: Endpoint = serde_yaml::from_value(raw).map_err(|e| {
-            io::Error::new(io::ErrorKind::InvalidData, format!("YAML parse error: {}", e))
-        })?;
-        if endpoint.task != "generate" && endpoint.task != "embed" {
+    let mut seen = HashSet::new();
+    for endpoint in &mut endpoints {
+        validate_endpoint(endpoint).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        if !seen.insert((endpoint.task.clone(), endpoint.url.clone())) {
             return Err(io::Error::new(
                 io::ErrorKind::InvalidData,
-                format!("Invalid task value: {}", endpoint.task),
+                format!("Duplicate {} endpoint: {}", endpoint.task, endpoint.url),
             ));
         }
-        endpoints.push(endpoint);
     }
     Ok(endpoints)
 }
 
+// Normalize the URL of an endpoint read from a file or request and check its settings
+pub fn validate_endpoint(endpoint: &mut Endpoint) -> Result<(), String> {
+    endpoint.url = normalize_url(&endpoint.url)?;
+    if !TASKS.contains(&endpoint.task.as_str()) {
+        // Rerankers serve /v1/rerank next to /v1/score, there is no task of their own
+        let hint = if endpoint.task == "rerank" { " (declare rerankers with task: score)" } else { "" };
+        return Err(format!("Invalid task value: {}{}", endpoint.task, hint));
+    }
+    if let Some(resolve) = &endpoint.resolve
+        && resolve != "all"
+        && resolve != "srv"
+    {
+        return Err(format!("Invalid resolve value: {}", resolve));
+    }
+    for (name, value) in endpoint.headers.iter().flatten() {
+        if HeaderName::from_bytes(name.as_bytes()).is_err() || HeaderValue::from_str(value).is_err() {
+            return Err(format!("Invalid header {}", name));
+        }
+    }
+    Ok(())
+}
+
 // -----------------------------------------------------------------------------
 // Misc Helper Functions
 // -----------------------------------------------------------------------------
-// Helper to split endpoints into two sets (generate vs. embed).
-pub fn partition_endpoints(all: Vec<Endpoint>) -> (Vec<Endpoint>, Vec<Endpoint>) {
-    let mut generate_endpoints = Vec::new();
-    let mut embed_endpoints = Vec::new();
+// Canonical form of an endpoint URL, which keys every per-endpoint map: lowercase scheme
+// and host, no default port and no trailing slash, so `http://Node1:80/` is `http://node1`.
+pub fn normalize_url(url: &str) -> Result<String, String> {
+    let parsed = reqwest::Url::parse(url.trim()).map_err(|e| format!("Invalid url {}: {}", url, e))?;
+    if !matches!(parsed.scheme(), "http" | "https") || parsed.host_str().is_none() {
+        return Err(format!("Invalid url {}: expected http(s)://host[:port]", url));
+    }
+    if parsed.query().is_some() || parsed.fragment().is_some() || !parsed.username().is_empty() {
+        return Err(format!("Invalid url {}: credentials, query and fragment are not allowed", url));
+    }
+    Ok(parsed.as_str().trim_end_matches('/').to_string())
+}
+
+// Keep the credentials of an endpoint out of logs
+fn redact_endpoint_secrets(endpoint: &Endpoint) {
+    redact_secret(&endpoint.access_token);
+    if let Some(password) = endpoint.proxy.as_ref().and_then(|proxy| proxy.password.as_deref()) {
+        redact_secret(password);
+    }
+    for value in endpoint.headers.iter().flat_map(|headers| headers.values()) {
+        redact_secret(value);
+    }
+}
+
+// Helper to separate DNS templates (with `resolve` set) from regular endpoints.
+pub fn split_dns_templates(all: Vec<Endpoint>) -> (Vec<Endpoint>, Vec<Endpoint>) {
+    all.into_iter().partition(|ep| ep.resolve.is_none())
+}
+
+// Helper to split endpoints into one set per task.
+pub fn partition_endpoints(all: Vec<Endpoint>) -> HashMap<String, Vec<Endpoint>> {
+    let mut partitioned: HashMap<String, Vec<Endpoint>> = TASKS
+        .iter()
+        .map(|task| (task.to_string(), Vec::new()))
+        .collect();
     for ep in all {
-        if ep.task == "generate" {
-            generate_endpoints.push(ep);
-        } else {
-            embed_endpoints.push(ep);
-        }
+        partitioned.entry(ep.task.clone()).or_default().push(ep);
     }
-    (generate_endpoints, embed_endpoints)
+    partitioned
 }
 
 // -----------------------------------------------------------------------------
 // App State
 // -----------------------------------------------------------------------------
+
+// Outcome of replacing a task's endpoints
+#[derive(Debug, Default)]
+pub struct EndpointChanges {
+    pub added: Vec<Endpoint>,
+    pub changed: Vec<Endpoint>,
+    // URLs
+    pub removed: Vec<String>,
+    pub unchanged: usize,
+}
+
+// Routing data of a single task
+#[derive(Default)]
+pub struct TaskState {
+    pub endpoints: Mutex<Vec<Endpoint>>,
+    pub health_status: Mutex<HashMap<String, EndpointHealth>>,
+    pub endpoint_models: Mutex<HashMap<String, Vec<Value>>>,
+    pub model_to_endpoints: Mutex<HashMap<String, Vec<String>>>,
+    // Model -> endpoints that served it, kept while they are down so that a model without a
+    // healthy endpoint is told apart from one that does not exist
+    pub last_served: Mutex<HashMap<String, Vec<String>>>,
+}
+
+impl TaskState {
+    pub fn new(endpoints: Vec<Endpoint>) -> Self {
+        endpoints.iter().for_each(redact_endpoint_secrets);
+        TaskState {
+            endpoints: Mutex::new(endpoints),
+            ..Default::default()
+        }
+    }
+
+
+    // Two-way sync of an endpoint's freshly fetched models into the task's maps
+    pub fn apply_models(&self, url: &str, models: Vec<Value>) {
+        let mut models_map = self.endpoint_models.lock().unwrap();
+        let mut model_to_endpoints_map = self.model_to_endpoints.lock().unwrap();
+
+        // Current known models
+        let current_models = models_map.get(url).cloned().unwrap_or_default();
+        let current_ids: HashSet<String> = current_models
+            .iter()
+            .filter_map(|m| m.get("id").and_then(|v| v.as_str()))
+            .map(String::from)
+            .collect();
+
+        // Freshly fetched models
+        let new_ids: HashSet<String> = models
+            .iter()
+            .filter_map(|m| m.get("id").and_then(|v| v.as_str()))
+            .map(String::from)
+            .collect();
+
+        // Identify add/remove
+        let to_add = new_ids.difference(&current_ids).cloned().collect::<HashSet<_>>();
+        let to_remove = current_ids.difference(&new_ids).cloned().collect::<HashSet<_>>();
+
+        // Update endpoint_models
+        models_map.insert(url.to_string(), models);
+
+        // Add new associations
+        for model_id in to_add {
+            let entry = model_to_endpoints_map.entry(model_id).or_default();
+            if !entry.iter().any(|u| u == url) {
+                entry.push(url.to_string());
+            }
+        }
+        // Remove stale associations
+        for model_id in to_remove {
+            if let Some(urls) = model_to_endpoints_map.get_mut(&model_id) {
+                urls.retain(|u| u != url);
+                if urls.is_empty() {
+                    model_to_endpoints_map.remove(&model_id);
+                }
+            }
+        }
+
+        // The endpoint serves what it answered with now, whatever it served before going down
+        self.forget_served(url, |model_id| !new_ids.contains(model_id));
+        let mut last_served = self.last_served.lock().unwrap();
+        for model_id in new_ids {
+            let entry = last_served.entry(model_id).or_default();
+            if !entry.iter().any(|u| u == url) {
+                entry.push(url.to_string());
+            }
+        }
+    }
+
+    // Drop what was learned about endpoints no longer in the task, e.g. written by a probe
+    // that finished after its endpoint was removed. Returns how many entries went.
+    pub fn retain_endpoints(&self) -> usize {
+        let active: HashSet<String> = self.endpoints.lock().unwrap().iter().map(|ep| ep.url.clone()).collect();
+        let mut removed = 0;
+        {
+            let mut health_status = self.health_status.lock().unwrap();
+            let before = health_status.len();
+            health_status.retain(|url, _| active.contains(url));
+            removed += before - health_status.len();
+        }
+        let mut models_map = self.endpoint_models.lock().unwrap();
+        let before = models_map.len();
+        models_map.retain(|url, _| active.contains(url));
+        removed += before - models_map.len();
+        let mut model_to_endpoints_map = self.model_to_endpoints.lock().unwrap();
+        let before = model_to_endpoints_map.len();
+        for urls in model_to_endpoints_map.values_mut() {
+            urls.retain(|url| models_map.contains_key(url));
+        }
+        model_to_endpoints_map.retain(|_, urls| !urls.is_empty());
+        removed += before - model_to_endpoints_map.len();
+        let mut last_served = self.last_served.lock().unwrap();
+        let before = last_served.len();
+        for urls in last_served.values_mut() {
+            urls.retain(|url| active.contains(url));
+        }
+        last_served.retain(|_, urls| !urls.is_empty());
+        removed + before - last_served.len()
+    }
+
+    // Drop the endpoint from the models `matches` picks, e.g. once it is removed
+    pub fn forget_served(&self, url: &str, matches: impl Fn(&str) -> bool) {
+        let mut last_served = self.last_served.lock().unwrap();
+        for (model_id, urls) in last_served.iter_mut() {
+            if matches(model_id) {
+                urls.retain(|u| u != url);
+            }
+        }
+        last_served.retain(|_, urls| !urls.is_empty());
+    }
+
+    // Endpoints that served the model before going down
+    pub fn last_served_by(&self, model: &str) -> Vec<String> {
+        self.last_served.lock().unwrap().get(model).cloned().unwrap_or_default()
+    }
+
+    // Forget an endpoint's models while it is down; they are still known to have been served
+    pub fn clear_models(&self, url: &str) {
+        // Remove the endpoint's URL from the model_to_endpoints map
+        {
+            let mut map_lock = self.model_to_endpoints.lock().unwrap();
+            for urls in map_lock.values_mut() {
+                urls.retain(|u| u != url);
+            }
+            map_lock.retain(|_, v| !v.is_empty());
+        }
+        // Remove from endpoint_models
+        self.endpoint_models.lock().unwrap().remove(url);
+    }
+}
+
 pub struct AppState {
-    // Generate-task data
-    pub endpoints_generate: Mutex<Vec<Endpoint>>,
-    pub health_status_generate: Mutex<HashMap<String, EndpointHealth>>,
-    pub endpoint_models_generate: Mutex<HashMap<String, Vec<Value>>>,
-    pub model_to_endpoints_generate: Mutex<HashMap<String, Vec<String>>>,
-
-    // Embed-task data
-    pub endpoints_embed: Mutex<Vec<Endpoint>>,
-    pub health_status_embed: Mutex<HashMap<String, EndpointHealth>>,
-    pub endpoint_models_embed: Mutex<HashMap<String, Vec<Value>>>,
-    pub model_to_endpoints_embed: Mutex<HashMap<String, Vec<String>>>,
-
-    // Auth tokens -> access groups
-    pub auth_tokens: Mutex<HashMap<String, Vec<String>>>,
-}
\ No newline at end of file
+    // Per-task data, see TASKS
+    pub generate: TaskState,
+    pub embed: TaskState,
+    pub pooling: TaskState,
+    pub score: TaskState,
+    pub transcribe: TaskState,
+    pub image: TaskState,
+    pub moderate: TaskState,
+
+    // Endpoints whose hosts are expanded via DNS by the discovery task
+    pub dns_templates: Mutex<Vec<Endpoint>>,
+
+    // Probed capabilities per endpoint URL (all tasks)
+    pub endpoint_capabilities: Mutex<HashMap<String, EndpointCapabilities>>,
+
+    // Scraped vLLM load per endpoint URL (all tasks), if scraping is on
+    pub backend_metrics: Mutex<HashMap<String, BackendMetrics>>,
+
+    // Last synthetic inference check per endpoint URL (all tasks), if deep checks are on
+    pub deep_checks: Mutex<HashMap<String, DeepCheck>>,
+
+    // Access groups -> auth tokens, see AuthTokens
+    pub auth_tokens: ArcSwap<AuthTokens>,
+
+    // Optional backend shared with other composer replicas
+    pub shared: SharedStore,
+
+    // Why the config files failed to load at startup, until a reload succeeds
+    pub config_error: Mutex<Option<String>>,
+
+    // Prometheus metrics exposed on /metrics
+    pub metrics: Metrics,
+
+    // Pooled HTTP clients for backend traffic
+    pub http: HttpClients,
+
+    // Which requests may send JSON without a JSON content type
+    pub body_policy: BodyPolicy,
+
+    // Token usage per conversation
+    pub conversations: ConversationBudgets,
+
+    // Request failure tracking per endpoint URL
+    pub breakers: CircuitBreakers,
+
+    // Error rates of proxied traffic per endpoint URL
+    pub outliers: OutlierDetector,
+
+    // Weight ramps of endpoints that just became healthy again
+    pub slow_start: SlowStart,
+
+    // Repeated prompts per auth token
+    pub loops: LoopDetector,
+
+    // Token usage per auth token, group and model
+    pub usage: UsageLedger,
+
+    // Tokens used this day and month per group and auth token, budgets come with the tokens
+    pub budgets: BudgetTracker,
+
+    // In-flight requests and TTFT per model
+    pub model_stats: ModelStats,
+
+    // Optional persistent request log backing /usage
+    pub usage_db: Option<UsageDb>,
+
+    // Requests per minute per bearer token, limits come with the auth tokens
+    pub rate_limiter: RateLimiter,
+
+    // Requests in flight per group, limits come with the auth tokens
+    pub concurrency: ConcurrencyLimiter,
+
+    // Requests in flight per endpoint and route class, limits come with the endpoints
+    pub endpoint_pools: PoolLimiter,
+
+    // Requests waiting for a pool slot
+    pub admission: AdmissionQueue,
+
+    // Cadences of the health check and model refresh loops
+    pub monitor_intervals: MonitorIntervals,
+
+    // Models serving requests for unavailable models
+    pub fallbacks: Fallbacks,
+
+    // Endpoints added by backends via /register
+    pub registrations: Registrations,
+
+    // Answers to deterministic chat completions
+    pub response_cache: ResponseCache,
+
+    // Chunking of long embedding input lists
+    pub embedding_split: EmbeddingSplit,
+
+    // Client headers and callers passed on to endpoints
+    pub forwarding: Forwarding,
+
+    // Screening of chat completions by a guard model
+    pub guard: GuardConfig,
+
+    // Weighted round-robin position per task and model
+    pub round_robin: WeightedRoundRobin,
+
+    // Sticky routing of sessions
+    pub affinity: SessionAffinity,
+
+    // How requests without a session are spread over a model's endpoints
+    pub routing_strategy: RoutingStrategy,
+
+    // Endpoints in the composer's own zone go first
+    pub zones: ZonePreference,
+
+    // Opt-in anonymous statistics served at /telemetry
+    pub telemetry: Telemetry,
+
+    // Probes in a row that change an endpoint's health
+    pub health_thresholds: HealthThresholds,
+
+    // Finish reasons per model and endpoint, flags endpoints truncating unusually often
+    pub truncation: TruncationDetector,
+
+    // Token changes made via /admin/tokens
+    pub token_admin: TokenAdmin,
+
+    // Prompts run through the proxy on a schedule to check the answers end to end
+    pub canaries: Canaries,
+
+    // Models and endpoints whose requests are traced, set via /admin/logging
+    pub debug_traces: DebugTraces,
+
+    // Draining on SIGTERM or /drain
+    pub shutdown: Shutdown,
+}
+
+impl AppState {
+    pub fn new(
+        endpoints: Vec<Endpoint>,
+        auth_config: AuthConfig,
+        shared: SharedStore,
+    ) -> Self {
+        let fallbacks = Fallbacks::from_env();
+        fallbacks.configure(&endpoints);
+        let (endpoints, dns_templates) = split_dns_templates(endpoints);
+        let mut partitioned = partition_endpoints(endpoints);
+        let mut take = |task: &str| TaskState::new(partitioned.remove(task).unwrap_or_default());
+        AppState {
+            generate: take("generate"),
+            embed: take("embed"),
+            pooling: take("pooling"),
+            score: take("score"),
+            transcribe: take("transcribe"),
+            image: take("image"),
+            moderate: take("moderate"),
+            dns_templates: Mutex::new(dns_templates),
+            endpoint_capabilities: Mutex::new(HashMap::new()),
+            backend_metrics: Mutex::new(HashMap::new()),
+            deep_checks: Mutex::new(HashMap::new()),
+            auth_tokens: ArcSwap::from_pointee(AuthTokens::new(auth_config, 1)),
+            shared,
+            config_error: Mutex::new(None),
+            metrics: Metrics::new(),
+            http: HttpClients::from_env(),
+            body_policy: BodyPolicy::from_env(),
+            conversations: ConversationBudgets::from_env(),
+            breakers: CircuitBreakers::from_env(),
+            outliers: OutlierDetector::from_env(),
+            slow_start: SlowStart::from_env(),
+            loops: LoopDetector::from_env(),
+            usage: UsageLedger::default(),
+            budgets: BudgetTracker::default(),
+            model_stats: ModelStats::default(),
+            usage_db: UsageDb::from_env(),
+            rate_limiter: RateLimiter::default(),
+            concurrency: ConcurrencyLimiter::default(),
+            endpoint_pools: PoolLimiter::default(),
+            admission: AdmissionQueue::from_env(),
+            monitor_intervals: MonitorIntervals::from_env(),
+            fallbacks,
+            registrations: Registrations::from_env(),
+            response_cache: ResponseCache::from_env(),
+            embedding_split: EmbeddingSplit::from_env(),
+            forwarding: Forwarding::from_env(),
+            guard: GuardConfig::from_env(),
+            round_robin: WeightedRoundRobin::default(),
+            affinity: SessionAffinity::from_env(),
+            routing_strategy: RoutingStrategy::from_env(),
+            zones: ZonePreference::from_env(),
+            telemetry: Telemetry::from_env(),
+            health_thresholds: HealthThresholds::from_env(),
+            truncation: TruncationDetector::from_env(),
+            token_admin: TokenAdmin::default(),
+            canaries: Canaries::from_env(),
+            debug_traces: DebugTraces::default(),
+            shutdown: Shutdown::from_env(),
+        }
+    }
+
+    // Data for a task; endpoint tasks are validated on load
+    pub fn task(&self, task: &str) -> &TaskState {
+        match task {
+            "embed" => &self.embed,
+            "pooling" => &self.pooling,
+            "score" => &self.score,
+            "transcribe" => &self.transcribe,
+            "image" => &self.image,
+            "moderate" => &self.moderate,
+            _ => &self.generate,
+        }
+    }
+
+    // All tasks in the order of TASKS
+    pub fn tasks(&self) -> [(&'static str, &TaskState); 7] {
+        [
+            ("generate", &self.generate),
+            ("embed", &self.embed),
+            ("pooling", &self.pooling),
+            ("score", &self.score),
+            ("transcribe", &self.transcribe),
+            ("image", &self.image),
+            ("moderate", &self.moderate),
+        ]
+    }
+
+    // Add an endpoint at runtime. Returns false if the task already has its URL.
+    // The caller is responsible for starting its monitor.
+    pub fn add_endpoint(&self, endpoint: Endpoint) -> bool {
+        let mut endpoints = self.task(&endpoint.task).endpoints.lock().unwrap();
+        if endpoints.iter().any(|ep| ep.url == endpoint.url) {
+            return false;
+        }
+        redact_endpoint_secrets(&endpoint);
+        endpoints.push(endpoint);
+        true
+    }
+
+    // The configuration of an endpoint currently in use
+    pub fn endpoint(&self, task: &str, url: &str) -> Option<Endpoint> {
+        self.task(task).endpoints.lock().unwrap().iter().find(|ep| ep.url == url).cloned()
+    }
+
+    // Remove an endpoint and everything learned about it; its monitor exits on its own
+    pub fn remove_endpoint(&self, task: &str, url: &str) -> bool {
+        let task_state = self.task(task);
+        {
+            let mut endpoints = task_state.endpoints.lock().unwrap();
+            let before = endpoints.len();
+            endpoints.retain(|ep| ep.url != url);
+            if endpoints.len() == before {
+                return false;
+            }
+        }
+        self.forget_endpoint(task_state, url);
+        true
+    }
+
+    // Whether the endpoint failed as many deep checks in a row as take it out of routing
+    pub fn fails_deep_checks(&self, url: &str) -> bool {
+        self.deep_checks
+            .lock()
+            .unwrap()
+            .get(url)
+            .is_some_and(|check| check.failures >= self.health_thresholds.failures)
+    }
+
+    // Drop what was learned about an endpoint that is no longer in its task
+    fn forget_endpoint(&self, task_state: &TaskState, url: &str) {
+        task_state.health_status.lock().unwrap().remove(url);
+        task_state.clear_models(url);
+        task_state.forget_served(url, |_| true);
+        self.endpoint_capabilities.lock().unwrap().remove(url);
+        self.backend_metrics.lock().unwrap().remove(url);
+        self.deep_checks.lock().unwrap().remove(url);
+        self.breakers.forget(url);
+        self.outliers.forget(url);
+        self.slow_start.forget(url);
+    }
+
+    // Make a task's endpoints match `endpoints`, e.g. on /reload. Unchanged endpoints keep
+    // their health, models and monitors. Endpoints whose settings changed start over like
+    // new ones; the caller starts the monitors of `added` and `changed`.
+    pub fn replace_endpoints(&self, task: &str, endpoints: Vec<Endpoint>) -> EndpointChanges {
+        endpoints.iter().for_each(redact_endpoint_secrets);
+        let task_state = self.task(task);
+        let mut changes = EndpointChanges::default();
+        {
+            let mut current = task_state.endpoints.lock().unwrap();
+            for endpoint in &endpoints {
+                match current.iter().find(|ep| ep.url == endpoint.url) {
+                    Some(existing) if existing == endpoint => changes.unchanged += 1,
+                    Some(_) => changes.changed.push(endpoint.clone()),
+                    None => changes.added.push(endpoint.clone()),
+                }
+            }
+            changes.removed = current
+                .iter()
+                .filter(|ep| !endpoints.iter().any(|new| new.url == ep.url))
+                .map(|ep| ep.url.clone())
+                .collect();
+            // Old monitors of removed and changed endpoints exit once they miss theirs here
+            *current = endpoints;
+        }
+        let changed_urls = changes.changed.iter().map(|ep| &ep.url);
+        for url in changes.removed.iter().chain(changed_urls) {
+            self.forget_endpoint(task_state, url);
+        }
+        changes
+    }
+
+    // Draining or maintenance if an operator set an endpoint to it
+    pub fn admin_state(&self, url: &str) -> Option<HealthState> {
+        self.tasks()
+            .iter()
+            .find_map(|(_, task_state)| task_state.health_status.lock().unwrap().get(url)?.admin_state)
+    }
+
+    // Put an endpoint into draining or maintenance, or back into service with None
+    pub fn set_admin_state(&self, url: &str, admin_state: Option<HealthState>) -> Result<(), String> {
+        let url = normalize_url(url)?;
+        for (_, task_state) in self.tasks() {
+            if !task_state.endpoints.lock().unwrap().iter().any(|ep| ep.url == url) {
+                continue;
+            }
+            let mut health_status = task_state.health_status.lock().unwrap();
+            let Some(health) = health_status.get_mut(&url) else {
+                return Err(format!("Endpoint {} has not been probed yet.", url));
+            };
+            info!(
+                "Endpoint {} set to {}",
+                url,
+                admin_state.map_or("active", HealthState::label)
+            );
+            health.admin_state = admin_state;
+            return Ok(());
+        }
+        Err(format!("Unknown endpoint {}.", url))
+    }
+
+    // Activate freshly loaded auth tokens, returns their version
+    pub fn replace_auth_tokens(&self, config: AuthConfig) -> u64 {
+        // Retried if another reload swapped in between
+        let previous = self
+            .auth_tokens
+            .rcu(|current| AuthTokens::new(config.clone(), current.version + 1));
+        previous.version + 1
+    }
+
+    // All configured endpoints across tasks
+    pub fn all_endpoints(&self) -> Vec<Endpoint> {
+        self.tasks()
+            .iter()
+            .flat_map(|(_, ts)| ts.endpoints.lock().unwrap().clone())
+            .collect()
+    }
+
+    // Entries in the maps keyed by endpoint or model, exported to spot leaks
+    pub fn map_sizes(&self) -> Vec<(&'static str, usize)> {
+        let sum = |size: fn(&TaskState) -> usize| -> usize {
+            self.tasks().iter().map(|(_, task_state)| size(task_state)).sum()
+        };
+        vec![
+            ("health_status", sum(|ts| ts.health_status.lock().unwrap().len())),
+            ("endpoint_models", sum(|ts| ts.endpoint_models.lock().unwrap().len())),
+            ("model_to_endpoints", sum(|ts| ts.model_to_endpoints.lock().unwrap().len())),
+            ("last_served", sum(|ts| ts.last_served.lock().unwrap().len())),
+            ("endpoint_capabilities", self.endpoint_capabilities.lock().unwrap().len()),
+            ("backend_metrics", self.backend_metrics.lock().unwrap().len()),
+            ("deep_checks", self.deep_checks.lock().unwrap().len()),
+            ("admission_queues", self.admission.entries()),
+            ("budgets", self.budgets.entries()),
+            ("breakers", self.breakers.entries()),
+            ("outliers", self.outliers.entries()),
+            ("slow_start", self.slow_start.entries()),
+            ("round_robin", self.round_robin.entries()),
+            ("model_stats", self.model_stats.entries()),
+            ("truncation", self.truncation.entries()),
+            ("http_clients", self.http.entries()),
+            ("dns_cache", self.http.dns.entries()),
+            ("response_cache", self.response_cache.entries()),
+        ]
+    }
+}
@@ -0,0 +1,237 @@
+// External crates
+use log::info;
+
+// Standard library
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+// Internal modules
+use crate::concurrency::GroupSlot;
+use crate::health::HealthState;
+use crate::state::AppState;
+
+// -----------------------------------------------------------------------------
+// Model Load Statistics
+// -----------------------------------------------------------------------------
+
+// Weight of the newest sample in the latency averages
+const TTFT_SMOOTHING: f64 = 0.2;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ModelLoad {
+    // Requests currently waiting on or streaming from a backend
+    pub in_flight: u64,
+    // Moving average of the time to the first streamed chunk
+    pub avg_ttft_ms: Option<f64>,
+}
+
+// Moving averages of an endpoint's latency for one model
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EndpointLatency {
+    // Time to the first streamed chunk
+    pub ttft_ms: Option<f64>,
+    // Time until a response was relayed in full, streamed or not
+    pub total_ms: Option<f64>,
+}
+
+// Live load per model and endpoint as seen by this composer
+#[derive(Default)]
+pub struct ModelStats {
+    models: Mutex<HashMap<String, ModelLoad>>,
+    // Endpoint URL -> requests in flight
+    endpoints: Mutex<HashMap<String, u64>>,
+    // (endpoint URL, model) -> latency
+    latencies: Mutex<HashMap<(String, String), EndpointLatency>>,
+}
+
+impl ModelStats {
+    pub fn get(&self, model: &str) -> ModelLoad {
+        self.models.lock().unwrap().get(model).copied().unwrap_or_default()
+    }
+
+    pub fn latency(&self, url: &str, model: &str) -> EndpointLatency {
+        let latencies = self.latencies.lock().unwrap();
+        latencies.get(&(url.to_string(), model.to_string())).copied().unwrap_or_default()
+    }
+
+    // Requests this composer has in flight at the endpoint
+    pub fn endpoint_in_flight(&self, url: &str) -> u64 {
+        self.endpoints.lock().unwrap().get(url).copied().unwrap_or_default()
+    }
+
+    // Drop idle models no longer served and idle endpoints that are gone, returns how many
+    pub fn retain(&self, served: &HashSet<String>, active: &HashSet<String>) -> usize {
+        let mut models = self.models.lock().unwrap();
+        let mut endpoints = self.endpoints.lock().unwrap();
+        let mut latencies = self.latencies.lock().unwrap();
+        let before = models.len() + endpoints.len() + latencies.len();
+        models.retain(|model, load| load.in_flight > 0 || served.contains(model));
+        endpoints.retain(|url, in_flight| *in_flight > 0 || active.contains(url));
+        latencies.retain(|(url, model), _| active.contains(url) && served.contains(model));
+        before - models.len() - endpoints.len() - latencies.len()
+    }
+
+    pub fn entries(&self) -> usize {
+        self.models.lock().unwrap().len()
+            + self.endpoints.lock().unwrap().len()
+            + self.latencies.lock().unwrap().len()
+    }
+
+    // Requests this composer has in flight at any endpoint
+    pub fn total_in_flight(&self) -> u64 {
+        self.endpoints.lock().unwrap().values().sum()
+    }
+
+    fn record_ttft(&self, url: &str, model: &str, ttft_ms: f64) {
+        let mut models = self.models.lock().unwrap();
+        let load = models.entry(model.to_string()).or_default();
+        load.avg_ttft_ms = Some(smoothed(load.avg_ttft_ms, ttft_ms));
+        let mut latencies = self.latencies.lock().unwrap();
+        let latency = latencies.entry((url.to_string(), model.to_string())).or_default();
+        latency.ttft_ms = Some(smoothed(latency.ttft_ms, ttft_ms));
+    }
+
+    fn record_total(&self, url: &str, model: &str, total_ms: f64) {
+        let mut latencies = self.latencies.lock().unwrap();
+        let latency = latencies.entry((url.to_string(), model.to_string())).or_default();
+        latency.total_ms = Some(smoothed(latency.total_ms, total_ms));
+    }
+}
+
+// Exponentially weighted moving average
+fn smoothed(avg: Option<f64>, sample: f64) -> f64 {
+    match avg {
+        Some(avg) => avg + TTFT_SMOOTHING * (sample - avg),
+        None => sample,
+    }
+}
+
+// Counts a request as in flight until dropped; streams carry it until they end
+pub struct InFlight {
+    state: Arc<AppState>,
+    model: String,
+    endpoint: String,
+    started: Instant,
+    first_chunk_seen: bool,
+    // Whether the response's latency counts, not for errors answered right away
+    timed: bool,
+    // The caller's concurrency slot and the endpoint's pool slot, released with the request
+    _slots: Vec<GroupSlot>,
+    // Task of a request counted as cancelled if it is dropped before it completes
+    cancellable: Option<String>,
+    completed: bool,
+}
+
+impl InFlight {
+    pub fn start(state: &Arc<AppState>, model: &str, endpoint: &str) -> Self {
+        *state
+            .model_stats
+            .endpoints
+            .lock()
+            .unwrap()
+            .entry(endpoint.to_string())
+            .or_default() += 1;
+        state
+            .model_stats
+            .models
+            .lock()
+            .unwrap()
+            .entry(model.to_string())
+            .or_default()
+            .in_flight += 1;
+        InFlight {
+            state: Arc::clone(state),
+            model: model.to_string(),
+            endpoint: endpoint.to_string(),
+            started: Instant::now(),
+            first_chunk_seen: false,
+            timed: true,
+            _slots: Vec::new(),
+            cancellable: None,
+            completed: false,
+        }
+    }
+
+    // Keep concurrency slots taken while the request is in flight
+    pub fn holding(mut self, slots: impl IntoIterator<Item = GroupSlot>) -> Self {
+        self._slots.extend(slots);
+        self
+    }
+
+    // Count the request as cancelled if it is dropped before `complete`, which happens when
+    // actix drops the handler or the response stream of a client that disconnected. The
+    // upstream request or stream goes with it, closing the connection to the endpoint, which
+    // makes vLLM abort the generation.
+    pub fn cancellable(mut self, task: &str) -> Self {
+        self.cancellable = Some(task.to_string());
+        self
+    }
+
+    // The response failed on the endpoint's side
+    pub fn complete(&mut self) {
+        self.completed = true;
+    }
+
+    // Leave the response out of the latency averages
+    pub fn untimed(&mut self) {
+        self.timed = false;
+    }
+
+    // The response was relayed in full, its latency counts towards the endpoint's average
+    pub fn finish(&mut self) {
+        self.completed = true;
+        if !self.timed {
+            return;
+        }
+        let total_ms = self.started.elapsed().as_secs_f64() * 1000.0;
+        self.state.model_stats.record_total(&self.endpoint, &self.model, total_ms);
+    }
+
+    // Time the first chunk of a stream
+    pub fn on_chunk(&mut self) {
+        if !self.first_chunk_seen && self.timed {
+            let ttft_ms = self.started.elapsed().as_secs_f64() * 1000.0;
+            self.state.model_stats.record_ttft(&self.endpoint, &self.model, ttft_ms);
+        }
+        self.first_chunk_seen = true;
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        if let Some(task) = &self.cancellable
+            && !self.completed
+        {
+            info!(
+                "client disconnected from {} request for model {} to endpoint {} after {}ms",
+                task,
+                self.model,
+                self.endpoint,
+                self.started.elapsed().as_millis()
+            );
+            self.state
+                .metrics
+                .requests_cancelled
+                .with_label_values(&[task, &self.model, &self.endpoint])
+                .inc();
+        }
+        if let Some(load) = self.state.model_stats.models.lock().unwrap().get_mut(&self.model) {
+            load.in_flight = load.in_flight.saturating_sub(1);
+        }
+        let idle = {
+            let mut endpoints = self.state.model_stats.endpoints.lock().unwrap();
+            match endpoints.get_mut(&self.endpoint) {
+                Some(in_flight) => {
+                    *in_flight = in_flight.saturating_sub(1);
+                    *in_flight == 0 && endpoints.remove(&self.endpoint).is_some()
+                }
+                None => false,
+            }
+        };
+        // The last request of a draining endpoint, it can be taken down now
+        if idle && self.state.admin_state(&self.endpoint) == Some(HealthState::Draining) {
+            info!("Endpoint {} is drained, no requests in flight", self.endpoint);
+        }
+    }
+}
@@ -0,0 +1,128 @@
+// External crates
+use log::info;
+use serde::Serialize;
+use serde_json::{json, Value};
+
+// Standard library
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::Instant;
+
+// Internal modules
+use crate::shared::now_ms;
+use crate::state::AppState;
+
+// -----------------------------------------------------------------------------
+// Telemetry
+// -----------------------------------------------------------------------------
+
+// Proxied requests of one task
+#[derive(Debug, Serialize, Clone, Copy, Default)]
+struct TaskCounts {
+    requests: u64,
+    // Transport errors and 5xx responses
+    errors: u64,
+    // 4xx responses of the backends
+    client_errors: u64,
+}
+
+// Opt-in anonymous operational statistics for a central capacity dashboard. Only counts are
+// kept: no tokens, groups, endpoint URLs or model names.
+pub struct Telemetry {
+    pub enabled: bool,
+    // Name the operator gives this site, if any
+    site: Option<String>,
+    started: Instant,
+    started_ms: u64,
+    // Task -> counts since startup
+    tasks: Mutex<BTreeMap<String, TaskCounts>>,
+}
+
+impl Telemetry {
+    // VLLM_COMPOSER_TELEMETRY (true/1) enables it, VLLM_COMPOSER_TELEMETRY_SITE names the site
+    pub fn from_env() -> Self {
+        let enabled = matches!(
+            std::env::var("VLLM_COMPOSER_TELEMETRY").as_deref(),
+            Ok("true") | Ok("1")
+        );
+        let site = std::env::var("VLLM_COMPOSER_TELEMETRY_SITE").ok().filter(|s| !s.is_empty());
+        if enabled {
+            info!("Serving anonymous telemetry at /telemetry");
+        }
+        Telemetry {
+            enabled,
+            site,
+            started: Instant::now(),
+            started_ms: now_ms(),
+            tasks: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    // Count a proxied request by its status ("error" for transport errors)
+    pub fn record(&self, task: &str, status: &str) {
+        if !self.enabled {
+            return;
+        }
+        let mut tasks = self.tasks.lock().unwrap();
+        let counts = tasks.entry(task.to_string()).or_default();
+        counts.requests += 1;
+        if status == "error" || status.starts_with('5') {
+            counts.errors += 1;
+        } else if status.starts_with('4') {
+            counts.client_errors += 1;
+        }
+    }
+
+    // The report served at /telemetry
+    pub fn report(&self, state: &AppState) -> Value {
+        let tasks = self.tasks.lock().unwrap().clone();
+        let mut total = TaskCounts::default();
+        let by_task: BTreeMap<String, Value> = tasks
+            .into_iter()
+            .map(|(task, counts)| {
+                total.requests += counts.requests;
+                total.errors += counts.errors;
+                total.client_errors += counts.client_errors;
+                (task, with_error_rate(counts))
+            })
+            .collect();
+
+        let (mut endpoints, mut healthy, mut models) = (0, 0, 0);
+        for (_, task_state) in state.tasks() {
+            endpoints += task_state.endpoints.lock().unwrap().len();
+            healthy += task_state
+                .health_status
+                .lock()
+                .unwrap()
+                .values()
+                .filter(|health| health.is_routable())
+                .count();
+            models += task_state.model_to_endpoints.lock().unwrap().len();
+        }
+
+        json!({
+            "composer_version": env!("CARGO_PKG_VERSION"),
+            "site": self.site,
+            "started_ms": self.started_ms,
+            "generated_ms": now_ms(),
+            "uptime_secs": self.started.elapsed().as_secs(),
+            "requests": with_error_rate(total),
+            "requests_by_task": by_task,
+            "endpoints": {
+                "total": endpoints,
+                "healthy": healthy,
+            },
+            "models": models,
+        })
+    }
+}
+
+fn with_error_rate(counts: TaskCounts) -> Value {
+    let mut value = json!(counts);
+    value["error_rate"] = json!(if counts.requests == 0 {
+        0.0
+    } else {
+        counts.errors as f64 / counts.requests as f64
+    });
+    value
+}
@@ -0,0 +1,92 @@
+// External crates
+use arc_swap::ArcSwap;
+use log::{info, warn};
+use rustls::crypto::ring::default_provider;
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use rustls::ServerConfig;
+
+// Standard library
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+// Internal modules
+use crate::reload::watch_files;
+
+// -----------------------------------------------------------------------------
+// TLS Termination
+// -----------------------------------------------------------------------------
+
+// The certificate served to clients. It is swapped when the files change, so rotated
+// certificates apply to new connections without a restart.
+#[derive(Debug)]
+pub struct ServerCert {
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    provider: Arc<CryptoProvider>,
+    current: ArcSwap<CertifiedKey>,
+}
+
+impl ServerCert {
+    pub fn load(cert_path: &Path, key_path: &Path) -> Result<Self, String> {
+        let provider = Arc::new(default_provider());
+        let current = load_certified_key(cert_path, key_path, &provider)?;
+        Ok(ServerCert {
+            cert_path: cert_path.to_path_buf(),
+            key_path: key_path.to_path_buf(),
+            provider,
+            current: ArcSwap::from_pointee(current),
+        })
+    }
+
+    // Read the files again; the served certificate stays if they are invalid
+    pub fn reload(&self) -> Result<(), String> {
+        let certified_key = load_certified_key(&self.cert_path, &self.key_path, &self.provider)?;
+        self.current.store(Arc::new(certified_key));
+        Ok(())
+    }
+
+    pub fn server_config(self: &Arc<Self>) -> Result<ServerConfig, String> {
+        ServerConfig::builder_with_provider(Arc::clone(&self.provider))
+            .with_safe_default_protocol_versions()
+            .map_err(|e| format!("Invalid TLS setup: {}", e))
+            .map(|builder| builder.with_no_client_auth().with_cert_resolver(Arc::clone(self) as _))
+    }
+}
+
+impl ResolvesServerCert for ServerCert {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(self.current.load_full())
+    }
+}
+
+fn load_certified_key(
+    cert_path: &Path,
+    key_path: &Path,
+    provider: &CryptoProvider,
+) -> Result<CertifiedKey, String> {
+    let chain = CertificateDer::pem_file_iter(cert_path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| format!("Failed to read TLS certificate {}: {}", cert_path.display(), e))?;
+    if chain.is_empty() {
+        return Err(format!("No certificate in {}", cert_path.display()));
+    }
+    let key = PrivateKeyDer::from_pem_file(key_path)
+        .map_err(|e| format!("Failed to read TLS key {}: {}", key_path.display(), e))?;
+    CertifiedKey::from_der(chain, key, provider)
+        .map_err(|e| format!("Invalid TLS certificate or key: {}", e))
+}
+
+// Reload the certificate whenever its files change
+pub async fn watch(cert: Arc<ServerCert>) {
+    let files = vec![cert.cert_path.clone(), cert.key_path.clone()];
+    watch_files(files, || match cert.reload() {
+        Ok(()) => info!("TLS certificate changed, serving the new one"),
+        // Between the writes of a rotation, certificate and key may not match yet
+        Err(e) => warn!("TLS certificate changed, but loading it failed: {}", e),
+    })
+    .await;
+}
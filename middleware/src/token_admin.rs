@@ -0,0 +1,226 @@
+// External crates
+use log::info;
+use serde::{Deserialize, Serialize};
+use serde_yaml::Value as YamlValue;
+
+// Standard library
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::Path;
+use std::sync::Mutex;
+
+// Internal modules
+use crate::auth::{entry_id, hash_token, random_hex, HASHED_TOKEN_PREFIX};
+use crate::logging::redact_secret;
+use crate::shared::now_ms;
+use crate::state::{load_auth_tokens_from_yaml, secrets_path, AppState, TokenEntry};
+use crate::token_expiry::parse_expiry;
+
+// -----------------------------------------------------------------------------
+// Token Administration
+// -----------------------------------------------------------------------------
+
+// A token as listed by GET /admin/tokens, never the token itself
+#[derive(Debug, Serialize)]
+pub struct TokenInfo {
+    pub id: String,
+    pub groups: Vec<String>,
+    pub hashed: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires: Option<String>,
+    pub expired: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+}
+
+// Optional metadata of a token created via POST /admin/tokens
+#[derive(Debug, Default, Deserialize)]
+pub struct TokenMetadata {
+    #[serde(default)]
+    pub expires: Option<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub owner: Option<String>,
+}
+
+// The tokens of the active secrets, one entry per token with all of its groups. Metadata
+// comes from the first of its entries that has it.
+pub fn list_tokens(groups: &HashMap<String, Vec<TokenEntry>>) -> Vec<TokenInfo> {
+    let now = now_ms() / 1000;
+    let mut tokens: BTreeMap<String, TokenInfo> = BTreeMap::new();
+    for (group, entries) in groups {
+        for entry in entries {
+            let info = tokens.entry(entry_id(&entry.token)).or_insert_with(|| TokenInfo {
+                id: entry_id(&entry.token),
+                groups: Vec::new(),
+                hashed: entry.token.starts_with(HASHED_TOKEN_PREFIX),
+                expires: None,
+                expired: entry.is_expired(now),
+                description: None,
+                owner: None,
+            });
+            info.groups.push(group.clone());
+            if info.expires.is_none() && entry.expires.is_some() {
+                info.expires = entry.expires.clone();
+                info.expired = entry.is_expired(now);
+            }
+            info.description = info.description.take().or_else(|| entry.description.clone());
+            info.owner = info.owner.take().or_else(|| entry.owner.clone());
+        }
+    }
+    let mut tokens: Vec<TokenInfo> = tokens.into_values().collect();
+    for info in &mut tokens {
+        info.groups.sort();
+    }
+    tokens
+}
+
+// Creates and revokes tokens by editing secrets.yaml, then activates the result like /reload
+#[derive(Default)]
+pub struct TokenAdmin {
+    // Edits of the secrets file one at a time
+    write_lock: Mutex<()>,
+}
+
+impl TokenAdmin {
+    // Add a fresh random token to the groups, stored hashed. Returns the token and its id.
+    pub fn create(
+        &self,
+        state: &AppState,
+        groups: &[String],
+        metadata: &TokenMetadata,
+    ) -> Result<(String, String), String> {
+        if groups.is_empty() {
+            return Err("`groups` must name at least one group.".to_string());
+        }
+        if let Some(expires) = &metadata.expires {
+            parse_expiry(expires)?;
+        }
+        let known = &state.auth_tokens.load().groups;
+        if let Some(unknown) = groups.iter().find(|g| !known.contains_key(*g)) {
+            return Err(format!("Unknown group `{}`.", unknown));
+        }
+        let token = random_hex(32).map_err(|e| e.to_string())?;
+        redact_secret(&token);
+        let entry = hash_token(&token).map_err(|e| e.to_string())?;
+        let id = entry_id(&entry);
+        // Tokens with metadata are written as a mapping, others as the entry alone
+        let mut fields = serde_yaml::Mapping::new();
+        for (key, value) in [
+            ("expires", &metadata.expires),
+            ("description", &metadata.description),
+            ("owner", &metadata.owner),
+        ] {
+            if let Some(value) = value {
+                fields.insert(key.into(), value.as_str().into());
+            }
+        }
+        let yaml_entry = if fields.is_empty() {
+            YamlValue::String(entry)
+        } else {
+            let mut mapping = serde_yaml::Mapping::new();
+            mapping.insert("token".into(), entry.into());
+            mapping.extend(fields);
+            YamlValue::Mapping(mapping)
+        };
+
+        self.edit_groups(state, |yaml_groups| {
+            for group_map in yaml_groups.iter_mut() {
+                let Some(group_map) = group_map.as_mapping_mut() else { continue };
+                for (group, entries) in group_map.iter_mut() {
+                    let in_groups = group.as_str().is_some_and(|g| groups.iter().any(|want| want == g));
+                    if !in_groups {
+                        continue;
+                    }
+                    // A group listed without tokens is null
+                    if entries.is_null() {
+                        *entries = YamlValue::Sequence(Vec::new());
+                    }
+                    if let Some(entries) = entries.as_sequence_mut() {
+                        entries.push(yaml_entry.clone());
+                    }
+                }
+            }
+        })?;
+        info!("Created token {} in groups {:?}", id, groups);
+        Ok((token, id))
+    }
+
+    // Remove the token with the id from all groups, false if there is none
+    pub fn revoke(&self, state: &AppState, id: &str) -> Result<bool, String> {
+        let mut removed = false;
+        self.edit_groups(state, |yaml_groups| {
+            for group_map in yaml_groups.iter_mut() {
+                let Some(group_map) = group_map.as_mapping_mut() else { continue };
+                for entries in group_map.values_mut().filter_map(YamlValue::as_sequence_mut) {
+                    let before = entries.len();
+                    entries.retain(|entry| entry_token(entry).is_none_or(|token| entry_id(token) != id));
+                    removed |= entries.len() != before;
+                }
+            }
+        })?;
+        if removed {
+            info!("Revoked token {}", id);
+        }
+        Ok(removed)
+    }
+
+    // Apply an edit to the `groups` list of secrets.yaml, write the file back and activate it.
+    // The file is written back as serde_yaml serializes it, which drops its comments.
+    fn edit_groups(
+        &self,
+        state: &AppState,
+        edit: impl FnOnce(&mut Vec<YamlValue>),
+    ) -> Result<(), String> {
+        let _guard = self.write_lock.lock().unwrap();
+        let contents =
+            fs::read_to_string(secrets_path()).map_err(|e| format!("Failed to read secrets: {}", e))?;
+        let mut secrets: YamlValue =
+            serde_yaml::from_str(&contents).map_err(|e| format!("Failed to parse secrets: {}", e))?;
+        let Some(groups) = secrets.get_mut("groups").and_then(YamlValue::as_sequence_mut) else {
+            return Err("Secrets have no `groups` list.".to_string());
+        };
+        edit(groups);
+
+        // Replace the file in one step so a crash never leaves half of it behind. A file
+        // bind-mounted on its own (as in docker-compose.yml) can't be replaced, renaming onto
+        // it fails as busy or across devices, and it is overwritten in place instead.
+        let serialized = serde_yaml::to_string(&secrets).map_err(|e| e.to_string())?;
+        let tmp_path = format!("{}.tmp", secrets_path().display());
+        let replaced = write_private(Path::new(&tmp_path), &serialized)
+            .and_then(|()| fs::rename(&tmp_path, secrets_path()));
+        if let Err(e) = replaced {
+            let _ = fs::remove_file(&tmp_path);
+            if !matches!(e.kind(), io::ErrorKind::ResourceBusy | io::ErrorKind::CrossesDevices) {
+                return Err(format!("Failed to write secrets: {}", e));
+            }
+            fs::write(secrets_path(), &serialized).map_err(|e| format!("Failed to write secrets: {}", e))?;
+        }
+
+        let config =
+            load_auth_tokens_from_yaml().map_err(|e| format!("Failed to reload secrets: {}", e))?;
+        let version = state.replace_auth_tokens(config);
+        info!("Activated auth tokens version {}", version);
+        Ok(())
+    }
+}
+
+// Write a new file with the permissions of secrets.yaml (0600 if it has none) and flush it to
+// disk, so it is never readable by others and complete once renamed
+fn write_private(path: &Path, contents: &str) -> io::Result<()> {
+    let mode = fs::metadata(secrets_path()).map_or(0o600, |meta| meta.permissions().mode() & 0o777);
+    let _ = fs::remove_file(path);
+    let mut file = OpenOptions::new().write(true).create_new(true).mode(mode).open(path)?;
+    file.write_all(contents.as_bytes())?;
+    file.sync_all()
+}
+
+// The token of a secrets.yaml entry, written alone or as a mapping with metadata
+fn entry_token(entry: &YamlValue) -> Option<&str> {
+    entry.as_str().or_else(|| entry.get("token").and_then(YamlValue::as_str))
+}
@@ -0,0 +1,118 @@
+// External crates
+use log::{info, warn};
+use tokio::time::sleep;
+
+// Standard library
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+
+// Internal modules
+use crate::auth::entry_id;
+use crate::shared::now_ms;
+use crate::state::AppState;
+use crate::usage::DAY_SECS;
+
+// -----------------------------------------------------------------------------
+// Token Expiry
+// -----------------------------------------------------------------------------
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(3600);
+
+// Unix time in seconds of an expiry in secrets.yaml: `YYYY-MM-DD` expires at the end of that
+// day (UTC), `YYYY-MM-DDTHH:MM:SSZ` at that moment
+pub fn parse_expiry(expires: &str) -> Result<u64, String> {
+    let invalid = || format!("Invalid expiry `{}`, expected YYYY-MM-DD or YYYY-MM-DDTHH:MM:SSZ", expires);
+    let (date, time) = match expires.trim().split_once('T') {
+        Some((date, time)) => (date, Some(time.strip_suffix('Z').ok_or_else(invalid)?)),
+        None => (expires.trim(), None),
+    };
+    let numbers = |s: &str, sep: char| -> Option<Vec<u64>> {
+        s.split(sep).map(|part| part.parse().ok()).collect()
+    };
+    let [year, month, day] = numbers(date, '-').ok_or_else(invalid)?[..] else {
+        return Err(invalid());
+    };
+    if !(1970..=9999).contains(&year) || !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return Err(invalid());
+    }
+    let days = days_from_civil(year, month, day);
+    let Some(time) = time else {
+        return Ok((days + 1) * DAY_SECS);
+    };
+    let [hours, minutes, seconds] = numbers(time, ':').ok_or_else(invalid)?[..] else {
+        return Err(invalid());
+    };
+    if hours > 23 || minutes > 59 || seconds > 59 {
+        return Err(invalid());
+    }
+    Ok(days * DAY_SECS + hours * 3600 + minutes * 60 + seconds)
+}
+
+// Days since the epoch of a date in the civil calendar
+fn days_from_civil(year: u64, month: u64, day: u64) -> u64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+// Warn about tokens that expire within VLLM_COMPOSER_TOKEN_EXPIRY_WARN_DAYS (default 7) or
+// already expired, once when they enter that window, and keep the expiry gauges current
+pub async fn run(state: Arc<AppState>) {
+    let warn_days = std::env::var("VLLM_COMPOSER_TOKEN_EXPIRY_WARN_DAYS")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(7u64);
+    let mut warned: HashSet<(String, bool)> = HashSet::new();
+    loop {
+        let now = now_ms() / 1000;
+        let mut current = HashSet::new();
+        let (mut expiring, mut expired) = (0, 0);
+        for (group, entries) in &state.auth_tokens.load().groups {
+            for entry in entries {
+                let Some(expires_at) = entry.expires_at else { continue };
+                if expires_at > now + warn_days * DAY_SECS {
+                    continue;
+                }
+                let is_expired = entry.is_expired(now);
+                if is_expired {
+                    expired += 1;
+                } else {
+                    expiring += 1;
+                }
+                let key = (entry_id(&entry.token), is_expired);
+                if !warned.contains(&key) {
+                    let details: Vec<String> = [
+                        entry.owner.as_ref().map(|owner| format!("owner {}", owner)),
+                        entry.description.clone(),
+                    ]
+                    .into_iter()
+                    .flatten()
+                    .collect();
+                    let details = if details.is_empty() {
+                        String::new()
+                    } else {
+                        format!(" ({})", details.join(", "))
+                    };
+                    let expires = entry.expires.as_deref().unwrap_or_default();
+                    if is_expired {
+                        info!(
+                            "Token {} of group {}{} expired {}, remove it from secrets",
+                            key.0, group, details, expires
+                        );
+                    } else {
+                        warn!("Token {} of group {}{} expires {}", key.0, group, details, expires);
+                    }
+                }
+                current.insert(key);
+            }
+        }
+        state.metrics.tokens_by_expiry.with_label_values(&["expiring"]).set(expiring);
+        state.metrics.tokens_by_expiry.with_label_values(&["expired"]).set(expired);
+        warned = current;
+        sleep(CHECK_INTERVAL).await;
+    }
+}
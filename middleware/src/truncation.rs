@@ -0,0 +1,170 @@
+// External crates
+use log::{info, warn};
+use serde_json::Value;
+
+// Standard library
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Mutex;
+
+// Internal modules
+use crate::metrics::Metrics;
+
+// -----------------------------------------------------------------------------
+// Truncation Detection
+// -----------------------------------------------------------------------------
+
+// Responses an endpoint and its peers need before their rates are compared
+const MIN_SAMPLES: usize = 50;
+
+// finish_reason of every choice of a non-streaming response
+pub fn finish_reasons_from_body(body: &str) -> Vec<String> {
+    let Ok(json) = serde_json::from_str::<Value>(body) else {
+        return Vec::new();
+    };
+    json.get("choices")
+        .and_then(Value::as_array)
+        .map(|choices| {
+            choices
+                .iter()
+                .filter_map(|choice| choice.get("finish_reason").and_then(Value::as_str))
+                .map(String::from)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+// Spots endpoints that cut responses off at the length limit far more often than the other
+// endpoints of the same model, as a replica with a too small max_model_len does
+pub struct TruncationDetector {
+    // Responses per endpoint and model the rates are taken over
+    window: usize,
+    // How far an endpoint's length rate may exceed its peers' before it is flagged
+    margin: f64,
+    // (model, endpoint URL) -> whether each recent response hit the length limit
+    recent: Mutex<HashMap<(String, String), VecDeque<bool>>>,
+    flagged: Mutex<HashSet<(String, String)>>,
+}
+
+impl TruncationDetector {
+    // VLLM_COMPOSER_TRUNCATION_WINDOW (default 200) and VLLM_COMPOSER_TRUNCATION_MARGIN
+    // (default 0.2)
+    pub fn from_env() -> Self {
+        TruncationDetector {
+            window: std::env::var("VLLM_COMPOSER_TRUNCATION_WINDOW")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(200usize)
+                .max(MIN_SAMPLES),
+            margin: std::env::var("VLLM_COMPOSER_TRUNCATION_MARGIN")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(0.2),
+            recent: Mutex::new(HashMap::new()),
+            flagged: Mutex::new(HashSet::new()),
+        }
+    }
+
+    // Count the finish reasons of a response and re-check the endpoint
+    pub fn record(&self, metrics: &Metrics, model: &str, endpoint: &str, reasons: &[String]) {
+        if reasons.is_empty() {
+            return;
+        }
+        for reason in reasons {
+            metrics
+                .finish_reasons
+                .with_label_values(&[model, endpoint, reason])
+                .inc();
+        }
+
+        let key = (model.to_string(), endpoint.to_string());
+        let anomalous = {
+            let mut recent = self.recent.lock().unwrap();
+            let samples = recent.entry(key.clone()).or_default();
+            for reason in reasons {
+                samples.push_back(reason == "length");
+            }
+            while samples.len() > self.window {
+                samples.pop_front();
+            }
+            self.is_anomalous(&recent, &key)
+        };
+
+        let changed = {
+            let mut flagged = self.flagged.lock().unwrap();
+            if anomalous { flagged.insert(key) } else { flagged.remove(&key) }
+        };
+        if changed {
+            metrics
+                .truncation_anomaly
+                .with_label_values(&[model, endpoint])
+                .set(anomalous as i64);
+            if anomalous {
+                warn!(
+                    "Endpoint {} cuts {} off at the length limit unusually often, check its max_model_len",
+                    endpoint, model
+                );
+            } else {
+                info!("Length truncations of {} on {} are back to normal", model, endpoint);
+            }
+        }
+    }
+
+    // Whether the endpoint's length rate exceeds that of the model's other endpoints by the margin
+    fn is_anomalous(
+        &self,
+        recent: &HashMap<(String, String), VecDeque<bool>>,
+        key: &(String, String),
+    ) -> bool {
+        let Some(own) = recent.get(key).filter(|samples| samples.len() >= MIN_SAMPLES) else {
+            return false;
+        };
+        let (mut peer_lengths, mut peer_samples) = (0, 0);
+        for ((model, endpoint), samples) in recent {
+            if *model == key.0 && *endpoint != key.1 && samples.len() >= MIN_SAMPLES {
+                peer_lengths += samples.iter().filter(|length| **length).count();
+                peer_samples += samples.len();
+            }
+        }
+        if peer_samples == 0 {
+            return false;
+        }
+        let rate = |lengths: usize, samples: usize| lengths as f64 / samples as f64;
+        let own_rate = rate(own.iter().filter(|length| **length).count(), own.len());
+        own_rate >= rate(peer_lengths, peer_samples) + self.margin
+    }
+
+    // Forget the responses of endpoints that are gone and clear their gauges, returns how
+    // many (model, endpoint) pairs went
+    pub fn retain(&self, metrics: &Metrics, active: &HashSet<String>) -> usize {
+        let mut recent = self.recent.lock().unwrap();
+        let before = recent.len();
+        recent.retain(|(_, url), _| active.contains(url));
+        let mut flagged = self.flagged.lock().unwrap();
+        flagged.retain(|(model, url)| {
+            let keep = active.contains(url);
+            if !keep {
+                let _ = metrics.truncation_anomaly.remove_label_values(&[model, url]);
+            }
+            keep
+        });
+        before - recent.len()
+    }
+
+    pub fn entries(&self) -> usize {
+        self.recent.lock().unwrap().len()
+    }
+
+    // Models whose responses the endpoint truncates unusually often
+    pub fn flagged_models(&self, endpoint: &str) -> Vec<String> {
+        let mut models: Vec<String> = self
+            .flagged
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, url)| url == endpoint)
+            .map(|(model, _)| model.clone())
+            .collect();
+        models.sort();
+        models
+    }
+}
@@ -0,0 +1,296 @@
+// External crates
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+// Standard library
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+
+// Internal modules
+use crate::shared::now_ms;
+
+// -----------------------------------------------------------------------------
+// Token Usage
+// -----------------------------------------------------------------------------
+
+// The OpenAI `usage` object; embeddings only report prompt tokens
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default)]
+pub struct Usage {
+    #[serde(default)]
+    pub prompt_tokens: u64,
+    #[serde(default)]
+    pub completion_tokens: u64,
+    #[serde(default)]
+    pub total_tokens: u64,
+}
+
+impl Usage {
+    pub fn from_value(value: &Value) -> Option<Usage> {
+        if !value.is_object() {
+            return None;
+        }
+        serde_json::from_value(value.clone()).ok()
+    }
+
+    // Some responses leave out the total
+    pub fn total(&self) -> u64 {
+        if self.total_tokens > 0 {
+            self.total_tokens
+        } else {
+            self.prompt_tokens + self.completion_tokens
+        }
+    }
+}
+
+// Usage reported in a non-streaming response body
+pub fn usage_from_body(body: &str) -> Option<Usage> {
+    let json: Value = serde_json::from_str(body).ok()?;
+    json.get("usage").and_then(Usage::from_value)
+}
+
+// Whether the client itself asked for usage in the stream
+pub fn stream_usage_requested(body: &Value) -> bool {
+    body.pointer("/stream_options/include_usage").and_then(Value::as_bool) == Some(true)
+}
+
+// Ask the backend to send usage in a final chunk of the stream
+pub fn request_stream_usage(body: &mut Value) {
+    if let Some(obj) = body.as_object_mut() {
+        let options = obj.entry("stream_options").or_insert_with(|| json!({}));
+        if let Some(options) = options.as_object_mut() {
+            options.insert("include_usage".to_string(), Value::Bool(true));
+        }
+    }
+}
+
+// Picks the usage and finish reasons out of a server-sent event stream as its chunks pass by.
+// Only complete lines are passed on; usage-only events are dropped if the client didn't ask
+// for them.
+pub struct SseUsageScanner {
+    // Start of a line split across chunks
+    pending: Vec<u8>,
+    usage: Option<Usage>,
+    finish_reasons: Vec<String>,
+    strip_usage_events: bool,
+    // An event was neither a JSON object nor [DONE]
+    malformed: bool,
+}
+
+impl SseUsageScanner {
+    pub fn new(strip_usage_events: bool) -> Self {
+        SseUsageScanner {
+            pending: Vec::new(),
+            usage: None,
+            finish_reasons: Vec::new(),
+            strip_usage_events,
+            malformed: false,
+        }
+    }
+
+    // The complete lines of the stream so far to pass on
+    pub fn feed(&mut self, chunk: &[u8]) -> Vec<u8> {
+        self.pending.extend_from_slice(chunk);
+        let mut out = Vec::with_capacity(self.pending.len());
+        while let Some(pos) = self.pending.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.pending.drain(..=pos).collect();
+            if self.scan_line(&line) {
+                out.extend_from_slice(&line);
+            }
+        }
+        out
+    }
+
+    // The unterminated rest of the stream and the last usage seen; the backend
+    // reports usage in the final chunk
+    pub fn finish(&mut self) -> (Vec<u8>, Option<Usage>) {
+        let rest = std::mem::take(&mut self.pending);
+        let rest = if self.scan_line(&rest) { rest } else { Vec::new() };
+        (rest, self.usage)
+    }
+
+    // Finish reasons of the choices seen so far
+    pub fn finish_reasons(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.finish_reasons)
+    }
+
+    // Whether the endpoint sent an event that isn't one
+    pub fn malformed(&self) -> bool {
+        self.malformed
+    }
+
+    // Returns whether to pass the line on
+    fn scan_line(&mut self, line: &[u8]) -> bool {
+        let Ok(line) = std::str::from_utf8(line) else {
+            return true;
+        };
+        let Some(data) = line.trim().strip_prefix("data:") else {
+            return true;
+        };
+        let payload = data.trim();
+        if !(payload.is_empty() || payload == "[DONE]" || payload.starts_with('{') && payload.ends_with('}')) {
+            self.malformed = true;
+        }
+        // Cheap checks first, most chunks carry no usage and no finish reason
+        let has_usage = data.contains("\"usage\"");
+        let has_finish_reason = data.contains("\"finish_reason\"")
+            && !data.contains("\"finish_reason\":null")
+            && !data.contains("\"finish_reason\": null");
+        if !has_usage && !has_finish_reason {
+            return true;
+        }
+        let Ok(event) = serde_json::from_str::<Value>(data.trim()) else {
+            return true;
+        };
+        if let Some(choices) = event.get("choices").and_then(Value::as_array) {
+            self.finish_reasons.extend(
+                choices
+                    .iter()
+                    .filter_map(|choice| choice.get("finish_reason").and_then(Value::as_str))
+                    .map(String::from),
+            );
+        }
+        let Some(usage) = event.get("usage").and_then(Usage::from_value) else {
+            return true;
+        };
+        self.usage = Some(usage);
+        let usage_only = event
+            .get("choices")
+            .and_then(Value::as_array)
+            .is_none_or(Vec::is_empty);
+        !(self.strip_usage_events && usage_only)
+    }
+}
+
+// Accumulated usage of one token, group or model
+#[derive(Debug, Serialize, Clone, Copy, Default)]
+pub struct UsageTotals {
+    pub requests: u64,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub total_tokens: u64,
+    // By the current prices of the models, left out if none of them has one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cost: Option<f64>,
+}
+
+impl UsageTotals {
+    pub fn add(&mut self, usage: &Usage) {
+        self.requests += 1;
+        self.prompt_tokens += usage.prompt_tokens;
+        self.completion_tokens += usage.completion_tokens;
+        self.total_tokens += usage.total();
+    }
+
+    pub fn merge(&mut self, other: &UsageTotals) {
+        self.requests += other.requests;
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+        self.total_tokens += other.total_tokens;
+        if let Some(cost) = other.cost {
+            self.cost = Some(self.cost.unwrap_or_default() + cost);
+        }
+    }
+}
+
+pub const HOUR_SECS: u64 = 3600;
+pub const DAY_SECS: u64 = 24 * HOUR_SECS;
+// Hourly buckets older than this are dropped
+const RETENTION_SECS: u64 = 35 * DAY_SECS;
+
+// Granularity of /usage rollups, aligned to UTC
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Window {
+    Hour,
+    Day,
+}
+
+impl Window {
+    pub fn parse(s: &str) -> Option<Window> {
+        match s {
+            "hour" => Some(Window::Hour),
+            "day" => Some(Window::Day),
+            _ => None,
+        }
+    }
+
+    pub fn secs(self) -> u64 {
+        match self {
+            Window::Hour => HOUR_SECS,
+            Window::Day => DAY_SECS,
+        }
+    }
+}
+
+// Usage of a group with a model within one window
+#[derive(Debug, Serialize, Clone)]
+pub struct UsageBucket {
+    // Unix time in seconds the window starts at
+    pub start: u64,
+    pub group: String,
+    pub model: String,
+    #[serde(flatten)]
+    pub totals: UsageTotals,
+}
+
+#[derive(Default)]
+struct LedgerState {
+    // Keyed by token id (see AuthInfo::token_id), never the token itself
+    by_token: HashMap<String, UsageTotals>,
+    // Hour start -> (group, model) -> usage
+    hourly: BTreeMap<u64, HashMap<(String, String), UsageTotals>>,
+}
+
+// Token usage of all responses since startup
+#[derive(Default)]
+pub struct UsageLedger {
+    state: Mutex<LedgerState>,
+}
+
+impl UsageLedger {
+    // Book a response; it counts for every group of the caller
+    pub fn record(&self, token_id: &str, groups: &[String], model: &str, usage: &Usage) {
+        let now = now_ms() / 1000;
+        let hour = now - now % HOUR_SECS;
+        let mut state = self.state.lock().unwrap();
+        state.by_token.entry(token_id.to_string()).or_default().add(usage);
+        if !state.hourly.contains_key(&hour) {
+            state.hourly.retain(|start, _| start + RETENTION_SECS > now);
+        }
+        let buckets = state.hourly.entry(hour).or_default();
+        for group in groups {
+            buckets
+                .entry((group.clone(), model.to_string()))
+                .or_default()
+                .add(usage);
+        }
+    }
+
+    // Usage between `since` and `until` (unix seconds) per window, group and model,
+    // optionally limited to some groups
+    pub fn rollup(&self, window: Window, since: u64, until: u64, groups: Option<&[String]>) -> Vec<UsageBucket> {
+        let mut rolled: BTreeMap<(u64, String, String), UsageTotals> = BTreeMap::new();
+        let state = self.state.lock().unwrap();
+        let first_hour = since - since % HOUR_SECS;
+        for (hour, buckets) in state.hourly.range(first_hour..until) {
+            let start = hour - hour % window.secs();
+            for ((group, model), totals) in buckets {
+                if groups.is_some_and(|groups| !groups.contains(group)) {
+                    continue;
+                }
+                rolled
+                    .entry((start, group.clone(), model.clone()))
+                    .or_default()
+                    .merge(totals);
+            }
+        }
+        rolled
+            .into_iter()
+            .map(|((start, group, model), totals)| UsageBucket { start, group, model, totals })
+            .collect()
+    }
+
+    // Usage per token id since startup
+    pub fn token_totals(&self) -> HashMap<String, UsageTotals> {
+        self.state.lock().unwrap().by_token.clone()
+    }
+}
@@ -0,0 +1,232 @@
+// External crates
+use log::{info, warn};
+use rusqlite::{params, Connection};
+
+// Standard library
+use std::collections::{BTreeMap, HashMap};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Mutex;
+
+// Internal modules
+use crate::usage::{Usage, UsageBucket, UsageTotals, Window};
+
+// -----------------------------------------------------------------------------
+// Usage Database
+// -----------------------------------------------------------------------------
+
+const SCHEMA: &str = r"
+CREATE TABLE IF NOT EXISTS requests (
+    id INTEGER PRIMARY KEY,
+    ts INTEGER NOT NULL,
+    token_hash TEXT NOT NULL,
+    groups TEXT NOT NULL,
+    model TEXT NOT NULL,
+    endpoint TEXT NOT NULL,
+    latency_ms INTEGER NOT NULL,
+    prompt_tokens INTEGER NOT NULL,
+    completion_tokens INTEGER NOT NULL,
+    total_tokens INTEGER NOT NULL,
+    cached INTEGER NOT NULL DEFAULT 0
+);
+CREATE INDEX IF NOT EXISTS requests_ts ON requests (ts);
+";
+
+// Key -> tokens used this month and today
+pub type Consumed = HashMap<String, (u64, u64)>;
+
+// One proxied request with known usage
+pub struct RequestRow {
+    // Unix seconds
+    pub ts: u64,
+    pub token_hash: String,
+    pub groups: Vec<String>,
+    pub model: String,
+    // Empty for answers from the response cache
+    pub endpoint: String,
+    pub latency_ms: u64,
+    pub usage: Usage,
+    // Answered from the response cache rather than by an endpoint
+    pub cached: bool,
+}
+
+// Optional SQLite store of per-request rows, so /usage survives restarts. Rows are written
+// by a background thread; queries use their own connection.
+pub struct UsageDb {
+    rows: Mutex<Sender<RequestRow>>,
+    reader: Mutex<Connection>,
+}
+
+impl UsageDb {
+    // Open (and create) the database at VLLM_COMPOSER_USAGE_DB, None if unset
+    pub fn from_env() -> Option<Self> {
+        let path = std::env::var("VLLM_COMPOSER_USAGE_DB").ok().filter(|p| !p.is_empty())?;
+        match UsageDb::open(&path) {
+            Ok(db) => {
+                info!("Persist usage to SQLite database {}", path);
+                Some(db)
+            }
+            Err(e) => {
+                warn!("Failed to open usage database {}, keeping usage in memory: {}", path, e);
+                None
+            }
+        }
+    }
+
+    fn open(path: &str) -> rusqlite::Result<Self> {
+        let writer = Connection::open(path)?;
+        // Readers don't block the writer
+        writer.pragma_update(None, "journal_mode", "WAL")?;
+        writer.execute_batch(SCHEMA)?;
+        // Databases written before answers from the cache were booked lack the column
+        let has_cached: bool = writer.query_row(
+            "SELECT COUNT(*) > 0 FROM pragma_table_info('requests') WHERE name = 'cached'",
+            [],
+            |row| row.get(0),
+        )?;
+        if !has_cached {
+            writer.execute_batch("ALTER TABLE requests ADD COLUMN cached INTEGER NOT NULL DEFAULT 0")?;
+        }
+        let reader = Connection::open(path)?;
+
+        let (sender, receiver) = mpsc::channel();
+        std::thread::spawn(move || write_rows(writer, receiver));
+        Ok(UsageDb {
+            rows: Mutex::new(sender),
+            reader: Mutex::new(reader),
+        })
+    }
+
+    pub fn insert(&self, row: RequestRow) {
+        if self.rows.lock().unwrap().send(row).is_err() {
+            warn!("Usage database writer stopped, request not persisted");
+        }
+    }
+
+    // Same as UsageLedger::rollup, over all persisted requests
+    pub fn rollup(
+        &self,
+        window: Window,
+        since: u64,
+        until: u64,
+        groups: Option<&[String]>,
+    ) -> rusqlite::Result<Vec<UsageBucket>> {
+        let reader = self.reader.lock().unwrap();
+        let mut statement = reader.prepare_cached(
+            "SELECT (r.ts / ?1) * ?1, g.value, r.model, COUNT(*), SUM(r.prompt_tokens),
+                    SUM(r.completion_tokens), SUM(r.total_tokens)
+             FROM requests r, json_each(r.groups) g
+             WHERE r.ts >= ?2 AND r.ts < ?3
+             GROUP BY 1, 2, 3",
+        )?;
+        let rows = statement.query_map(params![window.secs(), since, until], |row| {
+            Ok(UsageBucket {
+                start: row.get(0)?,
+                group: row.get(1)?,
+                model: row.get(2)?,
+                totals: UsageTotals {
+                    requests: row.get(3)?,
+                    prompt_tokens: row.get(4)?,
+                    completion_tokens: row.get(5)?,
+                    total_tokens: row.get(6)?,
+                    cost: None,
+                },
+            })
+        })?;
+        let mut buckets = BTreeMap::new();
+        for bucket in rows {
+            let bucket = bucket?;
+            if groups.is_some_and(|groups| !groups.contains(&bucket.group)) {
+                continue;
+            }
+            buckets.insert((bucket.start, bucket.group.clone(), bucket.model.clone()), bucket);
+        }
+        Ok(buckets.into_values().collect())
+    }
+
+    // Total tokens per group and per token hash since the starts of this month and of this
+    // day (unix seconds), as (this month, today)
+    pub fn consumption_since(&self, month: u64, day: u64) -> rusqlite::Result<(Consumed, Consumed)> {
+        let reader = self.reader.lock().unwrap();
+        let consumed = |sql: &str| -> rusqlite::Result<Consumed> {
+            let mut statement = reader.prepare_cached(sql)?;
+            let rows = statement.query_map(params![month, day], |row| {
+                Ok((row.get::<_, String>(0)?, (row.get(1)?, row.get(2)?)))
+            })?;
+            rows.collect()
+        };
+        let groups = consumed(
+            "SELECT g.value, SUM(r.total_tokens),
+                    SUM(CASE WHEN r.ts >= ?2 THEN r.total_tokens ELSE 0 END)
+             FROM requests r, json_each(r.groups) g
+             WHERE r.ts >= ?1
+             GROUP BY 1",
+        )?;
+        let tokens = consumed(
+            "SELECT token_hash, SUM(total_tokens), SUM(CASE WHEN ts >= ?2 THEN total_tokens ELSE 0 END)
+             FROM requests
+             WHERE ts >= ?1
+             GROUP BY 1",
+        )?;
+        Ok((groups, tokens))
+    }
+
+    // Usage per token hash over all persisted requests
+    pub fn token_totals(&self) -> rusqlite::Result<HashMap<String, UsageTotals>> {
+        let reader = self.reader.lock().unwrap();
+        let mut statement = reader.prepare_cached(
+            "SELECT token_hash, COUNT(*), SUM(prompt_tokens), SUM(completion_tokens), SUM(total_tokens)
+             FROM requests GROUP BY token_hash",
+        )?;
+        let rows = statement.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                UsageTotals {
+                    requests: row.get(1)?,
+                    prompt_tokens: row.get(2)?,
+                    completion_tokens: row.get(3)?,
+                    total_tokens: row.get(4)?,
+                    cost: None,
+                },
+            ))
+        })?;
+        rows.collect()
+    }
+}
+
+// Insert rows as they come, batching whatever queued up into one transaction
+fn write_rows(mut conn: Connection, rows: Receiver<RequestRow>) {
+    while let Ok(first) = rows.recv() {
+        let batch: Vec<RequestRow> = std::iter::once(first).chain(rows.try_iter()).collect();
+        if let Err(e) = insert_batch(&mut conn, &batch) {
+            warn!("Failed to persist {} usage rows: {}", batch.len(), e);
+        }
+    }
+}
+
+fn insert_batch(conn: &mut Connection, batch: &[RequestRow]) -> rusqlite::Result<()> {
+    let tx = conn.transaction()?;
+    {
+        let mut statement = tx.prepare_cached(
+            "INSERT INTO requests (ts, token_hash, groups, model, endpoint, latency_ms,
+                                   prompt_tokens, completion_tokens, total_tokens, cached)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+        )?;
+        for row in batch {
+            let mut totals = UsageTotals::default();
+            totals.add(&row.usage);
+            statement.execute(params![
+                row.ts,
+                row.token_hash,
+                serde_json::to_string(&row.groups).unwrap(),
+                row.model,
+                row.endpoint,
+                row.latency_ms,
+                totals.prompt_tokens,
+                totals.completion_tokens,
+                totals.total_tokens,
+                row.cached,
+            ])?;
+        }
+    }
+    tx.commit()
+}
@@ -0,0 +1,349 @@
+// External crates
+use serde::de::DeserializeOwned;
+use yaml_rust2::parser::{Event, MarkedEventReceiver, Parser};
+use yaml_rust2::scanner::Marker;
+
+// Standard library
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+
+// Internal modules
+use crate::auth::{validate_token_entry, METRICS_GROUP, OPERATOR_GROUPS};
+use crate::discovery::registration::Registrations;
+use crate::state::{validate_endpoint, Endpoint, Secrets, TokenEntry};
+
+// -----------------------------------------------------------------------------
+// Config Validation
+// -----------------------------------------------------------------------------
+
+// A problem in one of the config files
+#[derive(Debug)]
+pub struct Issue {
+    pub file: String,
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{}:{}: {}", self.file, line, self.message),
+            None => write!(f, "{}: {}", self.file, self.message),
+        }
+    }
+}
+
+// What a valid pair of files configures
+#[derive(Debug, Default)]
+pub struct ConfigSummary {
+    pub endpoints: usize,
+    pub groups: usize,
+    pub tokens: usize,
+}
+
+// Check endpoints.yaml and secrets.yaml on their own and against each other, without
+// touching a running composer
+pub fn validate_config(endpoints_path: &Path, secrets_path: &Path) -> Result<ConfigSummary, Vec<Issue>> {
+    let mut issues = Vec::new();
+    let endpoints = ConfigFile::read(endpoints_path, &mut issues)
+        .and_then(|file| file.parse::<Vec<Endpoint>>(&mut issues).map(|parsed| (file, parsed)));
+    let secrets = ConfigFile::read(secrets_path, &mut issues)
+        .and_then(|file| file.parse::<Secrets>(&mut issues).map(|parsed| (file, parsed)));
+
+    let mut summary = ConfigSummary::default();
+    // Group -> where endpoints list it
+    let mut served_groups: HashMap<String, Vec<Option<usize>>> = HashMap::new();
+    if let Some((file, endpoints)) = &endpoints {
+        summary.endpoints = endpoints.len();
+        let mut seen: HashMap<(String, String), Option<usize>> = HashMap::new();
+        for (i, endpoint) in endpoints.iter().enumerate() {
+            let index = i.to_string();
+            let line = file.line(&[&index]);
+            let mut normalized = endpoint.clone();
+            if let Err(e) = validate_endpoint(&mut normalized) {
+                issues.push(file.issue(line, e));
+                continue;
+            }
+            if let Some(first) = seen.insert((normalized.task.clone(), normalized.url.clone()), line) {
+                let first = first.map_or(String::new(), |first| format!(" (first at line {})", first));
+                let message = format!("Duplicate {} endpoint {}{}", normalized.task, normalized.url, first);
+                issues.push(file.issue(line, message));
+            }
+            if endpoint.groups.is_empty() {
+                let message = format!("Endpoint {} has no groups, no token reaches it", normalized.url);
+                issues.push(file.issue(line, message));
+            }
+            for (j, group) in endpoint.groups.iter().enumerate() {
+                let line = file.line(&[&index, "groups", &j.to_string()]);
+                served_groups.entry(group.clone()).or_default().push(line);
+            }
+        }
+    }
+
+    // Group -> where secrets define it
+    let mut defined_groups: HashMap<String, Option<usize>> = HashMap::new();
+    if let Some((file, secrets)) = &secrets {
+        for (i, group_map) in secrets.groups.iter().enumerate() {
+            let index = i.to_string();
+            for (group, specs) in group_map {
+                let line = file.line(&["groups", &index, group]);
+                if let Some(first) = defined_groups.insert(group.clone(), line) {
+                    let first = first.map_or(String::new(), |first| format!(" (first at line {})", first));
+                    let message =
+                        format!("Group {} is defined twice, only the last one counts{}", group, first);
+                    issues.push(file.issue(line, message));
+                }
+                if specs.is_empty() {
+                    issues.push(file.issue(line, format!("Group {} has no tokens", group)));
+                }
+                for (j, spec) in specs.iter().enumerate() {
+                    let line = file.line(&["groups", &index, group, &j.to_string()]);
+                    // Token values stay out of the messages, they are secrets
+                    let checked = TokenEntry::try_from(spec.clone())
+                        .and_then(|entry| validate_token_entry(&entry.token));
+                    if let Err(e) = checked {
+                        let message = format!("Group {}, token {}: {}", group, j + 1, e);
+                        issues.push(file.issue(line, message));
+                    }
+                    summary.tokens += 1;
+                }
+            }
+        }
+        summary.groups = defined_groups.len();
+
+        // Settings of groups no token is in
+        let limits: [(&str, Vec<&String>); 4] = [
+            ("rate_limits", secrets.rate_limits.keys().collect()),
+            ("concurrency_limits", secrets.concurrency_limits.keys().collect()),
+            ("priorities", secrets.priorities.keys().collect()),
+            ("budgets", secrets.budgets.keys().collect()),
+        ];
+        for (setting, groups) in limits {
+            for group in groups {
+                if !defined_groups.contains_key(group) {
+                    let line = file.line(&[setting, group]);
+                    let message = format!("{} names group {}, which has no tokens", setting, group);
+                    issues.push(file.issue(line, message));
+                }
+            }
+        }
+        for (i, policy) in secrets.policies.iter().enumerate() {
+            for (j, group) in policy.groups.iter().enumerate() {
+                if !defined_groups.contains_key(group) {
+                    let line = file.line(&["policies", &i.to_string(), "groups", &j.to_string()]);
+                    let message = format!("Policy names group {}, which has no tokens", group);
+                    issues.push(file.issue(line, message));
+                }
+            }
+        }
+    }
+
+    // Both files parsed: groups must meet on both sides
+    if let (Some((endpoints_file, _)), Some((secrets_file, _))) = (&endpoints, &secrets) {
+        let mut unreachable: Vec<_> = served_groups
+            .iter()
+            .filter(|(group, _)| !defined_groups.contains_key(*group))
+            .collect();
+        unreachable.sort();
+        for (group, lines) in unreachable {
+            for line in lines {
+                let message = format!(
+                    "Group {} has no tokens in {}, no one reaches the endpoint through it",
+                    group, secrets_file.name
+                );
+                issues.push(endpoints_file.issue(*line, message));
+            }
+        }
+        let register_group = Registrations::from_env().group;
+        let mut unused: Vec<_> = defined_groups
+            .iter()
+            .filter(|(group, _)| {
+                !served_groups.contains_key(*group)
+                    // Groups with a meaning of their own, their tokens need no endpoint
+                    && !OPERATOR_GROUPS.contains(&group.as_str())
+                    && group.as_str() != METRICS_GROUP
+                    && **group != register_group
+            })
+            .collect();
+        unused.sort();
+        for (group, line) in unused {
+            let message = format!(
+                "Tokens of group {} reach nothing, no endpoint in {} lists the group",
+                group, endpoints_file.name
+            );
+            issues.push(secrets_file.issue(*line, message));
+        }
+    }
+
+    if issues.is_empty() {
+        return Ok(summary);
+    }
+    issues.sort_by_key(|issue| (issue.file != endpoints_path.display().to_string(), issue.line));
+    Err(issues)
+}
+
+// A config file with the lines of its nodes
+struct ConfigFile {
+    name: String,
+    contents: String,
+    lines: YamlLines,
+}
+
+impl ConfigFile {
+    fn read(path: &Path, issues: &mut Vec<Issue>) -> Option<Self> {
+        let name = path.display().to_string();
+        match std::fs::read_to_string(path) {
+            Ok(contents) => Some(ConfigFile { lines: YamlLines::of(&contents), name, contents }),
+            Err(e) => {
+                issues.push(Issue { file: name, line: None, message: format!("Failed to read: {}", e) });
+                None
+            }
+        }
+    }
+
+    // Deserialize like the composer does, reporting unknown fields it would ignore
+    fn parse<T: DeserializeOwned>(&self, issues: &mut Vec<Issue>) -> Option<T> {
+        let mut unknown = Vec::new();
+        let parsed = serde_ignored::deserialize(serde_yaml::Deserializer::from_str(&self.contents), |path| {
+            unknown.push(segments(&path))
+        });
+        match parsed {
+            Ok(parsed) => {
+                for path in unknown {
+                    let keys: Vec<&str> = path.iter().map(String::as_str).collect();
+                    let field = path.last().map_or("", String::as_str);
+                    issues.push(self.issue(self.line(&keys), format!("Unknown field {}", field)));
+                }
+                Some(parsed)
+            }
+            Err(e) => {
+                let line = e.location().map(|location| location.line());
+                // The location is given as the line already
+                let message = e.to_string();
+                let message = message.split(" at line ").next().unwrap_or(&message);
+                issues.push(self.issue(line, message.to_string()));
+                None
+            }
+        }
+    }
+
+    fn line(&self, path: &[&str]) -> Option<usize> {
+        self.lines.0.get(&path.iter().map(|key| key.to_string()).collect::<Vec<_>>()).copied()
+    }
+
+    fn issue(&self, line: Option<usize>, message: String) -> Issue {
+        Issue { file: self.name.clone(), line, message }
+    }
+}
+
+// Path of an ignored field as map keys and sequence indices
+fn segments(path: &serde_ignored::Path) -> Vec<String> {
+    match path {
+        serde_ignored::Path::Root => Vec::new(),
+        serde_ignored::Path::Seq { parent, index } => {
+            let mut segments = segments(parent);
+            segments.push(index.to_string());
+            segments
+        }
+        serde_ignored::Path::Map { parent, key } => {
+            let mut segments = segments(parent);
+            segments.push(key.clone());
+            segments
+        }
+        serde_ignored::Path::Some { parent }
+        | serde_ignored::Path::NewtypeStruct { parent }
+        | serde_ignored::Path::NewtypeVariant { parent } => segments(parent),
+    }
+}
+
+// Line of every node by its path of map keys and sequence indices. serde_yaml keeps no
+// positions, so the file is parsed a second time for them.
+#[derive(Default)]
+struct YamlLines(HashMap<Vec<String>, usize>);
+
+enum Frame {
+    Sequence { next: usize },
+    // Key of the value being read, None while a key is expected
+    Mapping { key: Option<String> },
+}
+
+#[derive(Default)]
+struct LineRecorder {
+    lines: HashMap<Vec<String>, usize>,
+    frames: Vec<Frame>,
+    path: Vec<String>,
+}
+
+impl YamlLines {
+    fn of(contents: &str) -> Self {
+        let mut recorder = LineRecorder::default();
+        // Syntax errors are reported by serde_yaml, lines found so far are kept
+        let _ = Parser::new_from_str(contents).load(&mut recorder, false);
+        YamlLines(recorder.lines)
+    }
+}
+
+impl LineRecorder {
+    // A node starts: a key of the enclosing mapping or a value at the current path
+    fn start(&mut self, key: Option<&str>, line: usize) -> bool {
+        match self.frames.last_mut() {
+            Some(Frame::Mapping { key: pending @ None }) => {
+                let key = key.unwrap_or_default().to_string();
+                // A value is reported at its key, block values start on the next line
+                let mut path = self.path.clone();
+                path.push(key.clone());
+                self.lines.entry(path).or_insert(line);
+                *pending = Some(key);
+                return false;
+            }
+            Some(Frame::Mapping { key: Some(key) }) => self.path.push(key.clone()),
+            Some(Frame::Sequence { next }) => self.path.push(next.to_string()),
+            None => {}
+        }
+        self.lines.entry(self.path.clone()).or_insert(line);
+        true
+    }
+
+    // A value ended, the enclosing collection moves on
+    fn end(&mut self) {
+        match self.frames.last_mut() {
+            Some(Frame::Mapping { key }) => *key = None,
+            Some(Frame::Sequence { next }) => *next += 1,
+            None => return,
+        }
+        self.path.pop();
+    }
+}
+
+impl MarkedEventReceiver for LineRecorder {
+    fn on_event(&mut self, event: Event, mark: Marker) {
+        match event {
+            Event::Scalar(..) | Event::Alias(_) => {
+                let key = match &event {
+                    Event::Scalar(value, ..) => Some(value.as_str()),
+                    _ => None,
+                };
+                // Scalars end where they start, unless they are a key
+                let is_value = self.start(key, mark.line());
+                if is_value {
+                    self.end();
+                }
+            }
+            Event::SequenceStart(..) | Event::MappingStart(..) => {
+                // Collections as keys are not used by the config files
+                self.start(None, mark.line());
+                self.frames.push(if matches!(event, Event::SequenceStart(..)) {
+                    Frame::Sequence { next: 0 }
+                } else {
+                    Frame::Mapping { key: None }
+                });
+            }
+            Event::SequenceEnd | Event::MappingEnd => {
+                self.frames.pop();
+                self.end();
+            }
+            _ => {}
+        }
+    }
+}
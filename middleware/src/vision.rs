@@ -0,0 +1,73 @@
+// External crates
+use serde_json::Value;
+
+// Standard library
+use std::collections::HashMap;
+
+// Internal modules
+use crate::state::{Endpoint, EndpointCapabilities};
+
+// -----------------------------------------------------------------------------
+// Vision
+// -----------------------------------------------------------------------------
+
+// Content part types carrying images in chat messages
+const IMAGE_PART_TYPES: [&str; 3] = ["image_url", "input_image", "image"];
+
+// Fragments of model names of common vision-language model families
+const VISION_MODEL_HINTS: [&str; 14] = [
+    "-vl", "_vl", "vl-", "vision", "llava", "pixtral", "idefics", "internvl", "minicpm-v",
+    "molmo", "paligemma", "gemma-3", "llama-4", "mllama",
+];
+
+// Whether a chat request contains image content parts
+pub fn request_has_images(body: &Value) -> bool {
+    body.get("messages")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(|message| message.get("content").and_then(Value::as_array))
+        .flatten()
+        .any(|part| {
+            part.get("type")
+                .and_then(Value::as_str)
+                .is_some_and(|t| IMAGE_PART_TYPES.contains(&t))
+        })
+}
+
+// Guess vision support from served model metadata. Only ever positive, a text-only
+// name is no proof.
+pub fn detect_vision(models: &[Value]) -> Option<bool> {
+    models
+        .iter()
+        .flat_map(|model| ["id", "root"].map(|field| model.get(field).and_then(Value::as_str)))
+        .flatten()
+        .map(str::to_lowercase)
+        .any(|name| VISION_MODEL_HINTS.iter().any(|hint| name.contains(hint)))
+        .then_some(true)
+}
+
+// Whether an endpoint accepts images: the config override wins over the probe
+pub fn supports_vision(
+    endpoint: &Endpoint,
+    capabilities: &HashMap<String, EndpointCapabilities>,
+) -> Option<bool> {
+    endpoint.vision.or_else(|| {
+        capabilities
+            .get(&endpoint.url)
+            .and_then(|caps| caps.vision)
+    })
+}
+
+// Keep endpoints known to accept images. Endpoints with unknown support are only used
+// when no endpoint is known to accept them.
+pub fn filter_vision_capable(
+    endpoints: Vec<Endpoint>,
+    capabilities: &HashMap<String, EndpointCapabilities>,
+) -> Vec<Endpoint> {
+    let (supported, unknown): (Vec<Endpoint>, Vec<Endpoint>) = endpoints
+        .into_iter()
+        .filter(|ep| supports_vision(ep, capabilities) != Some(false))
+        .partition(|ep| supports_vision(ep, capabilities) == Some(true));
+    if supported.is_empty() { unknown } else { supported }
+}
@@ -0,0 +1,145 @@
+// Shared setup of the integration tests: composer state, mock vLLM backends and the app
+#![allow(dead_code)]
+
+// External crates
+use actix_web::dev::{Service, ServiceResponse};
+use actix_web::{test, App};
+use serde_json::json;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+// Standard library
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+// Internal modules
+use vllm_middleware::auth::AuthMiddleware;
+use vllm_middleware::monitoring::spawn_monitor;
+use vllm_middleware::shared::SharedStore;
+use vllm_middleware::state::{AppState, AuthConfig, Endpoint, TokenEntry};
+
+pub const ADMIN_TOKEN: &str = "admin-token";
+pub const STUDENT_TOKEN: &str = "student-token";
+
+// Groups "admin" and "student" with one token each
+pub fn auth_config() -> AuthConfig {
+    let entry = |token: &str| TokenEntry { token: token.to_string(), ..Default::default() };
+    AuthConfig {
+        groups: HashMap::from([
+            ("admin".to_string(), vec![entry(ADMIN_TOKEN)]),
+            ("student".to_string(), vec![entry(STUDENT_TOKEN)]),
+        ]),
+        ..Default::default()
+    }
+}
+
+// A generate endpoint open to both groups
+pub fn endpoint(url: &str) -> Endpoint {
+    Endpoint {
+        url: url.to_string(),
+        access_token: "backend-token".to_string(),
+        groups: vec!["admin".to_string(), "student".to_string()],
+        task: "generate".to_string(),
+        ..Default::default()
+    }
+}
+
+// Composer state over `endpoints`, with their monitors running
+pub fn state_with(endpoints: Vec<Endpoint>) -> Arc<AppState> {
+    monitored_state(endpoints, auth_config(), |_| {})
+}
+
+// Composer state over `endpoints` with `auth`, set up by `configure` before the monitors start
+pub fn monitored_state(
+    endpoints: Vec<Endpoint>,
+    auth: AuthConfig,
+    configure: impl FnOnce(&mut AppState),
+) -> Arc<AppState> {
+    let mut state = AppState::new(endpoints, auth, SharedStore::disabled());
+    configure(&mut state);
+    let state = Arc::new(state);
+    for endpoint in state.all_endpoints() {
+        spawn_monitor(endpoint, Arc::clone(&state));
+    }
+    state
+}
+
+// The composer's routes behind its auth middleware
+pub async fn app(
+    state: &Arc<AppState>,
+) -> impl Service<actix_http::Request, Response = ServiceResponse, Error = actix_web::Error> {
+    let state = Arc::clone(state);
+    test::init_service(
+        App::new()
+            .wrap(AuthMiddleware)
+            .configure(|cfg| vllm_middleware::configure(cfg, state, false)),
+    )
+    .await
+}
+
+// A vLLM server that is healthy and serves `models`
+pub async fn backend(models: &[&str]) -> MockServer {
+    let server = MockServer::start().await;
+    mount_health(&server, 200).await;
+    mount_models(&server, models).await;
+    server
+}
+
+pub async fn mount_models(server: &MockServer, models: &[&str]) {
+    let data: Vec<_> = models.iter().map(|id| json!({"id": id, "object": "model"})).collect();
+    Mock::given(method("GET"))
+        .and(path("/v1/models"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({"object": "list", "data": data})))
+        .mount(server)
+        .await;
+}
+
+pub async fn mount_health(server: &MockServer, status: u16) {
+    Mock::given(method("GET"))
+        .and(path("/health"))
+        .respond_with(ResponseTemplate::new(status))
+        .mount(server)
+        .await;
+}
+
+// A non-streamed chat completion answering `content`
+pub fn completion(model: &str, content: &str) -> serde_json::Value {
+    json!({
+        "id": "cmpl-1",
+        "object": "chat.completion",
+        "model": model,
+        "choices": [{"index": 0, "message": {"role": "assistant", "content": content}, "finish_reason": "stop"}],
+        "usage": {"prompt_tokens": 5, "completion_tokens": 2, "total_tokens": 7},
+    })
+}
+
+pub fn chat_request(model: &str, stream: bool) -> serde_json::Value {
+    json!({
+        "model": model,
+        "messages": [{"role": "user", "content": "Hello"}],
+        "stream": stream,
+    })
+}
+
+// Poll until `done` holds, panics after 15 seconds
+pub async fn wait_for(what: &str, done: impl Fn() -> bool) {
+    for _ in 0..150 {
+        if done() {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("timed out waiting for {}", what);
+}
+
+// Whether the task serves the model on the endpoint
+pub fn serves(state: &AppState, model: &str, url: &str) -> bool {
+    state
+        .generate
+        .model_to_endpoints
+        .lock()
+        .unwrap()
+        .get(model)
+        .is_some_and(|urls| urls.iter().any(|u| u == url))
+}
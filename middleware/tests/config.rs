@@ -0,0 +1,109 @@
+// Validation of endpoints.yaml and secrets.yaml as done by `vllm_middleware validate-config`, and
+// secrets.yaml as the token admin API writes it back
+
+// Standard library
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+
+// Internal modules
+use vllm_middleware::shared::SharedStore;
+use vllm_middleware::state::{load_auth_tokens_from_yaml, set_config_paths, AppState};
+use vllm_middleware::token_admin::TokenMetadata;
+use vllm_middleware::validate::validate_config;
+
+// Write `contents` to a file of its own in the temp directory
+fn config_file(name: &str, contents: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("vllm_composer_{}_{}", std::process::id(), name));
+    std::fs::write(&path, contents).unwrap();
+    path
+}
+
+#[test]
+fn templates_are_valid() {
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let summary = validate_config(
+        &root.join("endpoints.yaml.template"),
+        &root.join("secrets.yaml.template"),
+    )
+    .unwrap();
+    assert!(summary.endpoints > 0);
+    assert!(summary.tokens > 0);
+}
+
+#[test]
+fn reports_errors_with_their_lines() {
+    let endpoints = config_file(
+        "endpoints.yaml",
+        r#"- url: "http://a:8000"
+  access_token: x
+  groups: [student, ghosts]
+- url: "http://A:8000/"
+  access_token: y
+  groups: [student]
+  timeout:
+    request_secs: 5
+- url: "http://b:8000"
+  access_token: z
+  task: chat
+  groups: [student]
+"#,
+    );
+    let secrets = config_file(
+        "secrets.yaml",
+        r#"groups:
+  - student:
+      - studentstudent12
+      - token: abcdefghijklmnop
+        expires: "tomorrow"
+  - lonely:
+      - lonelylonelylone
+rate_limits:
+  nobody: 5
+"#,
+    );
+    let issues = validate_config(&endpoints, &secrets).unwrap_err();
+    let reported: Vec<(bool, Option<usize>, &str)> = issues
+        .iter()
+        .map(|issue| (issue.file == endpoints.display().to_string(), issue.line, issue.message.as_str()))
+        .collect();
+    let has = |endpoints_file: bool, line: usize, start: &str| {
+        reported
+            .iter()
+            .any(|(file, at, message)| *file == endpoints_file && *at == Some(line) && message.starts_with(start))
+    };
+    assert!(has(true, 3, "Group ghosts has no tokens"), "{:?}", reported);
+    assert!(has(true, 4, "Duplicate generate endpoint http://a:8000"), "{:?}", reported);
+    assert!(has(true, 7, "Unknown field timeout"), "{:?}", reported);
+    assert!(has(true, 9, "Invalid task value: chat"), "{:?}", reported);
+    assert!(has(false, 4, "Group student, token 2: Invalid expiry"), "{:?}", reported);
+    assert!(has(false, 6, "Tokens of group lonely reach nothing"), "{:?}", reported);
+    assert!(has(false, 9, "rate_limits names group nobody"), "{:?}", reported);
+    assert_eq!(issues.len(), 7);
+
+    let _ = std::fs::remove_file(endpoints);
+    let _ = std::fs::remove_file(secrets);
+}
+
+#[actix_web::test]
+async fn token_admin_rewrites_secrets_privately_without_comments() {
+    let endpoints = config_file("admin_endpoints.yaml", "[]\n");
+    let secrets = config_file(
+        "admin_secrets.yaml",
+        "# Tokens of the course\ngroups:\n  - student:\n      - studentstudent12\n",
+    );
+    std::fs::set_permissions(&secrets, std::fs::Permissions::from_mode(0o600)).unwrap();
+    set_config_paths(endpoints.clone(), secrets.clone());
+    let state = AppState::new(Vec::new(), load_auth_tokens_from_yaml().unwrap(), SharedStore::disabled());
+
+    let (token, _) =
+        state.token_admin.create(&state, &["student".to_string()], &TokenMetadata::default()).unwrap();
+    let contents = std::fs::read_to_string(&secrets).unwrap();
+    assert!(!contents.contains("# Tokens of the course"), "{}", contents);
+    assert!(contents.contains("studentstudent12"), "{}", contents);
+    assert!(!contents.contains(&token), "{}", contents);
+    assert_eq!(std::fs::metadata(&secrets).unwrap().permissions().mode() & 0o777, 0o600);
+    assert_eq!(state.auth_tokens.load().groups["student"].len(), 2);
+
+    let _ = std::fs::remove_file(endpoints);
+    let _ = std::fs::remove_file(secrets);
+}
@@ -0,0 +1,465 @@
+// Health transitions and reload semantics of endpoints against mock vLLM backends
+mod common;
+
+// External crates
+use actix_web::http::header;
+use actix_web::test;
+use serde_json::{json, Value};
+use wiremock::matchers::{method, path, query_param, query_param_is_missing};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+// Standard library
+use std::sync::Arc;
+use std::time::Duration;
+
+// Internal modules
+use common::*;
+use vllm_middleware::discovery::consul::{self, ConsulConfig};
+use vllm_middleware::discovery::docker::{self, DockerConfig, DockerHost};
+use vllm_middleware::discovery::registration::Registration;
+use vllm_middleware::discovery::kubernetes::{self, KubernetesConfig, ResourceKind};
+use vllm_middleware::health::HealthState;
+use vllm_middleware::monitoring::spawn_monitor;
+use vllm_middleware::state::{AppState, Endpoint, EndpointKind};
+
+fn health_state(state: &AppState, url: &str) -> Option<HealthState> {
+    state.generate.health_status.lock().unwrap().get(url).map(|health| health.state)
+}
+
+// -----------------------------------------------------------------------------
+// Health transitions
+// -----------------------------------------------------------------------------
+
+#[actix_web::test]
+async fn failing_endpoints_leave_routing_and_come_back() {
+    let server = backend(&["m1"]).await;
+    let url = server.uri();
+    let state = state_with(vec![endpoint(&url)]);
+    wait_for("the model", || serves(&state, "m1", &url)).await;
+    let app = app(&state).await;
+
+    // Failed probes in a row take the endpoint out, its models go with it
+    server.reset().await;
+    mount_health(&server, 500).await;
+    wait_for("the endpoint to fail", || {
+        health_state(&state, &url) == Some(HealthState::Unhealthy)
+    })
+    .await;
+    assert!(!serves(&state, "m1", &url));
+    let req = test::TestRequest::get()
+        .uri("/health-status")
+        .insert_header((header::AUTHORIZATION, format!("Bearer {}", ADMIN_TOKEN)))
+        .to_request();
+    let status: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(status[&url]["state"], "unhealthy");
+    assert_eq!(status[&url]["routable"], false);
+    // With what operators need to tell why
+    assert_eq!(status[&url]["last_error"], "/health: status 500");
+    assert!(status[&url]["consecutive_failures"].as_u64().unwrap() >= 3);
+    let last_check_ms = status[&url]["last_check_ms"].as_u64().unwrap();
+    assert!(last_check_ms >= status[&url]["last_error_ms"].as_u64().unwrap());
+    assert!(status[&url]["check_latency_ms"].is_u64());
+    assert_eq!(status[&url]["models"], json!([]));
+
+    // Its models are unavailable meanwhile rather than unknown
+    let chat = |model: &str| {
+        test::TestRequest::post()
+            .uri("/v1/chat/completions")
+            .insert_header((header::AUTHORIZATION, format!("Bearer {}", STUDENT_TOKEN)))
+            .set_json(chat_request(model, false))
+            .to_request()
+    };
+    let resp = test::call_service(&app, chat("m1")).await;
+    assert_eq!(resp.status(), 503);
+    assert!(resp.headers().contains_key("retry-after"));
+    assert_eq!(test::call_service(&app, chat("m2")).await.status(), 404);
+
+    // Passing probes bring it back, with its models
+    server.reset().await;
+    mount_health(&server, 200).await;
+    mount_models(&server, &["m1"]).await;
+    wait_for("the endpoint to recover", || {
+        health_state(&state, &url) == Some(HealthState::Healthy) && serves(&state, "m1", &url)
+    })
+    .await;
+    let req = test::TestRequest::get()
+        .uri("/health-status")
+        .insert_header((header::AUTHORIZATION, format!("Bearer {}", ADMIN_TOKEN)))
+        .to_request();
+    let status: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(status[&url]["consecutive_failures"], 0);
+    assert_eq!(status[&url]["last_error"], "/health: status 500");
+    assert_eq!(status[&url]["models"], json!(["m1"]));
+}
+
+#[actix_web::test]
+async fn rejected_access_tokens_are_auth_errors() {
+    let server = wiremock::MockServer::start().await;
+    mount_health(&server, 401).await;
+    let url = server.uri();
+    let state = state_with(vec![endpoint(&url)]);
+    wait_for("the auth error", || health_state(&state, &url) == Some(HealthState::AuthError)).await;
+    assert!(!serves(&state, "m1", &url));
+}
+
+#[actix_web::test]
+async fn ready_once_every_task_has_a_healthy_endpoint() {
+    let generator = backend(&["m1"]).await;
+    let embedder = wiremock::MockServer::start().await;
+    mount_health(&embedder, 500).await;
+    let mut embed = endpoint(&embedder.uri());
+    embed.task = "embed".to_string();
+    let state = state_with(vec![endpoint(&generator.uri()), embed]);
+    wait_for("the model", || serves(&state, "m1", &generator.uri())).await;
+    wait_for("the embedder to fail", || {
+        state.embed.health_status.lock().unwrap().get(&embedder.uri()).is_some()
+    })
+    .await;
+    let app = app(&state).await;
+
+    // No token needed, orchestrators probe it
+    let ready = || test::TestRequest::get().uri("/ready").to_request();
+    let resp = test::call_service(&app, ready()).await;
+    assert_eq!(resp.status(), 503);
+    let body: Value = test::read_body_json(resp).await;
+    assert_eq!(body["ready"], false);
+    assert_eq!(body["reasons"], json!(["No healthy embed endpoint."]));
+    assert_eq!(body["tasks"]["generate"]["models"], json!(["m1"]));
+    assert_eq!(body["tasks"]["embed"]["healthy_endpoints"], 0);
+
+    embedder.reset().await;
+    mount_health(&embedder, 200).await;
+    wait_for("the embedder", || {
+        state.embed.health_status.lock().unwrap().get(&embedder.uri()).is_some_and(|h| h.is_routable())
+    })
+    .await;
+    let resp = test::call_service(&app, ready()).await;
+    assert_eq!(resp.status(), 200);
+    let body: Value = test::read_body_json(resp).await;
+    assert_eq!(body["ready"], true);
+    assert_eq!(body["tasks"]["embed"]["healthy_endpoints"], 1);
+}
+
+// -----------------------------------------------------------------------------
+// Reload
+// -----------------------------------------------------------------------------
+
+#[actix_web::test]
+async fn reload_keeps_unchanged_endpoints() {
+    let kept = backend(&["m1"]).await;
+    let updated = backend(&["m1"]).await;
+    let removed = backend(&["m2"]).await;
+    let added = backend(&["m3"]).await;
+    let state = state_with(vec![
+        endpoint(&kept.uri()),
+        endpoint(&updated.uri()),
+        endpoint(&removed.uri()),
+    ]);
+    wait_for("the models", || {
+        serves(&state, "m1", &kept.uri())
+            && serves(&state, "m1", &updated.uri())
+            && serves(&state, "m2", &removed.uri())
+    })
+    .await;
+
+    let mut reweighted = endpoint(&updated.uri());
+    reweighted.weight = Some(2);
+    let changes = state.replace_endpoints(
+        "generate",
+        vec![endpoint(&kept.uri()), reweighted, endpoint(&added.uri())],
+    );
+    assert_eq!(changes.unchanged, 1);
+    assert_eq!(changes.changed.len(), 1);
+    assert_eq!(changes.added.len(), 1);
+    assert_eq!(changes.removed, vec![removed.uri()]);
+
+    // The unchanged endpoint is served throughout, the others start over
+    assert!(serves(&state, "m1", &kept.uri()));
+    assert_eq!(health_state(&state, &kept.uri()), Some(HealthState::Healthy));
+    assert!(!serves(&state, "m1", &updated.uri()));
+    assert!(!serves(&state, "m2", &removed.uri()));
+    assert_eq!(health_state(&state, &removed.uri()), None);
+
+    for endpoint in changes.added.into_iter().chain(changes.changed) {
+        spawn_monitor(endpoint, Arc::clone(&state));
+    }
+    wait_for("the new and updated endpoints", || {
+        serves(&state, "m1", &updated.uri()) && serves(&state, "m3", &added.uri())
+    })
+    .await;
+    assert!(!state.generate.model_to_endpoints.lock().unwrap().contains_key("m2"));
+}
+
+// -----------------------------------------------------------------------------
+// Discovery
+// -----------------------------------------------------------------------------
+
+#[actix_web::test]
+async fn kubernetes_discovery_follows_labeled_pods() {
+    let server = backend(&["m1"]).await;
+    let url = server.uri();
+    let port = server.address().port();
+    let api = MockServer::start().await;
+    let pods = "/api/v1/namespaces/ns/pods";
+    let pod = json!({
+        "metadata": {"name": "vllm-0", "labels": {"vllm-composer/groups": "admin.student"}},
+        "spec": {"containers": [{"ports": [{"containerPort": port}]}]},
+        "status": {
+            "phase": "Running",
+            "podIP": "127.0.0.1",
+            "conditions": [{"type": "Ready", "status": "True"}],
+        },
+    });
+    // The pod is listed once, the watch then reports it deleted
+    Mock::given(method("GET"))
+        .and(path(pods))
+        .and(query_param_is_missing("watch"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "metadata": {"resourceVersion": "1"},
+            "items": [pod],
+        })))
+        .up_to_n_times(1)
+        .with_priority(1)
+        .mount(&api)
+        .await;
+    Mock::given(method("GET"))
+        .and(path(pods))
+        .and(query_param_is_missing("watch"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "metadata": {"resourceVersion": "2"},
+            "items": [],
+        })))
+        .mount(&api)
+        .await;
+    Mock::given(method("GET"))
+        .and(path(pods))
+        .and(query_param("watch", "true"))
+        .and(query_param("resourceVersion", "1"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_string("{\"type\":\"DELETED\",\"object\":{}}\n")
+                .set_delay(Duration::from_millis(500)),
+        )
+        .mount(&api)
+        .await;
+
+    let state = state_with(Vec::new());
+    let config = KubernetesConfig {
+        api_url: api.uri(),
+        namespace: "ns".to_string(),
+        label_selector: "app=vllm".to_string(),
+        resource: ResourceKind::Pods,
+        resync_interval: Duration::from_secs(60),
+        watch: true,
+        default_access_token: "backend-token".to_string(),
+    };
+    tokio::spawn(kubernetes::run(config, Arc::clone(&state)));
+
+    wait_for("the discovered pod", || serves(&state, "m1", &url)).await;
+    let discovered = state.endpoint("generate", &url).unwrap();
+    assert_eq!(discovered.groups, ["admin", "student"]);
+    // Picked up from the watch long before the next resync
+    wait_for("the pod to go", || state.endpoint("generate", &url).is_none()).await;
+}
+
+#[actix_web::test]
+async fn docker_discovery_follows_labeled_containers() {
+    let server = backend(&["m1"]).await;
+    let url = server.uri();
+    let daemon = MockServer::start().await;
+    let container = json!({
+        "Names": ["/vllm"],
+        "Labels": {
+            "vllm-composer.groups": "admin,student",
+            "vllm-composer.port": server.address().port().to_string(),
+        },
+        "NetworkSettings": {"Networks": {"bridge": {"IPAddress": "127.0.0.1"}}},
+    });
+    // The container runs for one list, then it is stopped
+    Mock::given(method("GET"))
+        .and(path("/containers/json"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!([container])))
+        .up_to_n_times(1)
+        .with_priority(1)
+        .mount(&daemon)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/containers/json"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!([])))
+        .mount(&daemon)
+        .await;
+
+    let state = state_with(Vec::new());
+    let config = DockerConfig {
+        host: DockerHost::Http(daemon.uri()),
+        network: None,
+        poll_interval: Duration::from_millis(500),
+        default_access_token: "backend-token".to_string(),
+    };
+    tokio::spawn(docker::run(config, Arc::clone(&state)));
+
+    wait_for("the container", || state.endpoint("generate", &url).is_some()).await;
+    assert_eq!(state.endpoint("generate", &url).unwrap().groups, ["admin", "student"]);
+    wait_for("the container to go", || state.endpoint("generate", &url).is_none()).await;
+}
+
+#[actix_web::test]
+async fn consul_discovery_reads_tags_and_holds_the_catalog_query() {
+    let server = backend(&["m1"]).await;
+    let url = server.uri();
+    let consul = MockServer::start().await;
+    let instance = json!({
+        "Node": {"Address": "10.0.0.1"},
+        "Service": {
+            "ID": "vllm-1",
+            "Address": "127.0.0.1",
+            "Port": server.address().port(),
+            "Tags": ["groups=admin,student", "task=generate", "gpu"],
+            // Meta wins over tags
+            "Meta": {"access-token": "meta-token"},
+        },
+    });
+    Mock::given(method("GET"))
+        .and(path("/v1/health/service/vllm"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header("X-Consul-Index", "5")
+                .set_body_json(json!([instance]))
+                .set_delay(Duration::from_millis(100)),
+        )
+        .mount(&consul)
+        .await;
+
+    let state = state_with(Vec::new());
+    let config = ConsulConfig {
+        url: consul.uri(),
+        service: "vllm".to_string(),
+        token: None,
+        watch: true,
+        default_access_token: "backend-token".to_string(),
+    };
+    tokio::spawn(consul::run(config, Arc::clone(&state)));
+
+    wait_for("the instance", || state.endpoint("generate", &url).is_some()).await;
+    let discovered = state.endpoint("generate", &url).unwrap();
+    assert_eq!(discovered.groups, ["admin", "student"]);
+    assert_eq!(discovered.access_token, "meta-token");
+    // The next query waits on the index of the last answer
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    let requests = consul.received_requests().await.unwrap();
+    assert!(requests.iter().any(|r| r.url.query().is_some_and(|q| q.contains("index=5"))));
+}
+
+#[actix_web::test]
+async fn registered_endpoints_are_probed_before_they_get_requests() {
+    let server = backend(&["m1"]).await;
+    let url = server.uri();
+    let state = monitored_state(Vec::new(), auth_config(), |state| {
+        state.registrations.allowed_groups = vec!["student".to_string()];
+    });
+    let registration = |groups: Value| -> Registration {
+        let registration = json!({"url": url, "groups": groups, "access_token": "backend-token"});
+        serde_json::from_value(registration).unwrap()
+    };
+
+    // Only the allowed groups can be served
+    let refused = state.registrations.register(&state, &registration(json!(["admin", "student"])));
+    assert!(refused.is_err());
+    assert!(state.endpoint("generate", &url).is_none());
+
+    state.registrations.register(&state, &registration(json!(["student"]))).unwrap();
+    assert!(state.endpoint("generate", &url).is_some());
+    assert_eq!(health_state(&state, &url), Some(HealthState::Unhealthy));
+    wait_for("the registered endpoint", || serves(&state, "m1", &url)).await;
+    assert_eq!(health_state(&state, &url), Some(HealthState::Healthy));
+}
+
+// -----------------------------------------------------------------------------
+// Federation
+// -----------------------------------------------------------------------------
+
+#[actix_web::test]
+async fn composer_endpoints_serve_the_remote_models_of_their_task() {
+    let remote = MockServer::start().await;
+    mount_health(&remote, 200).await;
+    let model = |id: &str, task: &str| json!({"id": id, "object": "model", "capabilities": {"tasks": [task]}});
+    Mock::given(method("GET"))
+        .and(path("/v1/models"))
+        .and(query_param("details", "true"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "object": "list",
+            "data": [model("m1", "generate"), model("e1", "embed"), model("down", "generate")],
+        })))
+        .mount(&remote)
+        .await;
+    // Listed without an endpoint to route to
+    Mock::given(method("GET"))
+        .and(path("/model-to-endpoints"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "m1": ["http://vllm-a:8000"],
+            "e1": ["http://vllm-b:8000"],
+            "down": [],
+        })))
+        .mount(&remote)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/v1/chat/completions"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(completion("m1", "Hi")))
+        .mount(&remote)
+        .await;
+
+    let url = remote.uri();
+    let state = state_with(vec![Endpoint {
+        access_token: "service-token".to_string(),
+        kind: Some(EndpointKind::Composer),
+        ..endpoint(&url)
+    }]);
+    wait_for("the remote model", || serves(&state, "m1", &url)).await;
+    assert!(!serves(&state, "e1", &url));
+    assert!(!serves(&state, "down", &url));
+
+    let app = app(&state).await;
+    let req = test::TestRequest::post()
+        .uri("/v1/chat/completions")
+        .insert_header((header::AUTHORIZATION, format!("Bearer {}", STUDENT_TOKEN)))
+        .set_json(chat_request("m1", false))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 200);
+    // Forwarded with the remote composer's service token, not the caller's
+    let requests = remote.received_requests().await.unwrap();
+    let forwarded = requests.iter().find(|r| r.url.path() == "/v1/chat/completions").unwrap();
+    assert_eq!(forwarded.headers.get("authorization").unwrap(), "Bearer service-token");
+}
+
+// -----------------------------------------------------------------------------
+// Shutdown
+// -----------------------------------------------------------------------------
+
+#[actix_web::test]
+async fn drain_turns_new_requests_away() {
+    let server = backend(&["m1"]).await;
+    let state = state_with(vec![endpoint(&server.uri())]);
+    wait_for("the model", || serves(&state, "m1", &server.uri())).await;
+    let app = app(&state).await;
+
+    let req = test::TestRequest::post()
+        .uri("/drain")
+        .insert_header((header::AUTHORIZATION, format!("Bearer {}", ADMIN_TOKEN)))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 202);
+    assert!(state.shutdown.is_draining());
+
+    // Load balancers see the replica leave, clients are told to retry elsewhere
+    let req = test::TestRequest::get().uri("/health").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 503);
+    let req = test::TestRequest::post()
+        .uri("/v1/chat/completions")
+        .insert_header((header::AUTHORIZATION, format!("Bearer {}", ADMIN_TOKEN)))
+        .set_json(chat_request("m1", false))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 503);
+    assert!(resp.headers().contains_key(header::RETRY_AFTER));
+}
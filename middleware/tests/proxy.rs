@@ -0,0 +1,1306 @@
+// Auth, routing and relaying of the proxy routes against mock vLLM backends
+mod common;
+
+// External crates
+use actix_web::http::header;
+use actix_web::test;
+use reqwest::header::HeaderName;
+use serde_json::{json, Value};
+use wiremock::matchers::{body_partial_json, method, path};
+use wiremock::{Mock, Request, ResponseTemplate};
+
+// Standard library
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+
+// Internal modules
+use common::*;
+use vllm_middleware::admission::AdmissionQueue;
+use vllm_middleware::budgets::{Budget, GroupBudget};
+use vllm_middleware::cache::ResponseCache;
+use vllm_middleware::concurrency::EndpointPools;
+use vllm_middleware::embeddings::EmbeddingSplit;
+use vllm_middleware::forwarding::{Forwarding, UserForwarding};
+use vllm_middleware::moderation::GuardConfig;
+use vllm_middleware::monitoring::MonitorIntervals;
+use vllm_middleware::outliers::OutlierDetector;
+use vllm_middleware::policies::Policy;
+use vllm_middleware::pricing::Price;
+use vllm_middleware::routing::{LatencyConfig, RoutingStrategy, WeightedRoundRobin, ZonePreference};
+use vllm_middleware::slow_start::SlowStart;
+use vllm_middleware::state::{validate_endpoint, AuthStyle, Endpoint};
+
+// -----------------------------------------------------------------------------
+// Auth
+// -----------------------------------------------------------------------------
+
+#[actix_web::test]
+async fn rejects_missing_and_unknown_tokens() {
+    let state = state_with(Vec::new());
+    let app = app(&state).await;
+
+    let req = test::TestRequest::get().uri("/health").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 200);
+
+    let req = test::TestRequest::get().uri("/v1/models").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 401);
+
+    let req = test::TestRequest::get()
+        .uri("/v1/models")
+        .insert_header((header::AUTHORIZATION, "Bearer not-a-token"))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 401);
+    let body: Value = test::read_body_json(resp).await;
+    assert_eq!(body["error"]["code"], "invalid_api_key");
+
+    let req = test::TestRequest::get()
+        .uri("/v1/models")
+        .insert_header((header::AUTHORIZATION, format!("Bearer {}", STUDENT_TOKEN)))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 200);
+}
+
+#[actix_web::test]
+async fn sends_the_auth_style_and_headers_of_endpoints() {
+    let server = backend(&["m1"]).await;
+    Mock::given(method("POST"))
+        .and(path("/v1/chat/completions"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(completion("m1", "Hi")))
+        .mount(&server)
+        .await;
+    let mut azure = endpoint(&server.uri());
+    azure.auth_style = Some(AuthStyle::ApiKey);
+    azure.headers = Some([("X-Scope-OrgID".to_string(), "tenant-1".to_string())].into());
+    let state = state_with(vec![azure]);
+    wait_for("the model", || serves(&state, "m1", &server.uri())).await;
+    let app = app(&state).await;
+
+    let req = test::TestRequest::post()
+        .uri("/v1/chat/completions")
+        .insert_header((header::AUTHORIZATION, format!("Bearer {}", STUDENT_TOKEN)))
+        .set_json(chat_request("m1", false))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 200);
+
+    // Probes and proxied requests alike
+    let requests = server.received_requests().await.unwrap();
+    for request_path in ["/v1/models", "/v1/chat/completions"] {
+        let request = requests.iter().find(|request| request.url.path() == request_path).unwrap();
+        assert_eq!(request.headers.get("api-key").unwrap(), "backend-token");
+        assert_eq!(request.headers.get("x-scope-orgid").unwrap(), "tenant-1");
+        assert!(request.headers.get("authorization").is_none());
+    }
+}
+
+#[actix_web::test]
+async fn forwards_allowed_client_headers_and_the_caller() {
+    let server = backend(&["m1"]).await;
+    Mock::given(method("POST"))
+        .and(path("/v1/chat/completions"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(completion("m1", "Hi")))
+        .mount(&server)
+        .await;
+    let mut auth = auth_config();
+    auth.groups.get_mut("student").unwrap()[0].owner = Some("alice".to_string());
+    let state = monitored_state(vec![endpoint(&server.uri())], auth, |state| {
+        state.forwarding = Forwarding {
+            headers: vec![HeaderName::from_static("x-request-id")],
+            user: UserForwarding::Both,
+        };
+    });
+    wait_for("the model", || serves(&state, "m1", &server.uri())).await;
+    let app = app(&state).await;
+
+    let req = test::TestRequest::post()
+        .uri("/v1/chat/completions")
+        .insert_header((header::AUTHORIZATION, format!("Bearer {}", STUDENT_TOKEN)))
+        .insert_header(("X-Request-Id", "req-42"))
+        .insert_header(("X-Internal", "secret"))
+        .set_json(json!({"model": "m1", "messages": [], "user": "someone-else"}))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 200);
+
+    let requests = server.received_requests().await.unwrap();
+    let request = requests.iter().find(|request| request.url.path() == "/v1/chat/completions").unwrap();
+    assert_eq!(request.headers.get("x-request-id").unwrap(), "req-42");
+    assert_eq!(request.headers.get("x-forwarded-user").unwrap(), "alice");
+    assert!(request.headers.get("x-internal").is_none());
+    // The endpoint's token, not the caller's
+    assert_eq!(request.headers.get("authorization").unwrap(), "Bearer backend-token");
+    assert_eq!(request.body_json::<Value>().unwrap()["user"], "alice");
+}
+
+#[actix_web::test]
+async fn prices_usage_by_model() {
+    let server = backend(&["m1"]).await;
+    Mock::given(method("POST"))
+        .and(path("/v1/chat/completions"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(completion("m1", "Hi")))
+        .mount(&server)
+        .await;
+    let mut auth = auth_config();
+    auth.pricing = BTreeMap::from([("m*".to_string(), Price { prompt: 1.0, completion: 2.0 })]);
+    let state = monitored_state(vec![endpoint(&server.uri())], auth, |_| {});
+    wait_for("the model", || serves(&state, "m1", &server.uri())).await;
+    let app = app(&state).await;
+
+    let req = test::TestRequest::post()
+        .uri("/v1/chat/completions")
+        .insert_header((header::AUTHORIZATION, format!("Bearer {}", STUDENT_TOKEN)))
+        .set_json(chat_request("m1", false))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 200);
+
+    // 5 prompt and 2 completion tokens at 1 and 2 per 1000
+    let req = test::TestRequest::get()
+        .uri("/usage")
+        .insert_header((header::AUTHORIZATION, format!("Bearer {}", STUDENT_TOKEN)))
+        .to_request();
+    let usage: Value = test::call_and_read_body_json(&app, req).await;
+    let cost = usage["by_group"]["student"]["cost"].as_f64().unwrap();
+    assert!((cost - 0.009).abs() < 1e-9);
+    assert_eq!(usage["buckets"][0]["model"], "m1");
+
+    let req = test::TestRequest::get()
+        .uri("/metrics")
+        .insert_header((header::AUTHORIZATION, format!("Bearer {}", ADMIN_TOKEN)))
+        .to_request();
+    let metrics = String::from_utf8(test::call_and_read_body(&app, req).await.to_vec()).unwrap();
+    assert!(metrics.contains(r#"vllm_composer_cost_total{group="student",model="m1"} 0.009"#));
+    assert!(metrics.contains(r#"vllm_composer_tokens_total{group="student",kind="completion",model="m1"} 2"#));
+}
+
+#[actix_web::test]
+async fn operator_routes_need_an_operator_group() {
+    let state = state_with(Vec::new());
+    let app = app(&state).await;
+
+    let req = test::TestRequest::get()
+        .uri("/metrics")
+        .insert_header((header::AUTHORIZATION, format!("Bearer {}", STUDENT_TOKEN)))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 403);
+
+    let req = test::TestRequest::get()
+        .uri("/metrics")
+        .insert_header((header::AUTHORIZATION, format!("Bearer {}", ADMIN_TOKEN)))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 200);
+}
+
+#[actix_web::test]
+async fn hides_endpoints_of_other_groups() {
+    let server = backend(&["admin-model"]).await;
+    let mut admin_only = endpoint(&server.uri());
+    admin_only.groups = vec!["admin".to_string()];
+    let state = state_with(vec![admin_only]);
+    wait_for("the model", || serves(&state, "admin-model", &server.uri())).await;
+    let app = app(&state).await;
+
+    let list = |token: &str| {
+        test::TestRequest::get()
+            .uri("/v1/models")
+            .insert_header((header::AUTHORIZATION, format!("Bearer {}", token)))
+            .to_request()
+    };
+    let models: Value = test::call_and_read_body_json(&app, list(ADMIN_TOKEN)).await;
+    assert_eq!(models["data"].as_array().unwrap().len(), 1);
+    let models: Value = test::call_and_read_body_json(&app, list(STUDENT_TOKEN)).await;
+    assert!(models["data"].as_array().unwrap().is_empty());
+
+    let req = test::TestRequest::post()
+        .uri("/v1/chat/completions")
+        .insert_header((header::AUTHORIZATION, format!("Bearer {}", STUDENT_TOKEN)))
+        .set_json(chat_request("admin-model", false))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 404);
+}
+
+// -----------------------------------------------------------------------------
+// Routing
+// -----------------------------------------------------------------------------
+
+#[actix_web::test]
+async fn routes_rerank_requests_to_rerank_endpoints() {
+    let server = backend(&["reranker"]).await;
+    let ranking = json!({"results": [
+        {"index": 1, "relevance_score": 0.9},
+        {"index": 0, "relevance_score": 0.1},
+    ]});
+    Mock::given(method("POST"))
+        .and(path("/v1/rerank"))
+        .and(body_partial_json(json!({"model": "reranker", "query": "rust"})))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&ranking))
+        .mount(&server)
+        .await;
+    // Rerankers are score endpoints, `rerank` is no task of its own
+    let mut reranker = Endpoint { task: "rerank".to_string(), ..endpoint(&server.uri()) };
+    let error = validate_endpoint(&mut reranker).unwrap_err();
+    assert!(error.contains("task: score"), "{}", error);
+    let reranker = Endpoint { task: "score".to_string(), ..reranker };
+    let state = state_with(vec![reranker]);
+    wait_for("the reranker", || {
+        state.score.model_to_endpoints.lock().unwrap().contains_key("reranker")
+    })
+    .await;
+    let app = app(&state).await;
+
+    let req = test::TestRequest::post()
+        .uri("/v1/rerank")
+        .insert_header((header::AUTHORIZATION, format!("Bearer {}", STUDENT_TOKEN)))
+        .set_json(json!({"model": "reranker", "query": "rust", "documents": ["python", "cargo"]}))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 200);
+    let body: Value = test::read_body_json(resp).await;
+    assert_eq!(body, ranking);
+}
+
+#[actix_web::test]
+async fn passes_guided_fields_of_other_tasks_on() {
+    let server = backend(&["embedder"]).await;
+    let embedding = json!({"data": [{"index": 0, "embedding": [0.1, 0.2]}]});
+    Mock::given(method("POST"))
+        .and(path("/v1/embeddings"))
+        .and(body_partial_json(json!({"guided_regex": "[a-z]+"})))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&embedding))
+        .mount(&server)
+        .await;
+    let state = state_with(vec![Endpoint { task: "embed".to_string(), ..endpoint(&server.uri()) }]);
+    wait_for("the embedder", || {
+        state.embed.model_to_endpoints.lock().unwrap().contains_key("embedder")
+    })
+    .await;
+    let app = app(&state).await;
+
+    // Two guided fields would be turned away on a chat request
+    let req = test::TestRequest::post()
+        .uri("/v1/embeddings")
+        .insert_header((header::AUTHORIZATION, format!("Bearer {}", STUDENT_TOKEN)))
+        .set_json(json!({"model": "embedder", "input": "rust", "guided_json": 1, "guided_regex": "[a-z]+"}))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 200);
+    let body: Value = test::read_body_json(resp).await;
+    assert_eq!(body, embedding);
+}
+
+#[actix_web::test]
+async fn rotates_requests_over_endpoints() {
+    let first = backend(&["m1"]).await;
+    let second = backend(&["m1"]).await;
+    for (server, content) in [(&first, "first"), (&second, "second")] {
+        Mock::given(method("POST"))
+            .and(path("/v1/chat/completions"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(completion("m1", content)))
+            .expect(2)
+            .mount(server)
+            .await;
+    }
+    let state = state_with(vec![endpoint(&first.uri()), endpoint(&second.uri())]);
+    wait_for("both endpoints", || {
+        serves(&state, "m1", &first.uri()) && serves(&state, "m1", &second.uri())
+    })
+    .await;
+    let app = app(&state).await;
+
+    // Listed once in OpenAI's shape, each endpoint's entry on the admin route
+    let list = |uri: &str, token: &str| {
+        test::TestRequest::get()
+            .uri(uri)
+            .insert_header((header::AUTHORIZATION, format!("Bearer {}", token)))
+            .to_request()
+    };
+    let models: Value = test::call_and_read_body_json(&app, list("/v1/models", STUDENT_TOKEN)).await;
+    let data = models["data"].as_array().unwrap();
+    assert_eq!(data.len(), 1);
+    let keys: Vec<&String> = data[0].as_object().unwrap().keys().collect();
+    assert_eq!(keys, ["created", "id", "object", "owned_by"]);
+    assert_eq!(data[0]["id"], "m1");
+    let models: Value = test::call_and_read_body_json(&app, list("/admin/models", ADMIN_TOKEN)).await;
+    assert_eq!(models["data"].as_array().unwrap().len(), 2);
+    let details = list("/v1/models?details=true", STUDENT_TOKEN);
+    let models: Value = test::call_and_read_body_json(&app, details).await;
+    assert_eq!(models["data"][0]["capabilities"]["chat"], true);
+    assert_eq!(models["data"][0]["capabilities"]["embeddings"], false);
+
+    let mut answers = Vec::new();
+    for _ in 0..4 {
+        let req = test::TestRequest::post()
+            .uri("/v1/chat/completions")
+            .insert_header((header::AUTHORIZATION, format!("Bearer {}", STUDENT_TOKEN)))
+            .set_json(chat_request("m1", false))
+            .to_request();
+        let body: Value = test::call_and_read_body_json(&app, req).await;
+        answers.push(body["choices"][0]["message"]["content"].as_str().unwrap().to_string());
+    }
+    answers.sort();
+    assert_eq!(answers, ["first", "first", "second", "second"]);
+}
+
+#[actix_web::test]
+async fn round_robin_moves_on_only_once_a_pick_is_booked() {
+    let rotation = WeightedRoundRobin::default();
+    let candidates = vec![endpoint("http://first"), endpoint("http://second")];
+    let pick = || rotation.pick("generate", "m1", candidates.clone(), |_| 1.0).unwrap();
+
+    // A request routed again while it waits for room gets the same endpoint
+    let (first, credits) = pick();
+    assert_eq!(pick().0.url, first.url);
+    rotation.book(&credits);
+    let (second, _) = pick();
+    assert_ne!(second.url, first.url);
+}
+
+#[actix_web::test]
+async fn prefers_faster_endpoints_by_latency() {
+    let slow = backend(&["m1"]).await;
+    let fast = backend(&["m1"]).await;
+    for (server, delay) in [(&slow, 300), (&fast, 0)] {
+        Mock::given(method("POST"))
+            .and(path("/v1/chat/completions"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(completion("m1", "Hi"))
+                    .set_delay(Duration::from_millis(delay)),
+            )
+            .mount(server)
+            .await;
+    }
+    let state = monitored_state(
+        vec![endpoint(&slow.uri()), endpoint(&fast.uri())],
+        auth_config(),
+        |state| {
+            state.routing_strategy = RoutingStrategy::Latency(LatencyConfig::new(0));
+        },
+    );
+    wait_for("both endpoints", || {
+        serves(&state, "m1", &slow.uri()) && serves(&state, "m1", &fast.uri())
+    })
+    .await;
+    let app = app(&state).await;
+
+    // Each endpoint is timed once, then the fast one takes the requests
+    for _ in 0..6 {
+        let req = test::TestRequest::post()
+            .uri("/v1/chat/completions")
+            .insert_header((header::AUTHORIZATION, format!("Bearer {}", STUDENT_TOKEN)))
+            .set_json(chat_request("m1", false))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 200);
+    }
+    let chats = |requests: Vec<Request>| {
+        requests.iter().filter(|request| request.url.path() == "/v1/chat/completions").count()
+    };
+    assert_eq!(chats(slow.received_requests().await.unwrap()), 1);
+    assert_eq!(chats(fast.received_requests().await.unwrap()), 5);
+}
+
+#[actix_web::test]
+async fn stays_in_its_zone_until_local_endpoints_are_saturated() {
+    let local = backend(&["m1"]).await;
+    let remote = backend(&["m1"]).await;
+    for server in [&local, &remote] {
+        Mock::given(method("POST"))
+            .and(path("/v1/chat/completions"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(completion("m1", "Hi"))
+                    .set_delay(Duration::from_millis(300)),
+            )
+            .mount(server)
+            .await;
+    }
+    let in_zone = |server: &wiremock::MockServer, zone: &str| Endpoint {
+        zone: Some(zone.to_string()),
+        ..endpoint(&server.uri())
+    };
+    let state = monitored_state(
+        vec![in_zone(&local, "eu-1"), in_zone(&remote, "us-1")],
+        auth_config(),
+        |state| {
+            state.zones = ZonePreference::new(Some("eu-1".to_string()), 1.0);
+        },
+    );
+    wait_for("both endpoints", || {
+        serves(&state, "m1", &local.uri()) && serves(&state, "m1", &remote.uri())
+    })
+    .await;
+    let app = app(&state).await;
+    let chat = || {
+        test::TestRequest::post()
+            .uri("/v1/chat/completions")
+            .insert_header((header::AUTHORIZATION, format!("Bearer {}", STUDENT_TOKEN)))
+            .set_json(chat_request("m1", false))
+            .to_request()
+    };
+    let chats = |requests: Vec<Request>| {
+        requests.iter().filter(|request| request.url.path() == "/v1/chat/completions").count()
+    };
+
+    // One at a time, the local endpoint takes every request
+    for _ in 0..3 {
+        assert_eq!(test::call_service(&app, chat()).await.status(), 200);
+    }
+    assert_eq!(chats(local.received_requests().await.unwrap()), 3);
+    assert_eq!(chats(remote.received_requests().await.unwrap()), 0);
+
+    // With the local endpoint at its spill load, the next request crosses over
+    let (first, second) = futures::join!(test::call_service(&app, chat()), test::call_service(&app, chat()));
+    assert_eq!([first.status(), second.status()], [200, 200]);
+    assert_eq!(chats(local.received_requests().await.unwrap()), 4);
+    assert_eq!(chats(remote.received_requests().await.unwrap()), 1);
+    let metrics = state.metrics.render(&state);
+    assert!(metrics.contains("vllm_composer_zone_spills_total{task=\"generate\",zone=\"us-1\"} 1"));
+}
+
+#[actix_web::test]
+async fn ejects_endpoints_failing_too_often() {
+    let failing = backend(&["m1"]).await;
+    let working = backend(&["m1"]).await;
+    for (server, status) in [(&failing, 500), (&working, 200)] {
+        Mock::given(method("POST"))
+            .and(path("/v1/chat/completions"))
+            .respond_with(ResponseTemplate::new(status).set_body_json(completion("m1", "Hi")))
+            .mount(server)
+            .await;
+    }
+    let state = monitored_state(
+        vec![endpoint(&failing.uri()), endpoint(&working.uri())],
+        auth_config(),
+        |state| {
+            state.outliers = OutlierDetector::new(Some(0.5), 2, Duration::from_secs(60), Duration::from_secs(30));
+        },
+    );
+    wait_for("both endpoints", || {
+        serves(&state, "m1", &failing.uri()) && serves(&state, "m1", &working.uri())
+    })
+    .await;
+    let app = app(&state).await;
+
+    // Its /health passes, but its second failure ejects it
+    for _ in 0..8 {
+        let req = test::TestRequest::post()
+            .uri("/v1/chat/completions")
+            .insert_header((header::AUTHORIZATION, format!("Bearer {}", STUDENT_TOKEN)))
+            .set_json(chat_request("m1", false))
+            .to_request();
+        test::call_service(&app, req).await;
+    }
+    let chats = |requests: Vec<Request>| {
+        requests.iter().filter(|request| request.url.path() == "/v1/chat/completions").count()
+    };
+    assert_eq!(chats(failing.received_requests().await.unwrap()), 2);
+    assert_eq!(chats(working.received_requests().await.unwrap()), 6);
+
+    let req = test::TestRequest::get()
+        .uri("/health-status")
+        .insert_header((header::AUTHORIZATION, format!("Bearer {}", ADMIN_TOKEN)))
+        .to_request();
+    let status: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(status[&failing.uri()]["outlier"]["ejected"], true);
+    assert_eq!(status[&failing.uri()]["outlier"]["ejected_at_error_rate"], 1.0);
+    assert_eq!(status[&working.uri()]["outlier"]["ejected"], false);
+}
+
+#[actix_web::test]
+async fn ramps_up_endpoints_that_just_recovered() {
+    let recovered = backend(&["m1"]).await;
+    let steady = backend(&["m1"]).await;
+    for server in [&recovered, &steady] {
+        Mock::given(method("POST"))
+            .and(path("/v1/chat/completions"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(completion("m1", "Hi")))
+            .mount(server)
+            .await;
+    }
+    let state = monitored_state(
+        vec![endpoint(&recovered.uri()), endpoint(&steady.uri())],
+        auth_config(),
+        |state| {
+            state.slow_start = SlowStart::new(Some(Duration::from_secs(600)));
+        },
+    );
+    wait_for("both endpoints", || {
+        serves(&state, "m1", &recovered.uri()) && serves(&state, "m1", &steady.uri())
+    })
+    .await;
+    state.slow_start.begin(&recovered.uri());
+    let app = app(&state).await;
+
+    // About a tenth of the other's share at first
+    for _ in 0..22 {
+        let req = test::TestRequest::post()
+            .uri("/v1/chat/completions")
+            .insert_header((header::AUTHORIZATION, format!("Bearer {}", STUDENT_TOKEN)))
+            .set_json(chat_request("m1", false))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 200);
+    }
+    let chats = |requests: Vec<Request>| {
+        requests.iter().filter(|request| request.url.path() == "/v1/chat/completions").count()
+    };
+    assert_eq!(chats(recovered.received_requests().await.unwrap()), 2);
+    assert_eq!(chats(steady.received_requests().await.unwrap()), 20);
+
+    let req = test::TestRequest::get()
+        .uri("/health-status")
+        .insert_header((header::AUTHORIZATION, format!("Bearer {}", ADMIN_TOKEN)))
+        .to_request();
+    let status: Value = test::call_and_read_body_json(&app, req).await;
+    assert!(status[&recovered.uri()]["slow_start"]["share"].as_f64().unwrap() < 0.2);
+    assert!(status[&steady.uri()]["slow_start"].is_null());
+}
+
+#[actix_web::test]
+async fn routes_around_endpoints_failing_deep_checks() {
+    let broken = backend(&["m1"]).await;
+    let working = backend(&["m1"]).await;
+    for (server, status) in [(&broken, 500), (&working, 200)] {
+        Mock::given(method("POST"))
+            .and(path("/v1/chat/completions"))
+            .respond_with(ResponseTemplate::new(status).set_body_json(completion("m1", "Hi")))
+            .mount(server)
+            .await;
+    }
+    let state = monitored_state(
+        vec![endpoint(&broken.uri()), endpoint(&working.uri())],
+        auth_config(),
+        |state| {
+            state.monitor_intervals =
+                MonitorIntervals { deep_check: Some(Duration::from_secs(60)), ..state.monitor_intervals };
+            state.health_thresholds.failures = 1;
+        },
+    );
+    // /health passes on both, the broken one can't generate
+    wait_for("the deep checks", || {
+        let checks = state.deep_checks.lock().unwrap();
+        checks.get(&broken.uri()).is_some_and(|check| !check.passed)
+            && checks.get(&working.uri()).is_some_and(|check| check.passed)
+    })
+    .await;
+    let app = app(&state).await;
+
+    for _ in 0..4 {
+        let req = test::TestRequest::post()
+            .uri("/v1/chat/completions")
+            .insert_header((header::AUTHORIZATION, format!("Bearer {}", STUDENT_TOKEN)))
+            .set_json(chat_request("m1", false))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 200);
+    }
+    // Chat completions capped at a single token are deep checks
+    let chats = |requests: Vec<Request>| -> Vec<Value> {
+        requests
+            .iter()
+            .filter(|request| request.url.path() == "/v1/chat/completions")
+            .map(|request| request.body_json().unwrap())
+            .collect()
+    };
+    let to_broken = chats(broken.received_requests().await.unwrap());
+    assert!(to_broken.iter().all(|body| body["max_tokens"] == 1));
+    let to_working = chats(working.received_requests().await.unwrap());
+    assert_eq!(to_working.iter().filter(|body| body["max_tokens"] != 1).count(), 4);
+
+    let req = test::TestRequest::get()
+        .uri("/health-status")
+        .insert_header((header::AUTHORIZATION, format!("Bearer {}", ADMIN_TOKEN)))
+        .to_request();
+    let status: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(status[&broken.uri()]["deep_check"]["passed"], false);
+    assert_eq!(status[&broken.uri()]["deep_check"]["error"], "status 500");
+    assert_eq!(status[&working.uri()]["deep_check"]["model"], "m1");
+}
+
+#[actix_web::test]
+async fn queues_requests_while_endpoints_are_busy() {
+    let server = backend(&["m1"]).await;
+    Mock::given(method("POST"))
+        .and(path("/v1/chat/completions"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(completion("m1", "Hi"))
+                .set_delay(Duration::from_millis(300)),
+        )
+        .mount(&server)
+        .await;
+    let mut busy = endpoint(&server.uri());
+    busy.pools = Some(EndpointPools { batch: Some(1), ..Default::default() });
+    let state = monitored_state(vec![busy], auth_config(), |state| {
+        state.admission = AdmissionQueue::new(1, Duration::from_secs(5));
+    });
+    wait_for("the model", || serves(&state, "m1", &server.uri())).await;
+    let app = app(&state).await;
+
+    // One request runs, one waits for its slot, the third finds the queue full
+    let chat = || {
+        test::TestRequest::post()
+            .uri("/v1/chat/completions")
+            .insert_header((header::AUTHORIZATION, format!("Bearer {}", STUDENT_TOKEN)))
+            .set_json(chat_request("m1", false))
+            .to_request()
+    };
+    let (first, second, third) = futures::join!(
+        test::call_service(&app, chat()),
+        test::call_service(&app, chat()),
+        test::call_service(&app, chat()),
+    );
+    let mut statuses = vec![first.status(), second.status(), third.status()];
+    statuses.sort();
+    assert_eq!(statuses, [200, 200, 429]);
+    let full = [first, second, third].into_iter().find(|resp| resp.status() == 429).unwrap();
+    assert!(full.headers().contains_key("retry-after"));
+    let body: Value = test::read_body_json(full).await;
+    assert_eq!(body["error"]["code"], "queue_full");
+}
+
+#[actix_web::test]
+async fn higher_priority_requests_take_the_place_of_queued_ones() {
+    let server = backend(&["m1"]).await;
+    Mock::given(method("POST"))
+        .and(path("/v1/chat/completions"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(completion("m1", "Hi"))
+                .set_delay(Duration::from_millis(500)),
+        )
+        .mount(&server)
+        .await;
+    let mut busy = endpoint(&server.uri());
+    busy.pools = Some(EndpointPools { batch: Some(1), ..Default::default() });
+    let mut auth = auth_config();
+    auth.priorities = HashMap::from([("admin".to_string(), 10)]);
+    let state = monitored_state(vec![busy], auth, |state| {
+        state.admission = AdmissionQueue::new(1, Duration::from_secs(5));
+    });
+    wait_for("the model", || serves(&state, "m1", &server.uri())).await;
+    let app = app(&state).await;
+
+    // A student's request runs and another waits when an admin's arrives at the full queue
+    let chat = |token: &str| {
+        test::TestRequest::post()
+            .uri("/v1/chat/completions")
+            .insert_header((header::AUTHORIZATION, format!("Bearer {}", token)))
+            .set_json(chat_request("m1", false))
+            .to_request()
+    };
+    let later = |millis: u64, token: &'static str| {
+        let app = &app;
+        async move {
+            tokio::time::sleep(Duration::from_millis(millis)).await;
+            test::call_service(app, chat(token)).await
+        }
+    };
+    let (running, waiting, admin) =
+        futures::join!(later(0, STUDENT_TOKEN), later(100, STUDENT_TOKEN), later(200, ADMIN_TOKEN));
+    assert_eq!(running.status(), 200);
+    assert_eq!(admin.status(), 200);
+    assert_eq!(waiting.status(), 429);
+    let body: Value = test::read_body_json(waiting).await;
+    assert_eq!(body["error"]["code"], "queue_full");
+}
+
+#[actix_web::test]
+async fn turns_callers_away_once_their_budget_is_used_up() {
+    let server = backend(&["m1"]).await;
+    Mock::given(method("POST"))
+        .and(path("/v1/chat/completions"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(completion("m1", "Hi")))
+        .mount(&server)
+        .await;
+    let mut auth = auth_config();
+    let per_token = Budget { daily: Some(10), monthly: None };
+    auth.budgets = HashMap::from([("student".to_string(), GroupBudget { per_token, ..Default::default() })]);
+    let state = monitored_state(vec![endpoint(&server.uri())], auth, |_| {});
+    wait_for("the model", || serves(&state, "m1", &server.uri())).await;
+    let app = app(&state).await;
+
+    // Each response uses 7 tokens, the second one goes over the budget
+    let chat = |token: &str| {
+        test::TestRequest::post()
+            .uri("/v1/chat/completions")
+            .insert_header((header::AUTHORIZATION, format!("Bearer {}", token)))
+            .set_json(chat_request("m1", false))
+            .to_request()
+    };
+    for _ in 0..2 {
+        assert_eq!(test::call_service(&app, chat(STUDENT_TOKEN)).await.status(), 200);
+    }
+    let resp = test::call_service(&app, chat(STUDENT_TOKEN)).await;
+    assert_eq!(resp.status(), 429);
+    assert!(resp.headers().contains_key("retry-after"));
+    let body: Value = test::read_body_json(resp).await;
+    assert_eq!(body["error"]["code"], "budget_exceeded");
+    // Groups without a budget are not limited
+    assert_eq!(test::call_service(&app, chat(ADMIN_TOKEN)).await.status(), 200);
+
+    let req = test::TestRequest::get()
+        .uri("/budgets")
+        .insert_header((header::AUTHORIZATION, format!("Bearer {}", STUDENT_TOKEN)))
+        .to_request();
+    let budgets: Value = test::call_and_read_body_json(&app, req).await;
+    let daily = &budgets["groups"]["student"]["per_token"]["daily"];
+    assert_eq!(daily["limit"], 10);
+    assert_eq!(daily["used"], 14);
+    assert_eq!(daily["remaining"], 0);
+    assert_eq!(budgets["groups"]["student"]["daily"]["used"], 14);
+    assert_eq!(budgets["exhausted"]["period"], "daily");
+}
+
+#[actix_web::test]
+async fn books_answers_from_the_cache_against_budgets() {
+    let server = backend(&["m1"]).await;
+    Mock::given(method("POST"))
+        .and(path("/v1/chat/completions"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(completion("m1", "Hi")))
+        .expect(2)
+        .mount(&server)
+        .await;
+    let mut auth = auth_config();
+    let per_token = Budget { daily: Some(10), monthly: None };
+    auth.budgets = HashMap::from([("student".to_string(), GroupBudget { per_token, ..Default::default() })]);
+    let state = monitored_state(vec![endpoint(&server.uri())], auth, |state| {
+        state.response_cache = ResponseCache::new(Some(Duration::from_secs(60)), 1);
+    });
+    wait_for("the model", || serves(&state, "m1", &server.uri())).await;
+    let app = app(&state).await;
+
+    // The second answer comes from the cache and still uses up the budget
+    let chat = |token: &str| {
+        test::TestRequest::post()
+            .uri("/v1/chat/completions")
+            .insert_header((header::AUTHORIZATION, format!("Bearer {}", token)))
+            .insert_header(("X-Cache", "true"))
+            .set_json(chat_request("m1", false))
+            .to_request()
+    };
+    for expected in ["miss", "hit"] {
+        let resp = test::call_service(&app, chat(STUDENT_TOKEN)).await;
+        assert_eq!(resp.headers().get("x-cache").unwrap(), expected);
+    }
+    let resp = test::call_service(&app, chat(STUDENT_TOKEN)).await;
+    assert_eq!(resp.status(), 429);
+    // Callers of other groups don't get the students' answers
+    let resp = test::call_service(&app, chat(ADMIN_TOKEN)).await;
+    assert_eq!(resp.headers().get("x-cache").unwrap(), "miss");
+}
+
+#[actix_web::test]
+async fn policies_keep_groups_from_guided_decoding() {
+    let server = backend(&["m1"]).await;
+    let mut auth = auth_config();
+    auth.policies = vec![serde_json::from_value::<Policy>(json!({
+        "groups": ["student"],
+        "on_violation": "reject",
+        "fields": { "guided_grammar": { "allowed": false } }
+    }))
+    .unwrap()];
+    let state = monitored_state(vec![endpoint(&server.uri())], auth, |_| {});
+    wait_for("the model", || serves(&state, "m1", &server.uri())).await;
+    let app = app(&state).await;
+
+    let mut request = chat_request("m1", false);
+    request["guided_grammar"] = json!("root ::= \"yes\" | \"no\"");
+    let req = test::TestRequest::post()
+        .uri("/v1/chat/completions")
+        .insert_header((header::AUTHORIZATION, format!("Bearer {}", STUDENT_TOKEN)))
+        .set_json(&request)
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 400);
+    let body: Value = test::read_body_json(resp).await;
+    assert_eq!(body["error"]["message"], "`guided_grammar` is not allowed for model `m1`.");
+}
+
+#[actix_web::test]
+async fn falls_back_along_the_chains_of_endpoints() {
+    let server = backend(&["m1"]).await;
+    Mock::given(method("POST"))
+        .and(path("/v1/chat/completions"))
+        .and(body_partial_json(json!({ "model": "m1" })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(completion("m1", "Hi")))
+        .expect(1)
+        .mount(&server)
+        .await;
+    let mut with_fallbacks = endpoint(&server.uri());
+    with_fallbacks.fallbacks = Some(BTreeMap::from([("m2".to_string(), vec!["m1".to_string()])]));
+    let state = state_with(vec![with_fallbacks]);
+    wait_for("the model", || serves(&state, "m1", &server.uri())).await;
+    let app = app(&state).await;
+
+    let req = test::TestRequest::post()
+        .uri("/v1/chat/completions")
+        .insert_header((header::AUTHORIZATION, format!("Bearer {}", STUDENT_TOKEN)))
+        .set_json(chat_request("m2", false))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers().get("x-fallback-from").unwrap(), "m2");
+}
+
+#[actix_web::test]
+async fn unknown_models_are_not_found() {
+    let server = backend(&["m1"]).await;
+    let state = state_with(vec![endpoint(&server.uri())]);
+    wait_for("the model", || serves(&state, "m1", &server.uri())).await;
+    let app = app(&state).await;
+
+    let req = test::TestRequest::post()
+        .uri("/v1/chat/completions")
+        .insert_header((header::AUTHORIZATION, format!("Bearer {}", STUDENT_TOKEN)))
+        .set_json(chat_request("m2", false))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 404);
+    // In OpenAI's error schema, which the SDKs parse
+    let body: Value = test::read_body_json(resp).await;
+    assert_eq!(body["error"]["type"], "invalid_request_error");
+    assert_eq!(body["error"]["param"], "model");
+    assert_eq!(body["error"]["code"], "model_not_found");
+    assert_eq!(body["error"]["message"], "The model `m2` does not exist.");
+}
+
+#[actix_web::test]
+async fn splits_long_embedding_inputs_over_endpoints() {
+    // Embeds each input as its length
+    let embed = |req: &Request| {
+        let body: Value = serde_json::from_slice(&req.body).unwrap();
+        let inputs = body["input"].as_array().unwrap();
+        let data: Vec<Value> = inputs
+            .iter()
+            .enumerate()
+            .map(|(i, input)| {
+                json!({"object": "embedding", "index": i, "embedding": [input.as_str().unwrap().len()]})
+            })
+            .collect();
+        let usage = json!({"prompt_tokens": inputs.len(), "total_tokens": inputs.len()});
+        let resp = json!({"object": "list", "model": "e1", "data": data, "usage": usage});
+        ResponseTemplate::new(200).set_body_json(resp)
+    };
+    let first = backend(&["e1"]).await;
+    let second = backend(&["e1"]).await;
+    for server in [&first, &second] {
+        Mock::given(method("POST"))
+            .and(path("/v1/embeddings"))
+            .respond_with(embed)
+            .expect(1..)
+            .mount(server)
+            .await;
+    }
+    let embed_endpoint = |url: &str| Endpoint { task: "embed".to_string(), ..endpoint(url) };
+    let state = monitored_state(
+        vec![embed_endpoint(&first.uri()), embed_endpoint(&second.uri())],
+        auth_config(),
+        |state| {
+            state.embedding_split = EmbeddingSplit { chunk_size: Some(2) };
+        },
+    );
+    wait_for("both endpoints", || {
+        state.embed.model_to_endpoints.lock().unwrap().get("e1").is_some_and(|urls| urls.len() == 2)
+    })
+    .await;
+    let app = app(&state).await;
+
+    let req = test::TestRequest::post()
+        .uri("/v1/embeddings")
+        .insert_header((header::AUTHORIZATION, format!("Bearer {}", STUDENT_TOKEN)))
+        .set_json(json!({"model": "e1", "input": ["a", "bb", "ccc", "dddd", "eeeee"]}))
+        .to_request();
+    let resp: Value = test::call_and_read_body_json(&app, req).await;
+    let embeddings: Vec<(u64, u64)> = resp["data"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|item| (item["index"].as_u64().unwrap(), item["embedding"][0].as_u64().unwrap()))
+        .collect();
+    assert_eq!(embeddings, [(0, 1), (1, 2), (2, 3), (3, 4), (4, 5)]);
+    assert_eq!(resp["usage"]["prompt_tokens"], 5);
+}
+
+#[actix_web::test]
+async fn screens_chat_requests_of_guarded_groups() {
+    let chat = backend(&["m1"]).await;
+    Mock::given(method("POST"))
+        .and(path("/v1/chat/completions"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(completion("m1", "Hi")))
+        .expect(1)
+        .mount(&chat)
+        .await;
+    let guard = backend(&["guard"]).await;
+    Mock::given(method("POST"))
+        .and(path("/v1/chat/completions"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(completion("guard", "unsafe\nS1")))
+        .expect(1)
+        .mount(&guard)
+        .await;
+    let guard_endpoint = Endpoint { task: "moderate".to_string(), ..endpoint(&guard.uri()) };
+    let state = monitored_state(
+        vec![endpoint(&chat.uri()), guard_endpoint],
+        auth_config(),
+        |state| {
+            state.guard = GuardConfig { model: Some("guard".to_string()), groups: vec!["student".to_string()] };
+        },
+    );
+    wait_for("both models", || {
+        state.generate.model_to_endpoints.lock().unwrap().contains_key("m1")
+            && state.moderate.model_to_endpoints.lock().unwrap().contains_key("guard")
+    })
+    .await;
+    let app = app(&state).await;
+
+    let req = test::TestRequest::post()
+        .uri("/v1/chat/completions")
+        .insert_header((header::AUTHORIZATION, format!("Bearer {}", STUDENT_TOKEN)))
+        .set_json(chat_request("m1", false))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 400);
+    let body: Value = test::read_body_json(resp).await;
+    assert_eq!(body["error"]["code"], "content_policy_violation");
+
+    // Admins are not screened
+    let req = test::TestRequest::post()
+        .uri("/v1/chat/completions")
+        .insert_header((header::AUTHORIZATION, format!("Bearer {}", ADMIN_TOKEN)))
+        .set_json(chat_request("m1", false))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 200);
+}
+
+#[actix_web::test]
+async fn forwards_request_bodies_as_sent() {
+    let server = backend(&["m1"]).await;
+    Mock::given(method("POST"))
+        .and(path("/v1/chat/completions"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(completion("m1", "Hi")))
+        .mount(&server)
+        .await;
+    let mut aliased = endpoint(&server.uri());
+    aliased.aliases = Some([("fast".to_string(), "m1".to_string())].into());
+    let state = state_with(vec![aliased]);
+    wait_for("the model", || serves(&state, "m1", &server.uri())).await;
+    let app = app(&state).await;
+
+    // Spacing, key order and escapes survive, only the alias and the usage of streams change
+    let sent = [
+        r#"{ "metadata": {"model": "x"}, "messages": [{"role": "user", "content": "Caf\u00e9"}], "model": "m1" }"#,
+        r#"{"metadata": {"model": "x"}, "model" : "fast", "temperature": 0.50, "messages": []}"#,
+        r#" {"stream": true, "model": "m1", "messages": []}"#,
+    ];
+    for body in sent {
+        let req = test::TestRequest::post()
+            .uri("/v1/chat/completions")
+            .insert_header((header::AUTHORIZATION, format!("Bearer {}", STUDENT_TOKEN)))
+            .insert_header((header::CONTENT_TYPE, "application/json"))
+            .set_payload(body)
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 200);
+    }
+    let requests = server.received_requests().await.unwrap();
+    let forwarded: Vec<String> = requests
+        .iter()
+        .filter(|request| request.url.path() == "/v1/chat/completions")
+        .map(|request| String::from_utf8(request.body.clone()).unwrap())
+        .collect();
+    assert_eq!(
+        forwarded,
+        [
+            sent[0].to_string(),
+            r#"{"metadata": {"model": "x"}, "model" : "m1", "temperature": 0.50, "messages": []}"#.to_string(),
+            r#" {"stream_options":{"include_usage":true},"stream": true, "model": "m1", "messages": []}"#.to_string(),
+        ]
+    );
+}
+
+#[actix_web::test]
+async fn answers_aliases_under_the_requested_name() {
+    let server = backend(&["m1"]).await;
+    let completion = r#"{"id":"cmpl-1","object":"chat.completion","model":"m1","choices":[]}"#;
+    let events = concat!(
+        "data: {\"id\":\"cmpl-1\",\"model\":\"m1\",\"choices\":[{\"index\":0,\"delta\":{\"content\":\"m1\"}}]}\n\n",
+        "data: [DONE]\n\n",
+    );
+    Mock::given(method("POST"))
+        .and(path("/v1/chat/completions"))
+        .and(body_partial_json(json!({"stream": true})))
+        .respond_with(ResponseTemplate::new(200).set_body_raw(events, "text/event-stream"))
+        .with_priority(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/v1/chat/completions"))
+        .respond_with(ResponseTemplate::new(200).set_body_raw(completion, "application/json"))
+        .mount(&server)
+        .await;
+    let mut aliased = endpoint(&server.uri());
+    aliased.aliases = Some([("fast".to_string(), "m1".to_string())].into());
+    let state = state_with(vec![aliased]);
+    wait_for("the model", || serves(&state, "m1", &server.uri())).await;
+    let app = app(&state).await;
+
+    // Only the model field changes, key order and generated text stay as the endpoint sent them
+    let expected = [
+        r#"{"id":"cmpl-1","object":"chat.completion","model":"fast","choices":[]}"#,
+        concat!(
+            "data: {\"id\":\"cmpl-1\",\"model\":\"fast\",\"choices\":[{\"index\":0,\"delta\":{\"content\":\"m1\"}}]}\n\n",
+            "data: [DONE]\n\n",
+        ),
+    ];
+    for (stream, expected) in [false, true].into_iter().zip(expected) {
+        let req = test::TestRequest::post()
+            .uri("/v1/chat/completions")
+            .insert_header((header::AUTHORIZATION, format!("Bearer {}", STUDENT_TOKEN)))
+            .set_json(chat_request("fast", stream))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 200);
+        assert_eq!(test::read_body(resp).await, expected.as_bytes());
+    }
+}
+
+// -----------------------------------------------------------------------------
+// Streaming
+// -----------------------------------------------------------------------------
+
+#[actix_web::test]
+async fn relays_streamed_chunks() {
+    let server = backend(&["m1"]).await;
+    let chunk = |delta: Value, finish: Value| {
+        json!({
+            "id": "cmpl-1",
+            "object": "chat.completion.chunk",
+            "model": "m1",
+            "choices": [{"index": 0, "delta": delta, "finish_reason": finish}],
+        })
+    };
+    let usage = json!({
+        "id": "cmpl-1",
+        "object": "chat.completion.chunk",
+        "model": "m1",
+        "choices": [],
+        "usage": {"prompt_tokens": 5, "completion_tokens": 2, "total_tokens": 7},
+    });
+    let events: String = [
+        chunk(json!({"role": "assistant", "content": "Hel"}), Value::Null),
+        chunk(json!({"content": "lo"}), json!("stop")),
+        usage,
+    ]
+    .iter()
+    .map(|event| format!("data: {}\n\n", event))
+    .chain(["data: [DONE]\n\n".to_string()])
+    .collect();
+    Mock::given(method("POST"))
+        .and(path("/v1/chat/completions"))
+        .respond_with(ResponseTemplate::new(200).set_body_raw(events, "text/event-stream"))
+        .mount(&server)
+        .await;
+    let state = state_with(vec![endpoint(&server.uri())]);
+    wait_for("the model", || serves(&state, "m1", &server.uri())).await;
+    let app = app(&state).await;
+
+    let req = test::TestRequest::post()
+        .uri("/v1/chat/completions")
+        .insert_header((header::AUTHORIZATION, format!("Bearer {}", STUDENT_TOKEN)))
+        .set_json(chat_request("m1", true))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 200);
+    assert!(
+        resp.headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.starts_with("text/event-stream"))
+    );
+    let body = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+    assert!(body.contains("\"Hel\""));
+    assert!(body.contains("\"lo\""));
+    assert!(body.trim_end().ends_with("data: [DONE]"));
+    // The composer asked for usage to account for it, the client did not
+    assert!(!body.contains("\"usage\""));
+
+    let requests = server.received_requests().await.unwrap();
+    let forwarded: Value = requests
+        .iter()
+        .find(|request| request.url.path() == "/v1/chat/completions")
+        .unwrap()
+        .body_json()
+        .unwrap();
+    assert_eq!(forwarded["stream_options"]["include_usage"], json!(true));
+    let totals = state.usage.token_totals();
+    assert_eq!(totals.values().map(|totals| totals.completion_tokens).sum::<u64>(), 2);
+}
+
+#[actix_web::test]
+async fn counts_requests_the_client_abandons() {
+    let server = backend(&["m1"]).await;
+    Mock::given(method("POST"))
+        .and(path("/v1/chat/completions"))
+        .and(body_partial_json(json!({"stream": true})))
+        .respond_with(ResponseTemplate::new(200).set_body_raw("data: [DONE]\n\n", "text/event-stream"))
+        .with_priority(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/v1/chat/completions"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(completion("m1", "Hi"))
+                .set_delay(Duration::from_secs(5)),
+        )
+        .mount(&server)
+        .await;
+    let state = state_with(vec![endpoint(&server.uri())]);
+    wait_for("the model", || serves(&state, "m1", &server.uri())).await;
+    let app = app(&state).await;
+    let request = |stream: bool| {
+        test::TestRequest::post()
+            .uri("/v1/chat/completions")
+            .insert_header((header::AUTHORIZATION, format!("Bearer {}", STUDENT_TOKEN)))
+            .set_json(chat_request("m1", stream))
+            .to_request()
+    };
+    let cancelled = || {
+        state
+            .metrics
+            .requests_cancelled
+            .with_label_values(&["generate", "m1", &server.uri()])
+            .get()
+    };
+
+    // Gone while the endpoint generates, and before reading the stream
+    let pending = tokio::time::timeout(Duration::from_millis(200), test::call_service(&app, request(false)));
+    assert!(pending.await.is_err());
+    assert_eq!(cancelled(), 1);
+    drop(test::call_service(&app, request(true)).await);
+    assert_eq!(cancelled(), 2);
+    assert_eq!(state.model_stats.total_in_flight(), 0);
+
+    // Streams read to the end are not
+    test::read_body(test::call_service(&app, request(true)).await).await;
+    assert_eq!(cancelled(), 2);
+}
+
+#[actix_web::test]
+async fn translates_anthropic_message_streams() {
+    let server = backend(&["m1"]).await;
+    let chunk = |delta: Value, finish: Value| {
+        json!({"id": "chatcmpl-1", "choices": [{"index": 0, "delta": delta, "finish_reason": finish}]})
+    };
+    let usage = json!({
+        "id": "chatcmpl-1",
+        "choices": [],
+        "usage": {"prompt_tokens": 5, "completion_tokens": 2, "total_tokens": 7},
+    });
+    let events: String = [
+        chunk(json!({"role": "assistant", "content": "Hel"}), Value::Null),
+        chunk(json!({"content": "lo"}), json!("length")),
+        usage,
+    ]
+    .iter()
+    .map(|event| format!("data: {}\n\n", event))
+    .chain(["data: [DONE]\n\n".to_string()])
+    .collect();
+    Mock::given(method("POST"))
+        .and(path("/v1/chat/completions"))
+        .respond_with(ResponseTemplate::new(200).set_body_raw(events, "text/event-stream"))
+        .mount(&server)
+        .await;
+    let state = state_with(vec![endpoint(&server.uri())]);
+    wait_for("the model", || serves(&state, "m1", &server.uri())).await;
+    let app = app(&state).await;
+
+    // The Anthropic SDKs send their key as x-api-key
+    let req = test::TestRequest::post()
+        .uri("/v1/messages")
+        .insert_header(("x-api-key", STUDENT_TOKEN))
+        .set_json(json!({
+            "model": "m1",
+            "max_tokens": 2,
+            "system": "Be brief.",
+            "messages": [{"role": "user", "content": [{"type": "text", "text": "Hello"}]}],
+            "stream": true,
+        }))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 200);
+    let body = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+    let names: Vec<&str> = body.lines().filter_map(|line| line.strip_prefix("event: ")).collect();
+    assert_eq!(
+        names,
+        [
+            "message_start",
+            "content_block_start",
+            "content_block_delta",
+            "content_block_delta",
+            "content_block_stop",
+            "message_delta",
+            "message_stop",
+        ]
+    );
+    let delta: Value = body
+        .lines()
+        .filter_map(|line| line.strip_prefix("data: "))
+        .map(|data| serde_json::from_str::<Value>(data).unwrap())
+        .find(|event| event["type"] == "message_delta")
+        .unwrap();
+    assert_eq!(delta["delta"]["stop_reason"], "max_tokens");
+    assert_eq!(delta["usage"]["output_tokens"], 2);
+
+    let requests = server.received_requests().await.unwrap();
+    let forwarded: Value = requests
+        .iter()
+        .find(|request| request.url.path() == "/v1/chat/completions")
+        .unwrap()
+        .body_json()
+        .unwrap();
+    assert_eq!(
+        forwarded["messages"],
+        json!([{"role": "system", "content": "Be brief."}, {"role": "user", "content": "Hello"}])
+    );
+}
+
+#[actix_web::test]
+async fn answers_responses_requests_from_chat_completions() {
+    let server = backend(&["m1"]).await;
+    let mut answer = completion("m1", "Paris");
+    answer["choices"][0]["finish_reason"] = json!("length");
+    Mock::given(method("POST"))
+        .and(path("/v1/chat/completions"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(answer))
+        .mount(&server)
+        .await;
+    let state = state_with(vec![endpoint(&server.uri())]);
+    wait_for("the model", || serves(&state, "m1", &server.uri())).await;
+    let app = app(&state).await;
+
+    let req = test::TestRequest::post()
+        .uri("/v1/responses")
+        .insert_header((header::AUTHORIZATION, format!("Bearer {}", STUDENT_TOKEN)))
+        .set_json(json!({
+            "model": "m1",
+            "instructions": "Be brief.",
+            "input": [{"role": "user", "content": [{"type": "input_text", "text": "Capital of France?"}]}],
+            "max_output_tokens": 1,
+        }))
+        .to_request();
+    let resp: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(resp["object"], "response");
+    assert_eq!(resp["status"], "incomplete");
+    assert_eq!(resp["output"][0]["content"][0]["text"], "Paris");
+    assert_eq!(resp["usage"]["output_tokens"], 2);
+    assert_eq!(resp["max_output_tokens"], 1);
+
+    let requests = server.received_requests().await.unwrap();
+    let forwarded: Value = requests
+        .iter()
+        .find(|request| request.url.path() == "/v1/chat/completions")
+        .unwrap()
+        .body_json()
+        .unwrap();
+    assert_eq!(forwarded["max_tokens"], 1);
+    assert_eq!(
+        forwarded["messages"],
+        json!([{"role": "system", "content": "Be brief."}, {"role": "user", "content": "Capital of France?"}])
+    );
+}